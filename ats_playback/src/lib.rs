@@ -1,39 +1,227 @@
-use std::{fs, io, path::PathBuf};
+pub mod replay;
+mod summary;
+
+pub use summary::{packet_kind, summarize, RecordingSummary};
+
+use ats_usb::device::GeneralSettings;
+use ats_usb::packets::le;
+use ats_usb::packets::vm::Packet;
 use postcard::take_from_bytes;
-use ats_usb::packets::vm::{GeneralConfig, Packet};
+use std::{fs, io, path::PathBuf};
 
-pub fn read_file(path: &PathBuf) -> io::Result<(GeneralConfig, Vec<(u128, Packet)>)> {
+/// Load a recording saved by the desktop app: the [`GeneralSettings`] at the time of recording,
+/// followed by timestamped packets.
+pub fn read_file(path: &PathBuf) -> io::Result<(GeneralSettings, Vec<(u128, Packet)>)> {
     let data = fs::read(path)?;
-    let mut bytes: &[u8] = &data;
+    let (general_config, packets) = parse(&data)?;
+    Ok((general_config, packets.collect::<io::Result<_>>()?))
+}
 
-    let (general_config, rest) = take_from_bytes::<GeneralConfig>(bytes)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("GeneralConfig decode: {e:?}")))?;
-    bytes = rest;
+/// Save a recording in the format [`read_file`] loads.
+pub fn write_file(
+    path: &PathBuf,
+    general_config: &GeneralSettings,
+    packets: &[(u128, Packet)],
+) -> io::Result<()> {
+    fs::write(path, encode(general_config, packets)?)
+}
+
+/// Encode a recording: the postcard encoded settings, then each packet preceded by its timestamp
+/// as a little-endian `u128`.
+pub fn encode(general_config: &GeneralSettings, packets: &[(u128, Packet)]) -> io::Result<Vec<u8>> {
+    let encode_err =
+        |e: postcard::Error| io::Error::new(io::ErrorKind::InvalidData, format!("encode: {e:?}"));
+    let mut bytes = postcard::to_stdvec(general_config).map_err(encode_err)?;
+    for (timestamp, packet) in packets {
+        le::write_u128_le(&mut bytes, *timestamp);
+        bytes = postcard::to_extend(packet, bytes).map_err(encode_err)?;
+    }
+    Ok(bytes)
+}
+
+/// Copy the packets from `start_ms` up to `end_ms` (milliseconds after the first packet) of the
+/// recording at `input` to `output`, along with its settings.
+///
+/// With `rebase`, the copied timestamps count from `start_ms` instead of keeping their original
+/// values. Returns the number of packets copied.
+pub fn trim(
+    input: &PathBuf,
+    output: &PathBuf,
+    start_ms: u128,
+    end_ms: u128,
+    rebase: bool,
+) -> io::Result<usize> {
+    let (general_config, packets) = read_file(input)?;
+    let Some(&(first, _)) = packets.first() else {
+        write_file(output, &general_config, &[])?;
+        return Ok(0);
+    };
+    let (start, end) = (first.saturating_add(start_ms), first.saturating_add(end_ms));
+    let trimmed: Vec<_> = packets
+        .into_iter()
+        .filter(|(t, _)| (start..end).contains(t))
+        .map(|(t, p)| if rebase { (t - start, p) } else { (t, p) })
+        .collect();
+    write_file(output, &general_config, &trimmed)?;
+    Ok(trimmed.len())
+}
 
+/// Combine the recordings at `inputs` into a single timestamp ordered recording at `output`.
+///
+/// All inputs must have been recorded with the same settings. Packets with equal timestamps keep
+/// the order of `inputs`. Returns the number of packets written.
+pub fn merge(inputs: &[PathBuf], output: &PathBuf) -> io::Result<usize> {
+    let mut merged: Option<(GeneralSettings, Vec<u8>)> = None;
     let mut packets = Vec::new();
-    while !bytes.is_empty() {
-        if bytes.len() < 16 {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "EOF in timestamp"));
+    for input in inputs {
+        let (general_config, input_packets) = read_file(input)?;
+        // GeneralSettings isn't PartialEq, compare the encoded settings instead
+        let encoded = postcard::to_stdvec(&general_config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("encode: {e:?}")))?;
+        match &merged {
+            Some((_, first)) if *first != encoded => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} was recorded with different settings than {}",
+                        input.display(),
+                        inputs[0].display()
+                    ),
+                ));
+            }
+            Some(_) => {}
+            None => merged = Some((general_config, encoded)),
         }
+        packets.extend(input_packets);
+    }
+    let Some((general_config, _)) = merged else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no recordings to merge",
+        ));
+    };
+    packets.sort_by_key(|(timestamp, _)| *timestamp);
+    write_file(output, &general_config, &packets)?;
+    Ok(packets.len())
+}
 
-        let mut tsb = [0u8; 16];
-        tsb.copy_from_slice(&bytes[..16]);
-        let timestamp = u128::from_le_bytes(tsb);
+/// Decode the [`GeneralSettings`] at the start of a recording and return them with an iterator
+/// over the packets that follow.
+pub fn parse(data: &[u8]) -> io::Result<(GeneralSettings, Packets<'_>)> {
+    let (general_config, rest) = take_from_bytes::<GeneralSettings>(data).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("GeneralSettings decode: {e:?}"),
+        )
+    })?;
+    Ok((general_config, Packets { bytes: rest }))
+}
+
+/// The timestamped packets of a recording, decoded one at a time.
+///
+/// Yields at most one error, after which the iterator is exhausted.
+pub struct Packets<'a> {
+    bytes: &'a [u8],
+}
 
-        match take_from_bytes::<Packet>(&bytes[16..]) {
+impl Iterator for Packets<'_> {
+    type Item = io::Result<(u128, Packet)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let bytes = std::mem::take(&mut self.bytes);
+
+        let Some((timestamp, rest)) = le::read_u128_le(bytes) else {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "EOF in timestamp",
+            )));
+        };
+
+        match take_from_bytes::<Packet>(rest) {
             Ok((pkt, rest_after_pkt)) => {
-                let consumed = 16 + (bytes[16..].len() - rest_after_pkt.len());
-                bytes = &bytes[consumed..];
-                packets.push((timestamp, pkt));
-            }
-            Err(postcard::Error::DeserializeUnexpectedEnd) => {
-                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "EOF in Packet"));
-            }
-            Err(e) => {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Packet decode: {e:?}")));
+                self.bytes = rest_after_pkt;
+                Some(Ok((timestamp, pkt)))
             }
+            Err(postcard::Error::DeserializeUnexpectedEnd) => Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "EOF in Packet",
+            ))),
+            Err(e) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Packet decode: {e:?}"),
+            ))),
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ats_usb::packets::vm::{ImpactReport, PacketData};
+
+    pub(crate) fn impact(id: u8, timestamp: u128) -> (u128, Packet) {
+        let data = PacketData::ImpactReport(ImpactReport {
+            timestamp: timestamp as u32,
+        });
+        (timestamp, Packet { id, data })
+    }
+
+    /// A file path unique to this test process.
+    pub(crate) fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ats_playback-{}-{name}", std::process::id()))
+    }
+
+    pub(crate) fn write_recording(name: &str, packets: &[(u128, Packet)]) -> PathBuf {
+        let path = temp_path(name);
+        fs::write(&path, encode(&GeneralSettings::default(), packets).unwrap()).unwrap();
+        path
+    }
+
+    /// Timestamps and ids of the recording at `path`.
+    fn read_recording(path: &PathBuf) -> Vec<(u128, u8)> {
+        let data = fs::read(path).unwrap();
+        let (_, packets) = parse(&data).unwrap();
+        packets
+            .map(|p| p.map(|(t, p)| (t, p.id)).unwrap())
+            .collect()
+    }
 
-    Ok((general_config, packets))
+    #[test]
+    fn trim_keeps_packets_in_range() {
+        let packets: Vec<_> = (0..5).map(|i| impact(i, 1000 + 10 * i as u128)).collect();
+        let input = write_recording("trim-in", &packets);
+        let output = temp_path("trim-out");
+
+        assert_eq!(trim(&input, &output, 10, 30, true).unwrap(), 2);
+        assert_eq!(read_recording(&output), [(0, 1), (10, 2)]);
+
+        assert_eq!(trim(&input, &output, 10, 30, false).unwrap(), 2);
+        assert_eq!(read_recording(&output), [(1010, 1), (1020, 2)]);
+
+        assert_eq!(trim(&input, &output, 100, 200, true).unwrap(), 0);
+        assert_eq!(read_recording(&output), []);
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn merge_sorts_and_keeps_input_order_for_ties() {
+        let a = write_recording("merge-a", &[impact(1, 10), impact(2, 30), impact(3, 40)]);
+        let b = write_recording("merge-b", &[impact(4, 5), impact(5, 30), impact(6, 30)]);
+        let output = temp_path("merge-out");
+
+        assert_eq!(merge(&[a.clone(), b.clone()], &output).unwrap(), 6);
+        assert_eq!(
+            read_recording(&output),
+            [(5, 4), (10, 1), (30, 2), (30, 5), (30, 6), (40, 3)]
+        );
+
+        for path in [a, b, output] {
+            fs::remove_file(path).unwrap();
+        }
+    }
 }
@@ -0,0 +1,389 @@
+//! Headless replay of a recording over the UDP transport.
+//!
+//! The server plays the device side of [`ats_usb::udp`]: it answers discovery and pings from the
+//! desktop app, then sends the recorded packets with their original spacing.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ats_usb::packets::vm::Packet;
+use ats_usb::udp::{DISCOVER, PACKET_TAG, PING};
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+#[derive(Clone, Debug)]
+pub struct ReplayOptions {
+    /// Address to listen on, the client's discovery broadcast must reach it
+    pub bind_addr: SocketAddr,
+    /// Playback speed, 2.0 plays twice as fast as recorded
+    pub rate: f64,
+    /// Start over from the first packet after the last one
+    pub looping: bool,
+}
+
+impl ReplayOptions {
+    pub fn new(bind_addr: SocketAddr) -> Self {
+        Self {
+            bind_addr,
+            rate: 1.0,
+            looping: false,
+        }
+    }
+}
+
+/// Bind `options.bind_addr` and replay `packets` to the first client that connects.
+///
+/// Returns once the recording has played through, which never happens when looping. See
+/// [`PlaybackServer`] for control over a running replay.
+pub async fn serve(packets: &[(u128, Packet)], options: &ReplayOptions) -> io::Result<()> {
+    let socket = UdpSocket::bind(options.bind_addr).await?;
+    let server = PlaybackServer::new();
+    server.load(packets.to_vec());
+    server.set_rate(options.rate)?;
+    server.set_looping(options.looping);
+    server.play();
+    server.run(&socket).await
+}
+
+/// Playback engine for a loaded recording.
+///
+/// Clones share the same playback, so a frontend can keep one to call [`play`](Self::play),
+/// [`pause`](Self::pause), [`seek`](Self::seek) and [`set_rate`](Self::set_rate) while another
+/// drives [`run`](Self::run).
+#[derive(Clone, Default)]
+pub struct PlaybackServer {
+    state: Arc<Mutex<PlaybackState>>,
+    changed: Arc<Notify>,
+}
+
+struct PlaybackState {
+    packets: Vec<(u128, Packet)>,
+    /// Index of the next packet to send
+    position: usize,
+    /// Recording time in milliseconds at `anchor`, or at the pause
+    time: f64,
+    /// When `time` was last updated, `None` while paused
+    anchor: Option<Instant>,
+    rate: f64,
+    looping: bool,
+}
+
+impl Default for PlaybackState {
+    fn default() -> Self {
+        Self {
+            packets: Vec::new(),
+            position: 0,
+            time: 0.0,
+            anchor: None,
+            rate: 1.0,
+            looping: false,
+        }
+    }
+}
+
+impl PlaybackState {
+    fn current_time(&self, now: Instant) -> f64 {
+        match self.anchor {
+            Some(anchor) => self.time + (now - anchor).as_secs_f64() * 1000.0 * self.rate,
+            None => self.time,
+        }
+    }
+
+    /// Fold the elapsed time into `time` so the rate or play state can change from `now` on.
+    fn reanchor(&mut self, now: Instant) {
+        self.time = self.current_time(now);
+        if self.anchor.is_some() {
+            self.anchor = Some(now);
+        }
+    }
+
+    fn seek(&mut self, index: usize) {
+        self.position = index.min(self.packets.len());
+        if let Some(&(timestamp, _)) = self.packets.get(self.position).or(self.packets.last()) {
+            self.time = timestamp as f64;
+        }
+        if self.anchor.is_some() {
+            self.anchor = Some(Instant::now());
+        }
+    }
+}
+
+impl PlaybackServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the recording and rewind to its start, paused.
+    ///
+    /// Timestamps are the milliseconds written by the desktop app's recorder. Packets keep
+    /// their recorded ids.
+    pub fn load(&self, packets: Vec<(u128, Packet)>) {
+        let mut state = self.state.lock().unwrap();
+        state.packets = packets;
+        state.anchor = None;
+        state.seek(0);
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Playback speed, 2.0 plays twice as fast as recorded.
+    pub fn set_rate(&self, rate: f64) -> io::Result<()> {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid playback rate {rate}"),
+            ));
+        }
+        let mut state = self.state.lock().unwrap();
+        state.reanchor(Instant::now());
+        state.rate = rate;
+        drop(state);
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.state.lock().unwrap().rate
+    }
+
+    /// Start over from the first packet after the last one.
+    pub fn set_looping(&self, looping: bool) {
+        self.state.lock().unwrap().looping = looping;
+        self.changed.notify_one();
+    }
+
+    pub fn play(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.anchor.is_none() {
+            state.anchor = Some(Instant::now());
+        }
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.reanchor(Instant::now());
+        state.anchor = None;
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state.lock().unwrap().anchor.is_some()
+    }
+
+    /// Continue from packet `index`, clamped to the end of the recording.
+    pub fn seek(&self, index: usize) {
+        self.state.lock().unwrap().seek(index);
+        self.changed.notify_one();
+    }
+
+    /// Index of the next packet to be sent.
+    pub fn position(&self) -> usize {
+        self.state.lock().unwrap().position
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Serve the playback on an already bound socket.
+    ///
+    /// Waits for a client to complete the discovery handshake, then sends each packet when the
+    /// playback clock reaches it. A client that repeats the handshake mid-replay takes over the
+    /// stream. Returns when playback reaches the end of the recording without looping.
+    pub async fn run(&self, socket: &UdpSocket) -> io::Result<()> {
+        let mut peer = wait_for_client(socket).await?;
+        let mut buf = [0u8; 64];
+        loop {
+            let next = {
+                let mut state = self.state.lock().unwrap();
+                let playing = state.anchor.is_some();
+                if state.position >= state.packets.len() && playing {
+                    if !state.looping || state.packets.is_empty() {
+                        return Ok(());
+                    }
+                    state.seek(0);
+                }
+                match (state.anchor, state.packets.get(state.position)) {
+                    (Some(anchor), Some((timestamp, packet))) => {
+                        let wait = (*timestamp as f64 - state.time).max(0.0) / 1000.0 / state.rate;
+                        let deadline = anchor + Duration::from_secs_f64(wait);
+                        Some((deadline, state.position, packet.clone()))
+                    }
+                    _ => None,
+                }
+            };
+            let deadline = async {
+                match &next {
+                    Some((deadline, _, _)) => tokio::time::sleep_until(*deadline).await,
+                    None => std::future::pending::<()>().await,
+                }
+            };
+
+            tokio::select! {
+                _ = deadline => {
+                    if let Some((_, index, packet)) = &next {
+                        send_packet(socket, packet, peer).await?;
+                        let mut state = self.state.lock().unwrap();
+                        // A seek while sending already moved the position
+                        if state.position == *index {
+                            state.position += 1;
+                        }
+                    }
+                }
+                _ = self.changed.notified() => {}
+                r = socket.recv_from(&mut buf) => {
+                    // Errors are e.g. ICMP port unreachable, the client may come back
+                    if let Ok((n, from)) = r {
+                        answer(socket, &buf[..n], from, &mut peer).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Block until a client has pinged after discovery and return its address.
+async fn wait_for_client(socket: &UdpSocket) -> io::Result<SocketAddr> {
+    let mut buf = [0u8; 64];
+    loop {
+        let Ok((n, from)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let mut peer = from;
+        if answer(socket, &buf[..n], from, &mut peer).await? {
+            return Ok(from);
+        }
+    }
+}
+
+/// Respond to a handshake datagram from `from`, returns true if it was a ping.
+///
+/// Discovery makes `from` the new `peer`. Keepalives and anything else are ignored.
+async fn answer(
+    socket: &UdpSocket,
+    datagram: &[u8],
+    from: SocketAddr,
+    peer: &mut SocketAddr,
+) -> io::Result<bool> {
+    if datagram == DISCOVER {
+        socket.send_to(&DISCOVER, from).await?;
+        *peer = from;
+        Ok(false)
+    } else if datagram == PING {
+        socket.send_to(&PING, from).await?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+async fn send_packet(socket: &UdpSocket, packet: &Packet, peer: SocketAddr) -> io::Result<()> {
+    let mut raw = vec![PACKET_TAG];
+    raw.extend(postcard::to_stdvec(packet).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Packet encode: {e:?}"))
+    })?);
+    socket.send_to(&raw, peer).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ats_usb::packets::vm::{ImpactReport, PacketData};
+
+    /// Packets 0, 1 and 2, 100 ms apart.
+    fn recording() -> Vec<(u128, Packet)> {
+        (0..3)
+            .map(|id| {
+                let timestamp = 100 * u32::from(id);
+                let data = PacketData::ImpactReport(ImpactReport { timestamp });
+                (timestamp.into(), Packet { id, data })
+            })
+            .collect()
+    }
+
+    fn recording_time(server: &PlaybackServer) -> f64 {
+        server.state.lock().unwrap().current_time(Instant::now())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn play_pause_seek() {
+        let server = PlaybackServer::new();
+        server.load(recording());
+        assert!(!server.is_playing());
+        assert_eq!(server.position(), 0);
+
+        server.play();
+        assert!(server.is_playing());
+        tokio::time::advance(Duration::from_millis(50)).await;
+        server.pause();
+        assert!(!server.is_playing());
+        tokio::time::advance(Duration::from_millis(50)).await;
+        assert_eq!(recording_time(&server), 50.0);
+
+        server.set_rate(2.0).unwrap();
+        server.play();
+        tokio::time::advance(Duration::from_millis(25)).await;
+        assert_eq!(recording_time(&server), 100.0);
+
+        server.seek(2);
+        assert!(server.is_playing());
+        assert_eq!(server.position(), 2);
+        assert_eq!(recording_time(&server), 200.0);
+        server.seek(10);
+        assert_eq!(server.position(), 3);
+
+        server.load(recording());
+        assert!(!server.is_playing());
+        assert_eq!(server.position(), 0);
+        assert!(server.set_rate(0.0).is_err());
+        assert_eq!(server.rate(), 2.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_sends_from_seek_position_once_playing() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(socket.local_addr().unwrap()).await.unwrap();
+        let server = PlaybackServer::new();
+        server.load(recording());
+        let run = tokio::spawn({
+            let server = server.clone();
+            async move { server.run(&socket).await }
+        });
+
+        let mut buf = [0u8; 64];
+        for handshake in [DISCOVER, PING] {
+            client.send(&handshake).await.unwrap();
+            let n = client.recv(&mut buf).await.unwrap();
+            assert_eq!(buf[..n], handshake);
+        }
+        let mut recv_packet = async || {
+            let n = client.recv(&mut buf).await.unwrap();
+            assert_eq!(buf[0], PACKET_TAG);
+            postcard::from_bytes::<Packet>(&buf[1..n]).unwrap().id
+        };
+
+        // Nothing is sent while paused
+        let paused = tokio::time::timeout(Duration::from_secs(1), recv_packet()).await;
+        assert!(paused.is_err());
+
+        server.seek(1);
+        let start = Instant::now();
+        server.play();
+        assert_eq!(recv_packet().await, 1);
+        assert_eq!(recv_packet().await, 2);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        run.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Quick overview of a recording without keeping its packets.
+
+use std::collections::BTreeMap;
+use std::{fs, io, path::PathBuf};
+
+use ats_usb::device::GeneralSettings;
+use ats_usb::packets::vm::PacketData;
+
+#[derive(Clone, Debug)]
+pub struct RecordingSummary {
+    pub settings: GeneralSettings,
+    pub packet_count: usize,
+    /// Recorder timestamps (milliseconds) of the first and last packet, `None` if there are no
+    /// packets
+    pub first_timestamp: Option<u128>,
+    pub last_timestamp: Option<u128>,
+    /// Number of packets of each kind, see [`packet_kind`]
+    pub counts: BTreeMap<&'static str, usize>,
+}
+
+impl RecordingSummary {
+    /// Time between the first and last packet in milliseconds.
+    pub fn duration_ms(&self) -> u128 {
+        match (self.first_timestamp, self.last_timestamp) {
+            (Some(first), Some(last)) => last.saturating_sub(first),
+            _ => 0,
+        }
+    }
+}
+
+/// Summarize the recording at `path`.
+///
+/// Packets are decoded one at a time and dropped, so only the file itself is held in memory.
+pub fn summarize(path: &PathBuf) -> io::Result<RecordingSummary> {
+    let data = fs::read(path)?;
+    let (settings, packets) = crate::parse(&data)?;
+    let mut summary = RecordingSummary {
+        settings,
+        packet_count: 0,
+        first_timestamp: None,
+        last_timestamp: None,
+        counts: BTreeMap::new(),
+    };
+    for packet in packets {
+        let (timestamp, packet) = packet?;
+        summary.packet_count += 1;
+        summary.first_timestamp.get_or_insert(timestamp);
+        summary.last_timestamp = Some(timestamp);
+        *summary.counts.entry(packet_kind(&packet.data)).or_default() += 1;
+    }
+    Ok(summary)
+}
+
+/// Name of the packet's type, for the kinds the recorder writes. Anything else is `"Other"`.
+pub fn packet_kind(data: &PacketData) -> &'static str {
+    match data {
+        PacketData::AccelReport(_) => "AccelReport",
+        PacketData::ImpactReport(_) => "ImpactReport",
+        PacketData::CombinedMarkersReport(_) => "CombinedMarkersReport",
+        PacketData::PocMarkersReport(_) => "PocMarkersReport",
+        PacketData::ObjectReport(_) => "ObjectReport",
+        _ => "Other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{impact, write_recording};
+    use ats_usb::packets::vm::Packet;
+
+    #[test]
+    fn summarize_counts_kinds_and_duration() {
+        let ack = Packet {
+            id: 0,
+            data: PacketData::Ack(),
+        };
+        let packets = [impact(0, 100), (150, ack), impact(1, 400)];
+        let path = write_recording("summary", &packets);
+        let summary = summarize(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(summary.packet_count, 3);
+        assert_eq!(summary.first_timestamp, Some(100));
+        assert_eq!(summary.last_timestamp, Some(400));
+        assert_eq!(summary.duration_ms(), 300);
+        assert_eq!(
+            summary.counts,
+            BTreeMap::from([("ImpactReport", 2), ("Other", 1)])
+        );
+    }
+
+    #[test]
+    fn summarize_empty_recording() {
+        let path = write_recording("summary-empty", &[]);
+        let summary = summarize(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(summary.packet_count, 0);
+        assert_eq!(summary.first_timestamp, None);
+        assert_eq!(summary.duration_ms(), 0);
+        assert!(summary.counts.is_empty());
+    }
+}
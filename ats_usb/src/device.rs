@@ -11,12 +11,12 @@ use protodongers::{
     PocMarkersReport, VendorData,
 };
 
-pub use protodongers::ProductId;
+pub use protodongers::{Mode, ProductId};
 
 use std::{
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc, Mutex, Weak,
     },
     task::Poll,
@@ -27,7 +27,7 @@ use tokio::{
     sync::{mpsc, oneshot},
 };
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
-use tracing::{debug, error, info, instrument, trace, warn};
+use tracing::{debug, error, info, instrument, trace, warn, Instrument};
 
 use crate::packets::vm::{
     AccelConfig, AccelReport, BatteryReport, CombinedMarkersReport, ConfigKind, GeneralConfig,
@@ -58,6 +58,30 @@ fn default_camera_intrinsics() -> RosOpenCvIntrinsics<f32> {
 
 pub const COBS_DELIMITER: u8 = 0x00;
 
+/// Maximum number of register reads [`VmDevice::read_registers`] keeps in flight
+pub const READ_PIPELINE_DEPTH: usize = 16;
+
+/// How long the `*_acked` writes wait for the device's `Ack`, and [`VmDevice::read_registers`] for
+/// each register
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long [`VmDevice::flash_settings_confirmed`] waits for the flash to finish
+pub const FLASH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Formats a BLE device address as `AA:BB:CC:DD:EE:FF`, for log fields.
+pub struct DisplayAddr(pub [u8; 6]);
+
+impl std::fmt::Display for DisplayAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let a = self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a[0], a[1], a[2], a[3], a[4], a[5]
+        )
+    }
+}
+
 /// A struct that holds all configuration values together
 /// This is needed because the new protodonge API returns individual config items as enum variants
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -71,6 +95,110 @@ pub struct GeneralSettings {
     pub stereo_iso: Isometry3<f32>,
 }
 
+/// Every [`ConfigKind`], each one has a field in [`GeneralSettings`].
+pub const ALL_CONFIG_KINDS: [ConfigKind; 7] = [
+    ConfigKind::ImpactThreshold,
+    ConfigKind::SuppressMs,
+    ConfigKind::AccelConfig,
+    ConfigKind::GyroConfig,
+    ConfigKind::CameraModelNf,
+    ConfigKind::CameraModelWf,
+    ConfigKind::StereoIso,
+];
+
+impl GeneralSettings {
+    /// Store a single config value and return its kind. The match is exhaustive so a new
+    /// [`GeneralConfig`] variant won't compile until it has a field here, which is a reminder to
+    /// add its kind to [`ALL_CONFIG_KINDS`] as well.
+    pub fn set(&mut self, config: GeneralConfig) -> ConfigKind {
+        match config {
+            GeneralConfig::ImpactThreshold(val) => {
+                self.impact_threshold = val;
+                ConfigKind::ImpactThreshold
+            }
+            GeneralConfig::SuppressMs(val) => {
+                self.suppress_ms = val;
+                ConfigKind::SuppressMs
+            }
+            GeneralConfig::AccelConfig(val) => {
+                self.accel_config = val;
+                ConfigKind::AccelConfig
+            }
+            GeneralConfig::GyroConfig(val) => {
+                self.gyro_config = val;
+                ConfigKind::GyroConfig
+            }
+            GeneralConfig::CameraModelNf(val) => {
+                self.camera_model_nf = val;
+                ConfigKind::CameraModelNf
+            }
+            GeneralConfig::CameraModelWf(val) => {
+                self.camera_model_wf = val;
+                ConfigKind::CameraModelWf
+            }
+            GeneralConfig::StereoIso(val) => {
+                self.stereo_iso = val;
+                ConfigKind::StereoIso
+            }
+        }
+    }
+
+    /// Builder style [`set`](Self::set).
+    pub fn with(mut self, config: GeneralConfig) -> Self {
+        self.set(config);
+        self
+    }
+
+    /// Every field as its wire config, in the order of [`ALL_CONFIG_KINDS`]. Together with
+    /// [`set`](Self::set) this is the only place that maps fields to [`GeneralConfig`] variants.
+    pub fn configs(&self) -> [GeneralConfig; 7] {
+        [
+            GeneralConfig::ImpactThreshold(self.impact_threshold),
+            GeneralConfig::SuppressMs(self.suppress_ms),
+            GeneralConfig::AccelConfig(self.accel_config),
+            GeneralConfig::GyroConfig(self.gyro_config.clone()),
+            GeneralConfig::CameraModelNf(self.camera_model_nf.clone()),
+            GeneralConfig::CameraModelWf(self.camera_model_wf.clone()),
+            GeneralConfig::StereoIso(self.stereo_iso),
+        ]
+    }
+
+    /// Names of the fields that differ between `self` and `other`.
+    pub fn diff(&self, other: &Self) -> Vec<&'static str> {
+        // Not every config type implements PartialEq, compare the encoded values instead
+        fn differs<T: serde::Serialize>(a: &T, b: &T) -> bool {
+            postcard::to_allocvec(a).ok() != postcard::to_allocvec(b).ok()
+        }
+        let mut fields = vec![];
+        macro_rules! compare {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if differs(&self.$field, &other.$field) {
+                        fields.push(stringify!($field));
+                    }
+                )*
+            };
+        }
+        compare!(
+            impact_threshold,
+            suppress_ms,
+            accel_config,
+            gyro_config,
+            camera_model_nf,
+            camera_model_wf,
+            stereo_iso,
+        );
+        fields
+    }
+}
+
+/// Starts from the defaults and applies each config in turn.
+impl FromIterator<GeneralConfig> for GeneralSettings {
+    fn from_iter<I: IntoIterator<Item = GeneralConfig>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::default(), Self::with)
+    }
+}
+
 impl Default for GeneralSettings {
     fn default() -> Self {
         Self {
@@ -97,6 +225,8 @@ struct State {
     // id 255 is reserved for requests that don't care for a response
     response_channels: Mutex<[ResponseChannel; 255]>,
     streams_active: StreamsActive,
+    /// Last mode sent with `write_mode`, the protocol has no way to read it back
+    mode: Mutex<Option<Mode>>,
 }
 
 /// A helper struct to deal with cancellation
@@ -142,6 +272,13 @@ impl std::ops::IndexMut<PacketType> for StreamsActive {
     }
 }
 
+/// A marker report from either marker stream, see [`VmDevice::stream_markers`].
+pub enum MarkersReport {
+    Combined(CombinedMarkersReport),
+    /// From PAG7665QN based devices, which only have a nearfield sensor
+    Poc(PocMarkersReport),
+}
+
 pub struct PacketTransport {
     writer: mpsc::Sender<Packet>,
     incoming_rx: mpsc::Receiver<Packet>,
@@ -223,8 +360,8 @@ impl PacketTransport {
                     warn!("usb consume_end failed: {e:?}");
                     continue;
                 }
-                match postcard::from_bytes::<Packet>(&buf) {
-                    Ok(pkt) => {
+                match crate::framing::try_parse(&buf) {
+                    Ok((pkt, _)) => {
                         if incoming_tx.send(pkt).await.is_err() {
                             break;
                         }
@@ -243,13 +380,9 @@ impl PacketTransport {
     }
 
     /// Create a PacketTransport that routes VM packets through a MuxDevice
+    #[instrument(name = "mux_transport", skip(mux), fields(addr = %DisplayAddr(device_addr)))]
     pub fn mux(mux: MuxDevice, device_addr: [u8; 6]) -> Self {
-        use rand::Rng;
-        let transport_id: u32 = rand::thread_rng().gen();
-        debug!(
-            "PacketTransport::mux() [ID:{}] called for device {:02X}:{:02X}...",
-            transport_id, device_addr[0], device_addr[1]
-        );
+        debug!("creating mux transport");
         let (writer, mut writer_rx) = mpsc::channel::<Packet>(64);
         let (incoming_tx, incoming_rx) = mpsc::channel::<Packet>(128);
 
@@ -260,40 +393,116 @@ impl PacketTransport {
         {
             let mut channels = mux.device_packets_tx.lock().unwrap();
             channels.insert(device_addr, incoming_tx.clone());
-            debug!(
-                "mux: [ID:{}] registered packet channel for device {:02X}:{:02X}...",
-                transport_id, device_addr[0], device_addr[1]
-            );
+            debug!("registered packet channel");
         }
 
         // Clone what we need before moving mux
         let device_packets_tx = Arc::clone(&mux.device_packets_tx);
 
         // Writer task: wraps outgoing VM packets in MuxMsg::SendTo
-        let transport_id_writer = transport_id;
+        tokio::spawn(
+            async move {
+                debug!("mux writer task started");
+                while let Some(pkt) = writer_rx.recv().await {
+                    trace!("mux writer: sending packet");
+                    if let Err(e) = mux.send_to(device_addr, pkt).await {
+                        error!("mux send_to failed: {e}");
+                        break;
+                    }
+                }
+                debug!("mux writer task exits");
+            }
+            .in_current_span(),
+        );
+
+        // Cleanup task - unregister when cancelled
+        tokio::spawn(
+            async move {
+                cancel_reader.cancelled().await;
+                let mut channels = device_packets_tx.lock().unwrap();
+                channels.remove(&device_addr);
+                debug!("unregistered packet channel");
+            }
+            .in_current_span(),
+        );
+
+        PacketTransport {
+            writer,
+            incoming_rx,
+            cancel: Arc::new(cancel_token),
+        }
+    }
+
+    /// Wrap channels serviced by transport tasks spawned elsewhere, e.g. [`crate::udp`].
+    pub(crate) fn from_channels(
+        writer: mpsc::Sender<Packet>,
+        incoming_rx: mpsc::Receiver<Packet>,
+        cancel: tokio_util::sync::CancellationToken,
+    ) -> Self {
+        PacketTransport {
+            writer,
+            incoming_rx,
+            cancel: Arc::new(cancel),
+        }
+    }
+
+    /// Create a PacketTransport over a byte stream such as the simulator's TCP socket. Packets are
+    /// COBS framed, see [`crate::framing`].
+    pub fn tcp(stream: tokio::net::TcpStream) -> Self {
+        let (mut read_half, mut write_half) = stream.into_split();
+        let (writer, mut writer_rx) = mpsc::channel::<Packet>(64);
+        let (incoming_tx, incoming_rx) = mpsc::channel::<Packet>(128);
+
+        let cancel_token = tokio_util::sync::CancellationToken::new();
+        let cancel_reader = cancel_token.clone();
+
         tokio::spawn(async move {
-            debug!("mux writer task started [ID:{}]", transport_id_writer);
             while let Some(pkt) = writer_rx.recv().await {
-                trace!("mux writer: sending packet to device {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-                    device_addr[0], device_addr[1], device_addr[2], device_addr[3], device_addr[4], device_addr[5]);
-                if let Err(e) = mux.send_to(device_addr, pkt).await {
-                    error!("mux send_to failed: {e}");
+                let frame = match crate::framing::encode_packet(&pkt) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        error!("postcard serialize failed: {e}");
+                        continue;
+                    }
+                };
+                if let Err(e) = write_half.write_all(&frame).await {
+                    error!("tcp write_all failed: {e}");
                     break;
                 }
             }
-            debug!("mux writer task exits [ID:{}]", transport_id_writer);
+            info!("tcp writer exits");
         });
 
-        // Cleanup task - unregister when cancelled
-        let transport_id_cleanup = transport_id;
         tokio::spawn(async move {
-            cancel_reader.cancelled().await;
-            let mut channels = device_packets_tx.lock().unwrap();
-            channels.remove(&device_addr);
-            debug!(
-                "mux: [ID:{}] unregistered packet channel for device {:02X}:{:02X}...",
-                transport_id_cleanup, device_addr[0], device_addr[1]
-            );
+            let mut decoder = crate::framing::FrameDecoder::new();
+            let mut buf = [0u8; 1024];
+            'outer: loop {
+                let n = tokio::select! {
+                    _ = cancel_reader.cancelled() => break,
+                    r = read_half.read(&mut buf) => match r {
+                        Ok(0) => {
+                            info!("tcp connection closed");
+                            break;
+                        }
+                        Ok(n) => n,
+                        Err(e) => {
+                            error!("tcp read error: {e}");
+                            break;
+                        }
+                    },
+                };
+                for pkt in decoder.push_packets(&buf[..n]) {
+                    match pkt {
+                        Ok(pkt) => {
+                            if incoming_tx.send(pkt).await.is_err() {
+                                break 'outer;
+                            }
+                        }
+                        Err(e) => error!("postcard decode failed: {e:?}"),
+                    }
+                }
+            }
+            info!("tcp reader exits");
         });
 
         PacketTransport {
@@ -334,19 +543,29 @@ pub struct VmDevice {
     cancel: Arc<tokio_util::sync::CancellationToken>, // Signals dispatcher to exit when VmDevice drops
     ctrl_if: Option<Interface>,
     transport_cancel: Arc<tokio_util::sync::CancellationToken>, // Signals mux reader to exit when VmDevice drops
+    dispatcher_id: u32,
 }
 
 impl Drop for VmDevice {
     fn drop(&mut self) {
-        eprintln!("!!! VmDevice DROP called !!!");
+        trace!("VmDevice dropped");
         // Only cancel if this is the last clone
         if Arc::strong_count(&self.cancel) == 1 {
-            eprintln!("!!! VmDevice: Last clone dropping, canceling dispatcher !!!");
+            debug!("VmDevice: last clone dropped, cancelling dispatcher");
+            // Best effort, nobody is left to wait for the response. The USB and mux writer tasks
+            // drain their queue before exiting, so this still goes out after the cancellation.
+            let _ = self.transport.writer.try_send(Packet {
+                id: 255,
+                data: PacketData::StreamUpdate(StreamUpdate {
+                    packet_id: PacketType::End(),
+                    action: crate::packets::vm::StreamUpdateAction::DisableAll,
+                }),
+            });
             self.cancel.cancel(); // Cancel dispatcher
             self.transport_cancel.cancel(); // Cancel mux reader
         } else {
-            eprintln!(
-                "!!! VmDevice: Clone dropped, {} remaining !!!",
+            trace!(
+                "VmDevice: clone dropped, {} remaining",
                 Arc::strong_count(&self.cancel) - 1
             );
         }
@@ -369,6 +588,17 @@ where
 }
 
 impl VmDevice {
+    /// Whether the connection is still up. Turns false once the transport closes, e.g. when the
+    /// device is unplugged, or after [`disconnect`](Self::disconnect).
+    pub fn is_connected(&self) -> bool {
+        self.thread_state.strong_count() > 0
+    }
+
+    /// Sequential id of this connection's dispatcher, as it appears in the logs. Clones share it.
+    pub fn dispatcher_id(&self) -> u32 {
+        self.dispatcher_id
+    }
+
     pub fn from_transport(transport: PacketTransport, ctrl_if: Option<Interface>) -> Self {
         let (tx_only, mut incoming, transport_cancel) = transport.split();
 
@@ -376,12 +606,14 @@ impl VmDevice {
         let state = Arc::new(State {
             response_channels: Mutex::new(response_channels),
             streams_active: StreamsActive::default(),
+            mode: Mutex::new(None),
         });
         let thread_state = Arc::downgrade(&state);
         let state_cloned = Arc::clone(&state);
 
-        use rand::Rng;
-        let dispatcher_id: u32 = rand::thread_rng().gen();
+        // Only for correlating log lines, sequential so logs are comparable between runs
+        static NEXT_DISPATCHER_ID: AtomicU32 = AtomicU32::new(0);
+        let dispatcher_id = NEXT_DISPATCHER_ID.fetch_add(1, Ordering::Relaxed);
         debug!(
             "Created new VmDevice State [Dispatcher ID:{}]",
             dispatcher_id
@@ -395,7 +627,12 @@ impl VmDevice {
             use tokio_stream::StreamExt;
             loop {
                 tokio::select! {
-                    Some(reply) = incoming.next() => {
+                    reply = incoming.next() => {
+                        // The transport is gone, e.g. the device was unplugged
+                        let Some(reply) = reply else {
+                            debug!("Dispatcher: [ID:{}] transport closed", dispatcher_id_task);
+                            break;
+                        };
                         debug!("Dispatcher: [ID:{}] received packet id={}", dispatcher_id_task, reply.id);
                         debug!("Dispatcher: [ID:{}] packet type = {:?}", dispatcher_id_task, std::mem::discriminant(&reply.data));
                         let mut chans = state_cloned.response_channels.lock().unwrap();
@@ -445,6 +682,7 @@ impl VmDevice {
             cancel: Arc::new(cancel_token),
             ctrl_if,
             transport_cancel,
+            dispatcher_id,
         }
     }
 
@@ -496,9 +734,33 @@ impl VmDevice {
         }
         Ok(device)
     }
+
+    /// Connect to a device (e.g. the simulator) over TCP.
+    pub async fn connect_tcp(addr: impl tokio::net::ToSocketAddrs) -> Result<Self> {
+        let stream = tokio::net::TcpStream::connect(addr)
+            .await
+            .context("tcp connect failed")?;
+        stream.set_nodelay(true)?;
+        let transport = PacketTransport::tcp(stream);
+        let device = Self::from_transport(transport, None);
+        let _ = device.clear_all_streams().await;
+        Ok(device)
+    }
+
+    /// Discover a device over UDP by broadcasting to `broadcast_addr`. The connection is kept
+    /// alive and re-established automatically, see [`crate::udp`].
+    pub async fn connect_udp(broadcast_addr: std::net::SocketAddr) -> Result<Self> {
+        let transport = crate::udp::UdpTransport::new(broadcast_addr)
+            .connect()
+            .await?;
+        let device = Self::from_transport(transport, None);
+        let _ = device.clear_all_streams().await;
+        Ok(device)
+    }
+
+    #[instrument(skip(mux), fields(addr = %DisplayAddr(device_addr)))]
     pub async fn connect_via_mux(mux: MuxDevice, device_addr: [u8; 6]) -> Result<Self> {
-        debug!("VmDevice::connect_via_mux() creating new device for {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-            device_addr[0], device_addr[1], device_addr[2], device_addr[3], device_addr[4], device_addr[5]);
+        debug!("creating new device");
         let transport = PacketTransport::mux(mux, device_addr);
 
         let device = Self::from_transport(transport, None);
@@ -519,6 +781,7 @@ impl VmDevice {
             Duration::from_millis(2000),
             5,
         )
+        .instrument(tracing::debug_span!("disable_all"))
         .await
         {
             return Err(anyhow!("Failed to disable all streams"));
@@ -540,6 +803,17 @@ impl VmDevice {
         Ok(r)
     }
 
+    /// Send `data` with a real request id and wait up to [`ACK_TIMEOUT`] for an `Ack`.
+    async fn request_ack(&self, data: PacketData) -> Result<()> {
+        let r = tokio::time::timeout(ACK_TIMEOUT, self.request(data))
+            .await
+            .map_err(|_| anyhow!("timed out waiting for Ack"))??;
+        match r {
+            PacketData::Ack() => Ok(()),
+            _ => Err(anyhow!("unexpected response, expected Ack")),
+        }
+    }
+
     fn get_oneshot_slot(&self) -> anyhow::Result<(ResponseSlot, oneshot::Receiver<PacketData>)> {
         if let Some(thread_state) = self.thread_state.upgrade() {
             let mut response_channels = thread_state.response_channels.lock().unwrap();
@@ -625,6 +899,49 @@ impl VmDevice {
         Ok(r.data)
     }
 
+    /// Read several `(port, bank, address)` registers. Up to [`READ_PIPELINE_DEPTH`] requests are
+    /// sent before waiting for their responses, which saves most of the round trips on slow links.
+    /// The result is in the same order as `regs`. Fails if any response takes longer than
+    /// [`ACK_TIMEOUT`].
+    pub async fn read_registers(&self, regs: &[(Port, u8, u8)]) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(regs.len());
+        for chunk in regs.chunks(READ_PIPELINE_DEPTH) {
+            let mut pending = Vec::with_capacity(chunk.len());
+            for &(port, bank, address) in chunk {
+                let (slot, recv) = self.get_oneshot_slot()?;
+                let data = PacketData::ReadRegister(Register {
+                    port,
+                    bank,
+                    address,
+                });
+                self.send(Packet { id: slot.id, data }).await?;
+                pending.push((slot, recv, bank, address));
+            }
+            for (mut slot, recv, bank, address) in pending {
+                let r = tokio::time::timeout(ACK_TIMEOUT, recv)
+                    .await
+                    .map_err(|_| {
+                        anyhow!(
+                            "timed out reading register {bank:#x}:{address:#x} (request id {})",
+                            slot.id
+                        )
+                    })??
+                    .read_register_response()
+                    .with_context(|| "unexpected response")?;
+                slot.finished = true;
+                if r.bank != bank || r.address != address {
+                    return Err(anyhow!(
+                        "register response mismatch: expected {bank:#x}:{address:#x}, got {:#x}:{:#x}",
+                        r.bank,
+                        r.address
+                    ));
+                }
+                out.push(r.data);
+            }
+        }
+        Ok(out)
+    }
+
     pub async fn write_register(&self, port: Port, bank: u8, address: u8, data: u8) -> Result<()> {
         info!("write_register: port={port:?} bank={bank} addr={address} data={data}");
         let data = PacketData::WriteRegister(WriteRegister {
@@ -638,6 +955,25 @@ impl VmDevice {
         Ok(())
     }
 
+    /// Like [`write_register`](Self::write_register), but waits for the device to acknowledge the
+    /// write.
+    pub async fn write_register_acked(
+        &self,
+        port: Port,
+        bank: u8,
+        address: u8,
+        data: u8,
+    ) -> Result<()> {
+        info!("write_register_acked: port={port:?} bank={bank} addr={address} data={data}");
+        self.request_ack(PacketData::WriteRegister(WriteRegister {
+            port,
+            bank,
+            address,
+            data,
+        }))
+        .await
+    }
+
     pub async fn write_vendor(&self, tag: u8, data: &[u8]) -> Result<()> {
         assert!(tag > PacketType::VendorStart().into() && tag < PacketType::VendorEnd().into());
         let data_len = data.len();
@@ -658,6 +994,7 @@ impl VmDevice {
         Ok(())
     }
 
+    #[instrument(skip(self))]
     pub async fn read_config(&self, kind: crate::packets::vm::ConfigKind) -> Result<GeneralConfig> {
         let r = self
             .request(PacketData::ReadConfig(kind))
@@ -676,6 +1013,14 @@ impl VmDevice {
         Ok(())
     }
 
+    /// Like [`write_config`](Self::write_config), but waits for the device to acknowledge the
+    /// write.
+    pub async fn write_config_acked(&self, config: GeneralConfig) -> Result<()> {
+        info!("write_config_acked: {config:?}");
+        self.request_ack(PacketData::WriteConfig(config)).await
+    }
+
+    #[instrument(skip(self))]
     pub async fn read_prop(&self, kind: crate::packets::vm::PropKind) -> Result<Props> {
         let r = self
             .request(PacketData::ReadProp(kind))
@@ -687,45 +1032,18 @@ impl VmDevice {
     }
 
     /// Read all configuration values and return them as a single struct
+    #[instrument(skip(self))]
     pub async fn read_all_config(&self) -> Result<GeneralSettings> {
-        let impact_threshold = self.read_config(ConfigKind::ImpactThreshold).await?;
-        let suppress_ms = self.read_config(ConfigKind::SuppressMs).await?;
-        let accel_config = self.read_config(ConfigKind::AccelConfig).await?;
-        let gyro_config = self.read_config(ConfigKind::GyroConfig).await?;
-        let camera_model_nf = self.read_config(ConfigKind::CameraModelNf).await?;
-        let camera_model_wf = self.read_config(ConfigKind::CameraModelWf).await?;
-        let stereo_iso = self.read_config(ConfigKind::StereoIso).await?;
-
-        Ok(GeneralSettings {
-            impact_threshold: match impact_threshold {
-                GeneralConfig::ImpactThreshold(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for ImpactThreshold"),
-            },
-            suppress_ms: match suppress_ms {
-                GeneralConfig::SuppressMs(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for SuppressMs"),
-            },
-            accel_config: match accel_config {
-                GeneralConfig::AccelConfig(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for AccelConfig"),
-            },
-            gyro_config: match gyro_config {
-                GeneralConfig::GyroConfig(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for GyroConfig"),
-            },
-            camera_model_nf: match camera_model_nf {
-                GeneralConfig::CameraModelNf(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for CameraModelNf"),
-            },
-            camera_model_wf: match camera_model_wf {
-                GeneralConfig::CameraModelWf(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for CameraModelWf"),
-            },
-            stereo_iso: match stereo_iso {
-                GeneralConfig::StereoIso(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for StereoIso"),
-            },
-        })
+        let mut settings = GeneralSettings::default();
+        for kind in ALL_CONFIG_KINDS {
+            let expected = std::mem::discriminant(&kind);
+            let name = format!("{kind:?}");
+            let found = settings.set(self.read_config(kind).await?);
+            if std::mem::discriminant(&found) != expected {
+                anyhow::bail!("Unexpected config variant {found:?} for {name}");
+            }
+        }
+        Ok(settings)
     }
 
     pub async fn get_frame(&self) -> Result<([MotData; 16], [MotData; 16])> {
@@ -796,6 +1114,16 @@ impl VmDevice {
             .filter_map(|x| x.poc_markers_report()))
     }
 
+    /// Markers from both the combined and the POC marker streams, for code that doesn't know
+    /// which kind of device it is talking to.
+    pub async fn stream_markers(&self) -> Result<impl Stream<Item = MarkersReport> + Send + Sync> {
+        let combined = self.stream_combined_markers().await?;
+        let poc = self.stream_poc_markers().await?;
+        Ok(combined
+            .map(MarkersReport::Combined)
+            .merge(poc.map(MarkersReport::Poc)))
+    }
+
     pub async fn stream_accel(&self) -> Result<impl Stream<Item = AccelReport> + Send + Sync> {
         Ok(self
             .stream(PacketType::AccelReport())
@@ -828,34 +1156,61 @@ impl VmDevice {
         Ok(())
     }
 
-    pub async fn write_mode(&self, mode: protodongers::Mode) -> Result<()> {
+    /// Like [`flash_settings`](Self::flash_settings), but waits up to [`FLASH_TIMEOUT`] for the
+    /// device's `FlashSettingsResponse`, i.e. until the settings are actually in flash.
+    pub async fn flash_settings_confirmed(&self) -> Result<()> {
+        let r = tokio::time::timeout(FLASH_TIMEOUT, self.request(PacketData::FlashSettings()))
+            .await
+            .map_err(|_| anyhow!("timed out waiting for the device to finish flashing"))??;
+        match r {
+            PacketData::FlashSettingsResponse(..) => Ok(()),
+            _ => Err(anyhow!(
+                "unexpected response, expected FlashSettingsResponse"
+            )),
+        }
+    }
+
+    /// Switch the device's mode. The protocol has no way to query the mode, so the mode written
+    /// here is remembered for [`read_mode`](Self::read_mode) instead.
+    pub async fn write_mode(&self, mode: Mode) -> Result<()> {
         let data = PacketData::WriteMode(mode);
         let pkt = Packet { id: 255, data };
         self.transport.writer.send(pkt).await?;
+        if let Some(thread_state) = self.thread_state.upgrade() {
+            *thread_state.mode.lock().unwrap() = Some(mode);
+        }
         Ok(())
     }
 
+    /// Like [`write_mode`](Self::write_mode), but waits for the device to acknowledge the write.
+    pub async fn write_mode_acked(&self, mode: Mode) -> Result<()> {
+        self.request_ack(PacketData::WriteMode(mode)).await?;
+        if let Some(thread_state) = self.thread_state.upgrade() {
+            *thread_state.mode.lock().unwrap() = Some(mode);
+        }
+        Ok(())
+    }
+
+    /// The mode most recently written through this connection (shared between clones), or `None`
+    /// if it hasn't been set. The device doesn't report its mode, so this is the only read-back:
+    /// a new connection starts at `None` even if an earlier one changed the mode.
+    pub fn read_mode(&self) -> Option<Mode> {
+        *self.thread_state.upgrade()?.mode.lock().unwrap()
+    }
+
+    /// Mark every stream inactive and tell the device to stop streaming. Safe to call repeatedly.
+    #[instrument(skip(self))]
     pub async fn clear_all_streams(&self) -> Result<()> {
         // Mark all streams as inactive to stop new packets
         if let Some(thread_state) = self.thread_state.upgrade() {
-            for stream_type in 0u8..=4u8 {
-                let packet_type = match stream_type {
-                    0 => PacketType::ObjectReport(),
-                    1 => PacketType::CombinedMarkersReport(),
-                    2 => PacketType::PocMarkersReport(),
-                    3 => PacketType::AccelReport(),
-                    4 => PacketType::ImpactReport(),
-                    _ => continue,
-                };
-
-                if thread_state.streams_active[packet_type].load(Ordering::Relaxed) {
-                    info!("Clearing active stream: {:?}", packet_type);
-                    thread_state.streams_active[packet_type].store(false, Ordering::Relaxed);
+            for (packet_type, active) in thread_state.streams_active.0.iter().enumerate() {
+                if active.swap(false, Ordering::Relaxed) {
+                    info!("Clearing active stream: {packet_type}");
                 }
             }
         }
 
-        if let None = retry(
+        match retry(
             async || {
                 self.request(PacketData::StreamUpdate(StreamUpdate {
                     packet_id: PacketType::End(),
@@ -868,9 +1223,21 @@ impl VmDevice {
         )
         .await
         {
-            return Err(anyhow!("Failed to disable all streams"));
+            Some(Ok(_)) => Ok(()),
+            Some(Err(e)) => Err(e.context("Failed to disable all streams")),
+            None => Err(anyhow!("Failed to disable all streams")),
         }
-        Ok(())
+    }
+
+    /// Quiesce the device and shut down this connection, including every clone of this
+    /// `VmDevice`. Disabling the streams is best-effort, the device may already be gone.
+    #[instrument(skip(self))]
+    pub async fn disconnect(self) {
+        if let Err(e) = self.clear_all_streams().await {
+            warn!("failed to disable streams before disconnecting: {e:#}");
+        }
+        self.cancel.cancel();
+        self.transport_cancel.cancel();
     }
 }
 
@@ -938,8 +1305,9 @@ macro_rules! read_register_spec {
     ($name:ident : $ty:ty = $bank:literal; [$($addr:literal),*]) => {
         pub async fn $name(&self, port: Port) -> ::anyhow::Result<$ty> {
             let mut bytes = <$ty>::to_le_bytes(0);
-            for (byte, addr) in ::std::iter::zip(&mut bytes, [$($addr),*]) {
-                *byte = self.read_register(port, $bank, addr).await?;
+            let data = self.read_registers(&[$((port, $bank, $addr)),*]).await?;
+            for (byte, data) in ::std::iter::zip(&mut bytes, data) {
+                *byte = data;
             }
             Ok(<$ty>::from_le_bytes(bytes))
         }
@@ -1036,21 +1404,51 @@ use crate::packets::mux::{MuxMsg, SendTo, MAX_DEVICES};
 use heapless::Vec as HVec;
 use std::collections::HashMap;
 
+/// How long [`MuxDevice::request_device_list`] waits for further snapshot messages.
+pub const SNAPSHOT_SETTLE: Duration = Duration::from_millis(100);
+
+/// Bonded devices reported by the dongle, see [`MuxDevice::request_device_list`].
+#[derive(Clone, Debug)]
+pub struct DeviceList {
+    pub devices: HVec<[u8; 6], MAX_DEVICES>,
+    /// False if snapshots were still arriving when the request timed out
+    pub complete: bool,
+}
+
+/// How often the dongle is pinged when it has been quiet.
+pub const MUX_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the dongle may stay silent before [`MuxDevice::is_alive`] turns false.
+pub const MUX_LIVENESS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Shared between the mux router, the heartbeat task and [`MuxDevice`].
+#[derive(Debug)]
+pub struct MuxLiveness {
+    last_seen: Mutex<tokio::time::Instant>,
+    /// When the heartbeat ReadVersion still waiting for its response was sent. The router swallows
+    /// the next response so it doesn't reach a `receive_msg` caller that never asked for it, unless
+    /// it comes more than [`MUX_LIVENESS_TIMEOUT`] late, in which case it is passed on.
+    heartbeat_sent: Mutex<Option<tokio::time::Instant>>,
+}
+
+impl MuxLiveness {
+    /// Whether a heartbeat is waiting for its response and still within its deadline.
+    fn heartbeat_pending(sent: Option<tokio::time::Instant>) -> bool {
+        sent.is_some_and(|sent| sent.elapsed() < MUX_LIVENESS_TIMEOUT)
+    }
+}
+
 pub struct MuxTransport {
     writer: mpsc::Sender<MuxMsg>,
     snapshots_rx: mpsc::Receiver<HVec<[u8; 6], MAX_DEVICES>>,
     msg_rx: mpsc::Receiver<MuxMsg>,
     device_packets_tx: Arc<Mutex<HashMap<[u8; 6], mpsc::Sender<Packet>>>>,
+    liveness: Arc<MuxLiveness>,
 }
 
 impl MuxTransport {
     pub fn usb(mut in_ep: EndpointRead<Bulk>, mut out_ep: EndpointWrite<Bulk>) -> Self {
         let (writer, mut writer_rx) = mpsc::channel::<MuxMsg>(64);
-        let (snapshots_tx, snapshots_rx) = mpsc::channel::<HVec<[u8; 6], MAX_DEVICES>>(128);
-        let (msg_tx, msg_rx) = mpsc::channel::<MuxMsg>(128);
-        let device_packets_tx =
-            Arc::new(Mutex::new(HashMap::<[u8; 6], mpsc::Sender<Packet>>::new()));
-        let device_packets_tx_clone = Arc::clone(&device_packets_tx);
+        let (incoming_tx, incoming_rx) = mpsc::channel::<MuxMsg>(128);
 
         // Writer task
         tokio::spawn(async move {
@@ -1073,7 +1471,7 @@ impl MuxTransport {
             info!("mux usb writer exits");
         });
 
-        // Reader task - routes messages by type
+        // Reader task - decodes messages for the router
         tokio::spawn(async move {
             let mut reader = in_ep.until_short_packet();
             let mut io_errs = 0u8;
@@ -1111,13 +1509,89 @@ impl MuxTransport {
                     continue;
                 }
                 match postcard::from_bytes::<MuxMsg>(&buf) {
-                    Ok(MuxMsg::DevicesSnapshot(devices)) => {
-                        debug!("mux reader: routing DevicesSnapshot");
+                    Ok(msg) => {
+                        if incoming_tx.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => error!("mux postcard decode failed: {e:?}"),
+                }
+            }
+            info!("mux usb reader exits");
+        });
+
+        Self::from_channels(writer, incoming_rx)
+    }
+
+    /// Route the messages from `incoming` and keep the dongle pinged, for a transport whose own
+    /// tasks write `writer` to the dongle and feed `incoming` with decoded messages.
+    pub(crate) fn from_channels(
+        writer: mpsc::Sender<MuxMsg>,
+        mut incoming: mpsc::Receiver<MuxMsg>,
+    ) -> Self {
+        let (snapshots_tx, snapshots_rx) = mpsc::channel::<HVec<[u8; 6], MAX_DEVICES>>(128);
+        let (msg_tx, msg_rx) = mpsc::channel::<MuxMsg>(128);
+        let device_packets_tx =
+            Arc::new(Mutex::new(HashMap::<[u8; 6], mpsc::Sender<Packet>>::new()));
+        let device_packets_tx_clone = Arc::clone(&device_packets_tx);
+        let liveness = Arc::new(MuxLiveness {
+            last_seen: Mutex::new(tokio::time::Instant::now()),
+            heartbeat_sent: Mutex::new(None),
+        });
+        let liveness_router = Arc::clone(&liveness);
+        let liveness_heartbeat = Arc::clone(&liveness);
+
+        // Heartbeat task - pings a quiet dongle so `last_seen` keeps moving while it's alive. Exits
+        // once every MuxDevice is gone.
+        let heartbeat_writer = writer.downgrade();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MUX_HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(writer) = heartbeat_writer.upgrade() else {
+                    break;
+                };
+                let quiet = liveness_heartbeat.last_seen.lock().unwrap().elapsed();
+                if quiet < MUX_HEARTBEAT_INTERVAL {
+                    continue;
+                }
+                {
+                    let mut sent = liveness_heartbeat.heartbeat_sent.lock().unwrap();
+                    // Ping again once the last response is overdue
+                    if MuxLiveness::heartbeat_pending(*sent) {
+                        continue;
+                    }
+                    *sent = Some(tokio::time::Instant::now());
+                }
+                trace!("mux heartbeat");
+                if writer.send(MuxMsg::ReadVersion()).await.is_err() {
+                    break;
+                }
+            }
+            debug!("mux heartbeat exits");
+        });
+
+        // Router task - routes messages by type
+        tokio::spawn(async move {
+            while let Some(msg) = incoming.recv().await {
+                *liveness_router.last_seen.lock().unwrap() = tokio::time::Instant::now();
+                match msg {
+                    // Identical to the response a concurrent read_version expects, so swallowing
+                    // either one is fine as long as one is swallowed per heartbeat
+                    MuxMsg::ReadVersionResponse(_)
+                        if MuxLiveness::heartbeat_pending(
+                            liveness_router.heartbeat_sent.lock().unwrap().take(),
+                        ) =>
+                    {
+                        trace!("mux router: heartbeat response");
+                    }
+                    MuxMsg::DevicesSnapshot(devices) => {
+                        debug!("mux router: routing DevicesSnapshot");
                         let _ = snapshots_tx.send(devices).await;
                     }
-                    Ok(MuxMsg::DevicePacket(dev_pkt)) => {
+                    MuxMsg::DevicePacket(dev_pkt) => {
                         trace!(
-                            "mux reader: routing DevicePacket for {:02X}:{:02X}...",
+                            "mux router: routing DevicePacket for {:02X}:{:02X}...",
                             dev_pkt.dev[0],
                             dev_pkt.dev[1]
                         );
@@ -1130,20 +1604,19 @@ impl MuxTransport {
                             let _ = tx.send(dev_pkt.pkt).await;
                         } else {
                             trace!(
-                                "mux reader: no receiver registered for device {:02X}:{:02X}...",
+                                "mux router: no receiver registered for device {:02X}:{:02X}...",
                                 dev_pkt.dev[0],
                                 dev_pkt.dev[1]
                             );
                         }
                     }
-                    Ok(other) => {
-                        debug!("mux reader: routing control message");
+                    other => {
+                        debug!("mux router: routing control message");
                         let _ = msg_tx.send(other).await;
                     }
-                    Err(e) => error!("mux postcard decode failed: {e:?}"),
                 }
             }
-            info!("mux usb reader exits");
+            info!("mux router exits");
         });
 
         MuxTransport {
@@ -1151,6 +1624,7 @@ impl MuxTransport {
             snapshots_rx,
             msg_rx,
             device_packets_tx,
+            liveness,
         }
     }
 
@@ -1161,18 +1635,21 @@ impl MuxTransport {
         ReceiverStream<HVec<[u8; 6], MAX_DEVICES>>,
         ReceiverStream<MuxMsg>,
         Arc<Mutex<HashMap<[u8; 6], mpsc::Sender<Packet>>>>,
+        Arc<MuxLiveness>,
     ) {
         let MuxTransport {
             writer,
             snapshots_rx,
             msg_rx,
             device_packets_tx,
+            liveness,
         } = self;
         (
             writer,
             ReceiverStream::new(snapshots_rx),
             ReceiverStream::new(msg_rx),
             device_packets_tx,
+            liveness,
         )
     }
 }
@@ -1183,13 +1660,23 @@ pub struct MuxDevice {
     snapshots_rx: Arc<tokio::sync::Mutex<ReceiverStream<HVec<[u8; 6], MAX_DEVICES>>>>,
     msg_rx: Arc<tokio::sync::Mutex<ReceiverStream<MuxMsg>>>,
     device_packets_tx: Arc<Mutex<HashMap<[u8; 6], mpsc::Sender<Packet>>>>,
-    ctrl_if: Interface,
+    liveness: Arc<MuxLiveness>,
+    /// Read on connect, see [`MuxDevice::version`]
+    version: Arc<std::sync::OnceLock<protodongers::Version>>,
+    ctrl_if: Option<Interface>,
 }
 
 impl MuxDevice {
+    fn ctrl_if(&self) -> Result<&Interface> {
+        self.ctrl_if
+            .as_ref()
+            .ok_or_else(|| anyhow!("No control interface available"))
+    }
+
     #[allow(dead_code)]
     async fn ctrl_recv_polling(&self, timeout: std::time::Duration) -> Result<UsbMuxCtrlMsg> {
-        let idx = self.ctrl_if.interface_number() as u16;
+        let ctrl_if = self.ctrl_if()?;
+        let idx = ctrl_if.interface_number() as u16;
         let start = std::time::Instant::now();
         loop {
             let elapsed = start.elapsed();
@@ -1197,8 +1684,7 @@ impl MuxDevice {
                 return Err(anyhow!("timeout waiting for ctrl event"));
             }
             let slice = (timeout - elapsed).min(std::time::Duration::from_millis(500));
-            match self
-                .ctrl_if
+            match ctrl_if
                 .control_in(
                     ControlIn {
                         control_type: ControlType::Vendor,
@@ -1234,7 +1720,8 @@ impl MuxDevice {
     where
         F: FnMut(&UsbMuxCtrlMsg) -> bool,
     {
-        let idx = self.ctrl_if.interface_number() as u16;
+        let ctrl_if = self.ctrl_if()?;
+        let idx = ctrl_if.interface_number() as u16;
         let start = std::time::Instant::now();
         loop {
             let elapsed = start.elapsed();
@@ -1242,8 +1729,7 @@ impl MuxDevice {
                 return Err(anyhow!("timeout waiting for ctrl event"));
             }
             let slice = (timeout - elapsed).min(std::time::Duration::from_millis(500));
-            let reply = match self
-                .ctrl_if
+            let reply = match ctrl_if
                 .control_in(
                     ControlIn {
                         control_type: ControlType::Vendor,
@@ -1286,15 +1772,16 @@ impl MuxDevice {
 
     async fn ctrl_send(&self, msg: UsbMuxCtrlMsg) -> Result<()> {
         use std::time::Duration;
+        let ctrl_if = self.ctrl_if()?;
         let data = postcard::to_allocvec(&msg).map_err(|e| anyhow!("ctrl encode failed: {e}"))?;
-        self.ctrl_if
+        ctrl_if
             .control_out(
                 ControlOut {
                     control_type: ControlType::Vendor,
                     recipient: Recipient::Interface,
                     request: Self::USB_MUX_CTRL_REQ_SEND,
                     value: 0,
-                    index: self.ctrl_if.interface_number() as u16,
+                    index: ctrl_if.interface_number() as u16,
                     data: &data,
                 },
                 Duration::from_millis(1000),
@@ -1304,18 +1791,36 @@ impl MuxDevice {
         Ok(())
     }
 
-    pub fn from_transport(transport: MuxTransport, ctrl_if: Interface) -> Self {
-        let (writer, snapshots, control, device_packets_tx) = transport.split();
+    pub fn from_transport(transport: MuxTransport, ctrl_if: Option<Interface>) -> Self {
+        let (writer, snapshots, control, device_packets_tx, liveness) = transport.split();
 
         MuxDevice {
             writer,
             snapshots_rx: Arc::new(tokio::sync::Mutex::new(snapshots)),
             msg_rx: Arc::new(tokio::sync::Mutex::new(control)),
             device_packets_tx,
+            liveness,
+            version: Default::default(),
             ctrl_if,
         }
     }
 
+    /// The dongle's version as read on connect, `None` if that failed.
+    pub fn version(&self) -> Option<protodongers::Version> {
+        self.version.get().cloned()
+    }
+
+    /// When the dongle last sent anything, heartbeats included.
+    pub fn last_seen(&self) -> tokio::time::Instant {
+        *self.liveness.last_seen.lock().unwrap()
+    }
+
+    /// Whether the dongle was heard from within [`MUX_LIVENESS_TIMEOUT`]. It is pinged every
+    /// [`MUX_HEARTBEAT_INTERVAL`] while quiet, so this turns false soon after it is unplugged.
+    pub fn is_alive(&self) -> bool {
+        self.last_seen().elapsed() < MUX_LIVENESS_TIMEOUT
+    }
+
     pub async fn connect_usb(info: DeviceInfo) -> Result<Self> {
         let dev = info.open().await?;
 
@@ -1343,7 +1848,11 @@ impl MuxDevice {
 
             let ctrl_if = iface.clone();
             let transport = MuxTransport::usb(in_ep, out_ep);
-            return Ok(Self::from_transport(transport, ctrl_if));
+            let mux = Self::from_transport(transport, Some(ctrl_if));
+            if let Err(e) = mux.read_ctrl_version().await {
+                warn!("failed to read mux version: {e}");
+            }
+            return Ok(mux);
         }
         Err(anyhow!(
             "failed to find mux interface with required endpoints"
@@ -1375,6 +1884,16 @@ impl MuxDevice {
     }
 
     pub async fn request_devices(&self) -> Result<HVec<[u8; 6], MAX_DEVICES>> {
+        let list = self.request_device_list().await?;
+        if !list.complete {
+            warn!("device list still changing at timeout, returning partial list");
+        }
+        Ok(list.devices)
+    }
+
+    /// Request the bonded devices. The dongle may split the list across several snapshot
+    /// messages, so snapshots are merged until none arrives for [`SNAPSHOT_SETTLE`].
+    pub async fn request_device_list(&self) -> Result<DeviceList> {
         let mut last_err = None;
         for attempt in 0..3 {
             if attempt > 0 {
@@ -1401,10 +1920,40 @@ impl MuxDevice {
             // Wait for response with a 1-second timeout
             let total_timeout = tokio::time::Duration::from_secs(1);
 
-            match tokio::time::timeout(total_timeout, self.receive_snapshot()).await {
-                Ok(Ok(devices)) => return Ok(devices),
-                Ok(Err(e)) => last_err = Some(e),
-                Err(_) => last_err = Some(anyhow!("timeout waiting for DevicesSnapshot")),
+            let deadline = tokio::time::Instant::now() + total_timeout;
+
+            let mut devices = match tokio::time::timeout_at(deadline, self.receive_snapshot()).await
+            {
+                Ok(Ok(devices)) => devices,
+                Ok(Err(e)) => {
+                    last_err = Some(e);
+                    continue;
+                }
+                Err(_) => {
+                    last_err = Some(anyhow!("timeout waiting for DevicesSnapshot"));
+                    continue;
+                }
+            };
+            let settle_deadline = || deadline.min(tokio::time::Instant::now() + SNAPSHOT_SETTLE);
+            loop {
+                match tokio::time::timeout_at(settle_deadline(), self.receive_snapshot()).await {
+                    Ok(Ok(more)) => {
+                        for dev in more {
+                            if !devices.contains(&dev) && devices.push(dev).is_err() {
+                                warn!(
+                                    "more than {MAX_DEVICES} devices reported, ignoring the rest"
+                                );
+                            }
+                        }
+                    }
+                    Ok(Err(e)) => return Err(e),
+                    Err(_) => {
+                        return Ok(DeviceList {
+                            devices,
+                            complete: tokio::time::Instant::now() < deadline,
+                        })
+                    }
+                }
             }
         }
         Err(last_err.unwrap_or_else(|| anyhow!("request_devices failed")))
@@ -1422,11 +1971,40 @@ impl MuxDevice {
         self.send_msg(MuxMsg::UnsubscribeDeviceList).await
     }
 
+    /// Waits while the outgoing queue is full, see [`try_send_to`](Self::try_send_to) for a
+    /// variant that drops the packet instead.
     pub async fn send_to(&self, dev: [u8; 6], pkt: Packet) -> Result<()> {
+        if self.writer.capacity() == 0 {
+            debug!("mux outgoing queue full, waiting");
+        }
         let msg = MuxMsg::SendTo(SendTo { dev, pkt });
         self.send_msg(msg).await
     }
 
+    /// Queue a packet without waiting. Returns `Ok(false)` if the outgoing queue is full and the
+    /// packet was dropped, so callers forwarding high rate streams can throttle.
+    pub fn try_send_to(&self, dev: [u8; 6], pkt: Packet) -> Result<bool> {
+        use mpsc::error::TrySendError;
+        match self.writer.try_send(MuxMsg::SendTo(SendTo { dev, pkt })) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Full(_)) => {
+                warn!("mux outgoing queue full, dropping packet");
+                Ok(false)
+            }
+            Err(TrySendError::Closed(_)) => Err(anyhow!("send failed: channel closed")),
+        }
+    }
+
+    /// Number of messages waiting to be written to the dongle, out of
+    /// [`queue_capacity`](Self::queue_capacity).
+    pub fn queue_depth(&self) -> usize {
+        self.writer.max_capacity() - self.writer.capacity()
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.writer.max_capacity()
+    }
+
     pub async fn read_version(&self) -> Result<crate::packets::mux::Version> {
         self.send_msg(MuxMsg::ReadVersion()).await?;
 
@@ -1444,7 +2022,10 @@ impl MuxDevice {
             })
             .await?
         {
-            UsbMuxCtrlMsg::ReadVersionResponse(version) => Ok(version),
+            UsbMuxCtrlMsg::ReadVersionResponse(version) => {
+                let _ = self.version.set(version.clone());
+                Ok(version)
+            }
             other => Err(anyhow!("unexpected ctrl response: {other:?}")),
         }
     }
@@ -2009,44 +2590,16 @@ impl VmDevice {
 
     /// Read all config values via control endpoint (for devices that don't support bulk config).
     pub async fn read_all_config_ctrl(&self) -> Result<GeneralSettings> {
-        let impact_threshold = self.read_config_ctrl(ConfigKind::ImpactThreshold).await?;
-        let suppress_ms = self.read_config_ctrl(ConfigKind::SuppressMs).await?;
-        let accel_config = self.read_config_ctrl(ConfigKind::AccelConfig).await?;
-        let gyro_config = self.read_config_ctrl(ConfigKind::GyroConfig).await?;
-        let camera_model_nf = self.read_config_ctrl(ConfigKind::CameraModelNf).await?;
-        let camera_model_wf = self.read_config_ctrl(ConfigKind::CameraModelWf).await?;
-        let stereo_iso = self.read_config_ctrl(ConfigKind::StereoIso).await?;
-
-        Ok(GeneralSettings {
-            impact_threshold: match impact_threshold {
-                GeneralConfig::ImpactThreshold(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for ImpactThreshold"),
-            },
-            suppress_ms: match suppress_ms {
-                GeneralConfig::SuppressMs(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for SuppressMs"),
-            },
-            accel_config: match accel_config {
-                GeneralConfig::AccelConfig(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for AccelConfig"),
-            },
-            gyro_config: match gyro_config {
-                GeneralConfig::GyroConfig(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for GyroConfig"),
-            },
-            camera_model_nf: match camera_model_nf {
-                GeneralConfig::CameraModelNf(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for CameraModelNf"),
-            },
-            camera_model_wf: match camera_model_wf {
-                GeneralConfig::CameraModelWf(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for CameraModelWf"),
-            },
-            stereo_iso: match stereo_iso {
-                GeneralConfig::StereoIso(val) => val,
-                _ => anyhow::bail!("Unexpected config variant for StereoIso"),
-            },
-        })
+        let mut settings = GeneralSettings::default();
+        for kind in ALL_CONFIG_KINDS {
+            let expected = std::mem::discriminant(&kind);
+            let name = format!("{kind:?}");
+            let found = settings.set(self.read_config_ctrl(kind).await?);
+            if std::mem::discriminant(&found) != expected {
+                anyhow::bail!("Unexpected config variant {found:?} for {name}");
+            }
+        }
+        Ok(settings)
     }
 
     pub async fn set_name(&self, name: &str) -> Result<()> {
@@ -2094,3 +2647,430 @@ impl VmDevice {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mux without USB. What it writes to the dongle comes out of the receiver, messages sent
+    /// on the sender arrive as if from the dongle.
+    fn mux() -> (MuxDevice, mpsc::Receiver<MuxMsg>, mpsc::Sender<MuxMsg>) {
+        let (writer, written) = mpsc::channel(64);
+        let (incoming_tx, incoming) = mpsc::channel(128);
+        let mux = MuxDevice::from_transport(MuxTransport::from_channels(writer, incoming), None);
+        (mux, written, incoming_tx)
+    }
+
+    /// A device without a connection. What it sends comes out of the receiver, packets sent on
+    /// the sender arrive as if from the device.
+    fn vm_device() -> (VmDevice, mpsc::Receiver<Packet>, mpsc::Sender<Packet>) {
+        let (writer, written) = mpsc::channel(64);
+        let (incoming_tx, incoming) = mpsc::channel(128);
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let device = VmDevice::from_transport(
+            PacketTransport::from_channels(writer, incoming, cancel),
+            None,
+        );
+        (device, written, incoming_tx)
+    }
+
+    /// Answers each packet the device sends with `reply(data)` under the same id, or not at all if
+    /// that is `None`.
+    fn simulate(
+        mut written: mpsc::Receiver<Packet>,
+        incoming: mpsc::Sender<Packet>,
+        mut reply: impl FnMut(PacketData) -> Option<PacketData> + Send + 'static,
+    ) {
+        tokio::spawn(async move {
+            while let Some(pkt) = written.recv().await {
+                if let Some(data) = reply(pkt.data) {
+                    let _ = incoming.send(Packet { id: pkt.id, data }).await;
+                }
+            }
+        });
+    }
+
+    /// Like [`simulate`], but for a device connecting over TCP to the returned loopback address.
+    async fn tcp_simulator(
+        mut reply: impl FnMut(PacketData) -> Option<PacketData> + Send + 'static,
+    ) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut decoder = crate::framing::FrameDecoder::new();
+            let mut buf = [0; 1024];
+            while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                for pkt in decoder.push_packets(&buf[..n]) {
+                    let pkt = pkt.unwrap();
+                    if let Some(data) = reply(pkt.data) {
+                        let frame = crate::framing::encode_packet(&Packet { id: pkt.id, data });
+                        stream.write_all(&frame.unwrap()).await.unwrap();
+                    }
+                }
+            }
+        });
+        addr
+    }
+
+    fn version() -> crate::packets::mux::Version {
+        crate::packets::mux::Version {
+            protocol_semver: [0, 1, 0],
+            firmware_semver: [1, 2, 3],
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_mux_crosses_liveness_timeout() {
+        let (mux, mut written, _incoming) = mux();
+        assert!(mux.is_alive());
+        tokio::time::sleep(MUX_LIVENESS_TIMEOUT - MUX_HEARTBEAT_INTERVAL).await;
+        assert!(mux.is_alive());
+        assert!(matches!(written.try_recv(), Ok(MuxMsg::ReadVersion())));
+        tokio::time::sleep(MUX_HEARTBEAT_INTERVAL).await;
+        assert!(!mux.is_alive());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_heartbeat_expires() {
+        let (mux, mut written, incoming) = mux();
+        assert!(matches!(written.recv().await, Some(MuxMsg::ReadVersion())));
+        // Busy enough that the heartbeat isn't repeated, but its response never comes
+        for _ in 0..8 {
+            tokio::time::sleep(MUX_HEARTBEAT_INTERVAL / 2).await;
+            incoming
+                .send(MuxMsg::DevicesSnapshot(HVec::new()))
+                .await
+                .unwrap();
+        }
+        assert!(written.try_recv().is_err());
+        assert!(mux.is_alive());
+        incoming
+            .send(MuxMsg::ReadVersionResponse(version()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            mux.receive_msg().await.unwrap(),
+            MuxMsg::ReadVersionResponse(_)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn one_heartbeat_response_swallowed_after_silence() {
+        let (mux, _written, incoming) = mux();
+        tokio::time::sleep(4 * MUX_LIVENESS_TIMEOUT).await;
+        assert!(!mux.is_alive());
+        // The heartbeat's response and the one a read_version is waiting for
+        for _ in 0..2 {
+            incoming
+                .send(MuxMsg::ReadVersionResponse(version()))
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            mux.receive_msg().await.unwrap(),
+            MuxMsg::ReadVersionResponse(_)
+        ));
+        assert!(mux.is_alive());
+    }
+
+    #[tokio::test]
+    async fn try_send_to_reports_full_queue() {
+        let (mux, mut written, _incoming) = mux();
+        let pkt = || Packet {
+            id: 255,
+            data: PacketData::ObjectReportRequest(),
+        };
+        assert_eq!(mux.queue_depth(), 0);
+        while mux.try_send_to([1; 6], pkt()).unwrap() {}
+        assert_eq!(mux.queue_depth(), mux.queue_capacity());
+        assert!(!mux.try_send_to([1; 6], pkt()).unwrap());
+        written.recv().await.unwrap();
+        assert_eq!(mux.queue_depth(), mux.queue_capacity() - 1);
+        assert!(mux.try_send_to([1; 6], pkt()).unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn device_list_merges_split_snapshots() {
+        let (mux, mut written, incoming) = mux();
+        tokio::spawn(async move {
+            while let Some(msg) = written.recv().await {
+                if matches!(msg, MuxMsg::RequestDevices) {
+                    for part in [[[1; 6], [2; 6]], [[2; 6], [3; 6]]] {
+                        let snapshot = HVec::from_slice(&part).unwrap();
+                        incoming
+                            .send(MuxMsg::DevicesSnapshot(snapshot))
+                            .await
+                            .unwrap();
+                        tokio::time::sleep(SNAPSHOT_SETTLE / 2).await;
+                    }
+                }
+            }
+        });
+        let list = mux.request_device_list().await.unwrap();
+        assert!(list.complete);
+        assert_eq!(list.devices.as_slice(), &[[1; 6], [2; 6], [3; 6]]);
+    }
+
+    #[tokio::test]
+    async fn only_last_clone_cancels() {
+        let (device, mut written, _incoming) = vm_device();
+        let token = (*device.cancel).clone();
+        let clone = device.clone();
+        drop(clone);
+        assert!(!token.is_cancelled());
+        assert!(device.is_connected());
+        assert!(written.try_recv().is_err());
+        drop(device);
+        assert!(token.is_cancelled());
+        assert!(matches!(
+            written.recv().await.unwrap().data,
+            PacketData::StreamUpdate(StreamUpdate {
+                action: crate::packets::vm::StreamUpdateAction::DisableAll,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn acked_write_gets_ack() {
+        let (device, written, incoming) = vm_device();
+        simulate(written, incoming, |data| match data {
+            PacketData::WriteRegister(_) => Some(PacketData::Ack()),
+            _ => None,
+        });
+        device
+            .write_register_acked(Port::Nf, 0, 0x10, 1)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acked_write_times_out() {
+        let (device, written, incoming) = vm_device();
+        simulate(written, incoming, |_| None);
+        let start = tokio::time::Instant::now();
+        let err = device
+            .write_register_acked(Port::Nf, 0, 0x10, 1)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert_eq!(start.elapsed(), ACK_TIMEOUT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flash_waits_for_response() {
+        let (device, written, incoming) = vm_device();
+        simulate(written, incoming, |data| match data {
+            PacketData::FlashSettings() => Some(PacketData::FlashSettingsResponse()),
+            _ => None,
+        });
+        device.flash_settings_confirmed().await.unwrap();
+
+        let (device, written, incoming) = vm_device();
+        simulate(written, incoming, |_| None);
+        assert!(device.flash_settings_confirmed().await.is_err());
+    }
+
+    /// Register contents for the simulator, any function of the location will do.
+    fn register_value(bank: u8, address: u8) -> u8 {
+        bank.wrapping_mul(31) ^ address
+    }
+
+    fn register_reply(data: PacketData, skip: Option<u8>) -> Option<PacketData> {
+        let PacketData::ReadRegister(r) = data else {
+            return None;
+        };
+        (skip != Some(r.address)).then(|| {
+            PacketData::ReadRegisterResponse(crate::packets::vm::RegisterResponse {
+                bank: r.bank,
+                address: r.address,
+                data: register_value(r.bank, r.address),
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn batched_reads_match_sequential() {
+        let (device, written, incoming) = vm_device();
+        simulate(written, incoming, |data| register_reply(data, None));
+        // More than one pipeline's worth
+        let regs: Vec<_> = (0..READ_PIPELINE_DEPTH as u8 + 5)
+            .map(|i| (Port::Nf, i % 3, 0x40 + i))
+            .collect();
+        let mut sequential = vec![];
+        for &(port, bank, address) in &regs {
+            sequential.push(device.read_register(port, bank, address).await.unwrap());
+        }
+        assert_eq!(device.read_registers(&regs).await.unwrap(), sequential);
+        let expected: Vec<_> = regs.iter().map(|&(_, b, a)| register_value(b, a)).collect();
+        assert_eq!(sequential, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batched_read_times_out() {
+        let (device, written, incoming) = vm_device();
+        simulate(written, incoming, |data| register_reply(data, Some(0x42)));
+        let regs = [0x41, 0x42, 0x43].map(|address| (Port::Nf, 0, address));
+        let err = device.read_registers(&regs).await.unwrap_err();
+        assert!(err.to_string().contains("0x0:0x42"), "{err}");
+        // The abandoned requests don't leak their response slots
+        let state = device.thread_state.upgrade().unwrap();
+        let channels = state.response_channels.lock().unwrap();
+        assert!(channels.iter().all(|c| matches!(c, ResponseChannel::None)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clear_and_disconnect_twice() {
+        let (device, written, incoming) = vm_device();
+        simulate(written, incoming, |data| match data {
+            PacketData::StreamUpdate(_) => Some(PacketData::Ack()),
+            _ => None,
+        });
+        let _markers = device.stream_combined_markers().await.unwrap();
+        device.clear_all_streams().await.unwrap();
+        device.clear_all_streams().await.unwrap();
+        // Clearing released the stream, so it can be opened again
+        let _markers = device.stream_combined_markers().await.unwrap();
+
+        let clone = device.clone();
+        clone.disconnect().await;
+        device.clone().disconnect().await;
+        tokio::task::yield_now().await;
+        assert!(!device.is_connected());
+    }
+
+    #[tokio::test]
+    async fn read_all_config_reads_all_config_kinds() {
+        use std::mem::discriminant;
+        let (device, written, incoming) = vm_device();
+        let read = Arc::new(Mutex::new(vec![]));
+        let read_sim = Arc::clone(&read);
+        simulate(written, incoming, move |data| {
+            let PacketData::ReadConfig(kind) = data else {
+                return None;
+            };
+            read_sim.lock().unwrap().push(discriminant(&kind));
+            let config = GeneralSettings::default().configs().into_iter().find(|c| {
+                discriminant(&GeneralSettings::default().set(c.clone())) == discriminant(&kind)
+            });
+            Some(PacketData::ReadConfigResponse(config.unwrap()))
+        });
+        device.read_all_config().await.unwrap();
+        let expected: Vec<_> = ALL_CONFIG_KINDS.iter().map(discriminant).collect();
+        assert_eq!(*read.lock().unwrap(), expected);
+        // And configs() lines up with it
+        let mut settings = GeneralSettings::default();
+        let kinds: Vec<_> = settings
+            .configs()
+            .into_iter()
+            .map(|c| discriminant(&settings.set(c)))
+            .collect();
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn settings_round_trip_through_configs() {
+        let settings = GeneralSettings {
+            impact_threshold: 7,
+            suppress_ms: 9,
+            accel_config: AccelConfig {
+                accel_odr: 200,
+                ..Default::default()
+            },
+            gyro_config: GyroConfig {
+                b_x: 0.5,
+                ..Default::default()
+            },
+            camera_model_nf: RosOpenCvIntrinsics::from_params(150., 0., 150., 40., 50.),
+            camera_model_wf: RosOpenCvIntrinsics::from_params(30., 0., 30., 45., 45.),
+            stereo_iso: Isometry3::translation(0.01, 0., 0.),
+        };
+        // Nothing is left at its default, so a field lost on the way shows up in the diff
+        assert_eq!(settings.diff(&GeneralSettings::default()).len(), 7);
+        let rebuilt: GeneralSettings = settings.configs().into_iter().collect();
+        assert_eq!(rebuilt.diff(&settings), Vec::<&str>::new());
+    }
+
+    #[tokio::test]
+    async fn stream_markers_decodes_both_kinds() {
+        use nalgebra::Point2;
+        let (device, written, incoming) = vm_device();
+        let mut points = [Point2::new(0, 0); 16];
+        points[0] = Point2::new(100, 200);
+        simulate(written, incoming, move |data| match data {
+            PacketData::StreamUpdate(StreamUpdate { packet_id, .. }) => match packet_id {
+                PacketType::PocMarkersReport() => {
+                    Some(PacketData::PocMarkersReport(PocMarkersReport { points }))
+                }
+                PacketType::CombinedMarkersReport() => {
+                    Some(PacketData::CombinedMarkersReport(CombinedMarkersReport {
+                        nf_points: points,
+                        wf_points: [Point2::new(0, 0); 16],
+                    }))
+                }
+                _ => None,
+            },
+            _ => None,
+        });
+        let mut markers = Box::pin(device.stream_markers().await.unwrap());
+        let (mut combined, mut poc) = (0, 0);
+        for _ in 0..2 {
+            match markers.next().await.unwrap() {
+                MarkersReport::Combined(r) => {
+                    assert_eq!(r.nf_points[0], Point2::new(100, 200));
+                    combined += 1;
+                }
+                MarkersReport::Poc(r) => {
+                    assert_eq!(r.points[0], Point2::new(100, 200));
+                    poc += 1;
+                }
+            }
+        }
+        assert_eq!((combined, poc), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn tcp_read_config() {
+        let addr = tcp_simulator(|data| match data {
+            PacketData::StreamUpdate(_) => Some(PacketData::Ack()),
+            PacketData::ReadConfig(ConfigKind::ImpactThreshold) => Some(
+                PacketData::ReadConfigResponse(GeneralConfig::ImpactThreshold(42)),
+            ),
+            _ => None,
+        })
+        .await;
+        let device = VmDevice::connect_tcp(addr).await.unwrap();
+        let config = device.read_config(ConfigKind::ImpactThreshold).await;
+        assert!(matches!(config, Ok(GeneralConfig::ImpactThreshold(42))));
+    }
+
+    #[tokio::test]
+    async fn tcp_write_then_read_mode() {
+        let device_mode = Arc::new(Mutex::new(None));
+        let device_mode_sim = Arc::clone(&device_mode);
+        let addr = tcp_simulator(move |data| match data {
+            PacketData::StreamUpdate(_) => Some(PacketData::Ack()),
+            PacketData::WriteMode(mode) => {
+                *device_mode_sim.lock().unwrap() = Some(mode);
+                Some(PacketData::Ack())
+            }
+            _ => None,
+        })
+        .await;
+        let device = VmDevice::connect_tcp(addr).await.unwrap();
+        assert!(device.read_mode().is_none());
+        device.write_mode_acked(Mode::Image).await.unwrap();
+        assert!(matches!(*device_mode.lock().unwrap(), Some(Mode::Image)));
+        assert!(matches!(device.read_mode(), Some(Mode::Image)));
+        assert!(matches!(device.clone().read_mode(), Some(Mode::Image)));
+    }
+
+    #[tokio::test]
+    async fn dispatcher_ids_increase() {
+        let (first, _, _) = vm_device();
+        let (second, _, _) = vm_device();
+        assert!(second.dispatcher_id() > first.dispatcher_id());
+        assert_eq!(first.clone().dispatcher_id(), first.dispatcher_id());
+    }
+}
@@ -0,0 +1,227 @@
+//! COBS framing for byte-stream transports.
+//!
+//! USB bulk transfers delimit packets with short packets, but byte streams such as TCP need
+//! explicit framing. Each postcard-encoded [`Packet`] is COBS encoded and terminated with
+//! [`COBS_DELIMITER`](crate::device::COBS_DELIMITER).
+
+use crate::device::COBS_DELIMITER;
+use crate::packets::vm::Packet;
+
+/// Exact number of bytes `pkt` occupies when postcard encoded, before any framing.
+pub fn serialized_len(pkt: &Packet) -> postcard::Result<usize> {
+    postcard::serialize_with_flavor(pkt, postcard::ser_flavors::Size::default())
+}
+
+/// Encode a packet as a single delimited frame.
+pub fn encode_packet(pkt: &Packet) -> postcard::Result<Vec<u8>> {
+    postcard::to_stdvec_cobs(pkt)
+}
+
+/// Decode a frame produced by [`encode_packet`]. The trailing delimiter is optional. The frame is
+/// decoded in place.
+pub fn decode_packet(frame: &mut [u8]) -> postcard::Result<Packet> {
+    postcard::from_bytes_cobs(frame)
+}
+
+/// Decode one postcard-encoded packet from the start of untrusted `bytes`, returning it and the
+/// number of bytes consumed.
+///
+/// Meant for device input: malformed or truncated input is an error.
+pub fn try_parse(bytes: &[u8]) -> postcard::Result<(Packet, usize)> {
+    postcard::take_from_bytes::<Packet>(bytes).map(|(pkt, rest)| (pkt, bytes.len() - rest.len()))
+}
+
+/// Accumulates bytes from a stream and splits them into frames.
+///
+/// Bytes can be pushed in arbitrary chunks; a frame split across several reads is only returned
+/// once its delimiter arrives.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Push `bytes` and decode every frame they complete.
+    ///
+    /// Each complete frame yields one result, so a corrupt frame is reported without losing the
+    /// frames after it. Bytes of an unfinished frame stay buffered for the next call.
+    pub fn push_packets(&mut self, bytes: &[u8]) -> Vec<postcard::Result<Packet>> {
+        self.push(bytes);
+        std::iter::from_fn(|| self.next_frame())
+            .map(|mut frame| decode_packet(&mut frame))
+            .collect()
+    }
+
+    /// Take the next complete frame, without its delimiter. Empty frames (consecutive delimiters)
+    /// are skipped.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let end = self.buf.iter().position(|&b| b == COBS_DELIMITER)?;
+            let mut frame: Vec<u8> = self.buf.drain(..=end).collect();
+            frame.pop();
+            if !frame.is_empty() {
+                return Some(frame);
+            }
+        }
+    }
+
+    /// Number of buffered bytes not yet part of a complete frame.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::vm::{ConfigKind, PacketData, Port, WriteRegister};
+
+    fn sample_packets() -> Vec<Packet> {
+        vec![
+            Packet {
+                id: 1,
+                data: PacketData::Ack(),
+            },
+            Packet {
+                id: 2,
+                data: PacketData::WriteRegister(WriteRegister {
+                    port: Port::Nf,
+                    bank: 0,
+                    address: 0x10,
+                    data: 0xAB,
+                }),
+            },
+            Packet {
+                id: 255,
+                data: PacketData::ReadConfig(ConfigKind::ImpactThreshold),
+            },
+        ]
+    }
+
+    fn encoded(pkt: &Packet) -> Vec<u8> {
+        postcard::to_stdvec(pkt).unwrap()
+    }
+
+    /// xorshift64, good enough to generate test input without a rand dependency
+    fn random_bytes(state: &mut u64, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                *state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_valid_packets() {
+        for pkt in sample_packets() {
+            let mut bytes = encoded(&pkt);
+            let len = bytes.len();
+            assert_eq!(serialized_len(&pkt).unwrap(), len);
+            bytes.extend_from_slice(&[0xFF, 0xFF]);
+            let (parsed, consumed) = try_parse(&bytes).unwrap();
+            assert_eq!(consumed, len);
+            assert_eq!(encoded(&parsed), encoded(&pkt));
+        }
+    }
+
+    #[test]
+    fn parse_truncated_packets() {
+        for pkt in sample_packets() {
+            let bytes = encoded(&pkt);
+            for len in 0..bytes.len() {
+                assert!(try_parse(&bytes[..len]).is_err(), "{pkt:?} cut to {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn parse_random_bytes() {
+        let mut state = 0x9E37_79B9_7F4A_7C15;
+        for i in 0..10_000 {
+            let bytes = random_bytes(&mut state, i % 64);
+            if let Ok((pkt, consumed)) = try_parse(&bytes) {
+                assert!(consumed <= bytes.len());
+                // Whatever was decoded must survive a round trip
+                assert_eq!(
+                    try_parse(&encoded(&pkt)).unwrap().1,
+                    serialized_len(&pkt).unwrap()
+                );
+            }
+            // Random frames through the COBS path too
+            let mut frame = bytes.clone();
+            let _ = decode_packet(&mut frame);
+        }
+    }
+
+    fn stream() -> Vec<u8> {
+        sample_packets()
+            .iter()
+            .flat_map(|pkt| encode_packet(pkt).unwrap())
+            .collect()
+    }
+
+    fn assert_decoded(results: Vec<postcard::Result<Packet>>) {
+        let expected: Vec<_> = sample_packets().iter().map(encoded).collect();
+        let decoded: Vec<_> = results
+            .iter()
+            .map(|r| encoded(r.as_ref().unwrap()))
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn decoder_one_byte_at_a_time() {
+        let mut decoder = FrameDecoder::new();
+        let mut results = vec![];
+        for &byte in &stream() {
+            let decoded = decoder.push_packets(&[byte]);
+            // A frame only completes on its delimiter
+            assert!(decoded.is_empty() || byte == COBS_DELIMITER);
+            results.extend(decoded);
+        }
+        assert_decoded(results);
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn decoder_several_frames_in_one_buffer() {
+        let mut decoder = FrameDecoder::new();
+        assert_decoded(decoder.push_packets(&stream()));
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn decoder_keeps_unfinished_frame() {
+        let stream = stream();
+        let first = encode_packet(&sample_packets()[0]).unwrap().len();
+        let mut decoder = FrameDecoder::new();
+        // The first frame and half of the second
+        let split = first + 2;
+        assert_eq!(decoder.push_packets(&stream[..split]).len(), 1);
+        assert_eq!(decoder.pending(), 2);
+        assert_eq!(decoder.push_packets(&stream[split..]).len(), 2);
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn decoder_skips_empty_and_reports_corrupt_frames() {
+        let mut bytes = vec![COBS_DELIMITER, COBS_DELIMITER];
+        bytes.extend_from_slice(&[0x05, 0xFF, COBS_DELIMITER]);
+        bytes.extend(stream());
+        let results = FrameDecoder::new().push_packets(&bytes);
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_err());
+        assert_decoded(results.into_iter().skip(1).collect());
+    }
+}
@@ -1,4 +1,6 @@
 #[macro_use]
 mod macros;
 pub mod device;
+pub mod framing;
 pub mod packets;
+pub mod udp;
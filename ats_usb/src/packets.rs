@@ -1,5 +1,223 @@
 pub mod vm {
     pub use protodongers::*;
+
+    /// Sensor resolution (width, height) behind the coordinates of a `CombinedMarkersReport`
+    pub const SENSOR_RESOLUTION: [u16; 2] = [4096, 4096];
+
+    /// Sensor resolution (width, height) behind the coordinates of a `PocMarkersReport`: 320x240
+    /// pixels with 6 fractional bits
+    pub const POC_SENSOR_RESOLUTION: [u16; 2] = [20480, 15360];
+
+    /// m/s² per raw accelerometer LSB (±16 g range, 9.81 m/s² per g).
+    ///
+    /// protodongers scales the raw counts by this when it decodes an `AccelReport`, so
+    /// `AccelReport::accel` is already in m/s². [`MetersPerSecond2::from_raw`] converts raw counts
+    /// read some other way.
+    pub const ACCEL_SCALE: f32 = 9.81 / 2048.0;
+
+    /// Acceleration in m/s², as reported by [`AccelReport::accel`].
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct MetersPerSecond2(pub nalgebra::Vector3<f32>);
+
+    impl MetersPerSecond2 {
+        /// Convert raw accelerometer counts with [`ACCEL_SCALE`].
+        pub fn from_raw(raw: [i16; 3]) -> Self {
+            Self(nalgebra::Vector3::from(raw.map(f32::from)) * ACCEL_SCALE)
+        }
+    }
+
+    /// Angular velocity in rad/s, as reported by [`AccelReport::gyro`].
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct RadiansPerSecond(pub nalgebra::Vector3<f32>);
+
+    impl RadiansPerSecond {
+        pub fn to_degrees(self) -> nalgebra::Vector3<f32> {
+            self.0.map(f32::to_degrees)
+        }
+    }
+
+    /// Calibrated IMU readings with their units in the type.
+    ///
+    /// `AccelReport` stores both vectors as bare `Vector3<f32>`; prefer these over
+    /// `corrected_accel`/`corrected_gyro` where the value is passed on.
+    pub trait AccelReportExt {
+        /// Bias and scale corrected acceleration. The report is already scaled by [`ACCEL_SCALE`].
+        fn accel_si(&self, config: &AccelConfig) -> MetersPerSecond2;
+        /// Bias corrected angular velocity.
+        fn gyro_si(&self, config: &GyroConfig) -> RadiansPerSecond;
+    }
+
+    impl AccelReportExt for AccelReport {
+        fn accel_si(&self, config: &AccelConfig) -> MetersPerSecond2 {
+            MetersPerSecond2(self.corrected_accel(config))
+        }
+
+        fn gyro_si(&self, config: &GyroConfig) -> RadiansPerSecond {
+            RadiansPerSecond(self.corrected_gyro(config))
+        }
+    }
+
+    /// Position of an object in `[0, 1] × [0, 1]`, for a sensor reporting `cx` in
+    /// `0..sensor_width` and `cy` in `0..sensor_height`.
+    ///
+    /// The origin is the center of the top left pixel and `(1, 1)` the center of the bottom right
+    /// pixel, so at the default 4096×4096 resolution this divides by 4095.
+    pub fn normalized_point(
+        mot_data: &MotData,
+        sensor_width: u16,
+        sensor_height: u16,
+    ) -> nalgebra::Point2<f32> {
+        let max = |len: u16| f32::from(len.saturating_sub(1).max(1));
+        nalgebra::Point2::new(
+            mot_data.cx as f32 / max(sensor_width),
+            mot_data.cy as f32 / max(sensor_height),
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn point(cx: u16, cy: u16, resolution: [u16; 2]) -> nalgebra::Point2<f32> {
+            let mot_data = MotData {
+                cx,
+                cy,
+                ..Default::default()
+            };
+            normalized_point(&mot_data, resolution[0], resolution[1])
+        }
+
+        #[test]
+        fn normalized_corners() {
+            let p = |cx, cy| point(cx, cy, SENSOR_RESOLUTION);
+            assert_eq!(p(0, 0), nalgebra::Point2::new(0.0, 0.0));
+            assert_eq!(p(4095, 0), nalgebra::Point2::new(1.0, 0.0));
+            assert_eq!(p(0, 4095), nalgebra::Point2::new(0.0, 1.0));
+            assert_eq!(p(4095, 4095), nalgebra::Point2::new(1.0, 1.0));
+        }
+
+        #[test]
+        fn normalized_center() {
+            // 4096 is even, so the two middle pixels straddle the center
+            let below = point(2047, 2047, SENSOR_RESOLUTION);
+            let above = point(2048, 2048, SENSOR_RESOLUTION);
+            assert!(below.x < 0.5 && above.x > 0.5);
+            assert!((below.y + above.y - 1.0).abs() < 1e-6);
+            let [w, h] = POC_SENSOR_RESOLUTION;
+            assert_eq!(
+                point(w - 1, h - 1, POC_SENSOR_RESOLUTION),
+                nalgebra::Point2::new(1.0, 1.0)
+            );
+        }
+
+        #[test]
+        fn normalized_degenerate_resolution() {
+            assert_eq!(point(1, 1, [0, 1]), nalgebra::Point2::new(1.0, 1.0));
+        }
+
+        #[test]
+        fn accel_scale() {
+            // 2048 LSB per g at ±16 g
+            assert_eq!(ACCEL_SCALE * 2048.0, 9.81);
+            let one_g = MetersPerSecond2::from_raw([2048, -2048, 0]);
+            assert_eq!(one_g.0, nalgebra::Vector3::new(9.81, -9.81, 0.0));
+            let full_scale = MetersPerSecond2::from_raw([i16::MIN, 0, 1]);
+            assert_eq!(
+                full_scale.0,
+                nalgebra::Vector3::new(-16.0 * 9.81, 0.0, ACCEL_SCALE)
+            );
+        }
+    }
+}
+
+/// Little-endian integer IO for the byte layouts defined in this workspace, such as the recording
+/// file's timestamps and the sensor registers.
+///
+/// Everything on the wire and on disk is little-endian. Going through these helpers instead of
+/// calling `from_le_bytes`/`to_le_bytes` at each site keeps the byte order in one place.
+pub mod le {
+    macro_rules! le_helpers {
+        ($($ty:ty => $read:ident, $write:ident;)*) => {$(
+            #[doc = concat!("Split a little-endian `", stringify!($ty), "` off the front of `bytes`, `None` if it is too short.")]
+            pub fn $read(bytes: &[u8]) -> Option<($ty, &[u8])> {
+                let (head, rest) = bytes.split_first_chunk()?;
+                Some((<$ty>::from_le_bytes(*head), rest))
+            }
+
+            #[doc = concat!("Append `value` as a little-endian `", stringify!($ty), "`.")]
+            pub fn $write(out: &mut Vec<u8>, value: $ty) {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        )*};
+    }
+
+    le_helpers! {
+        u16 => read_u16_le, write_u16_le;
+        i16 => read_i16_le, write_i16_le;
+        u32 => read_u32_le, write_u32_le;
+        i32 => read_i32_le, write_i32_le;
+        u64 => read_u64_le, write_u64_le;
+        u128 => read_u128_le, write_u128_le;
+        f32 => read_f32_le, write_f32_le;
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn round_trip() {
+            let mut out = vec![];
+            write_u16_le(&mut out, 0xBEEF);
+            write_i16_le(&mut out, -2);
+            write_u32_le(&mut out, 0xDEAD_BEEF);
+            write_i32_le(&mut out, i32::MIN);
+            write_u64_le(&mut out, u64::MAX - 1);
+            write_u128_le(&mut out, 1 << 100);
+            write_f32_le(&mut out, -1.5);
+            assert_eq!(out.len(), 2 + 2 + 4 + 4 + 8 + 16 + 4);
+
+            let (a, rest) = read_u16_le(&out).unwrap();
+            let (b, rest) = read_i16_le(rest).unwrap();
+            let (c, rest) = read_u32_le(rest).unwrap();
+            let (d, rest) = read_i32_le(rest).unwrap();
+            let (e, rest) = read_u64_le(rest).unwrap();
+            let (f, rest) = read_u128_le(rest).unwrap();
+            let (g, rest) = read_f32_le(rest).unwrap();
+            assert_eq!(
+                (a, b, c, d, e, f, g),
+                (
+                    0xBEEF,
+                    -2,
+                    0xDEAD_BEEF,
+                    i32::MIN,
+                    u64::MAX - 1,
+                    1 << 100,
+                    -1.5
+                )
+            );
+            assert!(rest.is_empty());
+        }
+
+        #[test]
+        fn byte_order() {
+            let mut out = vec![];
+            write_u32_le(&mut out, 0x0403_0201);
+            assert_eq!(out, [1, 2, 3, 4]);
+            assert_eq!(
+                read_u16_le(&[0x34, 0x12, 0xFF]),
+                Some((0x1234, &[0xFF][..]))
+            );
+        }
+
+        #[test]
+        fn short_input() {
+            assert_eq!(read_u16_le(&[1]), None);
+            assert_eq!(read_u32_le(&[1, 2, 3]), None);
+            assert_eq!(read_u128_le(&[0; 15]), None);
+            assert_eq!(read_f32_le(&[]), None);
+        }
+    }
 }
 
 pub mod mux {
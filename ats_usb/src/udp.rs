@@ -0,0 +1,264 @@
+//! UDP transport with discovery, keepalive and automatic re-handshake.
+//!
+//! Datagram layout:
+//! - `[255, 3]` discovery, broadcast by the host. The device answers from its unicast address.
+//! - `[255, 1]` ping, sent by the host to the discovered address. The device echoes it back.
+//! - `[1, 1]` keepalive, sent by the host every [`UdpTransport::keepalive_interval`] together
+//!   with a ping. Devices don't answer keepalives, the echoed ping is what shows the device is
+//!   still there when it has nothing else to send.
+//! - `[0, ..]` a postcard-encoded [`Packet`], one per datagram, in either direction.
+//!
+//! If nothing is heard from the device for [`UdpTransport::response_timeout`], the transport
+//! repeats the discovery handshake. Outgoing packets are dropped while the device is lost.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+use crate::device::PacketTransport;
+use crate::packets::vm::Packet;
+
+pub const DISCOVER: [u8; 2] = [255, 3];
+pub const PING: [u8; 2] = [255, 1];
+pub const KEEPALIVE: [u8; 2] = [1, 1];
+pub const PACKET_TAG: u8 = 0;
+
+#[derive(Clone, Debug)]
+pub struct UdpTransport {
+    /// Where discovery datagrams are sent, e.g. `255.255.255.255:23456`
+    pub broadcast_addr: SocketAddr,
+    /// Local address to bind, `0.0.0.0:0` by default
+    pub bind_addr: SocketAddr,
+    pub keepalive_interval: Duration,
+    /// How long the device may stay silent before the handshake is repeated. Also the timeout for
+    /// each handshake step.
+    pub response_timeout: Duration,
+    /// Number of attempts for each handshake step
+    pub handshake_attempts: usize,
+}
+
+impl UdpTransport {
+    pub fn new(broadcast_addr: SocketAddr) -> Self {
+        Self {
+            broadcast_addr,
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+            keepalive_interval: Duration::from_millis(500),
+            response_timeout: Duration::from_secs(2),
+            handshake_attempts: 5,
+        }
+    }
+
+    /// Bind a socket, run the handshake and start the transport tasks.
+    pub async fn connect(self) -> Result<PacketTransport> {
+        let socket = UdpSocket::bind(self.bind_addr)
+            .await
+            .with_context(|| format!("failed to bind {}", self.bind_addr))?;
+        socket.set_broadcast(true)?;
+        let peer = self.handshake(&socket).await?;
+        info!("udp: connected to {peer}");
+        Ok(self.spawn(socket, peer))
+    }
+
+    /// Broadcast a discovery request, then ping whichever address answered. Returns the device's
+    /// unicast address.
+    pub async fn handshake(&self, socket: &UdpSocket) -> Result<SocketAddr> {
+        let mut buf = [0u8; 64];
+        for attempt in 0..self.handshake_attempts {
+            debug!(
+                "udp: discovery attempt {attempt} to {}",
+                self.broadcast_addr
+            );
+            socket.send_to(&DISCOVER, self.broadcast_addr).await?;
+            let peer = match tokio::time::timeout(self.response_timeout, socket.recv_from(&mut buf))
+                .await
+            {
+                Ok(Ok((_, peer))) => peer,
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => continue,
+            };
+
+            socket.send_to(&PING, peer).await?;
+            let pong = tokio::time::timeout(self.response_timeout, async {
+                loop {
+                    let (n, from) = socket.recv_from(&mut buf).await?;
+                    if from == peer && buf[..n] == PING {
+                        return std::io::Result::Ok(());
+                    }
+                }
+            })
+            .await;
+            match pong {
+                Ok(Ok(())) => return Ok(peer),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => warn!("udp: {peer} answered discovery but not ping"),
+            }
+        }
+        Err(anyhow!(
+            "no device answered at {} after {} attempts",
+            self.broadcast_addr,
+            self.handshake_attempts
+        ))
+    }
+
+    fn spawn(self, socket: UdpSocket, peer: SocketAddr) -> PacketTransport {
+        let socket = Arc::new(socket);
+        let (writer, mut writer_rx) = mpsc::channel::<Packet>(64);
+        let (incoming_tx, incoming_rx) = mpsc::channel::<Packet>(128);
+        let cancel_token = tokio_util::sync::CancellationToken::new();
+        let cancel = cancel_token.clone();
+
+        tokio::spawn(async move {
+            let mut peer = Some(peer);
+            let mut last_seen = Instant::now();
+            let mut keepalive = tokio::time::interval(self.keepalive_interval);
+            let mut buf = vec![0u8; 2048];
+            loop {
+                if peer.is_none() || last_seen.elapsed() > self.response_timeout {
+                    warn!("udp: device stopped responding, repeating handshake");
+                    peer = None;
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        r = self.handshake(&socket) => match r {
+                            Ok(p) => {
+                                info!("udp: reconnected to {p}");
+                                peer = Some(p);
+                            }
+                            Err(e) => {
+                                warn!("udp: handshake failed: {e}");
+                                // Don't let outgoing packets pile up while the device is gone
+                                while writer_rx.try_recv().is_ok() {}
+                                continue;
+                            }
+                        },
+                    }
+                    last_seen = Instant::now();
+                }
+                let Some(addr) = peer else { continue };
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = keepalive.tick() => {
+                        for datagram in [KEEPALIVE, PING] {
+                            if let Err(e) = socket.send_to(&datagram, addr).await {
+                                error!("udp keepalive failed: {e}");
+                            }
+                        }
+                    }
+                    pkt = writer_rx.recv() => {
+                        let Some(pkt) = pkt else { break };
+                        let mut raw = Vec::with_capacity(
+                            1 + crate::framing::serialized_len(&pkt).unwrap_or_default(),
+                        );
+                        raw.push(PACKET_TAG);
+                        if let Err(e) = postcard::to_io(&pkt, &mut raw) {
+                            error!("postcard serialize failed: {e}");
+                            continue;
+                        }
+                        if let Err(e) = socket.send_to(&raw, addr).await {
+                            error!("udp send failed: {e}");
+                        }
+                    }
+                    r = socket.recv_from(&mut buf) => {
+                        let (n, from) = match r {
+                            Ok(r) => r,
+                            Err(e) => {
+                                // e.g. ICMP port unreachable; let the timeout decide
+                                warn!("udp recv error: {e}");
+                                continue;
+                            }
+                        };
+                        if from != addr {
+                            continue;
+                        }
+                        last_seen = Instant::now();
+                        // Pings and keepalives only refresh `last_seen`
+                        if let [PACKET_TAG, data @ ..] = &buf[..n] {
+                            match crate::framing::try_parse(data) {
+                                Ok((pkt, _)) => {
+                                    if incoming_tx.send(pkt).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                Err(e) => error!("postcard decode failed: {e:?}"),
+                            }
+                        }
+                    }
+                }
+            }
+            info!("udp transport exits");
+        });
+
+        PacketTransport::from_channels(writer, incoming_rx, cancel_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// Answers discovery and echoes pings while `responding` is set, ignores everything else like
+    /// a device with nothing to stream. Returns its address and the number of discoveries seen.
+    async fn mock_device(responding: Arc<AtomicBool>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let discoveries = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let discoveries = discoveries.clone();
+            async move {
+                let mut buf = [0u8; 64];
+                loop {
+                    let Ok((n, from)) = socket.recv_from(&mut buf).await else {
+                        continue;
+                    };
+                    if buf[..n] == DISCOVER {
+                        discoveries.fetch_add(1, Ordering::SeqCst);
+                        let _ = socket.send_to(&DISCOVER, from).await;
+                    } else if buf[..n] == PING && responding.load(Ordering::SeqCst) {
+                        let _ = socket.send_to(&PING, from).await;
+                    }
+                }
+            }
+        });
+        (addr, discoveries)
+    }
+
+    fn transport(device: SocketAddr) -> UdpTransport {
+        UdpTransport {
+            bind_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            keepalive_interval: Duration::from_millis(20),
+            response_timeout: Duration::from_millis(100),
+            handshake_attempts: 3,
+            ..UdpTransport::new(device)
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_device_stays_connected() {
+        let (addr, discoveries) = mock_device(Arc::new(AtomicBool::new(true))).await;
+        let _transport = transport(addr).connect().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(discoveries.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn silent_device_is_rediscovered() {
+        let responding = Arc::new(AtomicBool::new(true));
+        let (addr, discoveries) = mock_device(responding.clone()).await;
+        let _transport = transport(addr).connect().await.unwrap();
+        responding.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(discoveries.load(Ordering::SeqCst) > 1);
+    }
+
+    #[tokio::test]
+    async fn connect_fails_without_device() {
+        let (addr, _) = mock_device(Arc::new(AtomicBool::new(false))).await;
+        assert!(transport(addr).connect().await.is_err());
+    }
+}
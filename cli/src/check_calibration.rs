@@ -0,0 +1,150 @@
+//! Calibration self-check against a recording
+
+use std::path::PathBuf;
+
+use ats_usb::packets::vm::PacketData;
+use nalgebra::{Point2, Vector3};
+use opencv_ros_camera::RosOpenCvIntrinsics;
+
+/// Markers further apart than this (degrees) are assumed to be different markers
+const MATCH_GATE_DEG: f64 = 5.0;
+
+/// Rounds of the fixed point undistortion
+const UNDISTORT_ITERATIONS: usize = 20;
+
+/// Check the stereo calibration stored in a recording against the markers it contains.
+///
+/// Every marker seen by the nearfield camera is also seen by the widefield camera. After
+/// undistorting both and rotating the widefield ray into the nearfield frame with the stereo iso,
+/// the two rays should coincide. The translation between the cameras is ignored, which is fine
+/// for markers a few meters away. Fails if the RMS angle between the rays exceeds `threshold_deg`.
+pub fn cmd_check_calibration(recording: &PathBuf, threshold_deg: f64) -> Result<(), String> {
+    let (settings, packets) = ats_playback::read_file(recording)
+        .map_err(|e| format!("Failed to read {}: {e}", recording.display()))?;
+
+    let rotation = settings.stereo_iso.rotation.cast::<f64>();
+    let mut errors = Vec::new();
+    let mut frames = 0usize;
+    for (_, packet) in &packets {
+        let PacketData::CombinedMarkersReport(report) = &packet.data else {
+            continue;
+        };
+        let nf_rays = rays(&settings.camera_model_nf, report.nf_points.iter());
+        let wf_rays: Vec<_> = rays(&settings.camera_model_wf, report.wf_points.iter())
+            .into_iter()
+            .map(|r| rotation * r)
+            .collect();
+        if nf_rays.is_empty() || wf_rays.is_empty() {
+            continue;
+        }
+        frames += 1;
+        for nf in &nf_rays {
+            let nearest = wf_rays
+                .iter()
+                .map(|wf| nf.angle(wf).to_degrees())
+                .min_by(f64::total_cmp);
+            if let Some(angle) = nearest.filter(|&a| a <= MATCH_GATE_DEG) {
+                errors.push(angle);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        return Err(format!(
+            "No markers seen by both cameras in {} packets",
+            packets.len()
+        ));
+    }
+    errors.sort_by(f64::total_cmp);
+    let n = errors.len() as f64;
+    let mean = errors.iter().sum::<f64>() / n;
+    let rms = (errors.iter().map(|e| e * e).sum::<f64>() / n).sqrt();
+    let p95 = errors[((n * 0.95) as usize).min(errors.len() - 1)];
+    let max = errors[errors.len() - 1];
+
+    println!("Frames with markers on both cameras: {frames}");
+    println!("Matched markers: {}", errors.len());
+    println!("Stereo error (deg): mean {mean:.3}, rms {rms:.3}, p95 {p95:.3}, max {max:.3}");
+    if rms <= threshold_deg {
+        println!("PASS (rms <= {threshold_deg} deg)");
+        Ok(())
+    } else {
+        Err(format!("FAIL (rms {rms:.3} > {threshold_deg} deg)"))
+    }
+}
+
+/// Undistorted unit rays for the reported marker positions, skipping unused (0, 0) slots.
+fn rays<'a>(
+    intrinsics: &RosOpenCvIntrinsics<f32>,
+    points: impl Iterator<Item = &'a Point2<u16>>,
+) -> Vec<Vector3<f64>> {
+    let k = intrinsics.k.cast::<f64>();
+    let dist = intrinsics.distortion.opencv_vec().cast::<f64>();
+    let (k1, k2, p1, p2, k3) = (dist[0], dist[1], dist[2], dist[3], dist[4]);
+    points
+        .filter(|p| **p != Point2::new(0, 0))
+        .map(|p| {
+            // Same fixed point iteration as OpenCV's undistortPoints
+            let y0 = (f64::from(p.y) - k.m23) / k.m22;
+            let x0 = (f64::from(p.x) - k.m13 - k.m12 * y0) / k.m11;
+            let (mut x, mut y) = (x0, y0);
+            for _ in 0..UNDISTORT_ITERATIONS {
+                let r2 = x * x + y * y;
+                let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                let dx = 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x);
+                let dy = p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y;
+                x = (x0 - dx) / radial;
+                y = (y0 - dy) / radial;
+            }
+            Vector3::new(x, y, 1.0).normalize()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ats_usb::device::GeneralSettings;
+    use ats_usb::packets::vm::Packet;
+    use nalgebra::{Isometry3, Vector3};
+
+    /// Both cameras report markers at the same pixels, 60 pixels apart.
+    fn markers() -> PacketData {
+        let slots: Vec<_> = (0..16)
+            .map(|i| [[300, 240], [360, 240]].get(i).copied().unwrap_or([0, 0]))
+            .collect();
+        let report = serde_json::json!({ "nf_points": slots, "wf_points": slots });
+        PacketData::CombinedMarkersReport(serde_json::from_value(report).unwrap())
+    }
+
+    /// Check a recording of one markers report with the default cameras and `stereo_iso`.
+    fn check(name: &str, stereo_iso: Isometry3<f32>, data: PacketData) -> Result<(), String> {
+        let settings = GeneralSettings {
+            stereo_iso,
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("ats-cli-{}-{name}", std::process::id()));
+        ats_playback::write_file(&path, &settings, &[(0, Packet { id: 0, data })]).unwrap();
+        let result = cmd_check_calibration(&path, 0.5);
+        std::fs::remove_file(path).unwrap();
+        result
+    }
+
+    #[test]
+    fn matching_cameras_pass() {
+        assert_eq!(check("good", Isometry3::identity(), markers()), Ok(()));
+    }
+
+    #[test]
+    fn rotated_stereo_iso_fails() {
+        let rotated = Isometry3::rotation(Vector3::y() * 2f32.to_radians());
+        let err = check("bad", rotated, markers()).unwrap_err();
+        assert!(err.starts_with("FAIL (rms 2.000"), "{err}");
+    }
+
+    #[test]
+    fn no_markers_fails() {
+        let err = check("empty", Isometry3::identity(), PacketData::Ack()).unwrap_err();
+        assert!(err.starts_with("No markers"), "{err}");
+    }
+}
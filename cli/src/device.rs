@@ -41,6 +41,15 @@ pub enum DeviceCommands {
     },
     /// Stream accelerometer data
     Stream,
+    /// Check the stereo calibration in a recording against its markers, no device needed
+    CheckCalibration {
+        /// Recording saved from the desktop app
+        #[arg(long)]
+        recording: std::path::PathBuf,
+        /// Maximum RMS stereo error in degrees
+        #[arg(long, default_value_t = 0.5)]
+        threshold: f64,
+    },
 }
 
 async fn list_devices(require_usb_mode: bool) -> Result<Vec<nusb::DeviceInfo>, String> {
@@ -306,5 +315,9 @@ pub async fn handle_command(
             let mut device = connect_to_device(device_index, true).await?;
             crate::calibration::cmd_stream(&mut device).await
         }
+        DeviceCommands::CheckCalibration {
+            recording,
+            threshold,
+        } => crate::check_calibration::cmd_check_calibration(&recording, threshold),
     }
 }
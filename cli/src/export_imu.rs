@@ -0,0 +1,164 @@
+//! Export command — write the IMU samples of a recording as CSV or NDJSON
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use ats_usb::packets::vm::{AccelReportExt, PacketData};
+use clap::{Args, ValueEnum};
+use nalgebra::Vector3;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ImuFormat {
+    /// Header row, then one comma separated row per sample
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+#[derive(Args)]
+pub struct ExportImuArgs {
+    /// Recording saved from the desktop app
+    pub file: PathBuf,
+
+    /// Output file, stdout if omitted
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ImuFormat::Csv)]
+    pub format: ImuFormat,
+
+    /// Skip the accel and gyro calibration stored in the recording
+    #[arg(long)]
+    pub raw: bool,
+}
+
+/// Write one row per `AccelReport`: device timestamp (µs), accel (m/s²) and gyro (rad/s).
+pub fn handle_export_imu(args: ExportImuArgs) -> Result<(), String> {
+    let (settings, packets) = ats_playback::read_file(&args.file)
+        .map_err(|e| format!("Failed to read {}: {e}", args.file.display()))?;
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let write_err = |e: io::Error| format!("Failed to write output: {e}");
+    if let ImuFormat::Csv = args.format {
+        writeln!(out, "timestamp,ax,ay,az,gx,gy,gz").map_err(write_err)?;
+    }
+    let mut rows = 0usize;
+    for (_, packet) in &packets {
+        let PacketData::AccelReport(report) = &packet.data else {
+            continue;
+        };
+        let (accel, gyro) = if args.raw {
+            (report.accel, report.gyro)
+        } else {
+            (
+                report.accel_si(&settings.accel_config).0,
+                report.gyro_si(&settings.gyro_config).0,
+            )
+        };
+        write_row(&mut out, args.format, report.timestamp, accel, gyro).map_err(write_err)?;
+        rows += 1;
+    }
+    out.flush().map_err(write_err)?;
+
+    eprintln!("Exported {rows} IMU samples from {}", args.file.display());
+    Ok(())
+}
+
+fn write_row(
+    out: &mut impl Write,
+    format: ImuFormat,
+    timestamp: u32,
+    accel: Vector3<f32>,
+    gyro: Vector3<f32>,
+) -> io::Result<()> {
+    match format {
+        ImuFormat::Csv => writeln!(
+            out,
+            "{timestamp},{},{},{},{},{},{}",
+            accel.x, accel.y, accel.z, gyro.x, gyro.y, gyro.z
+        ),
+        ImuFormat::Ndjson => {
+            let row = serde_json::json!({
+                "timestamp": timestamp,
+                "ax": accel.x,
+                "ay": accel.y,
+                "az": accel.z,
+                "gx": gyro.x,
+                "gy": gyro.y,
+                "gz": gyro.z,
+            });
+            writeln!(out, "{row}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ats_usb::device::GeneralSettings;
+    use ats_usb::packets::vm::{AccelReport, ImpactReport, Packet};
+
+    /// Export a recording of 3 accel reports and 2 impacts, returning the output lines.
+    fn export(format: ImuFormat) -> Vec<String> {
+        let packets: Vec<_> = (0..5u32)
+            .map(|i| {
+                let timestamp = 1000 * i;
+                let data = if i % 2 == 0 {
+                    PacketData::AccelReport(AccelReport {
+                        accel: Vector3::new(0.0, 0.0, 9.81),
+                        gyro: Vector3::zeros(),
+                        timestamp,
+                    })
+                } else {
+                    PacketData::ImpactReport(ImpactReport { timestamp })
+                };
+                (u128::from(i), Packet { id: 0, data })
+            })
+            .collect();
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("ats-cli-{}-{format:?}.rec", std::process::id()));
+        let output = file.with_extension("out");
+        ats_playback::write_file(&file, &GeneralSettings::default(), &packets).unwrap();
+
+        handle_export_imu(ExportImuArgs {
+            file: file.clone(),
+            output: Some(output.clone()),
+            format,
+            raw: false,
+        })
+        .unwrap();
+        let lines = std::fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        std::fs::remove_file(file).unwrap();
+        std::fs::remove_file(output).unwrap();
+        lines
+    }
+
+    #[test]
+    fn one_row_per_accel_report() {
+        let csv = export(ImuFormat::Csv);
+        assert_eq!(csv.len(), 1 + 3);
+        assert_eq!(csv[0], "timestamp,ax,ay,az,gx,gy,gz");
+        let timestamps: Vec<_> = csv[1..].iter().map(|row| row.split(',').next()).collect();
+        assert_eq!(timestamps, [Some("0"), Some("2000"), Some("4000")]);
+
+        let ndjson = export(ImuFormat::Ndjson);
+        assert_eq!(ndjson.len(), 3);
+        for row in ndjson {
+            let row: serde_json::Value = serde_json::from_str(&row).unwrap();
+            assert!(row["timestamp"].is_u64());
+        }
+    }
+}
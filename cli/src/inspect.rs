@@ -0,0 +1,25 @@
+//! Inspect command — summarize a recording before replaying it
+
+use std::path::PathBuf;
+
+use clap::Args;
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// Recording saved from the desktop app
+    pub file: PathBuf,
+}
+
+pub fn handle_inspect(args: InspectArgs) -> Result<(), String> {
+    let summary = ats_playback::summarize(&args.file)
+        .map_err(|e| format!("Failed to read {}: {e}", args.file.display()))?;
+
+    println!("Recording: {}", args.file.display());
+    println!("Duration: {:.3} s", summary.duration_ms() as f64 / 1000.0);
+    println!("Packets: {}", summary.packet_count);
+    for (kind, count) in &summary.counts {
+        println!("  {kind}: {count}");
+    }
+    println!("Settings: {:#?}", summary.settings);
+    Ok(())
+}
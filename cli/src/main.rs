@@ -7,7 +7,11 @@ use clap::{Parser, Subcommand};
 mod mux;
 mod device;
 mod calibration;
+mod check_calibration;
 mod bond;
+mod replay;
+mod export_imu;
+mod inspect;
 
 #[derive(Parser)]
 #[command(name = "ats-cli")]
@@ -39,6 +43,12 @@ enum Commands {
     },
     /// Create a manual bond between a dongle and a device
     Bond(bond::BondArgs),
+    /// Serve a recording to the desktop app over UDP, in place of a device
+    Replay(replay::ReplayArgs),
+    /// Write the IMU samples of a recording as CSV or NDJSON
+    ExportImu(export_imu::ExportImuArgs),
+    /// Print a recording's duration, packet counts and settings
+    Inspect(inspect::InspectArgs),
 }
 
 #[tokio::main]
@@ -49,6 +59,9 @@ async fn main() -> ExitCode {
         Commands::Mux { device, command } => mux::handle_command(device, command).await,
         Commands::Device { device, command } => device::handle_command(device, command).await,
         Commands::Bond(args) => bond::handle_bond(args).await,
+        Commands::Replay(args) => replay::handle_replay(args).await,
+        Commands::ExportImu(args) => export_imu::handle_export_imu(args),
+        Commands::Inspect(args) => inspect::handle_inspect(args),
     };
 
     match result {
@@ -0,0 +1,47 @@
+//! Replay command — serve a recording to the desktop app over UDP without a device
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use ats_playback::replay::ReplayOptions;
+use clap::Args;
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Recording saved from the desktop app
+    pub file: PathBuf,
+
+    /// Start over from the beginning when the recording ends
+    #[arg(long = "loop")]
+    pub looping: bool,
+
+    /// Playback speed, 2.0 plays twice as fast as recorded
+    #[arg(long, default_value_t = 1.0)]
+    pub rate: f64,
+
+    /// Address to listen on for the desktop app's discovery broadcast
+    #[arg(long, default_value = "0.0.0.0:23456")]
+    pub bind: SocketAddr,
+}
+
+pub async fn handle_replay(args: ReplayArgs) -> Result<(), String> {
+    let (_, packets) = ats_playback::read_file(&args.file)
+        .map_err(|e| format!("Failed to read {}: {e}", args.file.display()))?;
+
+    let options = ReplayOptions {
+        bind_addr: args.bind,
+        rate: args.rate,
+        looping: args.looping,
+    };
+    println!(
+        "Replaying {} packets from {} on {}",
+        packets.len(),
+        args.file.display(),
+        args.bind
+    );
+    ats_playback::replay::serve(&packets, &options)
+        .await
+        .map_err(|e| format!("Replay failed: {e}"))?;
+    println!("Replay finished");
+    Ok(())
+}
@@ -124,6 +124,32 @@ impl Window {
         }
     }
 
+    /// Get the window's position, measured from the top left corner of the screen.
+    ///
+    /// May be inaccurate on Unix platforms.
+    pub fn position(&self, _ctx: &UI) -> (c_int, c_int) {
+        let (mut x, mut y) = (0, 0);
+        unsafe { ui_sys::uiWindowPosition(self.uiWindow, &mut x, &mut y) }
+        (x, y)
+    }
+
+    /// Move the window. This is only a hint on Unix platforms.
+    pub fn set_position(&mut self, _ctx: &UI, x: c_int, y: c_int) {
+        unsafe { ui_sys::uiWindowSetPosition(self.uiWindow, x, y) }
+    }
+
+    /// Get the size of the window's content area, without decorations.
+    pub fn content_size(&self, _ctx: &UI) -> (c_int, c_int) {
+        let (mut width, mut height) = (0, 0);
+        unsafe { ui_sys::uiWindowContentSize(self.uiWindow, &mut width, &mut height) }
+        (width, height)
+    }
+
+    /// Resize the window's content area.
+    pub fn set_content_size(&mut self, _ctx: &UI, width: c_int, height: c_int) {
+        unsafe { ui_sys::uiWindowSetContentSize(self.uiWindow, width, height) }
+    }
+
     /// Check whether or not this window has margins around the edges.
     pub fn margined(&self, _ctx: &UI) -> bool {
         unsafe { ui_sys::uiWindowMargined(self.uiWindow) != 0 }
@@ -0,0 +1,56 @@
+//! Undistortion of one frame's worth of markers and of a full sensor grid, comparing
+//! `ats_cv::undistort_points` (one point at a time), the batched
+//! [`CameraParams::undistort_points`] and the precomputed [`UndistortMap`].
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use nalgebra::Point2;
+use vision_module_gui::camera::{CameraParams, UndistortMap, UNDISTORT_GRID};
+
+const SENSOR_RESOLUTION: [u16; 2] = ats_usb::packets::vm::SENSOR_RESOLUTION;
+
+fn points(n: usize) -> Vec<Point2<f32>> {
+    let [w, h] = SENSOR_RESOLUTION.map(f32::from);
+    (0..n)
+        .map(|i| {
+            let t = i as f32 / n as f32;
+            Point2::new(t * (w - 1.0), (1.0 - t * t) * (h - 1.0))
+        })
+        .collect()
+}
+
+fn undistort(c: &mut Criterion) {
+    let params = CameraParams {
+        fx: 2500.0,
+        fy: 2500.0,
+        cx: 2000.0,
+        cy: 2100.0,
+        skew: 0.0,
+        dist: [-0.15, 0.03, 0.0005, 0.0005, 0.0],
+    };
+    let intrinsics = params.to_intrinsics();
+    let ats_intrinsics = ats_common::ros_opencv_intrinsics_type_convert(&intrinsics);
+    let map = UndistortMap::build(&intrinsics, SENSOR_RESOLUTION, UNDISTORT_GRID);
+
+    let mut group = c.benchmark_group("undistort");
+    // A markers report carries up to 16 points per camera
+    for n in [16, 4096] {
+        let points = points(n);
+        group.bench_with_input(BenchmarkId::new("scalar", n), &points, |b, p| {
+            b.iter(|| ats_cv::undistort_points(&ats_intrinsics, black_box(p)))
+        });
+        group.bench_with_input(BenchmarkId::new("batch", n), &points, |b, p| {
+            b.iter(|| params.undistort_points(black_box(p)))
+        });
+        group.bench_with_input(BenchmarkId::new("map", n), &points, |b, p| {
+            b.iter(|| map.undistort_points(black_box(p)))
+        });
+    }
+    group.finish();
+
+    c.bench_function("undistort_map_build", |b| {
+        b.iter(|| UndistortMap::build(&intrinsics, SENSOR_RESOLUTION, UNDISTORT_GRID))
+    });
+}
+
+criterion_group!(benches, undistort);
+criterion_main!(benches);
@@ -0,0 +1,246 @@
+use nalgebra::{Matrix3, Point2, RowVector3, Vector3};
+
+/// Smooths the aimpoint between raycast updates.
+///
+/// Filters trade latency for smoothness; [`AimpointFilterKind`] selects one at runtime.
+pub trait AimpointFilter: Send {
+    /// Feeds a raw aimpoint measured `dt` seconds after the previous one and returns the
+    /// filtered aimpoint.
+    fn update(&mut self, p: Point2<f32>, dt: f32) -> Point2<f32>;
+
+    /// Forgets all state, the next update passes its measurement through unchanged.
+    fn reset(&mut self);
+}
+
+/// First order low-pass filter with time constant `time_constant` seconds.
+#[derive(Clone, Debug)]
+pub struct ExponentialFilter {
+    /// Time constant (seconds), larger is smoother but lags more
+    pub time_constant: f32,
+    state: Option<Point2<f32>>,
+}
+
+impl Default for ExponentialFilter {
+    fn default() -> Self {
+        Self {
+            time_constant: 0.03,
+            state: None,
+        }
+    }
+}
+
+impl ExponentialFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AimpointFilter for ExponentialFilter {
+    fn update(&mut self, p: Point2<f32>, dt: f32) -> Point2<f32> {
+        let state = match self.state {
+            Some(prev) if self.time_constant > 0.0 => {
+                let alpha = 1.0 - (-dt.max(0.0) / self.time_constant).exp();
+                prev + (p - prev) * alpha
+            }
+            _ => p,
+        };
+        self.state = Some(state);
+        state
+    }
+
+    fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+/// Constant acceleration Kalman filter run independently on each axis.
+///
+/// The state of each axis is position, velocity and acceleration, driven by white jerk noise.
+#[derive(Clone, Debug)]
+pub struct Pva2dFilter {
+    /// Spectral density of the jerk noise, larger follows fast motion more closely
+    pub process_noise: f32,
+    /// Variance of the measured aimpoint in normalized screen units
+    pub measurement_noise: f32,
+    axes: Option<[PvaAxis; 2]>,
+}
+
+#[derive(Clone, Debug)]
+struct PvaAxis {
+    x: Vector3<f32>,
+    p: Matrix3<f32>,
+}
+
+impl Default for Pva2dFilter {
+    fn default() -> Self {
+        Self {
+            process_noise: 50.0,
+            measurement_noise: 1e-5,
+            axes: None,
+        }
+    }
+}
+
+impl Pva2dFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PvaAxis {
+    fn new(z: f32, measurement_noise: f32) -> Self {
+        Self {
+            x: Vector3::new(z, 0.0, 0.0),
+            p: Matrix3::from_diagonal(&Vector3::new(measurement_noise, 1.0, 1.0)),
+        }
+    }
+
+    fn update(&mut self, z: f32, dt: f32, q: f32, r: f32) -> f32 {
+        let dt2 = dt * dt;
+        let dt3 = dt2 * dt;
+        let f = Matrix3::new(1.0, dt, dt2 / 2.0, 0.0, 1.0, dt, 0.0, 0.0, 1.0);
+        let process = Matrix3::new(
+            dt3 * dt2 / 20.0,
+            dt2 * dt2 / 8.0,
+            dt3 / 6.0,
+            dt2 * dt2 / 8.0,
+            dt3 / 3.0,
+            dt2 / 2.0,
+            dt3 / 6.0,
+            dt2 / 2.0,
+            dt,
+        ) * q;
+
+        // Predict
+        self.x = f * self.x;
+        self.p = f * self.p * f.transpose() + process;
+
+        // Correct
+        let h = RowVector3::new(1.0, 0.0, 0.0);
+        let s = self.p[(0, 0)] + r;
+        let k = self.p.column(0) / s;
+        self.x += k * (z - self.x[0]);
+        self.p = (Matrix3::identity() - k * h) * self.p;
+        self.x[0]
+    }
+}
+
+impl AimpointFilter for Pva2dFilter {
+    fn update(&mut self, p: Point2<f32>, dt: f32) -> Point2<f32> {
+        let (q, r) = (self.process_noise, self.measurement_noise);
+        match &mut self.axes {
+            Some([x, y]) => {
+                let dt = dt.max(0.0);
+                Point2::new(x.update(p.x, dt, q, r), y.update(p.y, dt, q, r))
+            }
+            None => {
+                self.axes = Some([PvaAxis::new(p.x, r), PvaAxis::new(p.y, r)]);
+                p
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.axes = None;
+    }
+}
+
+/// Selects which [`AimpointFilter`] the runner applies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AimpointFilterKind {
+    /// Raw raycast aimpoint, lowest latency
+    #[default]
+    None,
+    Exponential,
+    Pva2d,
+}
+
+impl AimpointFilterKind {
+    /// In the order shown in the UI.
+    pub const ALL: [AimpointFilterKind; 3] = [Self::None, Self::Exponential, Self::Pva2d];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Exponential => "Exponential",
+            Self::Pva2d => "PVA Kalman",
+        }
+    }
+
+    pub fn build(self) -> Option<Box<dyn AimpointFilter>> {
+        match self {
+            Self::None => None,
+            Self::Exponential => Some(Box::new(ExponentialFilter::new())),
+            Self::Pva2d => Some(Box::new(Pva2dFilter::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;
+
+    /// Output of `filter` for a unit step on x, one sample every `DT` seconds.
+    fn step_response(filter: &mut dyn AimpointFilter, samples: usize) -> Vec<f32> {
+        filter.update(Point2::new(0.0, 0.0), DT);
+        (0..samples)
+            .map(|_| filter.update(Point2::new(1.0, 0.0), DT).x)
+            .collect()
+    }
+
+    #[test]
+    fn first_update_passes_through() {
+        for kind in AimpointFilterKind::ALL {
+            let Some(mut filter) = kind.build() else {
+                continue;
+            };
+            let p = Point2::new(0.25, 0.75);
+            assert_eq!(filter.update(p, DT), p, "{}", kind.name());
+            filter.update(Point2::new(0.5, 0.5), DT);
+            filter.reset();
+            assert_eq!(filter.update(p, DT), p, "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn exponential_step_response() {
+        let mut filter = ExponentialFilter::new();
+        let response = step_response(&mut filter, 100);
+        // One sample after the step
+        let expected = 1.0 - (-DT / filter.time_constant).exp();
+        assert!((response[0] - expected).abs() < 1e-6);
+        assert!(response.windows(2).all(|w| w[0] <= w[1] && w[1] <= 1.0));
+        assert!((response[99] - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn pva_step_response() {
+        let response = step_response(&mut Pva2dFilter::new(), 100);
+        assert!(response[0] > 0.0 && response[0] < 1.0);
+        assert!((response[99] - 1.0).abs() < 1e-2);
+        // The y axis is filtered separately and stays put
+        let mut filter = Pva2dFilter::new();
+        filter.update(Point2::new(0.0, 0.5), DT);
+        assert_eq!(filter.update(Point2::new(1.0, 0.5), DT).y, 0.5);
+    }
+
+    #[test]
+    fn pva_lags_less_than_exponential() {
+        let exponential = step_response(&mut ExponentialFilter::new(), 100);
+        let pva = step_response(&mut Pva2dFilter::new(), 100);
+        // Samples until each filter first gets within 10% of the step
+        let rise = |r: &[f32]| r.iter().position(|&x| x >= 0.9).unwrap();
+        assert!(rise(&pva) <= rise(&exponential));
+    }
+
+    #[test]
+    fn exponential_without_time_constant_is_raw() {
+        let mut filter = ExponentialFilter {
+            time_constant: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(step_response(&mut filter, 3), [1.0, 1.0, 1.0]);
+    }
+}
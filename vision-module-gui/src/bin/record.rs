@@ -123,19 +123,14 @@ async fn main() -> ExitCode {
             }
         }
     }
-    let mut bytes = vec![];
-    postcard::to_slice(&general_config, &mut bytes).unwrap();
-    for (timestamp, packet_data) in packets.iter() {
-        let packet = ats_usb::packets::vm::Packet {
-            data: packet_data.clone(),
-            id: 0,
-        };
-        bytes.extend_from_slice(&timestamp.to_le_bytes());
-        postcard::to_slice(&packet, &mut bytes).unwrap();
-    }
+    let packets: Vec<_> = packets
+        .into_iter()
+        .map(|(timestamp, data)| (timestamp, ats_usb::packets::vm::Packet { data, id: 0 }))
+        .collect();
 
     eprintln!("Saving to {output_path}");
-    std::fs::write(output_path, &bytes).expect("Failed to write output");
+    ats_playback::write_file(&output_path.into(), &general_config, &packets)
+        .expect("Failed to write output");
 
     ExitCode::SUCCESS
 }
\ No newline at end of file
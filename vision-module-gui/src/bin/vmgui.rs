@@ -10,15 +10,22 @@ use iui::controls::{Area, FileTypeFilter, HorizontalBox};
 use iui::prelude::*;
 use leptos_reactive::{
     create_effect, RwSignal, SignalGet, SignalGetUntracked, SignalSet, SignalWith,
+    SignalWithUntracked,
 };
 use parking_lot::Mutex;
 use tokio::task::AbortHandle;
 use tracing::{error, info, warn, Level};
 use tracing_subscriber::EnvFilter;
+use vision_module_gui::aimpoint_filter::AimpointFilterKind;
+use vision_module_gui::export_image::{self, EXPORT_SIZE};
+use vision_module_gui::marker_pattern::MarkerPattern;
 use vision_module_gui::mot_runner::MotRunner;
+use vision_module_gui::prefs::{Prefs, WindowGeometry};
 use vision_module_gui::run_canvas::RunCanvas;
 use vision_module_gui::run_raw_canvas::RunRawCanvas;
+use vision_module_gui::shortcuts::Shortcut;
 use vision_module_gui::test_canvas::TestCanvas;
+use vision_module_gui::theme::Theme;
 use vision_module_gui::{config_window, plots_window, TestFrame};
 use vision_module_gui::{CloneButShorter, MotState};
 #[cfg(feature = "bevy")]
@@ -202,6 +209,16 @@ fn get_screens_dir() -> Option<PathBuf> {
     Some(app_cfg_root)
 }
 
+fn get_prefs_path() -> Option<PathBuf> {
+    match get_app_root(AppDataType::UserConfig, &APP_INFO) {
+        Ok(d) => Some(d.join("prefs.json")),
+        Err(e) => {
+            warn!("Failed to load app config: {e}");
+            None
+        }
+    }
+}
+
 pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -232,6 +249,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     let datapoints: Arc<Mutex<Vec<TestFrame>>> = Arc::new(Mutex::new(Vec::new()));
     let packets = Arc::new(Mutex::new(Vec::new()));
+    let fv_snapshots = Arc::new(Mutex::new(Vec::new()));
     let state = MotState::default();
     let ui_update: RwSignal<()> = leptos_reactive::create_rw_signal(());
 
@@ -264,6 +282,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let tracking_raw = RwSignal::new(false);
     let tracking = RwSignal::new(false);
+    let raw_undistort = RwSignal::new(false);
     let testing = RwSignal::new(false);
     let recording = RwSignal::new(false);
 
@@ -271,22 +290,66 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
         state,
         device: None,
         record_impact: false,
+        test_ready: false,
+        software_impact: false,
+        log_raw_aimpoint: false,
         record_packets: false,
         datapoints: datapoints.c(),
         packets: packets.c(),
+        fv_snapshots: fv_snapshots.c(),
         ui_update: ui_update.c(),
-        ui_ctx,
+        ui_ctx: Some(ui_ctx),
         general_config: GeneralSettings::default(),
         wfnf_realign: true,
+        show_screen_outline: false,
+        marker_pattern: Default::default(),
         screen_calibrations,
+        event_tx: tokio::sync::broadcast::channel(64).0,
     }));
 
+    let connection_status = config_window::ConnectionStatus::new();
+
     // Create a main_window into which controls can be placed
     let mut main_win =
         iui::prelude::Window::new(&ui, "ATS Vision Tool", 640, 480, WindowType::NoMenubar);
-    let (mut config_win, device_rs, accel_config_signal) =
-        config_window::config_window(&ui, simulator_addr, udp_addr, mot_runner.c(), tokio_handle);
-    let mut plots_window = plots_window::plots_window(&ui);
+    let (mut config_win, device_rs, accel_config_signal) = config_window::config_window(
+        &ui,
+        simulator_addr,
+        udp_addr,
+        mot_runner.c(),
+        connection_status,
+    );
+
+    // Restored now, saved when the app quits
+    let prefs_path = get_prefs_path();
+    let mut prefs = prefs_path.as_deref().map(Prefs::load).unwrap_or_default();
+    let theme = RwSignal::new(prefs.theme);
+    {
+        let mut runner = mot_runner.lock();
+        runner
+            .state
+            .fv_aimpoint_history
+            .set_capacity(prefs.aimpoint_history_len());
+        runner.marker_pattern = prefs.marker_pattern;
+        runner.state.impact_detector.threshold = prefs.software_impact_threshold();
+    }
+
+    let mut plots_window = plots_window::plots_window(&ui, prefs.redraw_interval(16));
+    let saved_windows = [
+        ("main", main_win.c()),
+        ("config", config_win.c()),
+        ("plots", plots_window.c()),
+    ];
+    for (name, window) in &saved_windows {
+        if let Some(geometry) = prefs.window(name) {
+            let mut window = window.c();
+            geometry.apply(&ui, &mut window);
+            // The main window is shown below in any case
+            if geometry.open && *name != "main" {
+                window.show(&ui);
+            }
+        }
+    }
 
     let mut test_win =
         iui::prelude::Window::new(&ui, "Aimpoint Test", 640, 480, WindowType::NoMenubar);
@@ -306,12 +369,31 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
             runner: mot_runner.c(),
             last_draw_width: None,
             last_draw_height: None,
+            theme,
         }),
     );
     let mut test_hbox = HorizontalBox::new(&ui);
     test_hbox.append(&ui, test_area.c(), LayoutStrategy::Stretchy);
     test_win.set_child(&ui, test_hbox);
 
+    // Keyboard equivalents of the buttons, the device-only ones do nothing while they're disabled
+    let on_shortcut = {
+        let ui = ui.c();
+        let config_win = config_win.c();
+        move |shortcut: Shortcut| {
+            let connected = device_rs.with_untracked(Option::is_some);
+            match shortcut {
+                Shortcut::Config => config_win.c().show(&ui),
+                Shortcut::Tracking if connected => tracking.set(!tracking.get_untracked()),
+                Shortcut::RawTracking if connected => {
+                    tracking_raw.set(!tracking_raw.get_untracked())
+                }
+                Shortcut::RunTest if connected => testing.set(true),
+                _ => {}
+            }
+        }
+    };
+
     vision_module_gui::layout! { &ui,
         let vert_box = VerticalBox(padded: true) {
             Compact: let grid = LayoutGrid(padded: true) {
@@ -333,6 +415,10 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                 })
                 (1, 1)(1, 1) Vertical (Fill, Fill) : let clear_packets_button = Button("Clear")
                 (2, 1)(1, 1) Vertical (Fill, Fill) : let save_packets_button = Button("Save")
+                (3, 1)(1, 1) Vertical (Fill, Fill) : let theme_combobox = Combobox() {}
+                (4, 1)(1, 1) Vertical (Fill, Fill) : let export_image_button = Button("Export Image")
+                (5, 1)(1, 1) Vertical (Fill, Fill) : let screen_outline_checkbox = Checkbox("Screen outline", checked: false)
+                (6, 1)(1, 1) Vertical (Fill, Fill) : let raw_undistort_checkbox = Checkbox("Undistort raw", checked: false)
             }
             Compact: let separator = HorizontalSeparator()
             Compact: let spacer = Spacer()
@@ -344,8 +430,12 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                         Compact: let remove_datapoint_btn = Button("Remove datapoint")
                         Compact: let clear_datapoints_btn = Button("Clear datapoints")
                         Compact: let record_impacts_cbx = Checkbox("Record impacts", checked: false)
+                        Compact: let software_impacts_cbx = Checkbox("Software impacts", checked: false)
+                        Compact: let log_raw_aimpoint_cbx = Checkbox("Log raw aimpoint", checked: false)
                         Compact: let save_datapoints_btn = Button("Save to file")
                     }
+                    (Compact, "Aimpoint filter"): let aimpoint_filter_combobox = Combobox() {}
+                    (Compact, "Marker pattern"): let marker_pattern_combobox = Combobox() {}
                 }
                 Compact: let separator = HorizontalSeparator()
             }
@@ -353,14 +443,23 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Stretchy: let run_raw_area = Area(Box::new(RunRawCanvas {
                     ctx: ui.c(),
                     runner: mot_runner.c(),
+                    on_shortcut: Box::new(on_shortcut.clone()),
+                    theme,
+                    undistort: raw_undistort,
+                    hover: Default::default(),
                 }))
             }
             Stretchy: let run_hbox = HorizontalBox() {
                 Stretchy: let run_area = Area(Box::new(RunCanvas {
                     ctx: ui.c(),
                     runner: mot_runner.c(),
+                    on_shortcut: Box::new(on_shortcut.clone()),
+                    theme,
+                    hover: Default::default(),
                 }))
             }
+            Compact: let status_separator = HorizontalSeparator()
+            Compact: let status_label = Label("")
         }
     }
     form_vbox.hide(&ui);
@@ -443,6 +542,27 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    // Status bar
+    create_effect({
+        let ui = ui.c();
+        let status_label = status_label.c();
+        move |_| {
+            let device = if device_rs.with(Option::is_some) {
+                match connection_status.device_name.get() {
+                    Some(name) => format!("Connected to {name}"),
+                    None => String::from("Connected"),
+                }
+            } else {
+                String::from("Disconnected")
+            };
+            let text = match connection_status.last_error.get() {
+                Some(error) => format!("{device}  |  {error}"),
+                None => device,
+            };
+            status_label.c().set_text(&ui, &text);
+        }
+    });
+
     // Disable buttons if no device is connected
     create_effect({
         let ui = ui.c();
@@ -522,7 +642,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
             let view = view.c();
             let new_device = device_rs.get();
             eprintln!(">>> Device changed: {}", if new_device.is_some() { "Some(device)" } else { "None" });
-            view.lock().device = new_device;
+            view.lock().set_device(new_device);
         }
     });
 
@@ -539,6 +659,16 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    // Hold off recording impacts until the orientation has converged after starting a test
+    create_effect({
+        let mot_runner = mot_runner.c();
+        move |_| {
+            if testing.get() {
+                mot_runner.lock().start_test();
+            }
+        }
+    });
+
     // Show the test window and form when running test or when calibrating marker offsets
     create_effect({
         let ui = ui.c();
@@ -598,6 +728,8 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                 position_x: None,
                 position_y: None,
                 position_z: None,
+                raw_aimpoint_x: None,
+                raw_aimpoint_y: None,
             };
 
             let runner = state.lock();
@@ -607,6 +739,10 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let fv_aimpoint = state.fv_aimpoint;
                 frame.fv_aimpoint_x = Some(fv_aimpoint.x);
                 frame.fv_aimpoint_y = Some(fv_aimpoint.y);
+                if runner.log_raw_aimpoint {
+                    frame.raw_aimpoint_x = Some(state.fv_aimpoint_raw.x);
+                    frame.raw_aimpoint_y = Some(state.fv_aimpoint_raw.y);
+                }
 
                 let gravity_vec = runner
                     .state
@@ -659,6 +795,84 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    software_impacts_cbx.on_toggled(&ui, {
+        let mot_runner = mot_runner.c();
+        move |checked| {
+            mot_runner.lock().software_impact = checked;
+        }
+    });
+
+    log_raw_aimpoint_cbx.on_toggled(&ui, {
+        let mot_runner = mot_runner.c();
+        move |checked| {
+            mot_runner.lock().log_raw_aimpoint = checked;
+        }
+    });
+
+    screen_outline_checkbox.on_toggled(&ui, {
+        let mot_runner = mot_runner.c();
+        move |checked| {
+            mot_runner.lock().show_screen_outline = checked;
+        }
+    });
+
+    raw_undistort_checkbox.on_toggled(&ui, {
+        let ui = ui.c();
+        let run_raw_area = run_raw_area.c();
+        move |checked| {
+            raw_undistort.set(checked);
+            run_raw_area.queue_redraw_all(&ui);
+        }
+    });
+
+    for kind in AimpointFilterKind::ALL {
+        aimpoint_filter_combobox.append(&ui, kind.name());
+    }
+    aimpoint_filter_combobox.set_selected(&ui, 0);
+    aimpoint_filter_combobox.on_selected(&ui, {
+        let mot_runner = mot_runner.c();
+        move |i| {
+            if let Some(&kind) = AimpointFilterKind::ALL.get(i as usize) {
+                mot_runner.lock().state.set_aimpoint_filter(kind);
+            }
+        }
+    });
+
+    for pattern in MarkerPattern::ALL {
+        marker_pattern_combobox.append(&ui, pattern.name());
+    }
+    let marker_pattern = mot_runner.lock().marker_pattern;
+    let selected_pattern = MarkerPattern::ALL.iter().position(|&p| p == marker_pattern);
+    marker_pattern_combobox.set_selected(&ui, selected_pattern.unwrap_or(0) as i32);
+    marker_pattern_combobox.on_selected(&ui, {
+        let mot_runner = mot_runner.c();
+        move |i| {
+            if let Some(&pattern) = MarkerPattern::ALL.get(i as usize) {
+                mot_runner.lock().marker_pattern = pattern;
+            }
+        }
+    });
+
+    for theme in Theme::ALL {
+        theme_combobox.append(&ui, theme.name());
+    }
+    let selected_theme = Theme::ALL.iter().position(|&t| t == theme.get_untracked());
+    theme_combobox.set_selected(&ui, selected_theme.unwrap_or(0) as i32);
+    theme_combobox.on_selected(&ui, {
+        let ui = ui.c();
+        let run_raw_area = run_raw_area.c();
+        let run_area = run_area.c();
+        let test_area = test_area.c();
+        move |i| {
+            if let Some(&selected) = Theme::ALL.get(i as usize) {
+                theme.set(selected);
+                run_raw_area.queue_redraw_all(&ui);
+                run_area.queue_redraw_all(&ui);
+                test_area.queue_redraw_all(&ui);
+            }
+        }
+    });
+
     save_datapoints_btn.on_clicked(&ui, {
         let ui = ui.c();
         let main_win = main_win.c();
@@ -719,8 +933,10 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     clear_packets_button.on_clicked(&ui, {
         let packets = packets.c();
+        let fv_snapshots = fv_snapshots.c();
         move |_| {
             packets.lock().clear();
+            fv_snapshots.lock().clear();
         }
     });
 
@@ -728,6 +944,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
         let ui = ui.c();
         let main_win = main_win.c();
         let packets = packets.c();
+        let fv_snapshots = fv_snapshots.c();
         let mot_runner = mot_runner.c();
         move |_| {
             let packets = packets.lock();
@@ -737,28 +954,80 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                 if path_buf.extension() != Some("bin".as_ref()) {
                     path_buf.as_mut_os_string().push(".bin");
                 }
-                let mut file = File::create(path_buf).expect("Could not create file");
-                let mut bytes = Vec::new();
-
-                postcard::to_slice(&mot_runner.lock().general_config, &mut bytes).unwrap();
-
-                for (timestamp, packet_data) in packets.iter() {
-                    let packet = ats_usb::packets::vm::Packet {
-                        data: packet_data.clone(),
-                        id: 0,
-                    };
-                    bytes.extend_from_slice(&timestamp.to_le_bytes());
-                    postcard::to_slice(&packet, &mut bytes).unwrap();
+                let packets: Vec<_> = packets
+                    .iter()
+                    .map(|(timestamp, data)| {
+                        let packet = ats_usb::packets::vm::Packet {
+                            data: data.clone(),
+                            id: 0,
+                        };
+                        (*timestamp, packet)
+                    })
+                    .collect();
+                let general_config = mot_runner.lock().general_config.clone();
+                ats_playback::write_file(&path_buf, &general_config, &packets)
+                    .expect("Could not write to file");
+
+                // Filter state snapshots go next to the recording, one json object per line
+                let fv_snapshots = fv_snapshots.lock();
+                if !fv_snapshots.is_empty() {
+                    let snapshot_path = path_buf.with_extension("fv.ndjson");
+                    let mut writer = std::io::BufWriter::new(
+                        File::create(snapshot_path).expect("Could not create file"),
+                    );
+                    for snapshot in fv_snapshots.iter() {
+                        snapshot
+                            .write_line(&mut writer)
+                            .expect("Could not write to file");
+                    }
                 }
+            }
+        }
+    });
 
-                file.write_all(&bytes).expect("Could not write to file");
+    export_image_button.on_clicked(&ui, {
+        let ui = ui.c();
+        let main_win = main_win.c();
+        let mot_runner = mot_runner.c();
+        move |_| {
+            if mot_runner.lock().device.is_none() {
+                main_win.modal_err(&ui, "Export failed", "No device connected");
+                return;
+            }
+            let raw = match (tracking_raw.get_untracked(), tracking.get_untracked()) {
+                (true, _) => true,
+                (_, true) => false,
+                _ => {
+                    main_win.modal_err(&ui, "Export failed", "Start tracking to export the canvas");
+                    return;
+                }
+            };
+            // Render before the dialog so the image shows the moment the button was clicked
+            let palette = theme.get_untracked().palette();
+            let image = export_image::render_tracking(
+                &mot_runner.lock(),
+                raw,
+                palette,
+                EXPORT_SIZE,
+                EXPORT_SIZE,
+            );
+            let path_buf =
+                main_win.save_file_with_filter(&ui, &[FileTypeFilter::new("png").extension("png")]);
+            if let Some(mut path_buf) = path_buf {
+                if path_buf.extension() != Some("png".as_ref()) {
+                    path_buf.as_mut_os_string().push(".png");
+                }
+                if let Err(e) = image.write_png(&path_buf) {
+                    main_win.modal_err(&ui, "Export failed", &e.to_string());
+                }
             }
         }
     });
 
     main_win.show(&ui);
 
-    ui.ui_timer(5, {
+    // Only the redraws are throttled, the runner keeps processing packets as they arrive
+    ui.ui_timer(prefs.redraw_interval(5), {
         let ui = ui.c();
         let run_raw_area = run_raw_area.c();
         let run_area = run_area.c();
@@ -781,6 +1050,17 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut ev = ui.event_loop();
     ev.run(&ui);
 
+    if let Some(path) = &prefs_path {
+        for (name, window) in &saved_windows {
+            prefs.set_window(name, WindowGeometry::capture(&ui, window));
+        }
+        prefs.theme = theme.get_untracked();
+        prefs.marker_pattern = mot_runner.lock().marker_pattern;
+        if let Err(e) = prefs.save(path) {
+            warn!("Failed to save prefs: {e}");
+        }
+    }
+
     leptos_rt.dispose();
     Ok(())
 }
@@ -0,0 +1,365 @@
+use nalgebra::{Matrix3, Point2, Vector2, Vector5};
+use opencv_ros_camera::{Distortion, RosOpenCvIntrinsics};
+
+/// Upper bound on the rounds of [`CameraParams::undistort_points`]
+pub const UNDISTORT_MAX_ITERATIONS: usize = 20;
+
+/// Update size in normalized image coordinates below which a point counts as undistorted
+pub const UNDISTORT_TOLERANCE: f32 = 1e-6;
+
+/// Cells (columns, rows) of the [`UndistortMap`]s the runner builds
+pub const UNDISTORT_GRID: [usize; 2] = [64, 64];
+
+/// Plain pinhole + OpenCV distortion parameters of a camera model.
+///
+/// `RosOpenCvIntrinsics` keeps these spread across its projection matrix and distortion struct;
+/// this is the flattened form for display, verification and round-tripping.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CameraParams {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    pub skew: f32,
+    /// OpenCV order: k1, k2, p1, p2, k3
+    pub dist: [f32; 5],
+}
+
+impl CameraParams {
+    pub fn from_intrinsics(intrinsics: &RosOpenCvIntrinsics<f32>) -> Self {
+        let k = &intrinsics.k;
+        let dist = intrinsics.distortion.opencv_vec();
+        Self {
+            fx: k.m11,
+            fy: k.m22,
+            cx: k.m13,
+            cy: k.m23,
+            skew: k.m12,
+            dist: [dist[0], dist[1], dist[2], dist[3], dist[4]],
+        }
+    }
+
+    pub fn to_intrinsics(&self) -> RosOpenCvIntrinsics<f32> {
+        RosOpenCvIntrinsics::from_params_with_distortion(
+            self.fx,
+            self.skew,
+            self.fy,
+            self.cx,
+            self.cy,
+            Distortion::from_opencv_vec(self.dist_coeffs()),
+        )
+    }
+
+    /// The 3x3 camera matrix `K`.
+    pub fn camera_matrix(&self) -> Matrix3<f32> {
+        Matrix3::new(
+            self.fx, self.skew, self.cx, 0.0, self.fy, self.cy, 0.0, 0.0, 1.0,
+        )
+    }
+
+    pub fn dist_coeffs(&self) -> Vector5<f32> {
+        Vector5::from(self.dist)
+    }
+
+    /// Undistort pixel coordinates in one batch.
+    ///
+    /// Runs the same fixed point iteration as OpenCV's `undistortPoints` on every point together,
+    /// dropping each point from the batch once its update is below [`UNDISTORT_TOLERANCE`]
+    /// normalized units, for at most [`UNDISTORT_MAX_ITERATIONS`] rounds. The result is projected
+    /// back with [`camera_matrix`](Self::camera_matrix), which matches the intrinsics' projection
+    /// matrix for models built by [`to_intrinsics`](Self::to_intrinsics).
+    pub fn undistort_points(&self, points: &[Point2<f32>]) -> Vec<Point2<f32>> {
+        let [k1, k2, p1, p2, k3] = self.dist;
+        let distorted: Vec<Vector2<f32>> = points
+            .iter()
+            .map(|p| {
+                let y = (p.y - self.cy) / self.fy;
+                Vector2::new((p.x - self.cx - self.skew * y) / self.fx, y)
+            })
+            .collect();
+        let mut undistorted = distorted.clone();
+        let mut active: Vec<usize> = (0..points.len()).collect();
+        for _ in 0..UNDISTORT_MAX_ITERATIONS {
+            active.retain(|&i| {
+                let (d, u) = (distorted[i], undistorted[i]);
+                let r2 = u.norm_squared();
+                let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                let tangential = Vector2::new(
+                    2.0 * p1 * u.x * u.y + p2 * (r2 + 2.0 * u.x * u.x),
+                    p1 * (r2 + 2.0 * u.y * u.y) + 2.0 * p2 * u.x * u.y,
+                );
+                let next = (d - tangential) / radial;
+                undistorted[i] = next;
+                (next - u).norm() > UNDISTORT_TOLERANCE
+            });
+            if active.is_empty() {
+                break;
+            }
+        }
+        undistorted
+            .into_iter()
+            .map(|u| {
+                Point2::new(
+                    self.fx * u.x + self.skew * u.y + self.cx,
+                    self.fy * u.y + self.cy,
+                )
+            })
+            .collect()
+    }
+}
+
+impl From<&RosOpenCvIntrinsics<f32>> for CameraParams {
+    fn from(intrinsics: &RosOpenCvIntrinsics<f32>) -> Self {
+        Self::from_intrinsics(intrinsics)
+    }
+}
+
+impl From<CameraParams> for RosOpenCvIntrinsics<f32> {
+    fn from(params: CameraParams) -> Self {
+        params.to_intrinsics()
+    }
+}
+
+/// Precomputed undistortion over the whole sensor, for hot loops with fixed intrinsics.
+///
+/// The exact undistortion is iterative. The map runs it once per grid node and interpolates
+/// bilinearly in between, trading a little accuracy where the distortion changes quickly for
+/// speed. Rebuild it when the intrinsics change.
+#[derive(Clone, Debug)]
+pub struct UndistortMap {
+    /// Number of nodes per row
+    cols: usize,
+    /// Pixels between neighbouring nodes
+    step: Vector2<f32>,
+    /// Undistorted position of each node, row major
+    nodes: Vec<Point2<f32>>,
+}
+
+impl UndistortMap {
+    /// Sample a `grid` of (columns, rows) cells spanning a sensor reporting `sensor_res`
+    /// (width, height) pixels, in the same pixel units as `intrinsics`. The nodes are undistorted
+    /// in one batch with [`CameraParams::undistort_points`].
+    pub fn build(
+        intrinsics: &RosOpenCvIntrinsics<f32>,
+        sensor_res: [u16; 2],
+        grid: [usize; 2],
+    ) -> Self {
+        let (cols, rows) = (grid[0].max(1) + 1, grid[1].max(1) + 1);
+        let span = |len: u16| f32::from(len.saturating_sub(1).max(1));
+        let step = Vector2::new(
+            span(sensor_res[0]) / (cols - 1) as f32,
+            span(sensor_res[1]) / (rows - 1) as f32,
+        );
+        let grid_points: Vec<_> = (0..rows)
+            .flat_map(|r| (0..cols).map(move |c| (c, r)))
+            .map(|(c, r)| Point2::new(c as f32 * step.x, r as f32 * step.y))
+            .collect();
+        let nodes = CameraParams::from_intrinsics(intrinsics).undistort_points(&grid_points);
+        Self { cols, step, nodes }
+    }
+
+    /// Approximate undistortion of a single pixel. Points outside the sensor are extrapolated from
+    /// the nearest cell.
+    pub fn undistort(&self, p: Point2<f32>) -> Point2<f32> {
+        let rows = self.nodes.len() / self.cols;
+        let g = p.coords.component_div(&self.step);
+        let cell = |v: f32, n: usize| (v.floor().max(0.0) as usize).min(n - 2);
+        let (c, r) = (cell(g.x, self.cols), cell(g.y, rows));
+        let (tx, ty) = (g.x - c as f32, g.y - r as f32);
+        let node = |c: usize, r: usize| self.nodes[r * self.cols + c].coords;
+        let top = node(c, r).lerp(&node(c + 1, r), tx);
+        let bottom = node(c, r + 1).lerp(&node(c + 1, r + 1), tx);
+        top.lerp(&bottom, ty).into()
+    }
+
+    /// [`undistort`](Self::undistort) every point, a drop-in for `ats_cv::undistort_points`.
+    pub fn undistort_points(&self, points: &[Point2<f32>]) -> Vec<Point2<f32>> {
+        points.iter().map(|&p| self.undistort(p)).collect()
+    }
+}
+
+/// An [`UndistortMap`] kept in sync with the intrinsics and resolution it is used with.
+#[derive(Clone, Debug, Default)]
+pub struct UndistortCache {
+    map: Option<(CameraParams, [u16; 2], UndistortMap)>,
+}
+
+impl UndistortCache {
+    /// The map for `intrinsics` at `sensor_res`, built with [`UNDISTORT_GRID`] on first use and
+    /// rebuilt whenever the camera parameters or the resolution change.
+    pub fn get(
+        &mut self,
+        intrinsics: &RosOpenCvIntrinsics<f32>,
+        sensor_res: [u16; 2],
+    ) -> &UndistortMap {
+        let params = CameraParams::from_intrinsics(intrinsics);
+        let stale = !matches!(&self.map, Some((p, r, _)) if *p == params && *r == sensor_res);
+        if stale {
+            let map = UndistortMap::build(intrinsics, sensor_res, UNDISTORT_GRID);
+            self.map = Some((params, sensor_res, map));
+        }
+        &self.map.as_ref().unwrap().2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ats_usb::packets::vm::SENSOR_RESOLUTION;
+
+    /// Roughly the nearfield and widefield lenses, in [`SENSOR_RESOLUTION`] pixels
+    fn cameras() -> [CameraParams; 2] {
+        [
+            CameraParams {
+                fx: 5900.0,
+                fy: 5900.0,
+                cx: 2048.0,
+                cy: 2048.0,
+                skew: 0.0,
+                dist: [-0.1, 0.05, 0.001, -0.001, 0.0],
+            },
+            CameraParams {
+                fx: 2500.0,
+                fy: 2500.0,
+                cx: 2000.0,
+                cy: 2100.0,
+                skew: 0.0,
+                dist: [-0.15, 0.03, 0.0005, 0.0005, 0.0],
+            },
+        ]
+    }
+
+    fn sensor_points() -> Vec<Point2<f32>> {
+        let [w, h] = SENSOR_RESOLUTION.map(f32::from);
+        (0..=40)
+            .flat_map(|r| (0..=40).map(move |c| (c, r)))
+            .map(|(c, r)| Point2::new(c as f32 / 40.0 * (w - 1.0), r as f32 / 40.0 * (h - 1.0)))
+            .collect()
+    }
+
+    fn max_error(a: &[Point2<f32>], b: &[Point2<f32>]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).norm())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn params_round_trip_intrinsics() {
+        let dist = [-0.12, 0.04, 0.002, -0.001, 0.01];
+        let intrinsics = RosOpenCvIntrinsics::from_params_with_distortion(
+            2500.0,
+            1.5,
+            2490.0,
+            2000.0,
+            2100.0,
+            Distortion::from_opencv_vec(Vector5::from(dist)),
+        );
+        let params = CameraParams::from(&intrinsics);
+        assert_eq!(
+            params,
+            CameraParams {
+                fx: 2500.0,
+                fy: 2490.0,
+                cx: 2000.0,
+                cy: 2100.0,
+                skew: 1.5,
+                dist,
+            }
+        );
+        assert_eq!(
+            params.camera_matrix(),
+            intrinsics.k.fixed_view::<3, 3>(0, 0)
+        );
+        assert_eq!(
+            params.dist_coeffs(),
+            intrinsics.distortion.opencv_vec().clone_owned()
+        );
+
+        let back = RosOpenCvIntrinsics::from(params);
+        assert_eq!(back.k, intrinsics.k);
+        assert_eq!(
+            back.distortion.opencv_vec(),
+            intrinsics.distortion.opencv_vec()
+        );
+        assert_eq!(CameraParams::from_intrinsics(&back), params);
+    }
+
+    #[test]
+    fn batch_matches_scalar_undistortion() {
+        let points = sensor_points();
+        for params in cameras() {
+            let scalar = ats_cv::undistort_points(
+                &ats_common::ros_opencv_intrinsics_type_convert(&params.to_intrinsics()),
+                &points,
+            );
+            let error = max_error(&params.undistort_points(&points), &scalar);
+            assert!(error < 0.01, "{params:?}: {error} px");
+        }
+        assert!(cameras()[0].undistort_points(&[]).is_empty());
+    }
+
+    #[test]
+    fn batch_without_distortion_is_identity() {
+        let params = CameraParams {
+            dist: [0.0; 5],
+            skew: 3.0,
+            ..cameras()[0]
+        };
+        let points = sensor_points();
+        assert!(max_error(&params.undistort_points(&points), &points) < 1e-3);
+    }
+
+    #[test]
+    fn map_matches_exact_undistortion() {
+        let points = sensor_points();
+        for params in cameras() {
+            let intrinsics = params.to_intrinsics();
+            let exact = ats_cv::undistort_points(
+                &ats_common::ros_opencv_intrinsics_type_convert(&intrinsics),
+                &points,
+            );
+            let map = UndistortMap::build(&intrinsics, SENSOR_RESOLUTION, UNDISTORT_GRID);
+            let error = max_error(&map.undistort_points(&points), &exact);
+            // Half a pixel is about 0.01% of the sensor width
+            assert!(error < 0.5, "{params:?}: {error} px");
+        }
+    }
+
+    #[test]
+    fn map_is_exact_on_nodes() {
+        let params = cameras()[1];
+        let intrinsics = params.to_intrinsics();
+        let map = UndistortMap::build(&intrinsics, [65, 65], [8, 8]);
+        let nodes: Vec<_> = (0..=8)
+            .flat_map(|r| (0..=8).map(move |c| Point2::new(c as f32 * 8.0, r as f32 * 8.0)))
+            .collect();
+        let exact = ats_cv::undistort_points(
+            &ats_common::ros_opencv_intrinsics_type_convert(&intrinsics),
+            &nodes,
+        );
+        assert!(max_error(&map.undistort_points(&nodes), &exact) < 1e-3);
+    }
+
+    #[test]
+    fn cache_rebuilds_on_change() {
+        let [nf, wf] = cameras();
+        let mut cache = UndistortCache::default();
+        let p = Point2::new(100.0, 100.0);
+        let first = cache
+            .get(&nf.to_intrinsics(), SENSOR_RESOLUTION)
+            .undistort(p);
+        assert_eq!(
+            cache
+                .get(&nf.to_intrinsics(), SENSOR_RESOLUTION)
+                .undistort(p),
+            first
+        );
+        let other = cache
+            .get(&wf.to_intrinsics(), SENSOR_RESOLUTION)
+            .undistort(p);
+        assert!((other - first).norm() > 1.0);
+        cache.get(&wf.to_intrinsics(), [2048, 2048]);
+        assert_eq!(cache.map.as_ref().unwrap().1, [2048, 2048]);
+    }
+}
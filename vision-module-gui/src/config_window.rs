@@ -1,14 +1,16 @@
+mod calibration_file;
 mod pag_sensor_settings;
 mod paj_sensor_settings;
 
-use std::{sync::Arc, time::Duration};
+use std::{cell::RefCell, rc::Rc, sync::Arc, time::Duration};
 
 use crate::{mot_runner::MotRunner, CloneButShorter};
 use anyhow::Result;
 use ats_usb::{
     device::{MuxDevice, VmConnectionInfo, VmDevice},
-    packets::vm::{AccelConfig, GeneralConfig, GyroConfig, Port, PropKind},
+    packets::vm::{AccelConfig, AccelReportExt, GyroConfig, Port, PropKind},
 };
+use calibration_file::CalibrationKind;
 use iui::{
     controls::{Button, Form},
     prelude::{Window, WindowType},
@@ -16,19 +18,37 @@ use iui::{
 };
 use leptos_reactive::{
     create_effect, create_rw_signal, ReadSignal, RwSignal, SignalGet, SignalGetUntracked,
-    SignalSet, SignalWith, SignalWithUntracked,
+    SignalSet, SignalUpdate, SignalWith, SignalWithUntracked,
 };
-use nusb::MaybeFuture as _;
 use opencv_ros_camera::RosOpenCvIntrinsics;
 use parking_lot::Mutex;
 use protodongers::control::device::TransportMode;
+use tokio_stream::StreamExt;
+
+/// What the main window's status bar shows about the connection.
+#[derive(Clone, Copy)]
+pub struct ConnectionStatus {
+    /// Name of the device that was connected last, as listed in the device combobox
+    pub device_name: RwSignal<Option<String>>,
+    /// Most recent connection or settings error, cleared by a successful connect
+    pub last_error: RwSignal<Option<String>>,
+}
+
+impl ConnectionStatus {
+    pub fn new() -> Self {
+        Self {
+            device_name: create_rw_signal(None),
+            last_error: create_rw_signal(None),
+        }
+    }
+}
 
 pub fn config_window(
     ui: &UI,
     simulator_addr: Option<String>,
     udp_addr: Option<String>,
     mot_runner: Arc<Mutex<MotRunner>>,
-    tokio_handle: &tokio::runtime::Handle,
+    status: ConnectionStatus,
 ) -> (
     Window,
     ReadSignal<Option<VmDevice>>,
@@ -36,26 +56,44 @@ pub fn config_window(
 ) {
     let ui_ctx = ui.async_context();
     let mut config_win = Window::new(&ui, "Config", 10, 10, WindowType::NoMenubar);
-    let tokio_handle = tokio_handle.clone();
+
+    let device = create_rw_signal(None);
+    let connected = move || device.with(|d| d.is_some());
+    let ping_result = create_rw_signal(String::new());
+    let device_filter = create_rw_signal(String::new());
+    let auto_reconnect = create_rw_signal(false);
+    // The last device that was connected successfully, for auto-reconnect
+    let last_device = create_rw_signal(None::<DeviceKey>);
+    // Whether a selection's connect/load task is running
+    let loading = create_rw_signal(LoadTracker::default());
+    // Restores the settings from before the last "Load defaults"
+    let defaults_undo = Rc::new(RefCell::new(None::<Box<dyn FnOnce()>>));
+    let can_undo_defaults = create_rw_signal(false);
+    let clipboard = Rc::new(RefCell::new(None::<SettingsClipboard>));
+    let has_clipboard = create_rw_signal(false);
+    // Set after the first click on "Paste settings" showed what would change
+    let paste_armed = create_rw_signal(false);
+
+    let (general_form, general_settings) =
+        GeneralSettingsForm::new(&ui, device.read_only(), mot_runner, config_win.c());
 
     config_win.on_closing(&ui, {
         let ui = ui.c();
+        let live_imu = general_settings.live_imu;
         move |win: &mut Window| {
+            // Don't keep the device streaming for a panel nobody can see
+            live_imu.set(false);
             win.hide(&ui);
         }
     });
 
-    let device = create_rw_signal(None);
-    let connected = move || device.with(|d| d.is_some());
-
-    let (general_form, general_settings) =
-        GeneralSettingsForm::new(&ui, device.read_only(), mot_runner, config_win.c());
-
     crate::layout! { &ui,
         let vbox = VerticalBox(padded: true) {
             Compact : let device_hbox = HorizontalBox(padded: true) {
                 Stretchy : let device_combobox = Combobox() {}
+                Compact : let device_filter_entry = Entry(signal: device_filter)
                 Compact : let refresh_button = Button("Refresh")
+                Compact : let auto_reconnect_checkbox = Checkbox("Auto-reconnect")
             }
             Compact : let tab_group = TabGroup() {} // sensor settings go in here
             Compact : let buttons_hbox = HorizontalBox(padded: true) {
@@ -63,6 +101,11 @@ pub fn config_window(
                 Compact : let save_button = Button("Save", enabled: connected)
                 Compact : let reload_button = Button("Reload", enabled: connected)
                 Compact : let load_defaults_button = Button("Load defaults", enabled: connected)
+                Compact : let undo_defaults_button = Button("Undo defaults", enabled: move || connected() && can_undo_defaults.get())
+                Compact : let copy_settings_button = Button("Copy settings", enabled: connected)
+                Compact : let paste_settings_button = Button("Paste settings", enabled: move || connected() && has_clipboard.get())
+                Compact : let ping_button = Button("Ping", enabled: connected)
+                Compact : let _ping_label = Label(move || ping_result.get())
             }
         }
     }
@@ -117,30 +160,59 @@ pub fn config_window(
 
     let device_list = create_rw_signal(Vec::<VmConnectionInfo>::new());
     let selected_index = create_rw_signal::<Option<i32>>(None);
+    // What each combobox item refers to, the combobox only shows a filtered subset of devices
+    let combobox_entries = create_rw_signal(Vec::<DeviceEntry>::new());
+    // Dropping this cancels the connect/load task of the previous selection
+    let cancel_load = Rc::new(RefCell::new(None::<tokio::sync::oneshot::Sender<()>>));
     let device_combobox_on_selected = {
         let ui = ui.c();
         let config_win = config_win.c();
         let sim_addr = simulator_addr.c();
         let udp_addr = udp_addr.c();
         let general_settings = general_settings.c();
+        let defaults_undo = defaults_undo.c();
         move |i| {
+            // Stop a still running load so it can't overwrite the settings of the new selection
+            let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+            cancel_load.replace(Some(cancel_tx));
+            let mut generation = 0;
+            loading.update(|l| generation = l.start());
             selected_index.set(Some(i));
+            defaults_undo.borrow_mut().take();
+            can_undo_defaults.set(false);
+            paste_armed.set(false);
             general_settings.clear();
             wf_settings.clear();
             nf_settings.clear();
             pag_settings.clear();
-            let Ok(i) = usize::try_from(i) else { return };
-            let _device = device_list.with_untracked(|d| d.get(i).cloned());
-            let sim_addr = sim_addr.c();
-            let udp_addr = udp_addr.c();
+            let Some(entry) = usize::try_from(i)
+                .ok()
+                .and_then(|i| combobox_entries.with_untracked(|e| e.get(i).copied()))
+            else {
+                loading.update(|l| l.finish(generation));
+                return;
+            };
+            let _device = match entry {
+                DeviceEntry::Device(ix) => device_list.with_untracked(|d| d.get(ix).cloned()),
+                _ => None,
+            };
+            let key = _device.as_ref().map(DeviceKey::new);
+            let name = match (entry, &_device, &sim_addr, &udp_addr) {
+                (DeviceEntry::Simulator, _, Some(addr), _) => format!("Simulator @ {addr}"),
+                (DeviceEntry::M4Hub, _, _, Some(addr)) => format!("M4Hub @ {addr}"),
+                (_, Some(device), _, _) => display_for_vm_connection(device),
+                _ => String::from("unknown device"),
+            };
+            let sim_addr = sim_addr.c().filter(|_| entry == DeviceEntry::Simulator);
+            let udp_addr = udp_addr.c().filter(|_| entry == DeviceEntry::M4Hub);
             let general_settings = general_settings.c();
             let device_signal = device.c(); // Clone the signal for the async task
             let task = async move {
                 // Clean up old device if it exists, BEFORE connecting to new one
                 if let Some(old_device) = device_signal.get_untracked() {
                     eprintln!("Cleaning up previous device connection...");
-                    let _ = old_device.clear_all_streams().await;
-                    eprintln!("Previous device cleaned up (slot reservation prevents conflicts)");
+                    old_device.disconnect().await;
+                    eprintln!("Previous device disconnected");
                 }
 
                 // NOW set device to None after cleanup
@@ -150,11 +222,14 @@ pub fn config_window(
                     eprintln!("Attempting to connect to device...");
                     _device.connect().await
                 } else if let Some(sim_addr) = sim_addr.as_ref() {
-                    unimplemented!();
-                    // Ok(VmDevice::connect_tcp(sim_addr)?)
+                    VmDevice::connect_tcp(sim_addr.as_str()).await
+                } else if let Some(udp_addr) = udp_addr.as_ref() {
+                    match udp_addr.parse() {
+                        Ok(addr) => VmDevice::connect_udp(addr).await,
+                        Err(e) => Err(anyhow::anyhow!("invalid udp address {udp_addr}: {e}")),
+                    }
                 } else {
-                    unimplemented!();
-                    // Ok(VmDevice::connect_hub("0.0.0.0:0", udp_addr.as_ref().unwrap()).await?)
+                    unreachable!();
                 };
                 match usb_device {
                     Ok(usb_device) => {
@@ -199,6 +274,11 @@ pub fn config_window(
                         }
                         eprintln!("All settings loaded, setting device...");
                         device.set(Some(usb_device));
+                        status.device_name.set(Some(name));
+                        status.last_error.set(None);
+                        if key.is_some() {
+                            last_device.set(key);
+                        }
                         eprintln!("Device set successfully");
                         Result::<()>::Ok(())
                     }
@@ -212,7 +292,17 @@ pub fn config_window(
                 let ui = ui.c();
                 let config_win = config_win.c();
                 async move {
-                    if let Err(e) = task.await {
+                    let result = tokio::select! {
+                        r = task => r,
+                        _ = cancel_rx => {
+                            eprintln!("Device load cancelled");
+                            loading.update(|l| l.finish(generation));
+                            return;
+                        }
+                    };
+                    loading.update(|l| l.finish(generation));
+                    if let Err(e) = result {
+                        status.last_error.set(Some(format!("Failed to connect: {e}")));
                         config_win
                             .modal_err_async(&ui, "Failed to connect", &e.to_string())
                             .await;
@@ -231,238 +321,140 @@ pub fn config_window(
         let udp_addr = udp_addr.c();
         move |_| {
             let mut device_combobox = device_combobox.c();
-            let saved_selection = selected_index.get_untracked();
+            // Keep the selected item selected even if filtering moved it
+            let saved_selection = selected_index.get_untracked().and_then(|i| {
+                let i = usize::try_from(i).ok()?;
+                combobox_entries.with_untracked(|e| e.get(i).copied())
+            });
+            let filter = device_filter.with(|f| f.trim().to_lowercase());
             device_combobox.clear(&ui);
+            let mut entries = vec![];
             device_list.with(|device_list| {
-                for device in device_list {
+                for (ix, device) in device_list.iter().enumerate() {
                     // Skip placeholder mux entries (used to keep connection alive when 0 devices)
                     if let VmConnectionInfo::ViaMux { device_addr, .. } = device {
                         if device_addr == &[0, 0, 0, 0, 0, 0] {
                             continue; // Skip placeholder
                         }
                     }
-                    device_combobox.append(&ui, &display_for_vm_connection(device));
+                    let name = display_for_vm_connection(device);
+                    if !name.to_lowercase().contains(&filter) {
+                        continue;
+                    }
+                    device_combobox.append(&ui, &name);
+                    entries.push(DeviceEntry::Device(ix));
                 }
             });
+            // The pseudo-entries are always listed, whatever the filter
             if let Some(sim_addr) = &simulator_addr {
                 device_combobox.append(&ui, &format!("Simulator @ {sim_addr}"));
+                entries.push(DeviceEntry::Simulator);
             }
             if let Some(udp_addr) = &udp_addr {
                 device_combobox.append(&ui, &format!("M4Hub @ {udp_addr}"));
+                entries.push(DeviceEntry::M4Hub);
             }
+            let new_index = saved_selection
+                .and_then(|saved| entries.iter().position(|e| *e == saved))
+                .map(|i| i as i32);
+            combobox_entries.set(entries);
             device_combobox.enable(&ui);
-            if let Some(idx) = saved_selection {
+            selected_index.set(new_index);
+            if let Some(idx) = new_index {
                 device_combobox.set_selected(&ui, idx);
             }
         }
     });
+    let refreshing = create_rw_signal(false);
     let refresh_device_list = {
         let config_win = config_win.c();
         let ui = ui.c();
-        let simulator_addr = simulator_addr.c();
-        let udp_addr = udp_addr.c();
         let ui_ctx = ui.async_context();
-        let device_combobox_on_selected = device_combobox_on_selected.c();
         move || {
             eprintln!("=== refresh_device_list called ===");
-
-            let devices = nusb::list_devices().wait();
-            let usb_devices: Vec<_> = match devices {
-                Ok(p) => p,
-                Err(e) => {
-                    config_win.modal_err(&ui, "Failed to list usb devices", &e.to_string());
-                    return;
-                }
-            }
-            .into_iter()
-            .filter(|info| info.vendor_id() == 0x1915)
-            .collect();
-
-            // Separate direct USB vision modules and mux devices, probing transport mode for vision modules
-            let mut vm_connections: Vec<VmConnectionInfo> = Vec::new();
-            let mut hub_devices: Vec<_> = Vec::new();
-
-            for info in usb_devices {
-                match info.product_id() {
-                    0x520F | 0x5210 | 0x5211 => {
-                        match tokio_handle.block_on(VmDevice::probe_transport_mode(&info)) {
-                            Ok(TransportMode::Usb) => {
-                                vm_connections.push(VmConnectionInfo::DirectUsb(info.clone()))
-                            }
-                            Ok(mode) => {
-                                eprintln!(
-                                    "Skipping {:04x}:{:04x} - transport mode {:?}",
-                                    info.vendor_id(),
-                                    info.product_id(),
-                                    mode
-                                );
-                            }
-                            Err(e) => {
-                                eprintln!(
-                                    "Skipping {:04x}:{:04x} - failed to probe mode: {e}",
-                                    info.vendor_id(),
-                                    info.product_id()
-                                );
-                            }
-                        }
-                    }
-                    0x5212 => hub_devices.push(info.clone()),
-                    _ => {}
-                }
+            // Enumeration and probing can be slow, so they run off the UI thread's call stack;
+            // clicks while a refresh is in flight are coalesced into it.
+            if refreshing.get_untracked() {
+                eprintln!("Refresh already in progress, ignoring");
+                return;
             }
+            refreshing.set(true);
+            let config_win = config_win.c();
+            let ui = ui.c();
+            ui_ctx.spawn(async move {
+                refresh_devices(&ui, &config_win, device_list).await;
+                refreshing.set(false);
+            });
+        }
+    };
+    refresh_device_list();
+    refresh_button.on_clicked(&ui, {
+        let refresh_device_list = refresh_device_list.c();
+        move |_| refresh_device_list()
+    });
 
-            eprintln!(
-                "Found {} USB-mode devices, {} mux devices",
-                vm_connections.len(),
-                hub_devices.len()
-            );
-
-            eprintln!("Found {} mux devices", hub_devices.len());
-            if !hub_devices.is_empty() {
-                // Check if we have an existing mux connection in the device list
-                // This will be reused without disrupting any active device connections
-                let existing_mux_and_devices = device_list.with_untracked(|list| {
-                    let mut mux = None;
-                    let mut mux_devices = Vec::new();
-                    for conn in list {
-                        if let VmConnectionInfo::ViaMux {
-                            mux: m,
-                            device_addr,
-                        } = conn
-                        {
-                            if mux.is_none() {
-                                mux = Some(m.clone());
-                            }
-                            mux_devices.push(*device_addr);
-                        }
-                    }
-                    mux.map(|m| (m, mux_devices))
-                });
-
-                if let Some((existing_mux, existing_mux_devices)) = existing_mux_and_devices {
-                    eprintln!("Reusing existing mux connection for refresh (with {} existing mux devices)", existing_mux_devices.len());
-
-                    let device_list = device_list.c();
-
-                    ui_ctx.spawn(async move {
-                        eprintln!("Mux refresh task started (reusing connection)");
-                        let mut all_connections = vm_connections;
-
-                        eprintln!("Querying devices from existing mux...");
-                        match existing_mux.request_devices().await {
-                            Ok(devices) => {
-                                eprintln!(
-                                    "Mux query successful, found {} device(s)",
-                                    devices.len()
-                                );
-                                for device_addr in devices {
-                                    eprintln!(
-                                        "  Device: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-                                        device_addr[0],
-                                        device_addr[1],
-                                        device_addr[2],
-                                        device_addr[3],
-                                        device_addr[4],
-                                        device_addr[5]
-                                    );
-                                    all_connections.push(VmConnectionInfo::ViaMux {
-                                        mux: existing_mux.clone(),
-                                        device_addr,
-                                    });
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to query existing mux: {}", e);
-                                eprintln!(
-                                    "Preserving {} existing mux devices in the list",
-                                    existing_mux_devices.len()
-                                );
-                                for device_addr in existing_mux_devices {
-                                    all_connections.push(VmConnectionInfo::ViaMux {
-                                        mux: existing_mux.clone(),
-                                        device_addr,
-                                    });
-                                }
-                            }
-                        }
-
-                        eprintln!(
-                            "Updating device list with {} total devices",
-                            all_connections.len()
-                        );
-                        device_list.set(all_connections);
-                        eprintln!("Mux refresh task completed");
-                    });
-                } else {
-                    eprintln!("No existing mux connection, creating new one");
-
-                    // Query mux devices asynchronously and update the list
-                    let device_list = device_list.c();
-
-                    eprintln!("Spawning mux query task...");
-                    ui_ctx.spawn(async move {
-                        eprintln!("Mux query task started");
-                        let mut all_connections = vm_connections;
-
-                        // Query each mux for connected devices
-                        for hub_info in hub_devices {
-                            eprintln!("Attempting to connect to mux...");
-                            match MuxDevice::connect_usb(hub_info.clone()).await {
-                                Ok(hub) => {
-                                    eprintln!("Mux connected successfully, requesting devices...");
-                                    match hub.request_devices().await {
-                                        Ok(devices) => {
-                                            eprintln!("Mux query successful, found {} device(s)", devices.len());
-                                            // If no devices, add a placeholder to keep mux alive for next refresh
-                                            if devices.is_empty() {
-                                                eprintln!("No devices found, adding placeholder to keep mux connection alive");
-                                                all_connections.push(VmConnectionInfo::ViaMux {
-                                                    mux: hub.clone(),
-                                                    device_addr: [0, 0, 0, 0, 0, 0], // Placeholder
-                                                });
-                                            } else {
-                                            for device_addr in devices {
-                                                eprintln!("  Device: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-                                                    device_addr[0], device_addr[1], device_addr[2],
-                                                    device_addr[3], device_addr[4], device_addr[5]);
-                                                all_connections.push(VmConnectionInfo::ViaMux {
-                                                    mux: hub.clone(),
-                                                    device_addr,
-                                                });
-                                            }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            eprintln!("Failed to query mux devices: {}", e);
-                                        }
-                                    }
-                                    eprintln!("Mux devices added to connection list");
-                                }
-                                Err(e) => {
-                                    eprintln!("Failed to connect to mux: {}", e);
-                                }
-                            }
-                        }
+    auto_reconnect_checkbox.on_toggled(&ui, move |checked| auto_reconnect.set(checked));
 
-                        eprintln!("Updating device list with {} total devices", all_connections.len());
-                        device_list.set(all_connections);
-                        eprintln!("Mux query task completed");
-                    });
+    // Auto-reconnect supervisor: notice when the connected device goes away and, once it shows up
+    // in the device list again, select it like the user would have. Never acts while a selection
+    // is loading, so it doesn't fight manual selection.
+    ui.spawn({
+        let device_combobox = device_combobox.c();
+        let device_combobox_on_selected = device_combobox_on_selected.c();
+        let ui = ui.c();
+        async move {
+            let mut poll = tokio::time::interval(Duration::from_secs(2));
+            // Only one attempt per appearance, a device that fails to load would otherwise pop up
+            // an error every poll
+            let mut attempted = false;
+            loop {
+                poll.tick().await;
+                if device.with_untracked(|d| d.as_ref().is_some_and(|d| !d.is_connected())) {
+                    eprintln!("Device disconnected");
+                    device.set(None);
+                    status.last_error.set(Some(String::from("Device disconnected")));
+                    // The list still has the old entry, look again after the next refresh
+                    refresh_device_list();
+                    continue;
                 }
-            } else {
-                // No muxes found, just use direct USB connections
-                device_list.set(vm_connections);
+                if device.with_untracked(Option::is_some) {
+                    attempted = false;
+                    continue;
+                }
+                if !auto_reconnect.get_untracked()
+                    || loading.with_untracked(LoadTracker::is_loading)
+                {
+                    continue;
+                }
+                let Some(key) = last_device.get_untracked() else {
+                    continue;
+                };
+                let keys: Vec<_> =
+                    device_list.with_untracked(|list| list.iter().map(DeviceKey::new).collect());
+                let reappearance = combobox_entries
+                    .with_untracked(|e| find_reappeared(&keys, e, &key, &mut attempted));
+                let i = match reappearance {
+                    Reappearance::Absent => {
+                        refresh_device_list();
+                        continue;
+                    }
+                    Reappearance::Hidden => continue,
+                    Reappearance::Select(i) => i,
+                };
+                eprintln!("Previously connected device is back, reconnecting");
+                let mut device_combobox = device_combobox.c();
+                device_combobox.set_selected(&ui, i as i32);
+                device_combobox_on_selected.c()(i as i32);
             }
         }
-    };
-    refresh_device_list();
-    refresh_button.on_clicked(&ui, move |_| refresh_device_list());
+    });
 
     let apply_button_on_click = {
         let config_win = config_win.c();
         let ui = ui.c();
         let general_settings = general_settings.c();
-        move |device: VmDevice| async move {
+        // `progress(step, total)` is called before each settings group is written
+        move |device: VmDevice, mut progress: Box<dyn FnMut(usize, usize)>| async move {
             let mut errors = vec![];
             general_settings.validate(&mut errors);
             if !errors.is_empty() {
@@ -506,27 +498,6 @@ pub fn config_window(
                             .await;
                         return false;
                     }
-
-                    if let Err(e) = wf_settings.apply(&device).await {
-                        config_win
-                            .modal_err_async(
-                                &ui,
-                                "Failed to apply wide field settings",
-                                &e.to_string(),
-                            )
-                            .await;
-                        return false;
-                    };
-                    if let Err(e) = nf_settings.apply(&device).await {
-                        config_win
-                            .modal_err_async(
-                                &ui,
-                                "Failed to apply near field settings",
-                                &e.to_string(),
-                            )
-                            .await;
-                        return false;
-                    };
                 }
                 Some(ats_usb::device::ProductId::AtsPro) => {
                     pag_settings.validate(&mut errors);
@@ -541,25 +512,29 @@ pub fn config_window(
                             .await;
                         return false;
                     }
-                    if let Err(e) = pag_settings.apply(&device).await {
-                        config_win
-                            .modal_err_async(
-                                &ui,
-                                "Failed to apply wide field settings",
-                                &e.to_string(),
-                            )
-                            .await;
-                        return false;
-                    };
                 }
                 _ => unreachable!(),
             }
-            if let Err(e) = general_settings.apply(&device).await {
+            let steps = ApplyStep::for_product(ats_usb::device::ProductId::from_u16(
+                general_settings.device_pid.get_untracked(),
+            ));
+            let result = apply_in_steps(steps, &mut progress, async |step| match step {
+                ApplyStep::WideField => wf_settings.apply(&device).await,
+                ApplyStep::NearField => nf_settings.apply(&device).await,
+                ApplyStep::Pag => pag_settings.apply(&device).await,
+                ApplyStep::General => general_settings.apply(&device).await,
+            })
+            .await;
+            if let Err((step, e)) = result {
+                let title = format!("Failed to apply {} settings", step.name());
+                if step == ApplyStep::General {
+                    status.last_error.set(Some(format!("{title}: {e}")));
+                }
                 config_win
-                    .modal_err_async(&ui, "Failed to apply general settings", &e.to_string())
+                    .modal_err_async(&ui, &title, &e.to_string())
                     .await;
                 return false;
-            };
+            }
             return true;
         }
     };
@@ -575,9 +550,17 @@ pub fn config_window(
             let mut apply_button = apply_button.c();
             let ui2 = ui.c();
             ui.spawn(async move {
-                f(device).await;
-                apply_button.set_text(&ui2, "Applied!");
-                tokio::time::sleep(Duration::from_secs(3)).await;
+                let progress = {
+                    let mut apply_button = apply_button.c();
+                    let ui = ui2.c();
+                    move |step, total| {
+                        apply_button.set_text(&ui, &format!("Applying {step}/{total}..."))
+                    }
+                };
+                if f(device, Box::new(progress)).await {
+                    apply_button.set_text(&ui2, "Applied!");
+                    tokio::time::sleep(Duration::from_secs(3)).await;
+                }
                 apply_button.set_text(&ui2, "Apply");
             });
         }
@@ -596,18 +579,28 @@ pub fn config_window(
                 let apply_button_on_click = apply_button_on_click.c();
                 let mut save_button = save_button.c();
                 async move {
-                    if !apply_button_on_click(device.clone()).await {
+                    let progress = {
+                        let mut save_button = save_button.c();
+                        let ui = ui.c();
+                        move |step, total| {
+                            save_button.set_text(&ui, &format!("Applying {step}/{total}..."))
+                        }
+                    };
+                    if !apply_button_on_click(device.clone(), Box::new(progress)).await {
                         // callback should have already displayed an error modal, just return
+                        save_button.set_text(&ui, "Save");
                         return;
                     }
-                    if let Err(e) = device.flash_settings().await {
+                    save_button.set_text(&ui, "Saving...");
+                    if let Err(e) = device.flash_settings_confirmed().await {
+                        status
+                            .last_error
+                            .set(Some(format!("Failed to flash settings: {e}")));
                         config_win
-                            .modal_err_async(
-                                &ui,
-                                "Failed to request flash settings",
-                                &e.to_string(),
-                            )
+                            .modal_err_async(&ui, "Failed to flash settings", &e.to_string())
                             .await;
+                        save_button.set_text(&ui, "Save");
+                        return;
                     }
                     save_button.set_text(&ui, "Saved!");
                     tokio::time::sleep(Duration::from_secs(3)).await;
@@ -618,8 +611,22 @@ pub fn config_window(
     });
     load_defaults_button.on_clicked(&ui, {
         let general_settings = general_settings.c();
+        let defaults_undo = defaults_undo.c();
         move |_| {
             if let Some(_device) = device.get_untracked() {
+                let restore = (
+                    general_settings.snapshot(),
+                    nf_settings.snapshot(),
+                    wf_settings.snapshot(),
+                    pag_settings.snapshot(),
+                );
+                *defaults_undo.borrow_mut() = Some(Box::new(move || {
+                    restore.0();
+                    restore.1();
+                    restore.2();
+                    restore.3();
+                }));
+                can_undo_defaults.set(true);
                 general_settings.load_defaults();
                 nf_settings.load_defaults();
                 wf_settings.load_defaults();
@@ -628,6 +635,105 @@ pub fn config_window(
         }
     });
 
+    undo_defaults_button.on_clicked(&ui, move |_| {
+        if let Some(restore) = defaults_undo.borrow_mut().take() {
+            restore();
+        }
+        can_undo_defaults.set(false);
+    });
+
+    copy_settings_button.on_clicked(&ui, {
+        let general_settings = general_settings.c();
+        let clipboard = clipboard.c();
+        move |_| {
+            if device.with_untracked(|d| d.is_none()) {
+                return;
+            }
+            *clipboard.borrow_mut() = Some(SettingsClipboard::copy(
+                &general_settings,
+                nf_settings,
+                wf_settings,
+                pag_settings,
+            ));
+            has_clipboard.set(true);
+            paste_armed.set(false);
+        }
+    });
+
+    create_effect({
+        let ui = ui.c();
+        let paste_settings_button = paste_settings_button.c();
+        move |_| {
+            let mut paste_settings_button = paste_settings_button.c();
+            let text = if paste_armed.get() {
+                "Confirm paste"
+            } else {
+                "Paste settings"
+            };
+            paste_settings_button.set_text(&ui, text);
+        }
+    });
+
+    paste_settings_button.on_clicked(&ui, {
+        let ui = ui.c();
+        let config_win = config_win.c();
+        let general_settings = general_settings.c();
+        move |_| {
+            let Some(clip) = clipboard.borrow().clone() else {
+                return;
+            };
+            if clip.device_pid != general_settings.device_pid.get_untracked() {
+                config_win.modal_err(
+                    &ui,
+                    "Cannot paste settings",
+                    "The settings were copied from a different kind of device",
+                );
+                return;
+            }
+            if !paste_armed.get_untracked() {
+                let changes = clip.general.diff(&general_settings.settings());
+                let changes = if changes.is_empty() {
+                    "No general settings change.".to_string()
+                } else {
+                    format!("Changed general settings: {}.", changes.join(", "))
+                };
+                config_win.modal_msg(
+                    &ui,
+                    "Paste settings",
+                    &format!(
+                        "{changes}\nSensor settings are replaced as well.\n\n\
+                        Click \"Confirm paste\" to fill in the form, then Apply or Save."
+                    ),
+                );
+                paste_armed.set(true);
+                return;
+            }
+            paste_armed.set(false);
+            clip.paste(&general_settings);
+        }
+    });
+
+    ping_button.on_clicked(&ui, {
+        let ui = ui.c();
+        move |_| {
+            let Some(device) = device.get_untracked() else {
+                return;
+            };
+            ping_result.set("Pinging...".into());
+            ui.spawn(async move {
+                let start = std::time::Instant::now();
+                let result =
+                    tokio::time::timeout(Duration::from_secs(2), device.read_prop(PropKind::Uuid))
+                        .await;
+                ping_result.set(match result {
+                    Ok(Ok(_)) => format!("{} ms", start.elapsed().as_millis()),
+                    Ok(Err(e)) => format!("Error: {e}"),
+                    Err(_) => "Timed out".into(),
+                });
+            });
+        }
+    });
+
     reload_button.on_clicked(&ui, {
         let general_settings = general_settings.c();
         move |_| {
@@ -650,6 +756,43 @@ pub fn config_window(
     )
 }
 
+/// Form values copied from one device, to be pasted into the forms while another device is
+/// selected. Per-device values such as the UUID are left out.
+#[derive(Clone)]
+struct SettingsClipboard {
+    device_pid: u16,
+    general: ats_usb::device::GeneralSettings,
+    /// Writes the copied sensor settings into the sensor forms
+    sensors: Rc<dyn Fn()>,
+}
+
+impl SettingsClipboard {
+    fn copy(
+        general: &GeneralSettingsForm,
+        nf: paj_sensor_settings::PajSensorSettingsForm,
+        wf: paj_sensor_settings::PajSensorSettingsForm,
+        pag: pag_sensor_settings::PagSensorSettingsForm,
+    ) -> Self {
+        let sensors = (nf.copy_values(), wf.copy_values(), pag.copy_values());
+        Self {
+            device_pid: general.device_pid.get_untracked(),
+            general: general.settings(),
+            sensors: Rc::new(move || {
+                sensors.0();
+                sensors.1();
+                sensors.2();
+            }),
+        }
+    }
+
+    /// Fill the forms in with the copied values. The sensor forms are the ones passed to
+    /// [`copy`](Self::copy).
+    fn paste(&self, general: &GeneralSettingsForm) {
+        general.set_settings(self.general.clone());
+        (self.sensors)();
+    }
+}
+
 #[derive(Clone)]
 struct GeneralSettingsForm {
     device_uuid: RwSignal<[u8; 6]>,
@@ -661,6 +804,8 @@ struct GeneralSettingsForm {
     nf_intrinsics: RwSignal<RosOpenCvIntrinsics<f32>>,
     wf_intrinsics: RwSignal<RosOpenCvIntrinsics<f32>>,
     stereo_iso: RwSignal<nalgebra::Isometry3<f32>>,
+    /// Whether the live IMU readings are shown, which keeps an accel stream open
+    live_imu: RwSignal<bool>,
     mot_runner: Arc<Mutex<MotRunner>>,
 }
 
@@ -671,18 +816,22 @@ impl GeneralSettingsForm {
         mot_runner: Arc<Mutex<MotRunner>>,
         win: Window,
     ) -> (Form, Self) {
-        let device_uuid = create_rw_signal([0; 6]);
-        let device_pid = create_rw_signal(0u16);
+        let this = Self::empty(mot_runner);
+        let Self {
+            device_uuid,
+            device_pid: _,
+            impact_threshold,
+            suppress_ms,
+            accel_config,
+            gyro_config,
+            nf_intrinsics,
+            wf_intrinsics,
+            stereo_iso,
+            live_imu,
+            ref mot_runner,
+        } = this;
         let connected = move || device.with(|d| d.is_some());
-        let impact_threshold = create_rw_signal(0);
-        let suppress_ms = create_rw_signal(0);
-        let accel_config = create_rw_signal(AccelConfig::default());
-        let gyro_config = create_rw_signal(GyroConfig::default());
-        let nf_intrinsics =
-            create_rw_signal(RosOpenCvIntrinsics::from_params(145., 0., 145., 45., 45.));
-        let wf_intrinsics =
-            create_rw_signal(RosOpenCvIntrinsics::from_params(34., 0., 34., 45., 45.));
-        let stereo_iso = create_rw_signal(nalgebra::Isometry3::identity());
+        let live_imu_reading = create_rw_signal(String::new());
         crate::layout! { &ui,
             let form = Form(padded: true) {
                 (Compact, "Device UUID") : let x = Label(move || {
@@ -711,6 +860,18 @@ impl GeneralSettingsForm {
                     Compact : let upload_gyro_config = Button("Upload")
                     Compact : let download_gyro_config = Button("Download")
                 }
+                (Compact, "IMU Calibration") : let x = HorizontalBox(padded: true) {
+                    Compact : let upload_imu_config = Button("Upload")
+                    Compact : let download_imu_config = Button("Download")
+                }
+                (Compact, "Live IMU") : let x = HorizontalBox(padded: true) {
+                    Compact : let live_imu_checkbox = Checkbox("Show")
+                    Compact : let x = Label(move || live_imu_reading.get())
+                }
+                (Compact, "All General Settings") : let x = HorizontalBox(padded: true) {
+                    Compact : let upload_all_config = Button("Upload")
+                    Compact : let download_all_config = Button("Download")
+                }
                 (Compact, "Nearfield Calibration") : let x = HorizontalBox(padded: true) {
                     Compact : let upload_nf_json = Button("Upload")
                     Compact : let download_nf_json = Button("Download")
@@ -738,6 +899,13 @@ impl GeneralSettingsForm {
         );
         set_accel_upload_handler(&ui, &mut upload_accel_config, accel_config.c(), win.c());
         set_gyro_upload_handler(&ui, &mut upload_gyro_config, gyro_config.c(), win.c());
+        set_imu_upload_handler(
+            &ui,
+            &mut upload_imu_config,
+            accel_config.c(),
+            gyro_config.c(),
+            win.c(),
+        );
         set_calibration_download_handlers(
             &ui,
             &mut download_nf_json,
@@ -750,6 +918,73 @@ impl GeneralSettingsForm {
         );
         set_accel_download_handler(&ui, &mut download_accel_config, accel_config.c(), win.c());
         set_gyro_download_handler(&ui, &mut download_gyro_config, gyro_config.c(), win.c());
+        set_imu_download_handler(
+            &ui,
+            &mut download_imu_config,
+            accel_config.c(),
+            gyro_config.c(),
+            win.c(),
+        );
+
+        live_imu_checkbox.on_toggled(&ui, move |checked| live_imu.set(checked));
+        create_effect({
+            let ui = ui.c();
+            let live_imu_checkbox = live_imu_checkbox.c();
+            move |_| {
+                let mut live_imu_checkbox = live_imu_checkbox.c();
+                live_imu_checkbox.set_checked(&ui, live_imu.get());
+            }
+        });
+
+        // (Re)start the accel stream when the panel is shown or the device changes. Dropping the
+        // previous sender stops the previous stream.
+        create_effect({
+            let ui = ui.c();
+            move |_: Option<Option<tokio::sync::oneshot::Sender<()>>>| {
+                live_imu_reading.set(String::new());
+                if !live_imu.get() {
+                    return None;
+                }
+                let device = device.get()?;
+                let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+                ui.spawn(async move {
+                    let mut stream = match device.stream_accel().await {
+                        Ok(s) => Box::pin(s),
+                        Err(e) => {
+                            live_imu_reading.set(format!("Unavailable: {e}"));
+                            return;
+                        }
+                    };
+                    // Reports arrive at the accel ODR, only show the latest a few times a second
+                    let mut redraw = tokio::time::interval(Duration::from_millis(100));
+                    let mut latest = None;
+                    loop {
+                        tokio::select! {
+                            _ = &mut stop_rx => break,
+                            report = stream.next() => match report {
+                                Some(report) => latest = Some(report),
+                                None => break,
+                            },
+                            _ = redraw.tick() => {
+                                let Some(report) = latest.take() else { continue };
+                                let accel = report.accel_si(&accel_config.get_untracked());
+                                let gyro = report
+                                    .gyro_si(&gyro_config.get_untracked())
+                                    .to_degrees();
+                                live_imu_reading.set(format!(
+                                    "|a| = {:6.3} m/s²  gyro = ({:7.2}, {:7.2}, {:7.2}) °/s",
+                                    accel.0.norm(),
+                                    gyro.x,
+                                    gyro.y,
+                                    gyro.z,
+                                ));
+                            }
+                        }
+                    }
+                });
+                Some(stop_tx)
+            }
+        });
 
         sync_stereo.on_clicked(&ui, {
             let stereo_iso = stereo_iso.c();
@@ -760,21 +995,55 @@ impl GeneralSettingsForm {
             }
         });
 
-        (
-            form,
-            Self {
-                impact_threshold,
-                suppress_ms,
-                accel_config,
-                gyro_config,
-                nf_intrinsics,
-                wf_intrinsics,
-                stereo_iso,
-                mot_runner,
-                device_uuid,
-                device_pid,
-            },
-        )
+        set_general_upload_handler(&ui, &mut upload_all_config, this.c(), win.c());
+        set_general_download_handler(&ui, &mut download_all_config, this.c(), win.c());
+
+        (form, this)
+    }
+
+    /// The form's values without any controls, [`new`](Self::new) adds those.
+    fn empty(mot_runner: Arc<Mutex<MotRunner>>) -> Self {
+        Self {
+            device_uuid: create_rw_signal([0; 6]),
+            device_pid: create_rw_signal(0u16),
+            impact_threshold: create_rw_signal(0),
+            suppress_ms: create_rw_signal(0),
+            accel_config: create_rw_signal(AccelConfig::default()),
+            gyro_config: create_rw_signal(GyroConfig::default()),
+            nf_intrinsics: create_rw_signal(RosOpenCvIntrinsics::from_params(
+                145., 0., 145., 45., 45.,
+            )),
+            wf_intrinsics: create_rw_signal(RosOpenCvIntrinsics::from_params(
+                34., 0., 34., 45., 45.,
+            )),
+            stereo_iso: create_rw_signal(nalgebra::Isometry3::identity()),
+            live_imu: create_rw_signal(false),
+            mot_runner,
+        }
+    }
+
+    /// The current form values. Call [`validate`](Self::validate) first, out of range values are
+    /// truncated.
+    fn settings(&self) -> ats_usb::device::GeneralSettings {
+        ats_usb::device::GeneralSettings {
+            impact_threshold: self.impact_threshold.get_untracked() as u8,
+            suppress_ms: self.suppress_ms.get_untracked() as u8,
+            accel_config: self.accel_config.get_untracked(),
+            gyro_config: self.gyro_config.get_untracked(),
+            camera_model_nf: self.nf_intrinsics.get_untracked(),
+            camera_model_wf: self.wf_intrinsics.get_untracked(),
+            stereo_iso: self.stereo_iso.get_untracked(),
+        }
+    }
+
+    fn set_settings(&self, settings: ats_usb::device::GeneralSettings) {
+        self.impact_threshold.set(settings.impact_threshold.into());
+        self.suppress_ms.set(settings.suppress_ms.into());
+        self.accel_config.set(settings.accel_config);
+        self.gyro_config.set(settings.gyro_config);
+        self.nf_intrinsics.set(settings.camera_model_nf);
+        self.wf_intrinsics.set(settings.camera_model_wf);
+        self.stereo_iso.set(settings.stereo_iso);
     }
 
     async fn load_from_device(&self, device: &VmDevice, first_load: bool) -> Result<u16> {
@@ -829,7 +1098,9 @@ impl GeneralSettingsForm {
         self.device_pid.set(product_id);
 
         if first_load {
-            self.mot_runner.lock().general_config = config;
+            let mut mot_runner = self.mot_runner.lock();
+            mot_runner.set_general_config(config);
+            mot_runner.emit(crate::mot_runner::RunnerEvent::SettingsLoaded);
         }
         Ok(product_id)
     }
@@ -875,49 +1146,14 @@ impl GeneralSettingsForm {
 
     /// Make sure to call `validate()` before calling this method.
     async fn apply(&self, device: &VmDevice) -> Result<()> {
-        let config = ats_usb::device::GeneralSettings {
-            impact_threshold: self.impact_threshold.get_untracked() as u8,
-            suppress_ms: self.suppress_ms.get_untracked() as u8,
-            accel_config: self.accel_config.get_untracked(),
-            gyro_config: self.gyro_config.get_untracked(),
-            camera_model_nf: self.nf_intrinsics.get_untracked(),
-            camera_model_wf: self.wf_intrinsics.get_untracked(),
-            stereo_iso: self.stereo_iso.get_untracked(),
-        };
+        let config = self.settings();
 
         // Write each config field separately using the new API
-        device
-            .write_config(GeneralConfig::ImpactThreshold(config.impact_threshold))
-            .await?;
-        device
-            .write_config(GeneralConfig::SuppressMs(config.suppress_ms))
-            .await?;
-        device
-            .write_config(GeneralConfig::AccelConfig(config.accel_config))
-            .await?;
-        device
-            .write_config(GeneralConfig::GyroConfig(config.gyro_config.clone()))
-            .await?;
-        device
-            .write_config(GeneralConfig::CameraModelNf(config.camera_model_nf.clone()))
-            .await?;
-        device
-            .write_config(GeneralConfig::CameraModelWf(config.camera_model_wf.clone()))
-            .await?;
-        device
-            .write_config(GeneralConfig::StereoIso(config.stereo_iso.clone()))
-            .await?;
-
-        {
-            let general_config = &mut self.mot_runner.lock().general_config;
-            general_config.impact_threshold = config.impact_threshold;
-            general_config.suppress_ms = config.suppress_ms;
-            general_config.accel_config = config.accel_config;
-            general_config.gyro_config = config.gyro_config;
-            general_config.camera_model_nf = config.camera_model_nf;
-            general_config.camera_model_wf = config.camera_model_wf;
-            general_config.stereo_iso = config.stereo_iso;
+        for general_config in config.configs() {
+            device.write_config(general_config).await?;
         }
+
+        self.mot_runner.lock().set_general_config(config);
         Ok(())
     }
 
@@ -926,6 +1162,28 @@ impl GeneralSettingsForm {
         self.gyro_config.set(GyroConfig::default());
     }
 
+    /// Capture the values touched by [`load_defaults`](Self::load_defaults). Calling the returned
+    /// closure restores them.
+    fn snapshot(&self) -> impl FnOnce() {
+        let this = self.clone();
+        let impact_threshold = self.impact_threshold.get_untracked();
+        let suppress_ms = self.suppress_ms.get_untracked();
+        let accel_config = self.accel_config.get_untracked();
+        let gyro_config = self.gyro_config.get_untracked();
+        let nf_intrinsics = self.nf_intrinsics.get_untracked();
+        let wf_intrinsics = self.wf_intrinsics.get_untracked();
+        let stereo_iso = self.stereo_iso.get_untracked();
+        move || {
+            this.impact_threshold.set(impact_threshold);
+            this.suppress_ms.set(suppress_ms);
+            this.accel_config.set(accel_config);
+            this.gyro_config.set(gyro_config);
+            this.nf_intrinsics.set(nf_intrinsics);
+            this.wf_intrinsics.set(wf_intrinsics);
+            this.stereo_iso.set(stereo_iso);
+        }
+    }
+
     fn load_defaults(&self) {
         self.impact_threshold.set(5);
         self.suppress_ms.set(100);
@@ -1162,9 +1420,10 @@ fn set_accel_upload_handler(
         let win = win.c();
         move |_| {
             if let Some(path) = win.open_file(&ui) {
-                let Ok(()) = (|| {
+                if let Err(e) = (|| {
                     let reader = std::fs::File::open(&path)?;
-                    let accel_config: AccelConfig = serde_json::from_reader(reader)?;
+                    let accel_config: AccelConfig =
+                        calibration_file::read(reader, CalibrationKind::Accel)?;
                     accel_config_signal.set(accel_config);
                     win.modal_msg(
                         &ui,
@@ -1172,9 +1431,8 @@ fn set_accel_upload_handler(
                         "Successfully uploaded configuration",
                     );
                     Ok::<(), Box<dyn std::error::Error>>(())
-                })() else {
-                    win.modal_err(&ui, "Failed to upload configuration", "Failed to read file");
-                    return;
+                })() {
+                    win.modal_err(&ui, "Failed to upload configuration", &e.to_string());
                 };
             } else {
                 win.modal_err(&ui, "Failed to upload configuration", "No file selected");
@@ -1196,7 +1454,11 @@ fn set_accel_download_handler(
             if let Some(path) = win.save_file(&ui) {
                 let Ok(()) = (|| {
                     let writer = std::fs::File::create(&path)?;
-                    serde_json::to_writer(writer, &accel_config_signal.get())?;
+                    calibration_file::write(
+                        writer,
+                        CalibrationKind::Accel,
+                        &accel_config_signal.get(),
+                    )?;
                     win.modal_msg(
                         &ui,
                         "Downloaded configuration",
@@ -1229,9 +1491,10 @@ fn set_gyro_upload_handler(
         let win = win.c();
         move |_| {
             if let Some(path) = win.open_file(&ui) {
-                let Ok(()) = (|| {
+                if let Err(e) = (|| {
                     let reader = std::fs::File::open(&path)?;
-                    let gyro_config: GyroConfig = serde_json::from_reader(reader)?;
+                    let gyro_config: GyroConfig =
+                        calibration_file::read(reader, CalibrationKind::Gyro)?;
                     gyro_config_signal.set(gyro_config);
                     win.modal_msg(
                         &ui,
@@ -1239,9 +1502,8 @@ fn set_gyro_upload_handler(
                         "Successfully uploaded configuration",
                     );
                     Ok::<(), Box<dyn std::error::Error>>(())
-                })() else {
-                    win.modal_err(&ui, "Failed to upload configuration", "Failed to read file");
-                    return;
+                })() {
+                    win.modal_err(&ui, "Failed to upload configuration", &e.to_string());
                 };
             } else {
                 win.modal_err(&ui, "Failed to upload configuration", "No file selected");
@@ -1263,7 +1525,11 @@ fn set_gyro_download_handler(
             if let Some(path) = win.save_file(&ui) {
                 let Ok(()) = (|| {
                     let writer = std::fs::File::create(&path)?;
-                    serde_json::to_writer(writer, &gyro_config_signal.get())?;
+                    calibration_file::write(
+                        writer,
+                        CalibrationKind::Gyro,
+                        &gyro_config_signal.get(),
+                    )?;
                     win.modal_msg(
                         &ui,
                         "Downloaded configuration",
@@ -1285,30 +1551,463 @@ fn set_gyro_download_handler(
     });
 }
 
-fn display_for_vm_connection(conn: &VmConnectionInfo) -> String {
-    match conn {
-        VmConnectionInfo::DirectUsb(info) => {
-            let mut out = String::new();
-            if let Some(m) = info.manufacturer_string() {
-                out.push_str(m);
+/// Accelerometer and gyroscope calibration bundled into one file.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ImuCalibration {
+    accel: AccelConfig,
+    gyro: GyroConfig,
+}
+
+fn set_imu_upload_handler(
+    ui: &UI,
+    upload_imu: &mut Button,
+    accel_config_signal: RwSignal<AccelConfig>,
+    gyro_config_signal: RwSignal<GyroConfig>,
+    win: Window,
+) {
+    upload_imu.on_clicked(&ui, {
+        let ui = ui.c();
+        let win = win.c();
+        move |_| {
+            if let Some(path) = win.open_file(&ui) {
+                if let Err(e) = (|| {
+                    let reader = std::fs::File::open(&path)?;
+                    let imu: ImuCalibration = calibration_file::read(reader, CalibrationKind::Imu)?;
+                    accel_config_signal.set(imu.accel);
+                    gyro_config_signal.set(imu.gyro);
+                    win.modal_msg(
+                        &ui,
+                        "Uploaded configuration",
+                        "Successfully uploaded configuration",
+                    );
+                    Ok::<(), Box<dyn std::error::Error>>(())
+                })() {
+                    win.modal_err(&ui, "Failed to upload configuration", &e.to_string());
+                };
+            } else {
+                win.modal_err(&ui, "Failed to upload configuration", "No file selected");
+            }
+        }
+    });
+}
+
+fn set_imu_download_handler(
+    ui: &UI,
+    download_imu: &mut Button,
+    accel_config_signal: RwSignal<AccelConfig>,
+    gyro_config_signal: RwSignal<GyroConfig>,
+    win: Window,
+) {
+    download_imu.on_clicked(&ui, {
+        let ui = ui.c();
+        let win = win.c();
+        move |_| {
+            if let Some(path) = win.save_file(&ui) {
+                let Ok(()) = (|| {
+                    let writer = std::fs::File::create(&path)?;
+                    let imu = ImuCalibration {
+                        accel: accel_config_signal.get(),
+                        gyro: gyro_config_signal.get(),
+                    };
+                    calibration_file::write(writer, CalibrationKind::Imu, &imu)?;
+                    win.modal_msg(
+                        &ui,
+                        "Downloaded configuration",
+                        "Successfully downloaded configuration",
+                    );
+                    Ok::<(), Box<dyn std::error::Error>>(())
+                })() else {
+                    win.modal_err(
+                        &ui,
+                        "Failed to download configuration",
+                        "Failed to write file",
+                    );
+                    return;
+                };
+            } else {
+                win.modal_err(&ui, "Failed to download configuration", "No file selected");
+            }
+        }
+    });
+}
+
+fn set_general_upload_handler(
+    ui: &UI,
+    upload_all: &mut Button,
+    form: GeneralSettingsForm,
+    win: Window,
+) {
+    upload_all.on_clicked(&ui, {
+        let ui = ui.c();
+        let win = win.c();
+        move |_| {
+            if let Some(path) = win.open_file(&ui) {
+                if let Err(e) = (|| {
+                    let reader = std::fs::File::open(&path)?;
+                    let settings: ats_usb::device::GeneralSettings =
+                        calibration_file::read(reader, CalibrationKind::General)?;
+                    form.set_settings(settings);
+                    win.modal_msg(
+                        &ui,
+                        "Uploaded configuration",
+                        "Successfully uploaded configuration",
+                    );
+                    Ok::<(), Box<dyn std::error::Error>>(())
+                })() {
+                    win.modal_err(&ui, "Failed to upload configuration", &e.to_string());
+                };
+            } else {
+                win.modal_err(&ui, "Failed to upload configuration", "No file selected");
+            }
+        }
+    });
+}
+
+fn set_general_download_handler(
+    ui: &UI,
+    download_all: &mut Button,
+    form: GeneralSettingsForm,
+    win: Window,
+) {
+    download_all.on_clicked(&ui, {
+        let ui = ui.c();
+        let win = win.c();
+        move |_| {
+            let mut errors = vec![];
+            form.validate(&mut errors);
+            if !errors.is_empty() {
+                win.modal_err(&ui, "General Validation Error", &errors.join("\n"));
+                return;
+            }
+            if let Some(path) = win.save_file(&ui) {
+                let Ok(()) = (|| {
+                    let writer = std::fs::File::create(&path)?;
+                    calibration_file::write(writer, CalibrationKind::General, &form.settings())?;
+                    win.modal_msg(
+                        &ui,
+                        "Downloaded configuration",
+                        "Successfully downloaded configuration",
+                    );
+                    Ok::<(), Box<dyn std::error::Error>>(())
+                })() else {
+                    win.modal_err(
+                        &ui,
+                        "Failed to download configuration",
+                        "Failed to write file",
+                    );
+                    return;
+                };
+            } else {
+                win.modal_err(&ui, "Failed to download configuration", "No file selected");
+            }
+        }
+    });
+}
+
+/// Enumerate USB vision modules and muxes, query the muxes for their devices, and publish the
+/// result to `device_list`. An existing mux connection in the list is reused so active device
+/// connections through it aren't disrupted.
+async fn refresh_devices(
+    ui: &UI,
+    config_win: &Window,
+    device_list: RwSignal<Vec<VmConnectionInfo>>,
+) {
+    let usb_devices: Vec<_> = match nusb::list_devices().await {
+        Ok(p) => p,
+        Err(e) => {
+            config_win
+                .modal_err_async(ui, "Failed to list usb devices", &e.to_string())
+                .await;
+            return;
+        }
+    }
+    .filter(|info| info.vendor_id() == 0x1915)
+    .collect();
+
+    // Separate direct USB vision modules and mux devices, probing transport mode for vision modules
+    let mut vm_connections: Vec<VmConnectionInfo> = Vec::new();
+    let mut hub_devices: Vec<_> = Vec::new();
+
+    for info in usb_devices {
+        match info.product_id() {
+            0x520F | 0x5210 | 0x5211 => match VmDevice::probe_transport_mode(&info).await {
+                Ok(TransportMode::Usb) => {
+                    vm_connections.push(VmConnectionInfo::DirectUsb(info.clone()))
+                }
+                Ok(mode) => {
+                    eprintln!(
+                        "Skipping {:04x}:{:04x} - transport mode {:?}",
+                        info.vendor_id(),
+                        info.product_id(),
+                        mode
+                    );
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Skipping {:04x}:{:04x} - failed to probe mode: {e}",
+                        info.vendor_id(),
+                        info.product_id()
+                    );
+                }
+            },
+            0x5212 => hub_devices.push(info.clone()),
+            _ => {}
+        }
+    }
+
+    eprintln!(
+        "Found {} USB-mode devices, {} mux devices",
+        vm_connections.len(),
+        hub_devices.len()
+    );
+
+    if hub_devices.is_empty() {
+        // No muxes found, just use direct USB connections
+        device_list.set(vm_connections);
+        return;
+    }
+
+    // Check if we have an existing mux connection in the device list
+    // This will be reused without disrupting any active device connections
+    let existing_mux_and_devices = device_list.with_untracked(|list| {
+        let mut mux = None;
+        let mut mux_devices = Vec::new();
+        for conn in list {
+            if let VmConnectionInfo::ViaMux {
+                mux: m,
+                device_addr,
+            } = conn
+            {
+                if mux.is_none() {
+                    mux = Some(m.clone());
+                }
+                mux_devices.push(*device_addr);
+            }
+        }
+        mux.map(|m| (m, mux_devices))
+    });
+
+    let mut all_connections = vm_connections;
+    if let Some((existing_mux, existing_mux_devices)) = existing_mux_and_devices {
+        eprintln!(
+            "Reusing existing mux connection for refresh (with {} existing mux devices)",
+            existing_mux_devices.len()
+        );
+        match existing_mux.request_devices().await {
+            Ok(devices) => {
+                eprintln!("Mux query successful, found {} device(s)", devices.len());
+                for device_addr in devices {
+                    all_connections.push(VmConnectionInfo::ViaMux {
+                        mux: existing_mux.clone(),
+                        device_addr,
+                    });
+                }
             }
-            if let Some(p) = info.product_string() {
-                if !out.is_empty() {
-                    out.push_str(" - ");
+            Err(e) => {
+                eprintln!("Failed to query existing mux: {}", e);
+                eprintln!(
+                    "Preserving {} existing mux devices in the list",
+                    existing_mux_devices.len()
+                );
+                for device_addr in existing_mux_devices {
+                    all_connections.push(VmConnectionInfo::ViaMux {
+                        mux: existing_mux.clone(),
+                        device_addr,
+                    });
                 }
-                out.push_str(p);
             }
-            if !out.is_empty() {
-                out.push_str(" ");
+        }
+    } else {
+        eprintln!("No existing mux connection, creating new one");
+        // Query each mux for connected devices
+        for hub_info in hub_devices {
+            let hub = match MuxDevice::connect_usb(hub_info.clone()).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to connect to mux: {}", e);
+                    continue;
+                }
+            };
+            match hub.request_devices().await {
+                Ok(devices) => {
+                    eprintln!("Mux query successful, found {} device(s)", devices.len());
+                    if devices.is_empty() {
+                        // Add a placeholder to keep the mux alive for the next refresh
+                        all_connections.push(VmConnectionInfo::ViaMux {
+                            mux: hub.clone(),
+                            device_addr: [0, 0, 0, 0, 0, 0],
+                        });
+                    }
+                    for device_addr in devices {
+                        all_connections.push(VmConnectionInfo::ViaMux {
+                            mux: hub.clone(),
+                            device_addr,
+                        });
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to query mux devices: {}", e);
+                }
             }
-            out.push_str(&format!(
-                "({:04x}:{:04x})",
-                info.vendor_id(),
-                info.product_id()
-            ));
-            out = out.replace('\x00', "");
-            out
         }
+    }
+
+    eprintln!(
+        "Updating device list with {} total devices",
+        all_connections.len()
+    );
+    device_list.set(all_connections);
+}
+
+/// An item of the config window's device combobox.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DeviceEntry {
+    /// Index into the device list
+    Device(usize),
+    Simulator,
+    M4Hub,
+}
+
+/// A group of settings the Apply button writes to the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ApplyStep {
+    WideField,
+    NearField,
+    Pag,
+    General,
+}
+
+impl ApplyStep {
+    /// The groups written for a product, in order. The general settings go last.
+    fn for_product(product: Option<ats_usb::device::ProductId>) -> &'static [Self] {
+        match product {
+            Some(ats_usb::device::ProductId::AtsPro) => &[Self::Pag, Self::General],
+            _ => &[Self::WideField, Self::NearField, Self::General],
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::WideField => "wide field",
+            Self::NearField => "near field",
+            Self::Pag => "PAG",
+            Self::General => "general",
+        }
+    }
+}
+
+/// Run `apply` for each of `steps` in order, calling `progress(step, total)` before each. Stops at
+/// the first step that fails.
+async fn apply_in_steps<E>(
+    steps: &[ApplyStep],
+    progress: &mut dyn FnMut(usize, usize),
+    mut apply: impl AsyncFnMut(ApplyStep) -> Result<(), E>,
+) -> Result<(), (ApplyStep, E)> {
+    for (i, &step) in steps.iter().enumerate() {
+        progress(i + 1, steps.len());
+        apply(step).await.map_err(|e| (step, e))?;
+    }
+    Ok(())
+}
+
+/// Whether the config window is loading a selection. Each selection's load gets a new generation,
+/// so a load that was cancelled by a newer selection can't mark that one as finished.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct LoadTracker {
+    generation: u64,
+    running: bool,
+}
+
+impl LoadTracker {
+    /// A new selection's load starts, superseding any previous one. Returns its generation.
+    fn start(&mut self) -> u64 {
+        self.generation += 1;
+        self.running = true;
+        self.generation
+    }
+
+    /// The load of `generation` exited, whether it finished, failed or was cancelled.
+    fn finish(&mut self, generation: u64) {
+        if generation == self.generation {
+            self.running = false;
+        }
+    }
+
+    fn is_loading(&self) -> bool {
+        self.running
+    }
+}
+
+/// Identifies a device across reconnects, unlike [`VmConnectionInfo`] which is only valid until
+/// the device is unplugged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum DeviceKey {
+    Usb {
+        vendor_id: u16,
+        product_id: u16,
+        serial_number: Option<String>,
+    },
+    Mux([u8; 6]),
+}
+
+impl DeviceKey {
+    fn new(conn: &VmConnectionInfo) -> Self {
+        match conn {
+            VmConnectionInfo::DirectUsb(info) => DeviceKey::Usb {
+                vendor_id: info.vendor_id(),
+                product_id: info.product_id(),
+                serial_number: info.serial_number().map(str::to_owned),
+            },
+            VmConnectionInfo::ViaMux { device_addr, .. } => DeviceKey::Mux(*device_addr),
+        }
+    }
+}
+
+/// What the auto-reconnect supervisor should do about the last connected device on one poll.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reappearance {
+    /// Not in the device list, or already tried since it appeared
+    Absent,
+    /// Listed, but hidden by the combobox filter
+    Hidden,
+    /// Select this combobox item
+    Select(usize),
+}
+
+/// Look for the device identified by `key` among `keys`, the keys of the device list, and its item
+/// in the combobox `entries`. `attempted` is set when the device is selected and reset once it is
+/// gone, so each appearance gets one attempt.
+fn find_reappeared(
+    keys: &[DeviceKey],
+    entries: &[DeviceEntry],
+    key: &DeviceKey,
+    attempted: &mut bool,
+) -> Reappearance {
+    let Some(ix) = keys.iter().position(|k| k == key) else {
+        *attempted = false;
+        return Reappearance::Absent;
+    };
+    if *attempted {
+        return Reappearance::Absent;
+    }
+    match entries.iter().position(|e| *e == DeviceEntry::Device(ix)) {
+        Some(i) => {
+            *attempted = true;
+            Reappearance::Select(i)
+        }
+        None => Reappearance::Hidden,
+    }
+}
+
+fn display_for_vm_connection(conn: &VmConnectionInfo) -> String {
+    match conn {
+        VmConnectionInfo::DirectUsb(info) => display_for_usb_device(
+            info.manufacturer_string(),
+            info.product_string(),
+            info.vendor_id(),
+            info.product_id(),
+            info.serial_number(),
+        ),
         VmConnectionInfo::ViaMux { device_addr, .. } => {
             format!(
                 "VM via Mux ({:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X})",
@@ -1323,6 +2022,35 @@ fn display_for_vm_connection(conn: &VmConnectionInfo) -> String {
     }
 }
 
+/// Renders e.g. `Odyssey - ATS VM (1915:520f) SN 1234`. Missing or empty (after stripping NULs)
+/// strings are left out; the vid:pid is always present.
+fn display_for_usb_device(
+    manufacturer: Option<&str>,
+    product: Option<&str>,
+    vendor_id: u16,
+    product_id: u16,
+    serial_number: Option<&str>,
+) -> String {
+    let clean = |s: Option<&str>| {
+        s.map(|s| s.replace('\x00', "").trim().to_owned())
+            .filter(|s| !s.is_empty())
+    };
+    let mut parts = vec![];
+    let name = [clean(manufacturer), clean(product)]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" - ");
+    if !name.is_empty() {
+        parts.push(name);
+    }
+    parts.push(format!("({vendor_id:04x}:{product_id:04x})"));
+    if let Some(serial) = clean(serial_number) {
+        parts.push(format!("SN {serial}"));
+    }
+    parts.join(" ")
+}
+
 /// Retry an asynchronous operation up to `limit` times.
 async fn retry<F, G>(mut op: F, timeout: Duration, limit: usize) -> Option<G::Output>
 where
@@ -1337,3 +2065,199 @@ where
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelled_load_keeps_newer_load_running() {
+        let mut loading = LoadTracker::default();
+        assert!(!loading.is_loading());
+        let first = loading.start();
+        let second = loading.start();
+        // The first load notices it was cancelled after the second one started
+        loading.finish(first);
+        assert!(loading.is_loading());
+        loading.finish(second);
+        assert!(!loading.is_loading());
+    }
+
+    #[test]
+    fn load_without_entry_finishes() {
+        let mut loading = LoadTracker::default();
+        let cancelled = loading.start();
+        // A selection without a device entry finishes right away, then the cancelled load exits
+        let empty = loading.start();
+        loading.finish(empty);
+        loading.finish(cancelled);
+        assert!(!loading.is_loading());
+    }
+
+    #[test]
+    fn reappeared_device_selected_once() {
+        let key = DeviceKey::Mux([1; 6]);
+        let keys = [DeviceKey::Mux([2; 6]), key.clone()];
+        let entries = [
+            DeviceEntry::Simulator,
+            DeviceEntry::Device(0),
+            DeviceEntry::Device(1),
+        ];
+        let mut attempted = false;
+        assert_eq!(
+            find_reappeared(&keys[..1], &entries, &key, &mut attempted),
+            Reappearance::Absent
+        );
+        assert_eq!(
+            find_reappeared(&keys, &entries, &key, &mut attempted),
+            Reappearance::Select(2)
+        );
+        // The attempt failed and the device is still listed
+        assert_eq!(
+            find_reappeared(&keys, &entries, &key, &mut attempted),
+            Reappearance::Absent
+        );
+        // Unplugged and back again
+        find_reappeared(&keys[..1], &entries, &key, &mut attempted);
+        assert_eq!(
+            find_reappeared(&keys, &entries, &key, &mut attempted),
+            Reappearance::Select(2)
+        );
+    }
+
+    #[test]
+    fn reappeared_device_matched_by_usb_identity() {
+        let usb = |serial_number: Option<&str>| DeviceKey::Usb {
+            vendor_id: 0x1915,
+            product_id: 0x520f,
+            serial_number: serial_number.map(str::to_owned),
+        };
+        let keys = [usb(Some("1234")), usb(Some("5678"))];
+        let entries = [DeviceEntry::Device(0), DeviceEntry::Device(1)];
+        let mut attempted = false;
+        assert_eq!(
+            find_reappeared(&keys, &entries, &usb(Some("5678")), &mut attempted),
+            Reappearance::Select(1)
+        );
+        let mut attempted = false;
+        assert_eq!(
+            find_reappeared(&keys, &entries, &usb(None), &mut attempted),
+            Reappearance::Absent
+        );
+    }
+
+    #[test]
+    fn filtered_out_device_not_attempted() {
+        let key = DeviceKey::Mux([1; 6]);
+        let keys = [key.clone()];
+        let mut attempted = false;
+        assert_eq!(
+            find_reappeared(&keys, &[DeviceEntry::Simulator], &key, &mut attempted),
+            Reappearance::Hidden
+        );
+        assert!(!attempted);
+        assert_eq!(
+            find_reappeared(&keys, &[DeviceEntry::Device(0)], &key, &mut attempted),
+            Reappearance::Select(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_progress_once_per_step() {
+        let steps = ApplyStep::for_product(Some(ats_usb::device::ProductId::AtsVm));
+        let mut calls = vec![];
+        let mut progress = |step, total| calls.push((step, total));
+        let mut applied = vec![];
+        apply_in_steps(steps, &mut progress, async |step| {
+            applied.push(step);
+            Ok::<_, ()>(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls, [(1, 3), (2, 3), (3, 3)]);
+        assert_eq!(applied, steps);
+        assert_eq!(applied.last(), Some(&ApplyStep::General));
+    }
+
+    #[tokio::test]
+    async fn apply_stops_at_failed_step() {
+        let steps = ApplyStep::for_product(Some(ats_usb::device::ProductId::AtsPro));
+        let mut calls = vec![];
+        let mut progress = |step, total| calls.push((step, total));
+        let result = apply_in_steps(steps, &mut progress, async |step| match step {
+            ApplyStep::Pag => Err("no response"),
+            _ => Ok(()),
+        })
+        .await;
+        assert_eq!(result, Err((ApplyStep::Pag, "no response")));
+        assert_eq!(calls, [(1, 2)]);
+    }
+
+    #[test]
+    fn clipboard_round_trip_keeps_device_values() {
+        let mot_runner = Arc::new(Mutex::new(crate::mot_runner::test_runner()));
+        let general = GeneralSettingsForm::empty(mot_runner);
+        let nf = paj_sensor_settings::PajSensorSettingsForm::empty(Port::Nf);
+        let wf = paj_sensor_settings::PajSensorSettingsForm::empty(Port::Wf);
+        let pag = pag_sensor_settings::PagSensorSettingsForm::empty();
+        general.device_uuid.set([1; 6]);
+        general.device_pid.set(0x520f);
+        general.impact_threshold.set(7);
+        general.suppress_ms.set(9);
+        general.accel_config.set(AccelConfig {
+            accel_odr: 200,
+            ..Default::default()
+        });
+        nf.load_defaults();
+        let copied = general.settings();
+        let clip = SettingsClipboard::copy(&general, nf, wf, pag);
+        assert_eq!(clip.device_pid, 0x520f);
+
+        // Another device of the same kind is selected
+        general.device_uuid.set([2; 6]);
+        general.load_defaults();
+        nf.clear();
+        assert!(!general.settings().diff(&copied).is_empty());
+
+        clip.paste(&general);
+        assert_eq!(general.settings().diff(&copied), Vec::<&str>::new());
+        assert_eq!(general.device_uuid.get_untracked(), [2; 6]);
+        let mut errors = vec![];
+        nf.validate(&mut errors);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn usb_device_display() {
+        let display = |manufacturer, product, serial| {
+            display_for_usb_device(manufacturer, product, 0x1915, 0x520f, serial)
+        };
+        assert_eq!(
+            display(Some("Odyssey"), Some("ATS VM"), Some("1234")),
+            "Odyssey - ATS VM (1915:520f) SN 1234"
+        );
+        assert_eq!(display(Some("Odyssey"), None, None), "Odyssey (1915:520f)");
+        assert_eq!(display(None, Some("ATS VM"), None), "ATS VM (1915:520f)");
+        assert_eq!(display(None, None, None), "(1915:520f)");
+        assert_eq!(display(None, None, Some("1234")), "(1915:520f) SN 1234");
+    }
+
+    #[test]
+    fn usb_device_display_skips_empty_strings() {
+        let display = |manufacturer, product, serial| {
+            display_for_usb_device(manufacturer, product, 0x1915, 0x520f, serial)
+        };
+        assert_eq!(
+            display(Some("Odyssey\0\0"), Some("\0"), Some("")),
+            "Odyssey (1915:520f)"
+        );
+        assert_eq!(
+            display(Some(" "), Some("ATS VM "), Some("12\x0034\0")),
+            "ATS VM (1915:520f) SN 1234"
+        );
+        assert_eq!(
+            display_for_usb_device(None, None, 0xa, 0xb, None),
+            "(000a:000b)"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Tagged JSON files for IMU calibrations and general settings.
+//!
+//! Files carry a `"type"` and `"version"` next to the calibration fields so that, e.g., a gyro
+//! calibration can't be uploaded as an accel calibration just because the field names overlap.
+//! Files without the tags predate them and are read as the current version.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+pub const CURRENT_VERSION: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationKind {
+    Accel,
+    Gyro,
+    Imu,
+    /// Everything in [`ats_usb::device::GeneralSettings`]
+    General,
+}
+
+impl CalibrationKind {
+    fn tag(self) -> &'static str {
+        match self {
+            CalibrationKind::Accel => "accel",
+            CalibrationKind::Gyro => "gyro",
+            CalibrationKind::Imu => "imu",
+            CalibrationKind::General => "general",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CalibrationFileError {
+    WrongType {
+        expected: &'static str,
+        found: String,
+    },
+    UnsupportedVersion(u64),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for CalibrationFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalibrationFileError::WrongType { expected, found } => write!(
+                f,
+                "This is a {found} calibration file, expected a {expected} calibration file"
+            ),
+            CalibrationFileError::UnsupportedVersion(v) => write!(
+                f,
+                "Unsupported calibration file version {v} (this build reads up to version {CURRENT_VERSION})"
+            ),
+            CalibrationFileError::Json(e) => write!(f, "Invalid calibration file: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CalibrationFileError {}
+
+impl From<serde_json::Error> for CalibrationFileError {
+    fn from(e: serde_json::Error) -> Self {
+        CalibrationFileError::Json(e)
+    }
+}
+
+pub fn write<T: Serialize>(
+    writer: impl std::io::Write,
+    kind: CalibrationKind,
+    config: &T,
+) -> Result<(), CalibrationFileError> {
+    let mut map = Map::new();
+    map.insert("type".into(), kind.tag().into());
+    map.insert("version".into(), CURRENT_VERSION.into());
+    match serde_json::to_value(config)? {
+        Value::Object(fields) => map.extend(fields),
+        other => {
+            map.insert("config".into(), other);
+        }
+    }
+    serde_json::to_writer(writer, &Value::Object(map))?;
+    Ok(())
+}
+
+pub fn read<T: DeserializeOwned>(
+    reader: impl std::io::Read,
+    kind: CalibrationKind,
+) -> Result<T, CalibrationFileError> {
+    let value: Value = serde_json::from_reader(reader)?;
+    let Value::Object(mut map) = value else {
+        // Unversioned non-object config
+        return Ok(serde_json::from_value(value)?);
+    };
+    if let Some(found) = map.remove("type") {
+        let found = found.as_str().unwrap_or("unknown").to_owned();
+        if found != kind.tag() {
+            return Err(CalibrationFileError::WrongType {
+                expected: kind.tag(),
+                found,
+            });
+        }
+    }
+    if let Some(version) = map.remove("version") {
+        match version.as_u64() {
+            Some(v) if (1..=CURRENT_VERSION).contains(&v) => {}
+            Some(v) => return Err(CalibrationFileError::UnsupportedVersion(v)),
+            None => return Err(CalibrationFileError::UnsupportedVersion(0)),
+        }
+    }
+    let value = match map.remove("config") {
+        Some(config) if map.is_empty() => config,
+        Some(config) => {
+            map.insert("config".into(), config);
+            Value::Object(map)
+        }
+        None => Value::Object(map),
+    };
+    Ok(serde_json::from_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Bias {
+        b_x: f32,
+        b_y: f32,
+        b_z: f32,
+    }
+
+    const BIAS: Bias = Bias {
+        b_x: 0.5,
+        b_y: -0.25,
+        b_z: 2.0,
+    };
+
+    fn written<T: Serialize>(kind: CalibrationKind, config: &T) -> Vec<u8> {
+        let mut buf = vec![];
+        write(&mut buf, kind, config).unwrap();
+        buf
+    }
+
+    #[test]
+    fn matched_round_trip() {
+        let buf = written(CalibrationKind::Gyro, &BIAS);
+        let value: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(value["type"], "gyro");
+        assert_eq!(value["version"], CURRENT_VERSION);
+        let read: Bias = read(&buf[..], CalibrationKind::Gyro).unwrap();
+        assert_eq!(read, BIAS);
+    }
+
+    #[test]
+    fn non_object_round_trip() {
+        let buf = written(CalibrationKind::Imu, &[1, 2, 3]);
+        let read: [i32; 3] = read(&buf[..], CalibrationKind::Imu).unwrap();
+        assert_eq!(read, [1, 2, 3]);
+    }
+
+    #[test]
+    fn mismatched_type() {
+        let buf = written(CalibrationKind::Gyro, &BIAS);
+        let err = read::<Bias>(&buf[..], CalibrationKind::Accel).unwrap_err();
+        assert!(matches!(
+            &err,
+            CalibrationFileError::WrongType { expected: "accel", found } if found == "gyro"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "This is a gyro calibration file, expected a accel calibration file"
+        );
+    }
+
+    #[test]
+    fn unversioned_file_reads_as_current() {
+        let json = r#"{"b_x": 0.5, "b_y": -0.25, "b_z": 2.0}"#;
+        let read: Bias = read(json.as_bytes(), CalibrationKind::Accel).unwrap();
+        assert_eq!(read, BIAS);
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let json = r#"{"type": "gyro", "version": 99, "b_x": 0.5, "b_y": -0.25, "b_z": 2.0}"#;
+        let err = read::<Bias>(json.as_bytes(), CalibrationKind::Gyro).unwrap_err();
+        assert!(matches!(err, CalibrationFileError::UnsupportedVersion(99)));
+        let json = r#"{"type": "gyro", "version": "1", "b_x": 0.5, "b_y": -0.25, "b_z": 2.0}"#;
+        let err = read::<Bias>(json.as_bytes(), CalibrationKind::Gyro).unwrap_err();
+        assert!(matches!(err, CalibrationFileError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn invalid_json() {
+        let err = read::<Bias>(&b"{"[..], CalibrationKind::Gyro).unwrap_err();
+        assert!(matches!(err, CalibrationFileError::Json(_)));
+        let err = read::<Bias>(&br#"{"b_x": 1}"#[..], CalibrationKind::Gyro).unwrap_err();
+        assert!(matches!(err, CalibrationFileError::Json(_)));
+    }
+}
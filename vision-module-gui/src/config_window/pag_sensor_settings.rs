@@ -29,18 +29,20 @@ pub struct PagSensorSettingsForm {
 impl PagSensorSettingsForm {
     pub fn new(ui: &UI, device: ReadSignal<Option<VmDevice>>) -> (Form, Self) {
         let connected = move || device.with(|d| d.is_some());
-        let cid = create_rw_signal(String::new());
-        let fps = create_rw_signal(0);
-        let exposure_us = create_rw_signal(0);
-        let area_threshold_min = create_rw_signal(0);
-        let area_threshold_max = create_rw_signal(0);
-        let light_threshold = create_rw_signal(0);
-        let gain = create_rw_signal(0);
-        // Circle detection parameters
-        let circle_r_min = create_rw_signal(0);
-        let circle_r_max = create_rw_signal(0);
-        let circle_k_min = create_rw_signal(0);
-        let circle_k_max = create_rw_signal(0);
+        let this = Self::empty();
+        let Self {
+            cid,
+            fps,
+            exposure_us,
+            gain,
+            area_threshold_min,
+            area_threshold_max,
+            light_threshold,
+            circle_r_min,
+            circle_r_max,
+            circle_k_min,
+            circle_k_max,
+        } = this;
 
         crate::layout! { &ui,
             let form = Form(padded: true) {
@@ -78,7 +80,7 @@ impl PagSensorSettingsForm {
             if let Some(device) = device.get_untracked() {
                 ui_ctx.spawn(async move {
                     _ = retry(
-                        || device.write_mode(ats_usb::packets::vm::Mode::Image),
+                        || device.write_mode(ats_usb::device::Mode::Image),
                         timeout,
                         3,
                     )
@@ -88,22 +90,25 @@ impl PagSensorSettingsForm {
             }
         });
 
-        (
-            form,
-            Self {
-                cid,
-                fps,
-                exposure_us,
-                gain,
-                area_threshold_min,
-                area_threshold_max,
-                light_threshold,
-                circle_r_min,
-                circle_r_max,
-                circle_k_min,
-                circle_k_max,
-            },
-        )
+        (form, this)
+    }
+
+    /// The form's values without any controls, [`new`](Self::new) adds those.
+    pub fn empty() -> Self {
+        Self {
+            cid: create_rw_signal(String::new()),
+            fps: create_rw_signal(0),
+            exposure_us: create_rw_signal(0),
+            gain: create_rw_signal(0),
+            area_threshold_min: create_rw_signal(0),
+            area_threshold_max: create_rw_signal(0),
+            light_threshold: create_rw_signal(0),
+            // Circle detection parameters
+            circle_r_min: create_rw_signal(0),
+            circle_r_max: create_rw_signal(0),
+            circle_k_min: create_rw_signal(0),
+            circle_k_max: create_rw_signal(0),
+        }
     }
 
     pub async fn load_from_device(&self, device: &VmDevice) -> Result<()> {
@@ -342,6 +347,53 @@ impl PagSensorSettingsForm {
         self.circle_k_max.set(0);
     }
 
+    /// Capture the current values. Calling the returned closure restores them.
+    pub fn snapshot(&self) -> impl FnOnce() {
+        let (cid_signal, cid) = (self.cid, self.cid.get_untracked());
+        let ints = [
+            self.fps,
+            self.exposure_us,
+            self.gain,
+            self.area_threshold_min,
+            self.area_threshold_max,
+            self.light_threshold,
+            self.circle_r_min,
+            self.circle_r_max,
+            self.circle_k_min,
+            self.circle_k_max,
+        ]
+        .map(|signal| (signal, signal.get_untracked()));
+        move || {
+            cid_signal.set(cid);
+            for (signal, value) in ints {
+                signal.set(value);
+            }
+        }
+    }
+
+    /// Capture the sensor settings, without the chip ID, so they can be written into the form any
+    /// number of times, e.g. after another device was selected.
+    pub fn copy_values(&self) -> impl Fn() {
+        let ints = [
+            self.fps,
+            self.exposure_us,
+            self.gain,
+            self.area_threshold_min,
+            self.area_threshold_max,
+            self.light_threshold,
+            self.circle_r_min,
+            self.circle_r_max,
+            self.circle_k_min,
+            self.circle_k_max,
+        ]
+        .map(|signal| (signal, signal.get_untracked()));
+        move || {
+            for (signal, value) in ints {
+                signal.set(value);
+            }
+        }
+    }
+
     pub fn load_defaults(&self) {
         self.fps.set(180);
         self.exposure_us.set(2000);
@@ -358,3 +410,24 @@ impl PagSensorSettingsForm {
         self.circle_k_max.set(0x1D); // 29 → 0.90625
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_values_leave_chip_id() {
+        let form = PagSensorSettingsForm::empty();
+        form.cid.set("0x7665".into());
+        form.load_defaults();
+        let paste = form.copy_values();
+        // Another device is selected
+        form.clear();
+        form.cid.set("0x7661".into());
+        paste();
+        assert_eq!(form.cid.get_untracked(), "0x7661");
+        assert_eq!(form.fps.get_untracked(), 180);
+        assert_eq!(form.area_threshold_max.get_untracked(), i32::from(u16::MAX));
+        assert_eq!(form.circle_k_max.get_untracked(), 0x1D);
+    }
+}
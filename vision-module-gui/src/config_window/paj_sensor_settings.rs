@@ -32,19 +32,23 @@ pub struct PajSensorSettingsForm {
 impl PajSensorSettingsForm {
     pub fn new(ui: &UI, device: ReadSignal<Option<VmDevice>>, port: Port) -> (Form, Self) {
         let connected = move || device.with(|d| d.is_some());
-        let pid = create_rw_signal(String::new());
-        let resolution_x = create_rw_signal(String::new());
-        let resolution_y = create_rw_signal(String::new());
-        let exposure_time = create_rw_signal(String::new());
-        let frame_period = create_rw_signal(String::new());
-        let brightness_threshold = create_rw_signal(String::new());
-        let noise_threshold = create_rw_signal(String::new());
-        let area_threshold_min = create_rw_signal(String::new());
-        let area_threshold_max = create_rw_signal(String::new());
-        let max_object_cnt = create_rw_signal(String::new());
-        let operation_mode = create_rw_signal(0);
-        let frame_subtraction = create_rw_signal(0);
-        let gain = create_rw_signal(0);
+        let this = Self::empty(port);
+        let Self {
+            port: _,
+            pid,
+            resolution_x,
+            resolution_y,
+            exposure_time,
+            frame_period,
+            brightness_threshold,
+            noise_threshold,
+            area_threshold_min,
+            area_threshold_max,
+            max_object_cnt,
+            operation_mode,
+            frame_subtraction,
+            gain,
+        } = this;
 
         let exposure_time_ms = move || match exposure_time.with(|s| s.parse::<u16>()) {
             Ok(n) => format!("{:.4}", f64::from(n) * 200.0 / 1e6),
@@ -94,25 +98,27 @@ impl PajSensorSettingsForm {
         for (label, _) in &GAIN_TABLE {
             gain_combobox.append(&ui, label);
         }
-        (
-            form,
-            Self {
-                port,
-                pid,
-                resolution_x,
-                resolution_y,
-                exposure_time,
-                frame_period,
-                brightness_threshold,
-                noise_threshold,
-                area_threshold_min,
-                area_threshold_max,
-                max_object_cnt,
-                operation_mode,
-                frame_subtraction,
-                gain,
-            },
-        )
+        (form, this)
+    }
+
+    /// The form's values without any controls, [`new`](Self::new) adds those.
+    pub fn empty(port: Port) -> Self {
+        Self {
+            port,
+            pid: create_rw_signal(String::new()),
+            resolution_x: create_rw_signal(String::new()),
+            resolution_y: create_rw_signal(String::new()),
+            exposure_time: create_rw_signal(String::new()),
+            frame_period: create_rw_signal(String::new()),
+            brightness_threshold: create_rw_signal(String::new()),
+            noise_threshold: create_rw_signal(String::new()),
+            area_threshold_min: create_rw_signal(String::new()),
+            area_threshold_max: create_rw_signal(String::new()),
+            max_object_cnt: create_rw_signal(String::new()),
+            operation_mode: create_rw_signal(0),
+            frame_subtraction: create_rw_signal(0),
+            gain: create_rw_signal(0),
+        }
     }
 
     pub async fn load_from_device(&self, device: &VmDevice) -> Result<()> {
@@ -306,6 +312,60 @@ impl PajSensorSettingsForm {
         self.gain.set(0);
     }
 
+    /// Capture the current values. Calling the returned closure restores them.
+    pub fn snapshot(&self) -> impl FnOnce() {
+        let strings = [
+            self.pid,
+            self.resolution_x,
+            self.resolution_y,
+            self.exposure_time,
+            self.frame_period,
+            self.brightness_threshold,
+            self.noise_threshold,
+            self.area_threshold_min,
+            self.area_threshold_max,
+            self.max_object_cnt,
+        ]
+        .map(|signal| (signal, signal.get_untracked()));
+        let ints = [self.operation_mode, self.frame_subtraction, self.gain]
+            .map(|signal| (signal, signal.get_untracked()));
+        move || {
+            for (signal, value) in strings {
+                signal.set(value);
+            }
+            for (signal, value) in ints {
+                signal.set(value);
+            }
+        }
+    }
+
+    /// Capture the sensor settings, without the product ID, so they can be written into the form
+    /// any number of times, e.g. after another device was selected.
+    pub fn copy_values(&self) -> impl Fn() {
+        let strings = [
+            self.resolution_x,
+            self.resolution_y,
+            self.exposure_time,
+            self.frame_period,
+            self.brightness_threshold,
+            self.noise_threshold,
+            self.area_threshold_min,
+            self.area_threshold_max,
+            self.max_object_cnt,
+        ]
+        .map(|signal| (signal, signal.get_untracked()));
+        let ints = [self.operation_mode, self.frame_subtraction, self.gain]
+            .map(|signal| (signal, signal.get_untracked()));
+        move || {
+            for (signal, value) in &strings {
+                signal.set(value.clone());
+            }
+            for (signal, value) in ints {
+                signal.set(value);
+            }
+        }
+    }
+
     pub fn load_defaults(&self) {
         self.resolution_x.update(|s| s.replace_range(.., "4095"));
         self.resolution_y.update(|s| s.replace_range(.., "4095"));
@@ -402,3 +462,27 @@ pub static GAIN_TABLE: [(&str, Gain); 16 * 3 + 1] = {
     omegalul[16 * 3] = ("8.0000", Gain::new(16, 3));
     omegalul
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_values_leave_product_id() {
+        let form = PajSensorSettingsForm::empty(Port::Nf);
+        form.pid.set("0x7630".into());
+        form.load_defaults();
+        let paste = form.copy_values();
+        // Another device is selected
+        form.clear();
+        form.pid.set("0x7631".into());
+        paste();
+        assert_eq!(form.pid.get_untracked(), "0x7631");
+        assert_eq!(form.resolution_x.get_untracked(), "4095");
+        assert_eq!(form.exposure_time.get_untracked(), "8192");
+        assert_eq!(form.gain.get_untracked(), Gain::index_from_reg(16, 0));
+        let mut errors = vec![];
+        form.validate(&mut errors);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+}
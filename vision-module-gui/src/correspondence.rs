@@ -0,0 +1,503 @@
+use crate::marker_pattern::MarkerPattern;
+use arrayvec::ArrayVec;
+use nalgebra::{Isometry3, Matrix3, Point2, Point3, Vector2};
+
+/// Maximum distance in normalized image coordinates between a detection and a model point for them
+/// to be considered the same marker
+pub const DEFAULT_MAX_MATCH_DISTANCE: f32 = 0.05;
+
+/// Assignment of model indices to detected markers.
+#[derive(Clone, Debug, Default)]
+pub struct MarkerMatch {
+    /// `pattern_ids[i]` is the index into the model's `object_points` for detection `i`, or `None`
+    /// if the detection is spurious.
+    pub pattern_ids: ArrayVec<Option<u8>, 16>,
+    /// Sum of distances between the matched detections and their model points
+    pub error: f32,
+}
+
+impl MarkerMatch {
+    pub fn matched_count(&self) -> usize {
+        self.pattern_ids.iter().filter(|id| id.is_some()).count()
+    }
+
+    /// Indices of detections that couldn't be matched to any model point.
+    pub fn unmatched(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pattern_ids
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| id.is_none())
+            .map(|(i, _)| i)
+    }
+}
+
+/// Greedily pair each detection with its nearest projected model point.
+///
+/// Pairs are taken closest first, each detection and each model point is used at most once, and
+/// pairs further apart than `max_distance` are left unmatched.
+pub fn match_projected(
+    detected: &[Point2<f32>],
+    projected: &[Point2<f32>],
+    max_distance: f32,
+) -> MarkerMatch {
+    let mut pairs = Vec::with_capacity(detected.len() * projected.len());
+    for (i, d) in detected.iter().enumerate().take(16) {
+        for (j, p) in projected.iter().enumerate() {
+            let dist = (d - p).norm();
+            if dist <= max_distance {
+                pairs.push((dist, i, j));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut result = MarkerMatch {
+        pattern_ids: detected.iter().take(16).map(|_| None).collect(),
+        error: 0.0,
+    };
+    let mut model_used = [false; 256];
+    for (dist, i, j) in pairs {
+        if result.pattern_ids[i].is_some() || model_used[j] {
+            continue;
+        }
+        model_used[j] = true;
+        result.pattern_ids[i] = Some(j as u8);
+        result.error += dist;
+    }
+    result
+}
+
+/// Replace detections within `radius` of each other with their centroid.
+///
+/// A marker split into two nearby blobs would otherwise look like an extra marker. Clusters are
+/// grown greedily in input order: each point joins the first cluster whose running centroid is
+/// within `radius`, and the merged points keep the order of each cluster's first member.
+pub fn merge_close_points<const N: usize>(points: &mut ArrayVec<Point2<f32>, N>, radius: f32) {
+    // Sum of the members and their count
+    let mut clusters = ArrayVec::<(Vector2<f32>, usize), N>::new();
+    for p in points.iter() {
+        let cluster = clusters
+            .iter_mut()
+            .find(|(sum, n)| (*sum / *n as f32 - p.coords).norm() <= radius);
+        match cluster {
+            Some((sum, n)) => {
+                *sum += p.coords;
+                *n += 1;
+            }
+            None => clusters.push((p.coords, 1)),
+        }
+    }
+    points.clear();
+    points.extend(
+        clusters
+            .into_iter()
+            .map(|(sum, n)| Point2::from(sum / n as f32)),
+    );
+}
+
+/// Maximum distance in normalized image coordinates a marker may move between two frames and still
+/// keep its model index
+pub const DEFAULT_MAX_MOVEMENT: f32 = 0.02;
+
+/// Keeps model indices stable across frames.
+///
+/// Close markers can trade places in a purely geometric match from one frame to the next, which
+/// makes the aimpoint jump. The tracker first gives each detection the model index of the nearest
+/// marker from the previous frame, within `max_movement`, and only falls back to the geometric
+/// match for detections it couldn't associate.
+#[derive(Clone, Debug)]
+pub struct MarkerTracker {
+    /// Model index and position of every matched marker in the previous frame
+    previous: ArrayVec<(u8, Point2<f32>), 16>,
+    pub max_movement: f32,
+}
+
+impl Default for MarkerTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MOVEMENT)
+    }
+}
+
+impl MarkerTracker {
+    pub fn new(max_movement: f32) -> Self {
+        Self {
+            previous: ArrayVec::new(),
+            max_movement,
+        }
+    }
+
+    /// Forget the previous frame, e.g. when the screen changes.
+    pub fn reset(&mut self) {
+        self.previous.clear();
+    }
+
+    /// Assign model indices to this frame's detections. `geometric` is the frame's match without
+    /// history, if there is one. The returned error only covers the temporally associated markers.
+    pub fn track(
+        &mut self,
+        detected: &[Point2<f32>],
+        geometric: Option<&MarkerMatch>,
+    ) -> MarkerMatch {
+        let previous_points: ArrayVec<_, 16> = self.previous.iter().map(|&(_, p)| p).collect();
+        let temporal = match_projected(detected, &previous_points, self.max_movement);
+        let mut result = MarkerMatch {
+            pattern_ids: temporal
+                .pattern_ids
+                .iter()
+                .map(|j| j.map(|j| self.previous[usize::from(j)].0))
+                .collect(),
+            error: temporal.error,
+        };
+
+        if let Some(geometric) = geometric {
+            let unmatched: ArrayVec<usize, 16> = result.unmatched().collect();
+            for i in unmatched {
+                let Some(Some(id)) = geometric.pattern_ids.get(i).copied() else {
+                    continue;
+                };
+                // The temporal association wins if both claim the same model point
+                if !result.pattern_ids.contains(&Some(id)) {
+                    result.pattern_ids[i] = Some(id);
+                }
+            }
+        }
+
+        self.previous = detected
+            .iter()
+            .zip(&result.pattern_ids)
+            .filter_map(|(&p, id)| Some(((*id)?, p)))
+            .collect();
+        result
+    }
+}
+
+/// Project model points into normalized image coordinates as seen from `pose`.
+///
+/// `pose` is the camera pose in the screen frame, the same convention as the foveated filter's
+/// position and orientation. Points behind the camera are projected to NaN so they never match.
+pub fn project_object_points(
+    pose: &Isometry3<f32>,
+    object_points: &[Point3<f32>],
+) -> ArrayVec<Point2<f32>, 16> {
+    object_points
+        .iter()
+        .take(16)
+        .map(|p| {
+            let p = pose.inverse_transform_point(p);
+            if p.z > 0.0 {
+                Point2::new(p.x / p.z, p.y / p.z)
+            } else {
+                Point2::new(f32::NAN, f32::NAN)
+            }
+        })
+        .collect()
+}
+
+/// Match detections to the model using a known (e.g. filtered) pose as the initial guess.
+pub fn match_with_pose(
+    detected: &[Point2<f32>],
+    object_points: &[Point3<f32>],
+    pose: &Isometry3<f32>,
+    max_distance: f32,
+) -> MarkerMatch {
+    let projected = project_object_points(pose, object_points);
+    match_projected(detected, &projected, max_distance)
+}
+
+/// Most detections [`match_by_search`] will try. The search grows with the fourth power of the
+/// detection count, so a frame with more is left to the pose based match.
+pub const MAX_SEARCH_DETECTIONS: usize = 8;
+
+/// Match detections to a planar model without any prior pose, e.g. when tracking starts.
+///
+/// Every combination of 4 detections and 4 model points is ordered with `pattern`'s
+/// [`choose_corners`](MarkerPattern::choose_corners) to form a homography hypothesis from the
+/// model plane (x, y) to the image. The hypothesis matching the most detections (lowest error on
+/// ties) wins. Returns `None` if there are fewer than 4 detections or model points, or more than
+/// [`MAX_SEARCH_DETECTIONS`] detections.
+pub fn match_by_search(
+    detected: &[Point2<f32>],
+    object_points: &[Point3<f32>],
+    pattern: MarkerPattern,
+    max_distance: f32,
+) -> Option<MarkerMatch> {
+    let object_points = &object_points[..object_points.len().min(16)];
+    if !(4..=MAX_SEARCH_DETECTIONS).contains(&detected.len()) || object_points.len() < 4 {
+        return None;
+    }
+
+    let mut best: Option<MarkerMatch> = None;
+    for model_ix in combinations4(object_points.len()) {
+        let model_quad = model_ix.map(|i| object_points[i].xy());
+        let Some(model_order) = pattern.choose_corners(&model_quad) else {
+            continue;
+        };
+        let model_quad = model_order.map(|i| model_quad[i]);
+        for detected_ix in combinations4(detected.len()) {
+            let image_quad = detected_ix.map(|i| detected[i]);
+            // A degenerate quad gives a meaningless homography
+            if crate::mot_runner::is_degenerate_quad(&image_quad) {
+                continue;
+            }
+            let Some(image_order) = pattern.choose_corners(&image_quad) else {
+                continue;
+            };
+            let image_quad = image_order.map(|i| image_quad[i]);
+            let Some(h) = perspective_transform_normalized(model_quad, image_quad) else {
+                continue;
+            };
+            let projected: ArrayVec<_, 16> = object_points
+                .iter()
+                .map(|p| {
+                    let q = h * p.xy().to_homogeneous();
+                    Point2::new(q.x / q.z, q.y / q.z)
+                })
+                .collect();
+            let candidate = match_projected(detected, &projected, max_distance);
+            let better = match &best {
+                None => true,
+                Some(b) => {
+                    candidate.matched_count() > b.matched_count()
+                        || (candidate.matched_count() == b.matched_count()
+                            && candidate.error < b.error)
+                }
+            };
+            if better {
+                best = Some(candidate);
+            }
+        }
+    }
+    best
+}
+
+/// [`ats_cv::get_perspective_transform`] with Hartley normalization, for better conditioning in
+/// f32.
+///
+/// Each quad is translated to its centroid and scaled to a mean distance of √2 before the 8x8
+/// solve, so pixel coordinates in the thousands don't swamp the unit entries. The returned
+/// homography maps `src` to `dst` in the original coordinates.
+pub fn perspective_transform_normalized(
+    src: [Point2<f32>; 4],
+    dst: [Point2<f32>; 4],
+) -> Option<Matrix3<f32>> {
+    let (src_t, src) = hartley_normalize(src)?;
+    let (dst_t, dst) = hartley_normalize(dst)?;
+    let h = ats_cv::get_perspective_transform(
+        src[0], src[1], src[2], src[3], dst[0], dst[1], dst[2], dst[3],
+    )?;
+    Some(dst_t.try_inverse()? * h * src_t)
+}
+
+/// Similarity transform taking `points` to zero mean and √2 mean distance, and the transformed
+/// points. `None` if the points coincide.
+fn hartley_normalize(points: [Point2<f32>; 4]) -> Option<(Matrix3<f32>, [Point2<f32>; 4])> {
+    let centroid = points.iter().map(|p| p.coords).sum::<Vector2<f32>>() / 4.0;
+    let mean_distance = points
+        .iter()
+        .map(|p| (p.coords - centroid).norm())
+        .sum::<f32>()
+        / 4.0;
+    if mean_distance <= f32::EPSILON {
+        return None;
+    }
+    let scale = std::f32::consts::SQRT_2 / mean_distance;
+    let t = Matrix3::new(
+        scale,
+        0.0,
+        -scale * centroid.x,
+        0.0,
+        scale,
+        -scale * centroid.y,
+        0.0,
+        0.0,
+        1.0,
+    );
+    Some((
+        t,
+        points.map(|p| Point2::from((p.coords - centroid) * scale)),
+    ))
+}
+
+fn combinations4(n: usize) -> impl Iterator<Item = [usize; 4]> {
+    (0..n).flat_map(move |a| {
+        (a + 1..n)
+            .flat_map(move |b| (b + 1..n).flat_map(move |c| (c + 1..n).map(move |d| [a, b, c, d])))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Screeninfogen's marker layout on a 1.6 x 0.9 m screen
+    fn object_points() -> [Point3<f32>; 6] {
+        [
+            (0.18, 0.29),
+            (0.15, 0.82),
+            (0.77, 0.8),
+            (0.51, 0.35),
+            (0.79, 0.17),
+            (0.49, 0.76),
+        ]
+        .map(|(x, y)| Point3::new(x * 1.6, y * 0.9, 0.0117))
+    }
+
+    fn seen_from_front() -> ArrayVec<Point2<f32>, 16> {
+        let pose = Isometry3::face_towards(
+            &Point3::new(0.7, 0.5, -2.5),
+            &Point3::new(0.8, 0.45, 0.0),
+            &nalgebra::Vector3::y(),
+        );
+        project_object_points(&pose, &object_points())
+    }
+
+    fn search(detected: &[Point2<f32>], pattern: MarkerPattern) -> Option<MarkerMatch> {
+        match_by_search(
+            detected,
+            &object_points(),
+            pattern,
+            DEFAULT_MAX_MATCH_DISTANCE,
+        )
+    }
+
+    #[test]
+    fn search_matches_clean_set() {
+        let detected = seen_from_front();
+        let expected: Vec<_> = (0..6).map(Some).collect();
+        for pattern in MarkerPattern::ALL {
+            let m = search(&detected, pattern).unwrap();
+            assert_eq!(m.pattern_ids.as_slice(), expected.as_slice(), "{pattern:?}");
+            assert!(m.error < 1e-4);
+        }
+    }
+
+    #[test]
+    fn search_flags_spurious_detection() {
+        let mut detected = seen_from_front();
+        detected.insert(2, Point2::new(0.3, -0.2));
+        let m = search(&detected, MarkerPattern::Rectangle).unwrap();
+        assert_eq!(m.unmatched().collect::<Vec<_>>(), [2]);
+        assert_eq!(m.matched_count(), 6);
+        assert_eq!(m.pattern_ids[3], Some(2));
+    }
+
+    #[test]
+    fn merge_inside_radius() {
+        let mut points: ArrayVec<_, 4> = [
+            Point2::new(0.1, 0.1),
+            Point2::new(0.5, 0.5),
+            Point2::new(0.102, 0.1),
+        ]
+        .into_iter()
+        .collect();
+        merge_close_points(&mut points, 0.005);
+        assert_eq!(points.len(), 2);
+        assert!((points[0] - Point2::new(0.101, 0.1)).norm() < 1e-6);
+        assert_eq!(points[1], Point2::new(0.5, 0.5));
+    }
+
+    #[test]
+    fn merge_keeps_points_outside_radius() {
+        let original: ArrayVec<_, 4> = [Point2::new(0.1, 0.1), Point2::new(0.11, 0.1)]
+            .into_iter()
+            .collect();
+        let mut points = original.clone();
+        merge_close_points(&mut points, 0.005);
+        assert_eq!(points, original);
+    }
+
+    #[test]
+    fn tracker_keeps_close_markers_stable() {
+        let mut tracker = MarkerTracker::default();
+        let geometric = |ids: [u8; 2]| MarkerMatch {
+            pattern_ids: ids.into_iter().map(Some).collect(),
+            error: 0.0,
+        };
+        let first = [Point2::new(0.1, 0.1), Point2::new(0.13, 0.1)];
+        let m = tracker.track(&first, Some(&geometric([0, 1])));
+        assert_eq!(m.pattern_ids.as_slice(), [Some(0), Some(1)]);
+
+        // Both markers moved towards each other and arrive in the other order. A fresh geometric
+        // match would swap them, the tracker follows each marker instead.
+        let second = [Point2::new(0.129, 0.1), Point2::new(0.111, 0.1)];
+        let m = tracker.track(&second, Some(&geometric([0, 1])));
+        assert_eq!(m.pattern_ids.as_slice(), [Some(1), Some(0)]);
+        assert!((m.error - 0.012).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tracker_falls_back_to_geometric_match() {
+        let mut tracker = MarkerTracker::default();
+        let first = [Point2::new(0.1, 0.1)];
+        let m = tracker.track(&first, None);
+        assert_eq!(m.pattern_ids.as_slice(), [None]);
+
+        let geometric = MarkerMatch {
+            pattern_ids: [Some(2), Some(4)].into_iter().collect(),
+            error: 0.0,
+        };
+        let m = tracker.track(&first, Some(&geometric));
+        assert_eq!(m.pattern_ids.as_slice(), [Some(2)]);
+        // A marker that wasn't in the previous frame takes its geometric index
+        let second = [Point2::new(0.1, 0.1), Point2::new(0.5, 0.5)];
+        let m = tracker.track(&second, Some(&geometric));
+        assert_eq!(m.pattern_ids.as_slice(), [Some(2), Some(4)]);
+    }
+
+    #[test]
+    fn normalized_transform_f32_accuracy() {
+        // The screen corners seen from well off to the side, in the POC sensor's coordinate range
+        // (tens of thousands), and the aimpoint range they map to
+        let camera = Isometry3::face_towards(
+            &Point3::new(-1.0, 0.45, -1.5),
+            &Point3::new(0.8, 0.45, 0.0),
+            &nalgebra::Vector3::y(),
+        );
+        let pixels = [(0.0, 0.0), (1.6, 0.0), (1.6, 0.9), (0.0, 0.9)].map(|(x, y)| {
+            let p = camera.inverse_transform_point(&Point3::new(x, y, 0.0));
+            Point2::new(29500.0 * p.x / p.z + 10240.0, 29500.0 * p.y / p.z + 7680.0)
+        });
+        let screen =
+            [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(x, y)| Point2::new(x, y));
+
+        let [p0, p1, p2, p3] = pixels.map(|p: Point2<f32>| p.cast::<f64>());
+        let [q0, q1, q2, q3] = screen.map(|p: Point2<f32>| p.cast::<f64>());
+        let reference = ats_cv::get_perspective_transform(p0, p1, p2, p3, q0, q1, q2, q3).unwrap();
+        let [p0, p1, p2, p3] = pixels;
+        let [q0, q1, q2, q3] = screen;
+        let unnormalized =
+            ats_cv::get_perspective_transform(p0, p1, p2, p3, q0, q1, q2, q3).unwrap();
+        let normalized = perspective_transform_normalized(pixels, screen).unwrap();
+
+        // Aimpoint error over a grid of image points
+        let error = |h: Matrix3<f32>| {
+            let h = h.cast::<f64>();
+            let mut max = 0.0f64;
+            for i in 0..=8 {
+                for j in 0..=8 {
+                    let p = nalgebra::Vector3::new(2560.0 * i as f64, 1920.0 * j as f64, 1.0);
+                    let (a, b) = (h * p, reference * p);
+                    max = max.max((a.xy() / a.z - b.xy() / b.z).norm());
+                }
+            }
+            max
+        };
+        let (unnormalized, normalized) = (error(unnormalized), error(normalized));
+        assert!(normalized < 1e-5, "{normalized}");
+        // No worse than the plain solve beyond f32 rounding
+        assert!(
+            normalized <= unnormalized + 1e-6,
+            "{normalized} > {unnormalized}"
+        );
+    }
+
+    #[test]
+    fn search_is_bounded() {
+        let mut detected = seen_from_front();
+        assert!(search(&detected[..3], MarkerPattern::Rectangle).is_none());
+        while detected.len() <= MAX_SEARCH_DETECTIONS {
+            detected.push(Point2::new(detected.len() as f32, 1.0));
+        }
+        assert!(search(&detected, MarkerPattern::Rectangle).is_none());
+    }
+}
@@ -2,6 +2,8 @@ use iui::controls::{FontDescription, SlantStyle, StretchStyle};
 use iui::draw::{self, text, Brush, FillMode, Path, SolidBrush, StrokeParams};
 use nalgebra::{Point2, Rotation2, SMatrix, Transform2, Vector2};
 
+use crate::theme::Color;
+
 pub fn draw_crosshair(ctx: &draw::DrawContext, path: &Path, x: f64, y: f64, r: f64) {
     path.new_figure(ctx, x - r, y);
     path.line_to(ctx, x + r, y);
@@ -88,9 +90,21 @@ pub fn draw_rect(
 }
 
 /// Draws a crosshair and associated text at a given position.
-pub fn draw_marker(ctx: &draw::DrawContext, path: &Path, position: Point2<f64>, label: &str) {
+pub fn draw_marker(
+    ctx: &draw::DrawContext,
+    path: &Path,
+    position: Point2<f64>,
+    label: &str,
+    text_color: Color,
+) {
     draw_crosshair(&ctx, path, position.x, position.y, 50.0);
-    draw_text(&ctx, position.x + 20.0, position.y + 20.0, label);
+    draw_text(
+        &ctx,
+        position.x + 20.0,
+        position.y + 20.0,
+        label,
+        text_color,
+    );
 }
 
 /// Draws a rotated crosshair and associated text at a given position.
@@ -99,9 +113,16 @@ pub fn draw_marker_rotated(
     path: &Path,
     position: Point2<f64>,
     label: &str,
+    text_color: Color,
 ) {
     draw_crosshair_rotated(&ctx, path, position.x, position.y, 50.0);
-    draw_text(&ctx, position.x + 20.0, position.y + 20.0 + 30.0, label);
+    draw_text(
+        &ctx,
+        position.x + 20.0,
+        position.y + 20.0 + 30.0,
+        label,
+        text_color,
+    );
 }
 
 /// Handles drawing a rectangle defined by boundaries and transforms.
@@ -163,7 +184,7 @@ pub fn draw_line(
     );
 }
 
-pub fn draw_text(ctx: &draw::DrawContext, x: f64, y: f64, s: &str) {
+pub fn draw_text(ctx: &draw::DrawContext, x: f64, y: f64, s: &str, color: Color) {
     let font_descriptor = FontDescription {
         family: "Courier New".into(),
         size: 12.0,
@@ -171,7 +192,11 @@ pub fn draw_text(ctx: &draw::DrawContext, x: f64, y: f64, s: &str) {
         slant: SlantStyle::Normal,
         stretch: StretchStyle::Normal,
     };
-    let attr_str = text::AttributedString::new(s);
+    let mut attr_str = text::AttributedString::new(s);
+    // `color` asserts on an empty range
+    if !s.is_empty() {
+        attr_str.color(.., color.r, color.g, color.b, color.a);
+    }
     let mut layout = attr_str.layout(&font_descriptor, 400.0, text::TextAlign::Left);
     ctx.draw_text(&mut layout, x, y);
 }
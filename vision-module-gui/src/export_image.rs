@@ -0,0 +1,208 @@
+//! PNG export of the tracking canvas.
+//!
+//! libui has no offscreen `DrawContext` and can't read back what an `Area` drew, so the export
+//! re-renders the canvas geometry into a software RGBA buffer. Only the line work is reproduced:
+//! the orientation border, up line, center diamond and marker crosshairs. Text labels, blob
+//! bounding boxes, reprojections and the screen outline are left out.
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use ats_usb::packets::vm::normalized_point;
+use nalgebra::{Point2, Rotation2, Scale2, Transform2, Translation2, Vector2, Vector3};
+
+use crate::mot_runner::MotRunner;
+use crate::theme::{Color, Palette};
+
+/// Size in pixels of exported images
+pub const EXPORT_SIZE: u32 = 1024;
+
+/// Used when the palette keeps the window's own background, which can't be read back
+const DEFAULT_BACKGROUND: Color = Color::rgb(1.0, 1.0, 1.0);
+
+/// An RGBA image drawn with single color lines.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Rows top to bottom, 4 bytes per pixel
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, background: Color) -> Self {
+        let px = [background.r, background.g, background.b, background.a].map(to_u8);
+        Self {
+            width,
+            height,
+            pixels: px.repeat(width as usize * height as usize),
+        }
+    }
+
+    /// Alpha blend `color` onto the pixel at (`x`, `y`), ignoring points outside the image.
+    fn blend(&mut self, x: i64, y: i64, color: Color) {
+        if x < 0 || y < 0 || x >= i64::from(self.width) || y >= i64::from(self.height) {
+            return;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        let px = &mut self.pixels[i..i + 4];
+        for (c, new) in px.iter_mut().zip([color.r, color.g, color.b]) {
+            *c = to_u8(f64::from(*c) / 255.0 * (1.0 - color.a) + new * color.a);
+        }
+        px[3] = to_u8(f64::from(px[3]) / 255.0 * (1.0 - color.a) + color.a);
+    }
+
+    /// Draw a line `thickness` pixels wide, without antialiasing.
+    pub fn line(&mut self, a: Point2<f64>, b: Point2<f64>, color: Color, thickness: f64) {
+        if ![a, b].iter().all(|p| p.x.is_finite() && p.y.is_finite()) {
+            return;
+        }
+        let steps = (b - a).abs().max().ceil().max(1.0) as usize;
+        // Bounds the work for points far outside the image
+        let steps = steps.min(4 * (self.width + self.height) as usize);
+        let half = (thickness / 2.0).max(0.5);
+        let r = half.ceil() as i64;
+        let mut last = None;
+        for i in 0..=steps {
+            let p = a + (b - a) * (i as f64 / steps as f64);
+            let center = (p.x.floor() as i64, p.y.floor() as i64);
+            if last == Some(center) {
+                continue;
+            }
+            last = Some(center);
+            for dy in -r + 1..=r {
+                for dx in -r + 1..=r {
+                    if f64::hypot(dx as f64 - 0.5, dy as f64 - 0.5) <= half {
+                        self.blend(center.0 + dx, center.1 + dy, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw a closed outline through `points`.
+    pub fn polygon(&mut self, points: &[Point2<f64>], color: Color, thickness: f64) {
+        for (i, &p) in points.iter().enumerate() {
+            self.line(p, points[(i + 1) % points.len()], color, thickness);
+        }
+    }
+
+    /// Same shape as [`draw_crosshair`](crate::custom_shapes::draw_crosshair).
+    pub fn crosshair(&mut self, p: Point2<f64>, r: f64, color: Color, thickness: f64) {
+        self.line(p - Vector2::x() * r, p + Vector2::x() * r, color, thickness);
+        self.line(p - Vector2::y() * r, p + Vector2::y() * r, color, thickness);
+    }
+
+    /// Same shape as [`draw_crosshair_rotated`](crate::custom_shapes::draw_crosshair_rotated).
+    pub fn crosshair_rotated(&mut self, p: Point2<f64>, r: f64, color: Color, thickness: f64) {
+        let r = r * std::f64::consts::FRAC_1_SQRT_2;
+        self.line(
+            p + Vector2::new(-r, -r),
+            p + Vector2::new(r, r),
+            color,
+            thickness,
+        );
+        self.line(
+            p + Vector2::new(-r, r),
+            p + Vector2::new(r, -r),
+            color,
+            thickness,
+        );
+    }
+
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+fn to_u8(c: f64) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Render the runner's current state the way
+/// [`tracking_canvas_helpers::draw`](crate::tracking_canvas_helpers::draw) lays it out on a
+/// `width` by `height` canvas, within the limits described in the [module docs](self).
+pub fn render_tracking(
+    runner: &MotRunner,
+    raw: bool,
+    palette: &Palette,
+    width: u32,
+    height: u32,
+) -> Image {
+    let state = &runner.state;
+    let mut image = Image::new(
+        width,
+        height,
+        palette.background.unwrap_or(DEFAULT_BACKGROUND),
+    );
+    let (awidth, aheight) = (f64::from(width), f64::from(height));
+    let center = Point2::new(awidth / 2.0, aheight / 2.0);
+    let draw_size = (awidth.min(aheight).powi(2) / 2.0).sqrt();
+
+    let gravity_vec = state.orientation.inverse_transform_vector(&Vector3::z());
+    let gravity_angle = f64::atan2(-gravity_vec.z as f64, -gravity_vec.x as f64) + PI / 2.;
+    let gravity_rot = Rotation2::new(-gravity_angle);
+
+    // Border
+    let border_tf = Transform2::from_matrix_unchecked(
+        Translation2::new(center.x, center.y).to_homogeneous()
+            * gravity_rot.to_homogeneous()
+            * Scale2::new(draw_size, draw_size).to_homogeneous(),
+    );
+    let (hw, hh) = if state.is_poc_markers {
+        (0.5, 0.375)
+    } else {
+        (0.5, 0.5)
+    };
+    let border =
+        [(-hw, -hh), (hw, -hh), (hw, hh), (-hw, hh)].map(|(x, y)| border_tf * Point2::new(x, y));
+    image.polygon(&border, palette.foreground, 1.0);
+
+    // Up direction
+    let angle = -gravity_angle - PI / 2.;
+    let up = center + Vector2::new(angle.cos(), angle.sin()) * 50.0;
+    image.line(center, up, palette.up, 2.0);
+
+    let draw_tf = Transform2::from_matrix_unchecked(
+        Translation2::new(center.x, center.y).to_homogeneous()
+            * Scale2::new(draw_size, draw_size).to_homogeneous(),
+    );
+
+    if raw {
+        let [sensor_width, sensor_height] = state.sensor_resolution();
+        let mot_data = state.nf_data.iter().chain(state.wf_data.iter()).flatten();
+        for mot_data in mot_data.filter(|m| m.area != 0) {
+            let p = normalized_point(mot_data, sensor_width, sensor_height).cast::<f64>()
+                - Vector2::new(0.5, 0.5);
+            image.crosshair(draw_tf * (gravity_rot * p), 50.0, palette.foreground, 2.0);
+        }
+    } else {
+        let sensor_max = state.sensor_max();
+        let p = runner.general_config.camera_model_nf.p;
+        let normalized_scale = Scale2::new(p.m11 / sensor_max.x, p.m22 / sensor_max.y);
+        let to_canvas = |p: Point2<f32>| draw_tf * (gravity_rot * (normalized_scale * p).cast());
+        for marker in &state.nf_markers2 {
+            let color = palette.marker(marker.pattern_id, 9, palette.foreground);
+            image.crosshair(to_canvas(marker.normalized), 50.0, color, 1.0);
+        }
+        for marker in &state.wf_markers2 {
+            let color = palette.marker(marker.pattern_id, 6, palette.pattern[0]);
+            image.crosshair_rotated(to_canvas(marker.normalized), 50.0, color, 1.0);
+        }
+    }
+
+    // Center point
+    let diamond = [(0.0, -4.0), (4.0, 0.0), (0.0, 4.0), (-4.0, 0.0)]
+        .map(|(x, y)| center + Vector2::new(x, y));
+    image.polygon(&diamond, palette.foreground, 2.0);
+
+    image
+}
@@ -0,0 +1,727 @@
+use arrayvec::ArrayVec;
+use ats_common::ScreenCalibration;
+use ats_cv::foveated::FoveatedAimpointState;
+use ats_cv::to_normalized_image_coordinates;
+use nalgebra::{
+    Isometry3, Matrix3, Point2, Point3, Rotation3, Translation3, UnitQuaternion, UnitVector3,
+    Vector3,
+};
+use opencv_ros_camera::RosOpenCvIntrinsics;
+
+/// Output of a single nearfield/widefield fusion.
+#[derive(Clone, Copy, Debug)]
+pub struct FoveatedResult {
+    /// Coordinates between 0.0 and 1.0
+    pub aimpoint: Point2<f32>,
+    pub distance: f32,
+    /// Pose of the vision module relative to the screen, in the runner's y-down convention
+    pub pose: Isometry3<f32>,
+    pub screen_id: u8,
+}
+
+/// Undistort pixel coordinates and convert them to normalized image coordinates.
+///
+/// `stereo_iso` is the widefield-to-nearfield transform and should be `None` for nearfield points.
+pub fn normalize_points(
+    points: &[Point2<f32>],
+    intrinsics: &RosOpenCvIntrinsics<f32>,
+    stereo_iso: Option<&Isometry3<f32>>,
+) -> ArrayVec<Point2<f32>, 16> {
+    let intrinsics = ats_common::ros_opencv_intrinsics_type_convert(intrinsics);
+    ats_cv::undistort_points(&intrinsics, points)
+        .into_iter()
+        .take(16)
+        .map(|p| to_normalized_image_coordinates(p, &intrinsics, stereo_iso))
+        .collect()
+}
+
+/// Project model points to sensor pixel coordinates, the inverse of [`normalize_points`].
+///
+/// `camera_iso` is the camera pose in the screen frame. Only the pinhole part of `intrinsics` is
+/// applied, lens distortion is ignored. Markers behind the camera or outside the `sensor_res`
+/// (width, height) frame are dropped, so the output can be shorter than `markers`.
+pub fn project_markers(
+    markers: &[Point3<f32>],
+    camera_iso: &Isometry3<f32>,
+    intrinsics: &RosOpenCvIntrinsics<f32>,
+    sensor_res: [u16; 2],
+) -> Vec<Point2<i16>> {
+    let k = &intrinsics.k;
+    let [width, height] = sensor_res.map(f32::from);
+    markers
+        .iter()
+        .filter_map(|p| {
+            let p = camera_iso.inverse_transform_point(p);
+            if p.z <= 0.0 {
+                return None;
+            }
+            let (x, y) = (p.x / p.z, p.y / p.z);
+            let u = k.m11 * x + k.m12 * y + k.m13;
+            let v = k.m22 * y + k.m23;
+            ((0.0..width).contains(&u) && (0.0..height).contains(&v))
+                .then(|| Point2::new(u.round() as i16, v.round() as i16))
+        })
+        .collect()
+}
+
+/// Corners of the calibrated screen in the screen frame, clockwise from the top left.
+///
+/// The corners are where `calibration.homography` maps the screen plane (z = 0) to the corners of
+/// the normalized aimpoint range. Returns `None` if the homography can't be inverted.
+pub fn screen_corners(calibration: &ScreenCalibration<f32>) -> Option<[Point3<f32>; 4]> {
+    let inverse = calibration.homography.try_inverse()?;
+    let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(x, y)| {
+        let p = inverse * Vector3::new(x, y, 1.0);
+        Point3::new(p.x / p.z, p.y / p.z, 0.0)
+    });
+    corners
+        .iter()
+        .all(|p| p.coords.iter().all(|c| c.is_finite()))
+        .then_some(corners)
+}
+
+/// Fuse one frame of nearfield and widefield markers into an aimpoint and pose.
+///
+/// Markers are raw pixel coordinates as reported by the sensors. Unlike the runner, no filter
+/// state is carried between calls: a fresh [`FoveatedAimpointState`] observes the markers once, so
+/// the result depends only on the arguments. Returns `None` if the markers can't be identified
+/// against any of `screen_calibrations`.
+pub fn fuse_foveated<const N: usize>(
+    nf_markers: &[Point2<f32>],
+    wf_markers: &[Point2<f32>],
+    nf_intrinsics: &RosOpenCvIntrinsics<f32>,
+    wf_intrinsics: &RosOpenCvIntrinsics<f32>,
+    stereo_iso: &Isometry3<f32>,
+    gravity: UnitVector3<f32>,
+    screen_calibrations: &ArrayVec<(u8, ScreenCalibration<f32>), N>,
+) -> Option<FoveatedResult> {
+    let nf_markers = normalize_points(nf_markers, nf_intrinsics, None)
+        .into_iter()
+        .map(|position| ats_cv::foveated::Marker {
+            position: position.cast(),
+        })
+        .collect::<ArrayVec<_, 16>>();
+    let wf_markers = normalize_points(wf_markers, wf_intrinsics, Some(stereo_iso))
+        .into_iter()
+        .map(|position| ats_cv::foveated::Marker {
+            position: position.cast(),
+        })
+        .collect::<ArrayVec<_, 16>>();
+    fuse_foveated_normalized(&nf_markers, &wf_markers, gravity, screen_calibrations)
+}
+
+/// [`fuse_foveated`] for markers already in normalized image coordinates, with the widefield
+/// markers in the nearfield frame.
+///
+/// The runner uses this to identify the screen in each markers report before holding it, see
+/// [`hold_screen`](crate::mot_runner::hold_screen).
+pub fn fuse_foveated_normalized<const N: usize>(
+    nf_markers: &[ats_cv::foveated::Marker],
+    wf_markers: &[ats_cv::foveated::Marker],
+    gravity: UnitVector3<f32>,
+    screen_calibrations: &ArrayVec<(u8, ScreenCalibration<f32>), N>,
+) -> Option<FoveatedResult> {
+    let mut fv_state = FoveatedAimpointState::new();
+    fv_state.observe_markers(nf_markers, wf_markers, gravity.cast(), screen_calibrations);
+    let (pose, aimpoint_and_d) =
+        ats_cv::helpers::raycast_update(screen_calibrations, &mut fv_state, None);
+    let (rotation, translation) = pose?;
+    let (aimpoint, distance) = aimpoint_and_d?;
+    Some(FoveatedResult {
+        aimpoint,
+        distance,
+        pose: flip_yz_pose(rotation, translation),
+        screen_id: fv_state.screen_id,
+    })
+}
+
+/// Convert a pose from `ats_cv`'s y-up convention to the y-down convention used by the GUI.
+pub fn flip_yz_pose(rotation: Matrix3<f32>, translation: Vector3<f32>) -> Isometry3<f32> {
+    let flip_yz = Matrix3::new(1., 0., 0., 0., -1., 0., 0., 0., -1.);
+    let rot = Rotation3::from_matrix_unchecked(flip_yz * rotation * flip_yz);
+    let trans = Translation3::from(flip_yz * translation);
+    Isometry3::from_parts(trans, UnitQuaternion::from_rotation_matrix(&rot))
+}
+
+/// Minimum number of marker correspondences needed to solve for a pose
+pub const MIN_POSE_MARKERS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FusionError {
+    /// Fewer than [`MIN_POSE_MARKERS`] detections have a model correspondence
+    InsufficientMarkers { found: usize },
+    /// The correspondences don't determine a unique pose (e.g. collinear markers)
+    Degenerate,
+    /// The solved pose puts the markers, or the screen along the boresight, behind the camera
+    BehindCamera,
+}
+
+impl std::fmt::Display for FusionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FusionError::InsufficientMarkers { found } => write!(
+                f,
+                "insufficient markers: found {found}, need at least {MIN_POSE_MARKERS}"
+            ),
+            FusionError::Degenerate => write!(f, "degenerate marker configuration"),
+            FusionError::BehindCamera => write!(f, "markers behind the camera"),
+        }
+    }
+}
+
+impl std::error::Error for FusionError {}
+
+/// Pose solved from a (possibly partial) set of marker correspondences.
+#[derive(Clone, Copy, Debug)]
+pub struct MarkerPose {
+    /// Coordinates between 0.0 and 1.0
+    pub aimpoint: Point2<f32>,
+    /// Distance along the boresight from the camera to the screen plane, in meters
+    pub distance: f32,
+    /// Camera pose in the screen frame, the inverse of the model-to-camera transform
+    pub pose: Isometry3<f32>,
+    pub markers_used: usize,
+    /// Mean distance between the markers and the reprojected model points, in normalized image
+    /// coordinates
+    pub reprojection_error: f32,
+}
+
+impl MarkerPose {
+    /// See [`source_confidence`].
+    pub fn confidence(&self) -> f32 {
+        source_confidence(self.markers_used, self.reprojection_error)
+    }
+}
+
+/// Reprojection error, in normalized image coordinates, at which [`source_confidence`] halves
+pub const CONFIDENCE_HALF_ERROR: f32 = 0.01;
+
+/// How much to trust an aimpoint estimate, in `[0, 1]`.
+///
+/// Zero below [`MIN_POSE_MARKERS`]. Each marker beyond the minimum halves the remaining doubt, and
+/// the result is scaled down as the reprojection error grows past [`CONFIDENCE_HALF_ERROR`].
+pub fn source_confidence(markers_used: usize, reprojection_error: f32) -> f32 {
+    if markers_used < MIN_POSE_MARKERS || !reprojection_error.is_finite() {
+        return 0.0;
+    }
+    let extra = (markers_used - MIN_POSE_MARKERS + 1).min(16) as i32;
+    let count = 1.0 - 0.5f32.powi(extra);
+    count * CONFIDENCE_HALF_ERROR / (CONFIDENCE_HALF_ERROR + reprojection_error.max(0.0))
+}
+
+/// Confidence weighted blend of the nearfield and widefield aimpoints.
+#[derive(Clone, Copy, Debug)]
+pub struct BlendedAimpoint {
+    /// Coordinates between 0.0 and 1.0
+    pub aimpoint: Point2<f32>,
+    /// Share of the nearfield estimate, `nf_weight + wf_weight == 1`
+    pub nf_weight: f32,
+    pub wf_weight: f32,
+}
+
+/// Average the nearfield and widefield aimpoints weighted by their confidence, e.g. from
+/// [`MarkerPose::confidence`]. A missing estimate counts as zero confidence. Returns `None` if
+/// neither has any confidence.
+pub fn blend_aimpoints(
+    nf: Option<(Point2<f32>, f32)>,
+    wf: Option<(Point2<f32>, f32)>,
+) -> Option<BlendedAimpoint> {
+    let weight = |source: Option<(Point2<f32>, f32)>| {
+        source.map_or(0.0, |(_, confidence)| confidence.max(0.0))
+    };
+    let (nf_confidence, wf_confidence) = (weight(nf), weight(wf));
+    let total = nf_confidence + wf_confidence;
+    if total <= 0.0 {
+        return None;
+    }
+    let (nf_weight, wf_weight) = (nf_confidence / total, wf_confidence / total);
+    let point = |source: Option<(Point2<f32>, f32)>, weight: f32| {
+        source.map_or(nalgebra::Vector2::zeros(), |(p, _)| p.coords * weight)
+    };
+    Some(BlendedAimpoint {
+        aimpoint: Point2::from(point(nf, nf_weight) + point(wf, wf_weight)),
+        nf_weight,
+        wf_weight,
+    })
+}
+
+/// Solve for the camera pose from nearfield markers that have a `pattern_id`, using whichever
+/// subset of the model is visible.
+///
+/// Markers without a `pattern_id`, or whose id is out of range for the calibration, are ignored.
+/// Fails with [`FusionError::BehindCamera`] unless both the markers and the screen along the
+/// boresight are in front of the camera.
+pub fn fuse_partial(
+    markers: &[crate::Marker],
+    calibration: &ScreenCalibration<f32>,
+) -> Result<MarkerPose, FusionError> {
+    let (image_points, object_points): (ArrayVec<_, 16>, ArrayVec<_, 16>) = markers
+        .iter()
+        .filter_map(|m| {
+            let object_point = calibration.object_points.get(usize::from(m.pattern_id?))?;
+            Some((m.normalized, *object_point))
+        })
+        .take(16)
+        .unzip();
+    let pose = solve_planar_pose(&image_points, &object_points)?;
+    let reprojected = crate::correspondence::project_object_points(&pose, &object_points);
+    let reprojection_error = image_points
+        .iter()
+        .zip(&reprojected)
+        .map(|(p, q)| (p - q).norm())
+        .sum::<f32>()
+        / image_points.len() as f32;
+
+    // Intersect the boresight with the screen plane (z = 0)
+    let origin = pose.translation.vector;
+    let direction = pose.rotation * Vector3::z();
+    if direction.z.abs() < f32::EPSILON {
+        return Err(FusionError::Degenerate);
+    }
+    let distance = -origin.z / direction.z;
+    if distance <= 0.0 {
+        return Err(FusionError::BehindCamera);
+    }
+    let hit = origin + direction * distance;
+    let aimpoint = calibration.homography * hit.xy().push(1.0);
+    Ok(MarkerPose {
+        aimpoint: Point2::new(aimpoint.x / aimpoint.z, aimpoint.y / aimpoint.z),
+        distance,
+        pose,
+        markers_used: image_points.len(),
+        reprojection_error,
+    })
+}
+
+/// Distance from the camera to the screen along the optical axis, solved from pixel coordinates of
+/// coplanar markers with known model positions. Returns `None` if the pose can't be solved, see
+/// [`solve_planar_pose`].
+///
+/// This is the depth of the model origin in the camera frame, unlike [`MarkerPose::distance`]
+/// which follows the boresight to wherever it hits the screen.
+pub fn estimate_distance(
+    object_points: &[nalgebra::Point3<f32>],
+    image_points: &[Point2<f32>],
+    intrinsics: &RosOpenCvIntrinsics<f32>,
+) -> Option<f32> {
+    let image_points = normalize_points(image_points, intrinsics, None);
+    let pose = solve_planar_pose(&image_points, object_points).ok()?;
+    let distance = pose.inverse().translation.z;
+    (distance > 0.0).then_some(distance)
+}
+
+/// Solve the camera pose from normalized image points and coplanar model points.
+///
+/// The model points must share (approximately) the same z. A homography from the model plane to
+/// the image is estimated with the DLT and decomposed into a rotation and translation. A pose
+/// with any model point at or behind the camera (z <= 0) is rejected.
+pub fn solve_planar_pose(
+    image_points: &[Point2<f32>],
+    object_points: &[nalgebra::Point3<f32>],
+) -> Result<Isometry3<f32>, FusionError> {
+    let n = image_points.len().min(object_points.len());
+    if n < MIN_POSE_MARKERS {
+        return Err(FusionError::InsufficientMarkers { found: n });
+    }
+
+    let mut ata = nalgebra::SMatrix::<f64, 9, 9>::zeros();
+    for (img, obj) in image_points.iter().zip(object_points) {
+        let (x, y) = (obj.x as f64, obj.y as f64);
+        let (u, v) = (img.x as f64, img.y as f64);
+        let rows = [
+            nalgebra::SVector::<f64, 9>::from([-x, -y, -1., 0., 0., 0., u * x, u * y, u]),
+            nalgebra::SVector::<f64, 9>::from([0., 0., 0., -x, -y, -1., v * x, v * y, v]),
+        ];
+        for row in rows {
+            ata += row * row.transpose();
+        }
+    }
+    let eigen = ata.symmetric_eigen();
+    let (min_ix, _) = eigen.eigenvalues.argmin();
+    let h = eigen.eigenvectors.column(min_ix);
+    let h = Matrix3::new(h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], h[8]);
+
+    let h1 = h.column(0).into_owned();
+    let h2 = h.column(1).into_owned();
+    let scale = 2.0 / (h1.norm() + h2.norm());
+    if !scale.is_finite() {
+        return Err(FusionError::Degenerate);
+    }
+    // The model is in front of the camera, so the translation must have positive z
+    let scale = if h[(2, 2)] < 0.0 { -scale } else { scale };
+    let r1 = h1 * scale;
+    let r2 = h2 * scale;
+    let r3 = r1.cross(&r2);
+    let rotation = Rotation3::from_matrix(&Matrix3::from_columns(&[r1, r2, r3]));
+
+    // Markers sit at a common depth in front of the screen plane
+    let depth = object_points[..n].iter().map(|p| p.z as f64).sum::<f64>() / n as f64;
+    let translation = h.column(2) * scale - rotation * Vector3::z() * depth;
+
+    let model_to_camera = Isometry3::from_parts(
+        Translation3::from(translation),
+        UnitQuaternion::from_rotation_matrix(&rotation),
+    );
+    if object_points[..n]
+        .iter()
+        .any(|p| (model_to_camera * p.cast::<f64>()).z <= 0.0)
+    {
+        return Err(FusionError::BehindCamera);
+    }
+    Ok(model_to_camera.inverse().cast())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraParams;
+    use ats_common::MARKER_PATTERN_LEN;
+    use ats_usb::packets::vm::SENSOR_RESOLUTION;
+
+    /// Lens without distortion, so projecting and normalizing are exact inverses
+    fn intrinsics(f: f32) -> RosOpenCvIntrinsics<f32> {
+        CameraParams {
+            fx: f,
+            fy: f,
+            cx: 2048.0,
+            cy: 2048.0,
+            skew: 0.0,
+            dist: [0.0; 5],
+        }
+        .to_intrinsics()
+    }
+
+    fn nf_intrinsics() -> RosOpenCvIntrinsics<f32> {
+        intrinsics(5900.0)
+    }
+
+    fn wf_intrinsics() -> RosOpenCvIntrinsics<f32> {
+        intrinsics(2500.0)
+    }
+
+    /// Screen size in meters
+    const SCREEN: [f32; 2] = [1.6, 0.9];
+
+    /// Calibration of a [`SCREEN`] sized screen with the markers in screeninfogen's layout
+    fn calibration() -> ScreenCalibration<f32> {
+        const LAYOUT: [(f32, f32); 6] = [
+            (0.18, 0.29),
+            (0.15, 0.82),
+            (0.77, 0.8),
+            (0.51, 0.35),
+            (0.79, 0.17),
+            (0.49, 0.76),
+        ];
+        let [w, h] = SCREEN;
+        ScreenCalibration {
+            rotation: Default::default(),
+            homography: Matrix3::new(1.0 / w, 0.0, 0.0, 0.0, 1.0 / h, 0.0, 0.0, 0.0, 1.0),
+            object_points: std::array::from_fn(|i| {
+                let (x, y) = LAYOUT[i % LAYOUT.len()];
+                Point3::new(x * w, y * h, 0.0117)
+            }),
+        }
+    }
+
+    /// Camera at `eye` pointed at `target`, in the screen frame
+    fn camera_pose(eye: Point3<f32>, target: Point3<f32>) -> Isometry3<f32> {
+        Isometry3::face_towards(&eye, &target, &Vector3::y())
+    }
+
+    /// The first `count` model points as seen from `pose`, with their model index
+    fn seen_markers(
+        calibration: &ScreenCalibration<f32>,
+        pose: &Isometry3<f32>,
+        count: usize,
+    ) -> Vec<crate::Marker> {
+        calibration
+            .object_points
+            .iter()
+            .take(count)
+            .enumerate()
+            .map(|(i, p)| {
+                let p = pose.inverse_transform_point(p);
+                crate::Marker {
+                    mot_id: i as u8,
+                    pattern_id: Some(i as u8),
+                    raw: Point2::origin(),
+                    normalized: Point2::from(p.xy() / p.z),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn partial_pose_full_set() {
+        let calibration = calibration();
+        let target = Point3::new(0.3 * SCREEN[0], 0.6 * SCREEN[1], 0.0);
+        let eye = Point3::new(0.9, 0.3, -2.5);
+        let pose = camera_pose(eye, target);
+        let markers = seen_markers(&calibration, &pose, MARKER_PATTERN_LEN);
+        let fused = fuse_partial(&markers, &calibration).unwrap();
+        assert_eq!(fused.markers_used, MARKER_PATTERN_LEN);
+        assert!((fused.aimpoint - Point2::new(0.3, 0.6)).norm() < 1e-3);
+        assert!((fused.distance - (target - eye).norm()).abs() < 1e-2);
+        assert!(fused.reprojection_error < 1e-4);
+    }
+
+    #[test]
+    fn partial_pose_four_markers() {
+        let calibration = calibration();
+        let target = Point3::new(0.5 * SCREEN[0], 0.5 * SCREEN[1], 0.0);
+        let pose = camera_pose(Point3::new(0.5, 0.6, -2.0), target);
+        let mut markers = seen_markers(&calibration, &pose, MARKER_PATTERN_LEN);
+        // The rest are spurious
+        for m in &mut markers[MIN_POSE_MARKERS..] {
+            m.pattern_id = None;
+        }
+        let fused = fuse_partial(&markers, &calibration).unwrap();
+        assert_eq!(fused.markers_used, MIN_POSE_MARKERS);
+        assert!((fused.aimpoint - Point2::new(0.5, 0.5)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn partial_pose_too_few_markers() {
+        let calibration = calibration();
+        let pose = camera_pose(Point3::new(0.8, 0.45, -2.0), Point3::new(0.8, 0.45, 0.0));
+        let markers = seen_markers(&calibration, &pose, MIN_POSE_MARKERS - 1);
+        assert_eq!(
+            fuse_partial(&markers, &calibration).unwrap_err(),
+            FusionError::InsufficientMarkers {
+                found: MIN_POSE_MARKERS - 1
+            }
+        );
+    }
+
+    #[test]
+    fn partial_pose_screen_behind_boresight() {
+        let calibration = calibration();
+        // Off to the left of the screen, looking along it and slightly away from it
+        let eye = Point3::new(-1.0, 0.45, -0.3);
+        let pose = camera_pose(eye, eye + Vector3::new(1.0, 0.0, -0.1));
+        let markers = seen_markers(&calibration, &pose, MARKER_PATTERN_LEN);
+        assert!(markers.iter().all(|m| m.normalized.x.abs() < 10.0));
+        assert_eq!(
+            fuse_partial(&markers, &calibration).unwrap_err(),
+            FusionError::BehindCamera
+        );
+    }
+
+    /// What one sensor reports for a module at `pose`: the model points that land on the sensor,
+    /// projected with [`project_markers`] and normalized again with [`normalize_points`], with
+    /// their model index. `stereo_iso` is the widefield-to-nearfield transform.
+    fn sensor_markers(
+        calibration: &ScreenCalibration<f32>,
+        pose: &Isometry3<f32>,
+        intrinsics: &RosOpenCvIntrinsics<f32>,
+        stereo_iso: Option<&Isometry3<f32>>,
+    ) -> Vec<crate::Marker> {
+        let camera_iso = stereo_iso.map_or(*pose, |stereo_iso| pose * stereo_iso);
+        calibration
+            .object_points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let projected = project_markers(
+                    std::slice::from_ref(p),
+                    &camera_iso,
+                    intrinsics,
+                    SENSOR_RESOLUTION,
+                );
+                let raw = projected.first()?.cast::<f32>();
+                let normalized = normalize_points(&[raw], intrinsics, stereo_iso)[0];
+                Some(crate::Marker {
+                    mot_id: i as u8,
+                    pattern_id: Some(i as u8),
+                    raw,
+                    normalized,
+                })
+            })
+            .collect()
+    }
+
+    /// Module poses and the screen points they aim at, normalized
+    fn synthetic_poses() -> Vec<(Isometry3<f32>, Point2<f32>)> {
+        let [w, h] = SCREEN;
+        [
+            ((0.8, 0.45, -2.5), (0.5, 0.5)),
+            ((0.5, 0.2, -3.0), (0.3, 0.4)),
+            ((1.4, 0.7, -2.0), (0.7, 0.65)),
+            // Aimed at a corner, the nearfield sensor only sees part of the pattern
+            ((0.2, 0.1, -2.0), (0.1, 0.1)),
+        ]
+        .into_iter()
+        .map(|((x, y, z), (u, v))| {
+            let target = Point3::new(u * w, v * h, 0.0);
+            (camera_pose(Point3::new(x, y, z), target), Point2::new(u, v))
+        })
+        .collect()
+    }
+
+    #[test]
+    fn synthetic_pipeline_recovers_aimpoint() {
+        let calibration = calibration();
+        let (nf_intrinsics, wf_intrinsics) = (nf_intrinsics(), wf_intrinsics());
+        let stereo_iso = Isometry3::rotation(Vector3::new(0.01, -0.02, 0.005));
+        let mut partial_nf = false;
+        for (pose, expected) in synthetic_poses() {
+            let nf = sensor_markers(&calibration, &pose, &nf_intrinsics, None);
+            let wf = sensor_markers(&calibration, &pose, &wf_intrinsics, Some(&stereo_iso));
+            assert_eq!(wf.len(), MARKER_PATTERN_LEN);
+            partial_nf |= nf.len() < MARKER_PATTERN_LEN;
+
+            let nf = fuse_partial(&nf, &calibration).ok();
+            let wf = fuse_partial(&wf, &calibration).unwrap();
+            for fused in nf.iter().chain([&wf]) {
+                assert!(
+                    (fused.aimpoint - expected).norm() < 5e-3,
+                    "{:?} != {expected:?}",
+                    fused.aimpoint
+                );
+                assert!((fused.pose.translation.vector - pose.translation.vector).norm() < 0.05);
+            }
+
+            let estimate = |p: &MarkerPose| (p.aimpoint, p.confidence());
+            let blend = blend_aimpoints(nf.as_ref().map(estimate), Some(estimate(&wf))).unwrap();
+            assert!((blend.aimpoint - expected).norm() < 5e-3);
+            assert!((blend.nf_weight + blend.wf_weight - 1.0).abs() < 1e-6);
+        }
+        assert!(partial_nf, "no pose exercised a partial nearfield view");
+    }
+
+    #[test]
+    fn fuse_foveated_side_on() {
+        let calibration = calibration();
+        let object_points = calibration.object_points;
+        let calibrations: ArrayVec<_, 2> = [(3, calibration)].into_iter().collect();
+        let (nf_intrinsics, wf_intrinsics) = (nf_intrinsics(), wf_intrinsics());
+        let stereo_iso = Isometry3::rotation(Vector3::new(0.01, -0.02, 0.005));
+        let target = Point3::new(0.4 * SCREEN[0], 0.55 * SCREEN[1], 0.0);
+        let eye = Point3::new(-0.9, 0.35, -2.0);
+        let pose = camera_pose(eye, target);
+        let pixels = |camera_iso: &Isometry3<f32>, intrinsics| -> Vec<Point2<f32>> {
+            project_markers(&object_points, camera_iso, intrinsics, SENSOR_RESOLUTION)
+                .iter()
+                .map(|p| p.cast())
+                .collect()
+        };
+        let nf = pixels(&pose, &nf_intrinsics);
+        let wf = pixels(&(pose * stereo_iso), &wf_intrinsics);
+        assert_eq!(nf.len(), MARKER_PATTERN_LEN);
+        assert_eq!(wf.len(), MARKER_PATTERN_LEN);
+        // The screen's y axis points down
+        let gravity = UnitVector3::new_normalize(pose.inverse_transform_vector(&Vector3::y()));
+
+        let fused = fuse_foveated(
+            &nf,
+            &wf,
+            &nf_intrinsics,
+            &wf_intrinsics,
+            &stereo_iso,
+            gravity,
+            &calibrations,
+        )
+        .unwrap();
+        assert_eq!(fused.screen_id, 3);
+        assert!(
+            (fused.aimpoint - Point2::new(0.4, 0.55)).norm() < 5e-3,
+            "{:?}",
+            fused.aimpoint
+        );
+        assert!((fused.distance - (target - eye).norm()).abs() < 0.05);
+    }
+
+    #[test]
+    fn confidence_grows_with_markers_and_falls_with_error() {
+        assert_eq!(source_confidence(MIN_POSE_MARKERS - 1, 0.0), 0.0);
+        assert_eq!(source_confidence(MIN_POSE_MARKERS, f32::NAN), 0.0);
+        let four = source_confidence(MIN_POSE_MARKERS, 0.0);
+        let six = source_confidence(MIN_POSE_MARKERS + 2, 0.0);
+        assert!(0.0 < four && four < six && six <= 1.0);
+        let noisy = source_confidence(MIN_POSE_MARKERS, CONFIDENCE_HALF_ERROR);
+        assert!((noisy - four / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn blend_without_widefield_confidence_is_nearfield() {
+        let nf = Point2::new(0.3, 0.7);
+        let wf = Point2::new(0.6, 0.2);
+        for wf in [None, Some((wf, 0.0))] {
+            let blend = blend_aimpoints(Some((nf, 0.4)), wf).unwrap();
+            assert_eq!(blend.aimpoint, nf);
+            assert_eq!((blend.nf_weight, blend.wf_weight), (1.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn blend_weights_by_confidence() {
+        let nf = Point2::new(0.0, 0.0);
+        let wf = Point2::new(1.0, 0.5);
+        let blend = blend_aimpoints(Some((nf, 0.75)), Some((wf, 0.25))).unwrap();
+        assert_eq!((blend.nf_weight, blend.wf_weight), (0.75, 0.25));
+        assert!((blend.aimpoint - Point2::new(0.25, 0.125)).norm() < 1e-6);
+        assert!(blend_aimpoints(Some((nf, 0.0)), None).is_none());
+        assert!(blend_aimpoints(None, None).is_none());
+    }
+
+    #[test]
+    fn project_then_normalize_round_trip() {
+        let intrinsics = nf_intrinsics();
+        let camera_iso =
+            Isometry3::new(Vector3::new(0.5, 0.4, -2.0), Vector3::new(0.05, -0.1, 0.2));
+        let markers = [
+            Point3::new(0.45, 0.3, 0.0),
+            Point3::new(0.6, 0.45, 0.0117),
+            Point3::new(0.5, 0.5, 0.0),
+        ];
+        let projected = project_markers(&markers, &camera_iso, &intrinsics, SENSOR_RESOLUTION);
+        assert_eq!(projected.len(), markers.len());
+
+        let pixels: Vec<_> = projected.iter().map(|p| p.cast::<f32>()).collect();
+        let normalized = normalize_points(&pixels, &intrinsics, None);
+        for (marker, n) in markers.iter().zip(&normalized) {
+            let p = camera_iso.inverse_transform_point(marker);
+            // Within a pixel, the projection rounds to whole pixels
+            assert!((n - p.xy() / p.z).norm() < 1.0 / 5900.0);
+        }
+    }
+
+    #[test]
+    fn estimate_distance_side_on() {
+        let calibration = calibration();
+        let intrinsics = wf_intrinsics();
+        // Well off to the side of the screen, looking back at its center
+        let target = Point3::new(0.5 * SCREEN[0], 0.5 * SCREEN[1], 0.0);
+        let pose = camera_pose(Point3::new(-1.2, 0.3, -1.8), target);
+        let object_points = &calibration.object_points;
+        let pixels: Vec<_> = project_markers(object_points, &pose, &intrinsics, SENSOR_RESOLUTION)
+            .iter()
+            .map(|p| p.cast::<f32>())
+            .collect();
+        assert_eq!(pixels.len(), object_points.len());
+
+        let distance = estimate_distance(object_points, &pixels, &intrinsics).unwrap();
+        let expected = pose.inverse_transform_point(&Point3::origin()).z;
+        assert!(
+            (distance - expected).abs() < 0.01 * expected,
+            "{distance} != {expected}"
+        );
+        // The model origin, the screen's top left corner, is nearer than the aimed at center
+        assert!(distance < (target.coords - pose.translation.vector).norm());
+    }
+
+    #[test]
+    fn project_drops_hidden_markers() {
+        let intrinsics = nf_intrinsics();
+        let camera_iso = Isometry3::translation(0.5, 0.5, -2.0);
+        let markers = [
+            // Behind the camera
+            Point3::new(0.5, 0.5, -3.0),
+            // Outside the field of view
+            Point3::new(5.0, 0.5, 0.0),
+            Point3::new(0.5, 0.5, 0.0),
+        ];
+        let projected = project_markers(&markers, &camera_iso, &intrinsics, SENSOR_RESOLUTION);
+        assert_eq!(projected, vec![Point2::new(2048, 2048)]);
+    }
+}
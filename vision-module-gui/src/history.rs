@@ -0,0 +1,222 @@
+use nalgebra::{Matrix3x1, Point2};
+
+/// One entry of the aimpoint history.
+#[derive(Clone, Copy, Debug)]
+pub struct AimpointSample {
+    /// Device timestamp in microseconds of the most recent IMU sample when this entry was recorded,
+    /// or `None` if no IMU sample had arrived yet
+    pub timestamp: Option<u32>,
+    /// Coordinates between 0.0 and 1.0
+    pub aimpoint: Point2<f32>,
+    /// `aimpoint` before smoothing
+    pub raw_aimpoint: Point2<f32>,
+    pub opposite_cant: f32,
+    pub translation: Matrix3x1<f32>,
+    pub distance: f32,
+}
+
+impl Default for AimpointSample {
+    fn default() -> Self {
+        Self {
+            timestamp: None,
+            aimpoint: Point2::new(0.0, 0.0),
+            raw_aimpoint: Point2::new(0.0, 0.0),
+            opposite_cant: 0.0,
+            translation: Matrix3x1::new(0.0, 0.0, 0.0),
+            distance: 0.0,
+        }
+    }
+}
+
+/// The aimpoint looked up for a single impact.
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct ImpactAimpoint {
+    pub impact_timestamp: u32,
+    /// Coordinates between 0.0 and 1.0
+    pub aimpoint: Point2<f32>,
+    pub distance: f32,
+}
+
+/// Number of [`ImpactAimpoint`]s kept by the runner, older ones are dropped
+pub const IMPACT_AIMPOINTS_LEN: usize = 64;
+
+/// Find the sample recorded closest in time to `timestamp`.
+///
+/// Timestamps are device microseconds and are compared with wrapping arithmetic, so a lookup
+/// across a counter rollover still finds the right entry. Samples without a timestamp are ignored;
+/// returns `None` if there are no timestamped samples.
+pub fn nearest_in_time(history: &[AimpointSample], timestamp: u32) -> Option<&AimpointSample> {
+    history
+        .iter()
+        .filter_map(|s| Some((s, s.timestamp?)))
+        .min_by_key(|(_, t)| timestamp.wrapping_sub(*t).min(t.wrapping_sub(timestamp)))
+        .map(|(s, _)| s)
+}
+
+/// Default number of samples kept in an [`AimpointHistory`]
+pub const DEFAULT_HISTORY_LEN: usize = 80;
+
+/// Largest history length taken from the prefs
+pub const MAX_HISTORY_LEN: usize = 16384;
+
+/// Fixed-capacity ring buffer of recent aimpoint samples.
+///
+/// The capacity is chosen at construction; once full, each push evicts the oldest sample.
+#[derive(Clone, Debug)]
+pub struct AimpointHistory {
+    samples: Vec<AimpointSample>,
+    capacity: usize,
+    next: usize,
+}
+
+impl Default for AimpointHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LEN)
+    }
+}
+
+impl AimpointHistory {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            samples: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn push(&mut self, sample: AimpointSample) {
+        if self.samples.len() < self.capacity {
+            self.samples.push(sample);
+        } else {
+            self.samples[self.next] = sample;
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    /// The most recently pushed sample.
+    pub fn latest(&self) -> Option<&AimpointSample> {
+        let ix = (self.next + self.capacity - 1) % self.capacity;
+        self.samples.get(ix)
+    }
+
+    /// Samples from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &AimpointSample> {
+        let (newer, older) = self.samples.split_at(self.next.min(self.samples.len()));
+        older.iter().chain(newer)
+    }
+
+    /// Change the capacity, keeping the newest samples that still fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        let mut resized = Self::new(capacity);
+        let skip = self.len().saturating_sub(resized.capacity);
+        for &sample in self.iter().skip(skip) {
+            resized.push(sample);
+        }
+        *self = resized;
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.next = 0;
+    }
+
+    /// See [`nearest_in_time`].
+    pub fn nearest_in_time(&self, timestamp: u32) -> Option<&AimpointSample> {
+        nearest_in_time(&self.samples, timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: Option<u32>, x: f32) -> AimpointSample {
+        AimpointSample {
+            timestamp,
+            aimpoint: Point2::new(x, 0.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn nearest_in_time_picks_closest_sample() {
+        let history = [
+            sample(Some(1_000), 0.1),
+            sample(Some(2_000), 0.2),
+            sample(Some(3_000), 0.3),
+        ];
+        assert_eq!(nearest_in_time(&history, 2_400).unwrap().aimpoint.x, 0.2);
+        assert_eq!(nearest_in_time(&history, 2_600).unwrap().aimpoint.x, 0.3);
+        assert_eq!(nearest_in_time(&history, 0).unwrap().aimpoint.x, 0.1);
+        assert_eq!(nearest_in_time(&history, 10_000).unwrap().aimpoint.x, 0.3);
+    }
+
+    #[test]
+    fn nearest_in_time_across_rollover() {
+        let history = [sample(Some(u32::MAX - 100), 0.1), sample(Some(50), 0.2)];
+        assert_eq!(nearest_in_time(&history, 20).unwrap().aimpoint.x, 0.2);
+        assert_eq!(
+            nearest_in_time(&history, u32::MAX - 90).unwrap().aimpoint.x,
+            0.1
+        );
+    }
+
+    #[test]
+    fn nearest_in_time_without_timestamps() {
+        assert!(nearest_in_time(&[], 1_000).is_none());
+        let history = [sample(None, 0.1), sample(None, 0.2)];
+        assert!(nearest_in_time(&history, 1_000).is_none());
+        let history = [sample(None, 0.1), sample(Some(5_000), 0.2)];
+        assert_eq!(nearest_in_time(&history, 0).unwrap().aimpoint.x, 0.2);
+    }
+
+    #[test]
+    fn history_evicts_oldest() {
+        let mut history = AimpointHistory::new(3);
+        assert!(history.is_empty());
+        assert!(history.latest().is_none());
+        for i in 0..5 {
+            history.push(sample(Some(i), i as f32));
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.latest().unwrap().timestamp, Some(4));
+        let timestamps: Vec<_> = history.iter().map(|s| s.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [2, 3, 4]);
+        assert_eq!(history.nearest_in_time(0).unwrap().timestamp, Some(2));
+    }
+
+    #[test]
+    fn history_set_capacity_keeps_newest() {
+        let mut history = AimpointHistory::new(5);
+        for i in 0..7 {
+            history.push(sample(Some(i), 0.0));
+        }
+        history.set_capacity(2);
+        assert_eq!(history.capacity(), 2);
+        let timestamps: Vec<_> = history.iter().map(|s| s.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [5, 6]);
+
+        history.set_capacity(4);
+        history.push(sample(Some(7), 0.0));
+        let timestamps: Vec<_> = history.iter().map(|s| s.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [5, 6, 7]);
+        assert_eq!(history.latest().unwrap().timestamp, Some(7));
+
+        history.clear();
+        assert!(history.is_empty());
+        assert_eq!(AimpointHistory::new(0).capacity(), 1);
+    }
+}
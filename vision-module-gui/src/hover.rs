@@ -0,0 +1,81 @@
+use nalgebra::Point2;
+
+/// Cursor distance in pixels within which a drawn marker counts as hovered
+pub const HOVER_RADIUS: f64 = 15.0;
+
+/// A marker as drawn on a tracking canvas, with the details shown when hovering it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarkerReadout {
+    /// Where the marker was drawn, in canvas coordinates
+    pub position: Point2<f64>,
+    /// "nf" or "wf"
+    pub camera: &'static str,
+    /// Slot in the report the marker came from
+    pub id: u8,
+    pub pattern_id: Option<u8>,
+    pub screen_id: u8,
+    /// Coordinates as reported by the sensor
+    pub raw: Point2<f32>,
+    /// Normalized image coordinates, or between 0.0 and 1.0 on the raw canvas
+    pub normalized: Point2<f32>,
+    /// Blob area, only object reports have it
+    pub area: Option<u32>,
+}
+
+impl MarkerReadout {
+    /// One line summary for the canvas.
+    pub fn describe(&self) -> String {
+        let mut s = format!(
+            "{} id={} pattern={} screen={} raw=({:.1}, {:.1}) normalized=({:.4}, {:.4})",
+            self.camera,
+            self.id,
+            self.pattern_id
+                .map_or_else(|| String::from("-"), |id| id.to_string()),
+            self.screen_id,
+            self.raw.x,
+            self.raw.y,
+            self.normalized.x,
+            self.normalized.y,
+        );
+        if let Some(area) = self.area {
+            s += &format!(" area={area}");
+        }
+        s
+    }
+}
+
+/// Hover inspection state of a tracking canvas.
+///
+/// The canvas records the markers it drew with [`set_drawn`](Self::set_drawn) and the cursor
+/// with [`set_cursor`](Self::set_cursor); [`hovered`](Self::hovered) is then the drawn marker
+/// nearest the cursor.
+#[derive(Clone, Debug, Default)]
+pub struct MarkerHover {
+    drawn: Vec<MarkerReadout>,
+    cursor: Option<Point2<f64>>,
+}
+
+impl MarkerHover {
+    pub fn set_drawn(&mut self, drawn: Vec<MarkerReadout>) {
+        self.drawn = drawn;
+    }
+
+    /// Move the cursor, `None` once it leaves the canvas. Returns true if the hovered marker
+    /// changed, so the readout needs a redraw.
+    pub fn set_cursor(&mut self, cursor: Option<Point2<f64>>) -> bool {
+        let before = self.hovered().cloned();
+        self.cursor = cursor;
+        self.hovered() != before.as_ref()
+    }
+
+    /// The drawn marker nearest the cursor, `None` if none is within [`HOVER_RADIUS`].
+    pub fn hovered(&self) -> Option<&MarkerReadout> {
+        let cursor = self.cursor?;
+        self.drawn
+            .iter()
+            .map(|m| ((m.position - cursor).norm(), m))
+            .filter(|(d, _)| *d <= HOVER_RADIUS)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, m)| m)
+    }
+}
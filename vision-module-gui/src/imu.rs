@@ -0,0 +1,520 @@
+use nalgebra::{UnitQuaternion, Vector3};
+use std::collections::VecDeque;
+
+/// Standard gravity in m/s^2
+pub const GRAVITY: f32 = 9.80665;
+
+/// Mean gravity error (radians) below which the orientation is considered converged
+pub const ORIENTATION_CONVERGED_THRESHOLD: f32 = 0.05;
+
+/// Tracks the gyro zero-rate offset while the device is held still.
+///
+/// A sample counts as stationary when the gyro magnitude is small and the accel magnitude is close
+/// to gravity. Once the device has been stationary for `settle_time` seconds, the estimate is
+/// nudged toward the measured rate with time constant `time_constant` seconds.
+#[derive(Clone, Debug)]
+pub struct GyroBiasEstimator {
+    /// Maximum gyro magnitude (rad/s) considered stationary
+    pub gyro_threshold: f32,
+    /// Maximum deviation of the accel magnitude from gravity (m/s^2) considered stationary
+    pub accel_threshold: f32,
+    /// Seconds of continuous stillness required before the estimate is updated
+    pub settle_time: f32,
+    /// Time constant (seconds) of the low-pass filter applied to the estimate
+    pub time_constant: f32,
+    bias: Vector3<f32>,
+    stationary_time: f32,
+}
+
+impl Default for GyroBiasEstimator {
+    fn default() -> Self {
+        Self {
+            gyro_threshold: 0.05,
+            accel_threshold: 0.3,
+            settle_time: 0.5,
+            time_constant: 5.0,
+            bias: Vector3::zeros(),
+            stationary_time: 0.0,
+        }
+    }
+}
+
+impl GyroBiasEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current running bias estimate in rad/s.
+    pub fn bias(&self) -> Vector3<f32> {
+        self.bias
+    }
+
+    /// True if the last samples were still long enough for the estimate to be updating.
+    pub fn is_stationary(&self) -> bool {
+        self.stationary_time >= self.settle_time
+    }
+
+    pub fn reset(&mut self) {
+        self.bias = Vector3::zeros();
+        self.stationary_time = 0.0;
+    }
+
+    /// Feed one IMU sample (`dt` in seconds) and return the bias-corrected gyro rate.
+    pub fn update(&mut self, accel: Vector3<f32>, gyro: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        let corrected = gyro - self.bias;
+        let still = corrected.norm() < self.gyro_threshold
+            && (accel.norm() - GRAVITY).abs() < self.accel_threshold;
+        if !still {
+            self.stationary_time = 0.0;
+            return corrected;
+        }
+        self.stationary_time += dt;
+        if self.is_stationary() {
+            let alpha = (dt / self.time_constant).clamp(0.0, 1.0);
+            self.bias += (gyro - self.bias) * alpha;
+        }
+        gyro - self.bias
+    }
+}
+
+/// Samples averaged by the default [`OrientationQuality`], one second at 100 Hz
+pub const DEFAULT_ORIENTATION_WINDOW: usize = 100;
+
+/// Measures how well an orientation estimate agrees with the accelerometer.
+///
+/// Each sample records the angle between the gravity direction predicted by the orientation and
+/// the measured accel direction. The metric is the mean of that angle over the last `window`
+/// samples, so it decays as the filter settles after a reset.
+#[derive(Clone, Debug)]
+pub struct OrientationQuality {
+    angles: VecDeque<f32>,
+    window: usize,
+}
+
+impl Default for OrientationQuality {
+    fn default() -> Self {
+        Self::new(DEFAULT_ORIENTATION_WINDOW)
+    }
+}
+
+impl OrientationQuality {
+    pub fn new(window: usize) -> Self {
+        Self {
+            angles: VecDeque::with_capacity(window),
+            window: window.max(1),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.angles.clear();
+    }
+
+    /// Add a sample. `orientation` rotates sensor-frame vectors into the earth frame (z up), as
+    /// produced by Madgwick.
+    pub fn update(&mut self, orientation: &UnitQuaternion<f32>, accel: Vector3<f32>) {
+        if let Some(angle) = gravity_error(orientation, accel) {
+            if self.angles.len() == self.window {
+                self.angles.pop_front();
+            }
+            self.angles.push_back(angle);
+        }
+    }
+
+    /// Mean gravity error in radians over the window, or `None` if no samples have been seen.
+    pub fn mean_error(&self) -> Option<f32> {
+        if self.angles.is_empty() {
+            None
+        } else {
+            Some(self.angles.iter().sum::<f32>() / self.angles.len() as f32)
+        }
+    }
+
+    /// True once the window is full and its mean error is below `threshold` radians.
+    pub fn is_converged(&self, threshold: f32) -> bool {
+        self.angles.len() == self.window && self.mean_error().is_some_and(|e| e < threshold)
+    }
+}
+
+/// Angle in radians between the gravity direction predicted by `orientation` and the measured
+/// `accel` direction. Returns `None` if `accel` is zero.
+pub fn gravity_error(orientation: &UnitQuaternion<f32>, accel: Vector3<f32>) -> Option<f32> {
+    let measured = accel.try_normalize(f32::EPSILON)?;
+    let predicted = orientation.inverse_transform_vector(&Vector3::z());
+    Some(predicted.dot(&measured).clamp(-1.0, 1.0).acos())
+}
+
+/// Default [`ImpactDetector::threshold`] in g
+pub const DEFAULT_SOFTWARE_IMPACT_THRESHOLD_G: f32 = 4.0;
+
+/// Software impact detector for devices without hardware impact detection.
+///
+/// Fires when the low-pass filtered accel magnitude rises above `threshold`, then stays quiet for
+/// `suppress_ms` so a single impact only fires once.
+#[derive(Clone, Debug)]
+pub struct ImpactDetector {
+    /// Filtered accel magnitude (m/s^2) that triggers an impact
+    pub threshold: f32,
+    /// Minimum time between impacts in milliseconds
+    pub suppress_ms: u32,
+    /// Weight of the newest sample in the magnitude filter, 1.0 disables filtering
+    pub alpha: f32,
+    filtered: Option<f32>,
+    last_impact: Option<u32>,
+}
+
+impl Default for ImpactDetector {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_SOFTWARE_IMPACT_THRESHOLD_G * GRAVITY,
+            suppress_ms: 100,
+            alpha: 0.5,
+            filtered: None,
+            last_impact: None,
+        }
+    }
+}
+
+impl ImpactDetector {
+    pub fn new(threshold: f32, suppress_ms: u32) -> Self {
+        Self {
+            threshold,
+            suppress_ms,
+            ..Default::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.filtered = None;
+        self.last_impact = None;
+    }
+
+    /// Feed one accel sample with its device timestamp in microseconds. Returns true if an impact
+    /// fired on this sample.
+    pub fn update(&mut self, accel: Vector3<f32>, timestamp: u32) -> bool {
+        let magnitude = accel.norm();
+        let filtered = match self.filtered {
+            Some(f) => f + (magnitude - f) * self.alpha,
+            None => magnitude,
+        };
+        self.filtered = Some(filtered);
+
+        if filtered < self.threshold {
+            return false;
+        }
+        if let Some(last) = self.last_impact {
+            if timestamp.wrapping_sub(last) < self.suppress_ms.saturating_mul(1000) {
+                return false;
+            }
+        }
+        self.last_impact = Some(timestamp);
+        true
+    }
+}
+
+/// One calibrated IMU sample.
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct AccelSample {
+    /// Device timestamp in microseconds
+    pub timestamp: u32,
+    /// m/s^2
+    pub accel: Vector3<f32>,
+    /// rad/s
+    pub gyro: Vector3<f32>,
+}
+
+/// Number of [`ImpactAccelWindow`]s kept by the runner, older ones are dropped
+pub const IMPACT_ACCEL_WINDOWS_LEN: usize = 16;
+
+/// The IMU samples from `pre_us` before to `post_us` after an impact.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ImpactAccelWindow {
+    pub impact_timestamp: u32,
+    /// Oldest first
+    pub samples: Vec<AccelSample>,
+}
+
+impl ImpactAccelWindow {
+    /// Largest accel magnitude in the window in m/s^2, or `None` if it has no samples.
+    pub fn peak_accel(&self) -> Option<f32> {
+        self.samples
+            .iter()
+            .map(|s| s.accel.norm())
+            .max_by(f32::total_cmp)
+    }
+}
+
+/// Keeps the recent IMU samples and cuts out the window around each impact.
+///
+/// An impact is usually reported before the samples that follow it have arrived, so its window is
+/// held back until a sample at least `post_us` after the impact has been pushed. Timestamps are
+/// device microseconds and compared with wrapping arithmetic.
+#[derive(Clone, Debug)]
+pub struct ImpactAccelRecorder {
+    /// Microseconds of samples kept before the impact
+    pub pre_us: u32,
+    /// Microseconds of samples kept after the impact
+    pub post_us: u32,
+    samples: VecDeque<AccelSample>,
+    pending: Vec<u32>,
+}
+
+/// Impacts stamped further than this (microseconds) ahead of the newest sample are on a
+/// different clock and are captured right away instead of waiting for samples that never come
+const MAX_IMPACT_LEAD_US: i32 = 1_000_000;
+
+impl Default for ImpactAccelRecorder {
+    fn default() -> Self {
+        Self::new(50_000, 100_000)
+    }
+}
+
+impl ImpactAccelRecorder {
+    pub fn new(pre_us: u32, post_us: u32) -> Self {
+        Self {
+            pre_us,
+            post_us,
+            samples: VecDeque::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.pending.clear();
+    }
+
+    /// Mark an impact at device time `timestamp`. Its window is returned by a later
+    /// [`push`](Self::push).
+    pub fn impact(&mut self, timestamp: u32) {
+        self.pending.push(timestamp);
+    }
+
+    /// Add the next sample and return the windows of all impacts it completes.
+    pub fn push(&mut self, sample: AccelSample) -> Vec<ImpactAccelWindow> {
+        let latest = sample.timestamp;
+        self.samples.push_back(sample);
+        // A pending impact can't need anything older than this
+        let keep = self.pre_us.saturating_add(self.post_us);
+        while self
+            .samples
+            .front()
+            .is_some_and(|s| latest.wrapping_sub(s.timestamp) > keep)
+        {
+            self.samples.pop_front();
+        }
+
+        let post = self.post_us.min(i32::MAX as u32) as i32;
+        let (done, pending) = self.pending.iter().partition::<Vec<u32>, _>(|&&t| {
+            let since = latest.wrapping_sub(t) as i32;
+            since >= post || since < -MAX_IMPACT_LEAD_US
+        });
+        self.pending = pending;
+        done.into_iter().map(|t| self.window(t)).collect()
+    }
+
+    fn window(&self, impact_timestamp: u32) -> ImpactAccelWindow {
+        let start = impact_timestamp.wrapping_sub(self.pre_us);
+        let len = self.pre_us.saturating_add(self.post_us);
+        ImpactAccelWindow {
+            impact_timestamp,
+            samples: self
+                .samples
+                .iter()
+                .filter(|s| s.timestamp.wrapping_sub(start) <= len)
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accel_sample(timestamp: u32) -> AccelSample {
+        AccelSample {
+            timestamp,
+            accel: Vector3::new(0.0, 0.0, GRAVITY),
+            gyro: Vector3::zeros(),
+        }
+    }
+
+    #[test]
+    fn gyro_bias_converges_while_still() {
+        let mut estimator = GyroBiasEstimator::new();
+        let accel = Vector3::new(0.0, 0.0, GRAVITY);
+        let offset = Vector3::new(0.01, -0.02, 0.005);
+        let dt = 0.01;
+        // 30 s at 100 Hz is six time constants
+        for _ in 0..3000 {
+            estimator.update(accel, offset, dt);
+        }
+        assert!(estimator.is_stationary());
+        assert!((estimator.bias() - offset).norm() < 1e-4);
+        assert!(estimator.update(accel, offset, dt).norm() < 1e-4);
+    }
+
+    #[test]
+    fn gyro_bias_waits_for_settle_time() {
+        let mut estimator = GyroBiasEstimator::new();
+        let accel = Vector3::new(0.0, 0.0, GRAVITY);
+        let offset = Vector3::new(0.01, 0.0, 0.0);
+        for _ in 0..40 {
+            estimator.update(accel, offset, 0.01);
+        }
+        assert!(!estimator.is_stationary());
+        assert_eq!(estimator.bias(), Vector3::zeros());
+    }
+
+    #[test]
+    fn gyro_bias_ignores_motion() {
+        let mut estimator = GyroBiasEstimator::new();
+        let still = Vector3::new(0.0, 0.0, GRAVITY);
+        let rotating = Vector3::new(0.0, 1.0, 0.0);
+        for _ in 0..1000 {
+            assert_eq!(estimator.update(still, rotating, 0.01), rotating);
+        }
+        // Still gyro, but the device is being accelerated
+        let moving = Vector3::new(0.0, 5.0, GRAVITY);
+        for _ in 0..1000 {
+            estimator.update(moving, Vector3::new(0.01, 0.0, 0.0), 0.01);
+        }
+        assert!(!estimator.is_stationary());
+        assert_eq!(estimator.bias(), Vector3::zeros());
+
+        estimator.update(still, Vector3::zeros(), 1.0);
+        assert!(estimator.is_stationary());
+        estimator.reset();
+        assert!(!estimator.is_stationary());
+    }
+
+    #[test]
+    fn orientation_quality_decays_after_reset() {
+        use ahrs::Ahrs;
+
+        // Level and still, but the filter starts tilted 30 degrees, as after a reset
+        let accel = Vector3::new(0.0, 0.0, GRAVITY);
+        let tilt = UnitQuaternion::from_euler_angles(30f32.to_radians(), 0.0, 0.0);
+        let mut madgwick = ahrs::Madgwick::new_with_quat(0.01, 0.1, tilt);
+        let mut quality = OrientationQuality::default();
+
+        quality.update(&madgwick.quat, accel);
+        let initial = quality.mean_error().unwrap();
+        assert!((initial - 30f32.to_radians()).abs() < 1e-3);
+
+        let mut previous = initial;
+        for _ in 0..10 {
+            for _ in 0..100 {
+                madgwick.update_imu(&Vector3::zeros(), &accel).unwrap();
+                quality.update(&madgwick.quat, accel);
+            }
+            let error = quality.mean_error().unwrap();
+            assert!(error <= previous, "{error} > {previous}");
+            previous = error;
+        }
+        assert!(previous < ORIENTATION_CONVERGED_THRESHOLD);
+        assert!(quality.is_converged(ORIENTATION_CONVERGED_THRESHOLD));
+    }
+
+    #[test]
+    fn orientation_quality_needs_full_window() {
+        let mut quality = OrientationQuality::new(3);
+        let level = UnitQuaternion::identity();
+        let accel = Vector3::new(0.0, 0.0, GRAVITY);
+        quality.update(&level, accel);
+        quality.update(&level, accel);
+        assert_eq!(quality.mean_error(), Some(0.0));
+        assert!(!quality.is_converged(ORIENTATION_CONVERGED_THRESHOLD));
+        quality.update(&level, accel);
+        assert!(quality.is_converged(ORIENTATION_CONVERGED_THRESHOLD));
+    }
+
+    #[test]
+    fn impact_detector_threshold_and_debounce() {
+        let mut detector = ImpactDetector::new(4.0 * GRAVITY, 100);
+        detector.alpha = 1.0;
+        let rest = Vector3::new(0.0, 0.0, GRAVITY);
+        let spike = Vector3::new(0.0, 0.0, 6.0 * GRAVITY);
+        let below = Vector3::new(0.0, 0.0, 3.9 * GRAVITY);
+
+        assert!(!detector.update(rest, 0));
+        assert!(!detector.update(below, 1_000));
+        assert!(detector.update(spike, 2_000));
+        // Within the 100 ms suppress time
+        assert!(!detector.update(spike, 3_000));
+        assert!(!detector.update(rest, 50_000));
+        assert!(!detector.update(spike, 101_000));
+        assert!(detector.update(spike, 102_000));
+    }
+
+    #[test]
+    fn impact_detector_filters_single_sample_spikes() {
+        let mut detector = ImpactDetector::new(4.0 * GRAVITY, 100);
+        let rest = Vector3::new(0.0, 0.0, GRAVITY);
+        assert!(!detector.update(rest, 0));
+        // Halfway between rest and 6 g with the default filter
+        assert!(!detector.update(Vector3::new(0.0, 0.0, 6.0 * GRAVITY), 1_000));
+        assert!(detector.update(Vector3::new(0.0, 0.0, 8.0 * GRAVITY), 2_000));
+        detector.reset();
+        assert!(detector.update(Vector3::new(0.0, 0.0, 8.0 * GRAVITY), 3_000));
+    }
+
+    #[test]
+    fn recorder_waits_for_post_impact_samples() {
+        let mut recorder = ImpactAccelRecorder::new(20_000, 30_000);
+        for t in (0..100_000).step_by(10_000) {
+            assert!(recorder.push(accel_sample(t)).is_empty());
+        }
+        recorder.impact(95_000);
+        assert!(recorder.push(accel_sample(100_000)).is_empty());
+        assert!(recorder.push(accel_sample(110_000)).is_empty());
+        assert!(recorder.push(accel_sample(120_000)).is_empty());
+        let windows = recorder.push(accel_sample(130_000));
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].impact_timestamp, 95_000);
+        let timestamps: Vec<_> = windows[0].samples.iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, [80_000, 90_000, 100_000, 110_000, 120_000]);
+        assert!(recorder.push(accel_sample(140_000)).is_empty());
+    }
+
+    #[test]
+    fn recorder_window_across_rollover() {
+        let mut recorder = ImpactAccelRecorder::new(10_000, 10_000);
+        let start = u32::MAX - 25_000;
+        for i in 0..4 {
+            recorder.push(accel_sample(start.wrapping_add(i * 10_000)));
+        }
+        recorder.impact(start.wrapping_add(30_000));
+        let windows = recorder.push(accel_sample(start.wrapping_add(40_000)));
+        assert_eq!(windows.len(), 1);
+        let timestamps: Vec<_> = windows[0].samples.iter().map(|s| s.timestamp).collect();
+        assert_eq!(
+            timestamps,
+            [20_000, 30_000, 40_000].map(|t| start.wrapping_add(t))
+        );
+    }
+
+    #[test]
+    fn recorder_flushes_impact_on_another_clock() {
+        let mut recorder = ImpactAccelRecorder::new(10_000, 10_000);
+        recorder.push(accel_sample(1_000));
+        recorder.impact(50_000_000);
+        let windows = recorder.push(accel_sample(2_000));
+        assert_eq!(windows.len(), 1);
+        assert!(windows[0].samples.is_empty());
+    }
+
+    #[test]
+    fn window_peak_accel() {
+        let mut window = ImpactAccelWindow {
+            impact_timestamp: 0,
+            samples: vec![],
+        };
+        assert_eq!(window.peak_accel(), None);
+        window.samples = vec![accel_sample(0), accel_sample(1)];
+        window.samples[1].accel = Vector3::new(3.0, 4.0, 0.0);
+        assert_eq!(window.peak_accel(), Some(GRAVITY));
+        window.samples[1].accel = Vector3::new(30.0, 40.0, 0.0);
+        assert_eq!(window.peak_accel(), Some(50.0));
+    }
+}
@@ -5,16 +5,31 @@ use ats_usb::packets::vm::MotData;
 use nalgebra::Isometry3;
 use nalgebra::{Matrix3, Matrix3x1, Point2, Rotation3};
 use serde::Serialize;
+use std::collections::VecDeque;
 
+pub mod aimpoint_filter;
+pub mod camera;
 pub mod config_window;
 pub mod consts;
+pub mod correspondence;
 pub mod custom_shapes;
+pub mod export_image;
+pub mod fusion;
+pub mod history;
+pub mod hover;
+pub mod imu;
 pub mod layout_macro;
+pub mod marker_pattern;
 pub mod mot_runner;
 pub mod plots_window;
+pub mod prefs;
+pub mod rate;
 pub mod run_canvas;
 pub mod run_raw_canvas;
+pub mod shortcuts;
+pub mod snapshot;
 pub mod test_canvas;
+pub mod theme;
 pub mod tracking_canvas_helpers;
 
 pub trait CloneButShorter: Clone {
@@ -34,6 +49,10 @@ pub struct TestFrame {
     pub position_x: Option<f32>,
     pub position_y: Option<f32>,
     pub position_z: Option<f32>,
+    /// Aimpoint before smoothing, only filled in when [`mot_runner::MotRunner::log_raw_aimpoint`]
+    /// is set
+    pub raw_aimpoint_x: Option<f32>,
+    pub raw_aimpoint_y: Option<f32>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug)]
@@ -67,6 +86,8 @@ impl From<ScreenInfo> for ats_common::ScreenCalibration<f32> {
 pub struct MotState {
     // Coordinates between 0.0 and 1.0
     pub fv_aimpoint: Point2<f32>,
+    /// `fv_aimpoint` before it went through `aimpoint_filter`
+    pub fv_aimpoint_raw: Point2<f32>,
 
     pub distance: f32,
 
@@ -86,31 +107,127 @@ pub struct MotState {
     /// vs regular CombinedMarkersReport (4095x4095)
     pub is_poc_markers: bool,
 
+    /// Screen being tracked, held by [`screen_hysteresis`](Self::screen_hysteresis) so it doesn't
+    /// flicker. The filter, raycast and marker matching only use this screen's calibration, see
+    /// [`mot_runner::hold_screen`].
     pub screen_id: u8,
+    pub screen_hysteresis: mot_runner::ScreenHysteresis,
     pub orientation: Rotation3<f32>,
     pub madgwick: ahrs::Madgwick<f32>,
+    pub gyro_bias: imu::GyroBiasEstimator,
+    pub orientation_quality: imu::OrientationQuality,
+    pub impact_detector: imu::ImpactDetector,
 
     pub rotation_mat: Matrix3<f32>,
     pub translation_mat: Matrix3x1<f32>,
 
     pub fv_state: ats_cv::foveated::FoveatedAimpointState,
     pub fv_zero_offset: Isometry3<f32>,
+    /// Keeps the nearfield model indices stable from frame to frame
+    pub nf_tracker: correspondence::MarkerTracker,
+    /// Undistortion of the reported marker positions, see [`camera::UndistortMap`]
+    pub nf_undistort: camera::UndistortCache,
+    pub wf_undistort: camera::UndistortCache,
+    /// Consecutive markers reports with too few markers to track, see
+    /// [`mot_runner::TRACKING_LOST_FRAMES`]
+    pub frames_without_markers: u32,
+    pub tracking_status: mot_runner::TrackingStatus,
+    /// Confidence weighted blend of the nearfield and widefield pose solves from the latest
+    /// markers report, `None` if neither had enough identified markers
+    pub aimpoint_blend: Option<fusion::BlendedAimpoint>,
+    /// Smoothing applied to `fv_aimpoint`, see [`MotState::set_aimpoint_filter`]
+    pub aimpoint_filter: Option<Box<dyn aimpoint_filter::AimpointFilter>>,
+    /// When `aimpoint_filter` was last updated, for its time step
+    pub aimpoint_filter_time: Option<std::time::Instant>,
 
-    pub fv_aimpoint_history: [(Point2<f32>, f32, Matrix3x1<f32>); 80],
-    pub fv_aimpoint_history_index: usize,
+    pub fv_aimpoint_history: history::AimpointHistory,
+    /// Device timestamp of the most recent IMU sample, used to stamp the aimpoint history
+    pub last_imu_timestamp: Option<u32>,
+    /// Aimpoint at the time of each recent impact, nearest in time from the history. Holds the
+    /// last [`history::IMPACT_AIMPOINTS_LEN`] impacts, oldest first.
+    pub impact_aimpoints: VecDeque<history::ImpactAimpoint>,
+    pub impact_accel: imu::ImpactAccelRecorder,
+    /// IMU samples around each recent impact, in the order the windows completed. Holds the last
+    /// [`imu::IMPACT_ACCEL_WINDOWS_LEN`] windows.
+    pub impact_accel_windows: VecDeque<imu::ImpactAccelWindow>,
+
+    /// Redraws of the tracking canvas
+    pub redraw_rate: rate::RateCounter,
+    /// Markers, accel and impact reports received from the device
+    pub packet_rate: rate::RateCounter,
+}
+
+impl MotState {
+    /// True once the Madgwick orientation agrees with the measured gravity direction.
+    pub fn orientation_converged(&self) -> bool {
+        self.orientation_quality
+            .is_converged(imu::ORIENTATION_CONVERGED_THRESHOLD)
+    }
+
+    /// Resolution (width, height) of the coordinates in the latest markers report.
+    pub fn sensor_resolution(&self) -> [u16; 2] {
+        if self.is_poc_markers {
+            ats_usb::packets::vm::POC_SENSOR_RESOLUTION
+        } else {
+            ats_usb::packets::vm::SENSOR_RESOLUTION
+        }
+    }
+
+    /// Largest coordinate along each axis of the latest markers report.
+    pub fn sensor_max(&self) -> nalgebra::Vector2<f32> {
+        let [w, h] = self.sensor_resolution();
+        nalgebra::Vector2::new(f32::from(w - 1), f32::from(h - 1))
+    }
+
+    /// Returns the pose filter, marker tracker and aimpoint filter to their initial state, e.g.
+    /// after tracking was lost, so stale state doesn't bias the next observations.
+    pub fn reset_tracking(&mut self) {
+        self.fv_state = FoveatedAimpointState::new();
+        self.nf_tracker.reset();
+        self.screen_hysteresis.reset();
+        if let Some(filter) = self.aimpoint_filter.as_mut() {
+            filter.reset();
+        }
+        self.aimpoint_filter_time = None;
+    }
+
+    /// Replaces the aimpoint smoothing filter, starting it fresh from the next aimpoint.
+    pub fn set_aimpoint_filter(&mut self, kind: aimpoint_filter::AimpointFilterKind) {
+        self.aimpoint_filter = kind.build();
+        self.aimpoint_filter_time = None;
+    }
+
+    /// Runs `aimpoint` through the selected filter, if any.
+    pub fn filter_aimpoint(&mut self, aimpoint: Point2<f32>) -> Point2<f32> {
+        let Some(filter) = self.aimpoint_filter.as_mut() else {
+            return aimpoint;
+        };
+        let now = std::time::Instant::now();
+        let dt = self
+            .aimpoint_filter_time
+            .map(|t| now.duration_since(t).as_secs_f32())
+            .unwrap_or(0.0);
+        self.aimpoint_filter_time = Some(now);
+        filter.update(aimpoint, dt)
+    }
 }
 
 impl Default for MotState {
     fn default() -> Self {
         Self {
             fv_aimpoint: Point2::new(0.0, 0.0),
+            fv_aimpoint_raw: Point2::new(0.0, 0.0),
             distance: 0.0,
             nf_data: None,
             wf_data: None,
             is_poc_markers: false,
             screen_id: 0,
+            screen_hysteresis: Default::default(),
             orientation: Rotation3::identity(),
             madgwick: ahrs::Madgwick::new(1. / 100., 0.04),
+            gyro_bias: imu::GyroBiasEstimator::new(),
+            orientation_quality: imu::OrientationQuality::default(),
+            impact_detector: imu::ImpactDetector::default(),
             rotation_mat: Default::default(),
             translation_mat: Default::default(),
             nf_points: Default::default(),
@@ -122,8 +239,21 @@ impl Default for MotState {
             wf_markers2: Default::default(),
             fv_state: FoveatedAimpointState::new(),
             fv_zero_offset: Isometry3::identity(),
-            fv_aimpoint_history: [(Point2::new(0.0, 0.0), 0., Matrix3x1::new(0.0, 0.0, 0.0)); 80],
-            fv_aimpoint_history_index: 0,
+            nf_tracker: Default::default(),
+            nf_undistort: Default::default(),
+            wf_undistort: Default::default(),
+            frames_without_markers: 0,
+            tracking_status: Default::default(),
+            aimpoint_blend: None,
+            aimpoint_filter: None,
+            aimpoint_filter_time: None,
+            fv_aimpoint_history: history::AimpointHistory::default(),
+            last_imu_timestamp: None,
+            impact_aimpoints: VecDeque::with_capacity(history::IMPACT_AIMPOINTS_LEN),
+            impact_accel: imu::ImpactAccelRecorder::default(),
+            impact_accel_windows: VecDeque::with_capacity(imu::IMPACT_ACCEL_WINDOWS_LEN),
+            redraw_rate: Default::default(),
+            packet_rate: Default::default(),
         }
     }
 }
@@ -131,6 +261,8 @@ impl Default for MotState {
 pub struct Marker {
     pub mot_id: u8,
     pub pattern_id: Option<u8>,
+    /// Position as reported by the sensor, before undistortion
+    pub raw: Point2<f32>,
     pub normalized: Point2<f32>,
 }
 
@@ -141,3 +273,64 @@ impl Marker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_tracking_forgets_history() {
+        let mut state = MotState::default();
+        state.set_aimpoint_filter(aimpoint_filter::AimpointFilterKind::Pva2d);
+        let stale = Point2::new(0.2, 0.3);
+        for _ in 0..50 {
+            state.filter_aimpoint(stale);
+        }
+        let detections = [Point2::new(0.1, 0.1), Point2::new(0.2, 0.1)];
+        let geometric = correspondence::MarkerMatch {
+            pattern_ids: [Some(0), Some(1)].into_iter().collect(),
+            error: 0.0,
+        };
+        state.nf_tracker.track(&detections, Some(&geometric));
+        state.screen_hysteresis.update(1);
+
+        state.reset_tracking();
+
+        // The first aimpoint after the reset passes through instead of being pulled towards the
+        // stale one
+        let fresh = Point2::new(0.8, 0.7);
+        assert_eq!(state.filter_aimpoint(fresh), fresh);
+        // The markers keep no index without a new match
+        let m = state.nf_tracker.track(&detections, None);
+        assert_eq!(m.matched_count(), 0);
+        // and the first screen seen is taken at once
+        assert_eq!(state.screen_hysteresis.update(2), 2);
+    }
+
+    #[test]
+    fn sensor_resolution_follows_report_kind() {
+        use ats_usb::packets::vm::{normalized_point, POC_SENSOR_RESOLUTION, SENSOR_RESOLUTION};
+        use nalgebra::Vector2;
+
+        let mut state = MotState::default();
+        for (is_poc, resolution) in [(false, SENSOR_RESOLUTION), (true, POC_SENSOR_RESOLUTION)] {
+            state.is_poc_markers = is_poc;
+            assert_eq!(state.sensor_resolution(), resolution);
+            let [w, h] = resolution;
+            let sensor_max = state.sensor_max();
+            assert_eq!(sensor_max, Vector2::new(f32::from(w - 1), f32::from(h - 1)));
+
+            // Normalizing a pixel and scaling it back by the sensor max gives the same pixel
+            for (cx, cy) in [(0, 0), (w / 3, h / 2), (w - 1, h - 1)] {
+                let mot_data = MotData {
+                    cx,
+                    cy,
+                    ..Default::default()
+                };
+                let normalized = normalized_point(&mot_data, w, h);
+                let pixel = normalized.coords.component_mul(&sensor_max);
+                assert!((pixel - Vector2::new(f32::from(cx), f32::from(cy))).norm() < 1e-2);
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use nalgebra::{Matrix3, Matrix3x2, Point2};
+
+/// Layout of the four fiducial markers around a screen.
+///
+/// Model points are on the unit square with y pointing down and are listed clockwise from the top
+/// left, the same order [`choose_corners`](MarkerPattern::choose_corners) returns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum MarkerPattern {
+    /// One marker at each corner. Symmetric under a half turn, so the sensor is assumed to be held
+    /// roughly upright.
+    #[default]
+    Rectangle,
+    /// Top markers at a quarter and three quarters of the width. Identifiable at any roll.
+    Trapezoid,
+}
+
+impl MarkerPattern {
+    pub const ALL: [MarkerPattern; 2] = [Self::Rectangle, Self::Trapezoid];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rectangle => "Rectangle",
+            Self::Trapezoid => "Trapezoid",
+        }
+    }
+
+    pub fn object_points(self) -> [Point2<f32>; 4] {
+        match self {
+            Self::Rectangle => [
+                Point2::new(0.0, 0.0),
+                Point2::new(1.0, 0.0),
+                Point2::new(1.0, 1.0),
+                Point2::new(0.0, 1.0),
+            ],
+            Self::Trapezoid => [
+                Point2::new(0.25, 0.0),
+                Point2::new(0.75, 0.0),
+                Point2::new(1.0, 1.0),
+                Point2::new(0.0, 1.0),
+            ],
+        }
+    }
+
+    /// Pick which of `points` is which model point.
+    ///
+    /// Returns indices into `points` in the order of [`object_points`](Self::object_points), or
+    /// `None` unless there are exactly four points.
+    pub fn choose_corners(self, points: &[Point2<f32>]) -> Option<[usize; 4]> {
+        let points: &[Point2<f32>; 4] = points.try_into().ok()?;
+        match self {
+            Self::Rectangle => Some(rectangle_corners(points)),
+            Self::Trapezoid => best_affine_rotation(points, &self.object_points()),
+        }
+    }
+}
+
+/// Order by [`sort_rectangle`](crate::mot_runner::sort_rectangle), which yields top left, top
+/// right, bottom right, bottom left.
+fn rectangle_corners(points: &[Point2<f32>; 4]) -> [usize; 4] {
+    let mut sorted = *points;
+    crate::mot_runner::sort_rectangle(&mut sorted);
+    let mut used = [false; 4];
+    sorted.map(|p| {
+        // Bitwise so NaN coordinates still find themselves
+        let bits = |p: Point2<f32>| p.coords.map(f32::to_bits);
+        let i = (0..4)
+            .find(|&i| !used[i] && bits(points[i]) == bits(p))
+            .unwrap();
+        used[i] = true;
+        i
+    })
+}
+
+/// Walk the points clockwise and return the starting point whose least squares affine fit to
+/// `model` has the smallest residual.
+///
+/// An affine fit can tell the corners of an asymmetric pattern apart as long as the perspective
+/// distortion is mild compared to the pattern's asymmetry.
+fn best_affine_rotation(points: &[Point2<f32>; 4], model: &[Point2<f32>; 4]) -> Option<[usize; 4]> {
+    let centroid = points
+        .iter()
+        .fold(Point2::origin(), |c, p| c + p.coords / 4.0);
+    let mut order = [0, 1, 2, 3];
+    // Increasing angle is clockwise on screen with y pointing down
+    order.sort_by(|&a, &b| {
+        let angle = |i: usize| {
+            let d = points[i] - centroid;
+            d.y.atan2(d.x)
+        };
+        angle(a).total_cmp(&angle(b))
+    });
+
+    // The model side of the normal equations doesn't depend on the rotation
+    let m = nalgebra::Matrix4x3::from_fn(|r, c| if c < 2 { model[r][c] } else { 1.0 });
+    let mtm_inv: Matrix3<f32> = (m.transpose() * m).try_inverse()?;
+
+    (0..4)
+        .map(|rot| {
+            let ix = [0, 1, 2, 3].map(|k| order[(k + rot) % 4]);
+            let p = nalgebra::Matrix4x2::from_fn(|r, c| points[ix[r]][c]);
+            let affine: Matrix3x2<f32> = mtm_inv * m.transpose() * p;
+            let residual = (m * affine - p).norm_squared();
+            (residual, ix)
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, ix)| ix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Rotation2, Vector2};
+
+    /// The pattern's model points seen rotated by `angle`, scaled and moved, in shuffled order.
+    /// Returns the points and the model index of each.
+    fn shuffled(pattern: MarkerPattern, angle: f32) -> ([Point2<f32>; 4], [usize; 4]) {
+        let model = pattern.object_points();
+        let rotation = Rotation2::new(angle);
+        let order = [2, 0, 3, 1];
+        let points = order
+            .map(|i| Point2::from(rotation * model[i].coords * 300.0 + Vector2::new(900.0, 400.0)));
+        (points, order)
+    }
+
+    /// Model index of each chosen corner
+    fn chosen(pattern: MarkerPattern, angle: f32) -> [usize; 4] {
+        let (points, model_ix) = shuffled(pattern, angle);
+        pattern
+            .choose_corners(&points)
+            .unwrap()
+            .map(|i| model_ix[i])
+    }
+
+    #[test]
+    fn rectangle_corners_upright() {
+        for angle in [0.0, 0.2, -0.3] {
+            assert_eq!(chosen(MarkerPattern::Rectangle, angle), [0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn trapezoid_corners_any_roll() {
+        for angle in [0.0, 1.0, 2.5, -2.0, std::f32::consts::PI] {
+            assert_eq!(chosen(MarkerPattern::Trapezoid, angle), [0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn corners_need_four_points() {
+        let (points, _) = shuffled(MarkerPattern::Trapezoid, 0.0);
+        for pattern in MarkerPattern::ALL {
+            assert_eq!(pattern.choose_corners(&points[..3]), None);
+        }
+    }
+}
@@ -1,16 +1,22 @@
+use crate::camera::UndistortMap;
+use crate::correspondence;
+use crate::history::{AimpointSample, ImpactAimpoint, IMPACT_AIMPOINTS_LEN};
+use crate::marker_pattern::MarkerPattern;
+use crate::snapshot::FoveatedSnapshot;
 use crate::{CloneButShorter, Marker, TestFrame};
 use ahrs::Ahrs;
 use arrayvec::ArrayVec;
 use ats_common::MARKER_PATTERN_LEN;
 use ats_cv::{calculate_rotational_offset, to_normalized_image_coordinates};
-use ats_usb::device::{GeneralSettings, VmDevice};
-use ats_usb::packets::vm::{CombinedMarkersReport, MotData};
+use ats_usb::device::{GeneralSettings, MarkersReport, VmDevice};
+use ats_usb::packets::vm::{AccelReport, CombinedMarkersReport, ImpactReport, MotData};
 use iui::concurrent::Context;
 use leptos_reactive::RwSignal;
-use nalgebra::{Matrix3, Point2, Rotation3, Scalar, Translation3, UnitVector3, Vector2, Vector3};
+use nalgebra::{convert, Isometry3, Point2, RealField, Scalar, UnitVector3, Vector2, Vector3};
 use opencv_ros_camera::RosOpenCvIntrinsics;
 use parking_lot::Mutex;
 use protodongers::PocMarkersReport;
+use std::cmp::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, UNIX_EPOCH};
 use tokio_stream::StreamExt;
@@ -80,49 +86,315 @@ pub fn sort_diamond<T: Scalar + PartialOrd>(a: &mut [Point2<T>]) {
 /// ```
 ///
 /// Sort them into the order a, b, d, c
-pub fn sort_rectangle<T: Scalar + PartialOrd>(a: &mut [Point2<T>]) {
-    if a[0].y > a[2].y {
-        a.swap(0, 2);
+///
+/// The points are walked clockwise around their centroid, starting from the left end of the top
+/// edge, the edge with the smallest mean y (then smallest mean x). Ties in angle are broken by
+/// position, so the result doesn't depend on the input order. Returns `false` if the quad is
+/// degenerate, see [`is_degenerate_quad`]; the order is still deterministic but may not match the
+/// physical corners.
+pub fn sort_rectangle<T: RealField + Copy>(a: &mut [Point2<T>]) -> bool {
+    let quad = [a[0], a[1], a[2], a[3]];
+    let centroid = quad.iter().fold(Vector2::zeros(), |c, p| c + p.coords) * convert::<_, T>(0.25);
+    let cmp = |x: T, y: T| x.partial_cmp(&y).unwrap_or(Ordering::Equal);
+
+    let mut order = [0, 1, 2, 3];
+    // Increasing angle is clockwise on screen with y pointing down
+    order.sort_by(|&i, &j| {
+        let angle = |k: usize| {
+            let d = quad[k].coords - centroid;
+            d.y.atan2(d.x)
+        };
+        cmp(angle(i), angle(j))
+            .then(cmp(quad[i].x, quad[j].x))
+            .then(cmp(quad[i].y, quad[j].y))
+    });
+
+    let edge_sum = |k: usize| quad[order[k]].coords + quad[order[(k + 1) % 4]].coords;
+    let start = (0..4)
+        .min_by(|&i, &j| {
+            let (ei, ej) = (edge_sum(i), edge_sum(j));
+            cmp(ei.y, ej.y).then(cmp(ei.x, ej.x))
+        })
+        .unwrap_or(0);
+    for (k, p) in a.iter_mut().take(4).enumerate() {
+        *p = quad[order[(start + k) % 4]];
     }
-    if a[1].y > a[3].y {
-        a.swap(1, 3);
+    !is_degenerate_quad(&quad)
+}
+
+/// Twice the area of a corner triangle, relative to the squared distance between the two furthest
+/// corners, below which [`is_degenerate_quad`] rejects a quad
+pub const DEGENERATE_QUAD_RATIO: f64 = 1e-3;
+
+/// True if two corners coincide or any three are nearly collinear.
+pub fn is_degenerate_quad<T: RealField + Copy>(quad: &[Point2<T>; 4]) -> bool {
+    let mut diameter2 = T::zero();
+    for i in 0..4 {
+        for j in i + 1..4 {
+            diameter2 = diameter2.max((quad[i] - quad[j]).norm_squared());
+        }
     }
-    if a[0].y > a[1].y {
-        a.swap(0, 1);
+    if diameter2 <= T::zero() {
+        return true;
     }
-    if a[2].y > a[3].y {
-        a.swap(2, 3);
+    let threshold = diameter2 * convert::<_, T>(DEGENERATE_QUAD_RATIO);
+    (0..4).any(|skip| {
+        let [p, q, r] = [0, 1, 2, 3]
+            .into_iter()
+            .filter(|&k| k != skip)
+            .map(|k| quad[k])
+            .collect::<ArrayVec<_, 3>>()
+            .into_inner()
+            .unwrap();
+        (q - p).perp(&(r - p)).abs() <= threshold
+    })
+}
+
+pub fn sort_points<T: RealField + Copy>(a: &mut [Point2<T>]) -> bool {
+    sort_rectangle(a)
+}
+
+/// Consecutive markers reports with fewer than [`MARKER_PATTERN_LEN`] markers on both sensors
+/// after which tracking counts as lost and the filters are reset
+pub const TRACKING_LOST_FRAMES: u32 = 30;
+
+/// Detections closer than this fraction of the sensor width are taken to be one marker split in
+/// two, see [`merge_point_tuples`]
+pub const MARKER_MERGE_RADIUS: f32 = 0.01;
+
+/// Default number of consecutive reports that must agree on a new screen before switching to it
+pub const DEFAULT_SCREEN_SWITCH_FRAMES: u32 = 5;
+
+/// Keeps the tracked screen from flipping on a few ambiguous reports near a screen boundary.
+///
+/// A different screen id has to be observed for `switch_frames` consecutive reports before it is
+/// accepted. The first screen after construction or [`reset`](Self::reset) is accepted at once.
+#[derive(Clone, Debug)]
+pub struct ScreenHysteresis {
+    pub switch_frames: u32,
+    current: Option<u8>,
+    /// Screen waiting to be accepted and how many reports in a row it was seen
+    candidate: Option<(u8, u32)>,
+}
+
+impl Default for ScreenHysteresis {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCREEN_SWITCH_FRAMES)
     }
-    if a[1].y > a[2].y {
-        a.swap(1, 2);
+}
+
+impl ScreenHysteresis {
+    pub fn new(switch_frames: u32) -> Self {
+        Self {
+            switch_frames,
+            current: None,
+            candidate: None,
+        }
     }
-    if a[0].x > a[1].x {
-        a.swap(0, 1);
+
+    pub fn reset(&mut self) {
+        self.current = None;
+        self.candidate = None;
     }
-    if a[2].x < a[3].x {
-        a.swap(2, 3);
+
+    /// Screen being tracked, `None` until the first [`update`](Self::update).
+    pub fn current(&self) -> Option<u8> {
+        self.current
+    }
+
+    /// Feed the screen id observed in one report and return the screen to track.
+    pub fn update(&mut self, observed: u8) -> u8 {
+        let current = *self.current.get_or_insert(observed);
+        if observed == current {
+            self.candidate = None;
+            return current;
+        }
+        let count = match self.candidate {
+            Some((id, count)) if id == observed => count + 1,
+            _ => 1,
+        };
+        if count >= self.switch_frames {
+            self.current = Some(observed);
+            self.candidate = None;
+            observed
+        } else {
+            self.candidate = Some((observed, count));
+            current
+        }
     }
 }
 
-pub fn sort_points<T: Scalar + PartialOrd>(a: &mut [Point2<T>]) {
-    sort_rectangle(a);
+/// Calibrations to observe and raycast against while `held` is the tracked screen: only that
+/// screen's, or all of them before any screen was held or if `held` has no calibration.
+pub fn held_calibrations<const N: usize>(
+    screen_calibrations: &ArrayVec<(u8, ats_common::ScreenCalibration<f32>), N>,
+    held: Option<u8>,
+) -> ArrayVec<(u8, ats_common::ScreenCalibration<f32>), N> {
+    let held_only: ArrayVec<_, N> = screen_calibrations
+        .iter()
+        .filter(|(id, _)| Some(*id) == held)
+        .cloned()
+        .collect();
+    if held_only.is_empty() {
+        screen_calibrations.clone()
+    } else {
+        held_only
+    }
+}
+
+/// Feed the screen identified in one markers report, `None` if none was, through
+/// [`MotState::screen_hysteresis`](crate::MotState::screen_hysteresis) and return the
+/// calibrations for the held screen, see [`held_calibrations`].
+///
+/// Sets [`MotState::screen_id`](crate::MotState::screen_id) to the held screen.
+pub fn hold_screen<const N: usize>(
+    state: &mut crate::MotState,
+    observed: Option<u8>,
+    screen_calibrations: &ArrayVec<(u8, ats_common::ScreenCalibration<f32>), N>,
+) -> ArrayVec<(u8, ats_common::ScreenCalibration<f32>), N> {
+    let held = match observed {
+        Some(observed) => Some(state.screen_hysteresis.update(observed)),
+        None => state.screen_hysteresis.current(),
+    };
+    if let Some(held) = held {
+        state.screen_id = held;
+    }
+    held_calibrations(screen_calibrations, held)
+}
+
+/// Mean distance in normalized image coordinates between the matched nearfield markers and their
+/// model points above which tracking counts as degraded
+pub const DEGRADED_MATCH_ERROR: f32 = 0.01;
+
+/// How well the runner is following the markers, e.g. for dimming the aimpoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrackingStatus {
+    /// A full marker pattern, matched to the model with low error
+    Tracking,
+    /// Too few markers or a poor match, the aimpoint may be off
+    #[default]
+    Degraded,
+    /// No full marker pattern for [`TRACKING_LOST_FRAMES`] reports
+    Lost,
+}
+
+impl TrackingStatus {
+    /// Classify one markers report.
+    ///
+    /// `marker_count` is the larger of the nearfield and widefield marker counts and
+    /// `mean_match_error` the mean nearfield match distance, `None` if nothing matched.
+    pub fn classify(
+        marker_count: usize,
+        mean_match_error: Option<f32>,
+        frames_without_markers: u32,
+    ) -> Self {
+        if frames_without_markers >= TRACKING_LOST_FRAMES {
+            Self::Lost
+        } else if marker_count >= MARKER_PATTERN_LEN
+            && mean_match_error.is_some_and(|e| e <= DEGRADED_MATCH_ERROR)
+        {
+            Self::Tracking
+        } else {
+            Self::Degraded
+        }
+    }
 }
 
 pub struct MotRunner {
     pub state: crate::MotState,
     pub device: Option<VmDevice>,
+    /// Set with [`set_general_config`](Self::set_general_config)
     pub general_config: GeneralSettings,
     pub record_impact: bool,
+    /// Set once the orientation has converged since [`start_test`](Self::start_test). Impacts are
+    /// only recorded while it is set, so a test never starts on an unsettled orientation.
+    pub test_ready: bool,
+    /// Detect impacts from the accel stream in addition to hardware impact reports, for devices
+    /// without impact detection configured
+    pub software_impact: bool,
+    /// Add the unsmoothed aimpoint to recorded datapoints, for tuning the aimpoint filter
+    pub log_raw_aimpoint: bool,
     pub record_packets: bool,
     pub datapoints: Arc<Mutex<Vec<crate::TestFrame>>>,
     pub packets: Arc<Mutex<Vec<(u128, ats_usb::packets::vm::PacketData)>>>,
+    /// One filter snapshot per recorded markers report, pointing into `packets` by
+    /// [`FoveatedSnapshot::packet_index`]
+    pub fv_snapshots: Arc<Mutex<Vec<FoveatedSnapshot>>>,
     pub ui_update: RwSignal<()>,
-    pub ui_ctx: Context,
+    /// `None` without a GUI, e.g. in test rigs
+    pub ui_ctx: Option<Context>,
     pub wfnf_realign: bool,
+    /// Draw the calibrated screen outline on the tracking canvas
+    pub show_screen_outline: bool,
+    /// Layout of the markers, for telling them apart when there's no pose to match against
+    pub marker_pattern: MarkerPattern,
     pub screen_calibrations: ArrayVec<
         (u8, ats_common::ScreenCalibration<f32>),
         { (ats_common::MAX_SCREEN_ID + 1) as usize },
     >,
+    /// See [`MotRunner::events`]
+    pub event_tx: tokio::sync::broadcast::Sender<RunnerEvent>,
+}
+
+/// Milestones of a session, for test rigs that need more than the UI's side effects.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunnerEvent {
+    Connected,
+    Disconnected,
+    /// The device's general settings were loaded into [`MotRunner::general_config`]
+    SettingsLoaded,
+    /// The first markers report with any points since [`run`] started
+    FirstMarkerSeen,
+    /// From a hardware impact report or the software impact detector
+    ImpactDetected {
+        timestamp: u32,
+    },
+    /// The orientation filter converged, impacts are recorded from now on
+    AimpointConverged,
+    /// [`MotState::tracking_status`](crate::MotState::tracking_status) changed
+    TrackingStatusChanged(TrackingStatus),
+}
+
+impl MotRunner {
+    /// Subscribe to the runner's events. Only events sent after subscribing are received.
+    pub fn events(&self) -> tokio::sync::broadcast::Receiver<RunnerEvent> {
+        self.event_tx.subscribe()
+    }
+
+    pub fn emit(&self, event: RunnerEvent) {
+        tracing::debug!("runner event: {event:?}");
+        // Fails when nobody is subscribed, which is the normal case outside of test rigs
+        let _ = self.event_tx.send(event);
+    }
+
+    /// Replace the general settings. The software impact detector takes their suppress time but
+    /// keeps its own threshold, see [`Prefs::software_impact_threshold_g`]: the firmware's
+    /// `impact_threshold` has no documented unit to convert from.
+    ///
+    /// [`Prefs::software_impact_threshold_g`]: crate::prefs::Prefs::software_impact_threshold_g
+    pub fn set_general_config(&mut self, config: GeneralSettings) {
+        self.state.impact_detector.suppress_ms = config.suppress_ms.into();
+        self.general_config = config;
+    }
+
+    /// Start a test: impact recording waits until the orientation has converged again, see
+    /// [`RunnerEvent::AimpointConverged`].
+    pub fn start_test(&mut self) {
+        self.test_ready = false;
+    }
+
+    pub fn set_device(&mut self, device: Option<VmDevice>) {
+        match (&self.device, &device) {
+            (None, Some(_)) => {
+                self.state.redraw_rate.reset();
+                self.state.packet_rate.reset();
+                self.emit(RunnerEvent::Connected);
+            }
+            (Some(_), None) => self.emit(RunnerEvent::Disconnected),
+            _ => {}
+        }
+        self.device = device;
+    }
 }
 
 pub async fn run(runner: Arc<Mutex<MotRunner>>) {
@@ -188,30 +460,83 @@ pub async fn frame_loop(runner: Arc<Mutex<MotRunner>>) {
 //     (rot, trans, fv_aimpoint)
 // }
 
-fn my_raycast_update(runner: &mut MotRunner) {
-    let screen_calibrations = runner.screen_calibrations.clone();
+/// Returns whether the raycast produced an aimpoint.
+fn my_raycast_update(runner: &mut MotRunner) -> bool {
+    let screen_calibrations = held_calibrations(
+        &runner.screen_calibrations,
+        runner.state.screen_hysteresis.current(),
+    );
     let fv_state = &mut runner.state.fv_state;
     let offset = runner.state.fv_zero_offset;
     let (pose, aimpoint_and_d) =
         ats_cv::helpers::raycast_update(&screen_calibrations, fv_state, Some(offset));
     if let Some(pose) = pose {
-        let flip_yz = Matrix3::new(1., 0., 0., 0., -1., 0., 0., 0., -1.);
-
-        let rot = Rotation3::from_matrix_unchecked(flip_yz * pose.0 * flip_yz);
-        let trans = Translation3::from(flip_yz * pose.1);
-        runner.state.rotation_mat = *rot.matrix();
-        runner.state.translation_mat = trans.vector;
-    }
-    if let Some(aimpoint_and_d) = aimpoint_and_d {
-        runner.state.fv_aimpoint = aimpoint_and_d.0;
-        runner.state.distance = aimpoint_and_d.1;
+        let pose = crate::fusion::flip_yz_pose(pose.0, pose.1);
+        runner.state.rotation_mat = *pose.rotation.to_rotation_matrix().matrix();
+        runner.state.translation_mat = pose.translation.vector;
     }
+    let Some((aimpoint, distance)) = aimpoint_and_d else {
+        return false;
+    };
+    runner.state.fv_aimpoint_raw = aimpoint;
+    runner.state.fv_aimpoint = runner.state.filter_aimpoint(aimpoint);
+    runner.state.distance = distance;
+    true
 }
 
-/// Wrapper to track whether markers came from POC or combined report
-enum MarkersReport {
-    Combined(CombinedMarkersReport),
-    Poc(PocMarkersReport),
+/// Solve the pose separately from the nearfield and the widefield markers that have a model index
+/// and blend the two aimpoints by their confidence, see [`crate::fusion::blend_aimpoints`].
+///
+/// The blend is kept for display. When the foveated filter couldn't raycast (`raycast` is false),
+/// e.g. because only part of the pattern is visible, it also becomes the aimpoint, with the pose
+/// of the more confident source.
+fn blend_update(runner: &mut MotRunner, raycast: bool) {
+    let screen_id = runner.state.screen_id;
+    let Some((_, calibration)) = runner
+        .screen_calibrations
+        .iter()
+        .find(|(id, _)| *id == screen_id)
+    else {
+        runner.state.aimpoint_blend = None;
+        return;
+    };
+    let solve = |markers: &[Marker]| match crate::fusion::fuse_partial(markers, calibration) {
+        Ok(marker_pose) => Some(marker_pose),
+        Err(e) => {
+            tracing::trace!("No partial pose: {e}");
+            None
+        }
+    };
+    let nf = solve(&runner.state.nf_markers2);
+    let wf = solve(&runner.state.wf_markers2);
+    let estimate = |pose: &Option<crate::fusion::MarkerPose>| {
+        pose.as_ref().map(|p| (p.aimpoint, p.confidence()))
+    };
+    let blend = crate::fusion::blend_aimpoints(estimate(&nf), estimate(&wf));
+    runner.state.aimpoint_blend = blend;
+
+    if raycast {
+        return;
+    }
+    let Some(blend) = blend else {
+        return;
+    };
+    let Some(marker_pose) = [nf, wf]
+        .into_iter()
+        .flatten()
+        .max_by(|a, b| a.confidence().total_cmp(&b.confidence()))
+    else {
+        return;
+    };
+    let pose = crate::fusion::flip_yz_pose(
+        *marker_pose.pose.rotation.to_rotation_matrix().matrix(),
+        marker_pose.pose.translation.vector,
+    );
+    runner.state.rotation_mat = *pose.rotation.to_rotation_matrix().matrix();
+    runner.state.translation_mat = pose.translation.vector;
+    runner.state.fv_aimpoint_raw = blend.aimpoint;
+    runner.state.fv_aimpoint = runner.state.filter_aimpoint(blend.aimpoint);
+    runner.state.distance = marker_pose.distance;
 }
 
 async fn markers_loop(runner: Arc<Mutex<MotRunner>>) {
@@ -219,41 +544,49 @@ async fn markers_loop(runner: Arc<Mutex<MotRunner>>) {
         Some(d) => d.c(),
         None => return,
     };
-    let combined_markers_stream = match device.stream_combined_markers().await {
+    let mut markers_stream = match device.stream_markers().await {
         Ok(stream) => stream,
         Err(e) => {
-            tracing::error!("Failed to stream combined markers: {:?}", e);
+            tracing::error!("Failed to stream markers: {:?}", e);
             return;
         }
     };
-    let poc_markers_stream = match device.stream_poc_markers().await {
-        Ok(stream) => stream,
-        Err(e) => {
-            tracing::error!("Failed to stream poc markers: {:?}", e);
-            return;
-        }
-    };
-
-    let mut markers_stream = combined_markers_stream
-        .map(MarkersReport::Combined)
-        .merge(poc_markers_stream.map(MarkersReport::Poc));
 
+    let mut marker_seen = false;
     while let Some(report) = markers_stream.next().await {
-        let (is_poc, nf_points, wf_points) = match report {
-            MarkersReport::Poc(poc) => (true, poc.points, Default::default()),
-            MarkersReport::Combined(combined) => (false, combined.nf_points, combined.wf_points),
-        };
+        markers_report(&mut runner.lock(), report, &mut marker_seen);
+    }
+}
+
+/// Process one markers report. `marker_seen` is set with the first report that has any points.
+pub fn markers_report(runner: &mut MotRunner, report: MarkersReport, marker_seen: &mut bool) {
+    let (is_poc, nf_points, wf_points) = match report {
+        MarkersReport::Poc(poc) => (true, poc.points, Default::default()),
+        MarkersReport::Combined(combined) => (false, combined.nf_points, combined.wf_points),
+    };
+
+    runner.state.packet_rate.tick(std::time::Instant::now());
 
-        let mut runner = runner.lock();
+    // Unused slots of the report are zero, see `create_point_tuples`
+    let has_points = nf_points
+        .iter()
+        .chain(wf_points.iter())
+        .any(|p| *p != Point2::new(0, 0));
+    if !*marker_seen && has_points {
+        *marker_seen = true;
+        runner.emit(RunnerEvent::FirstMarkerSeen);
+    }
 
-        // Track whether this is a POC marker report
-        runner.state.is_poc_markers = is_poc;
+    // Track whether this is a POC marker report
+    runner.state.is_poc_markers = is_poc;
+    let merge_radius = MARKER_MERGE_RADIUS * f32::from(runner.state.sensor_resolution()[0]);
 
-        // Helper closure to process points (applies camera model transforms)
-        let process_points = |points, camera_model, stereo_iso| {
-            let point_tuples = create_point_tuples(points);
+    // Helper closure to process points (applies camera model transforms)
+    let process_points =
+        |points, camera_model: &RosOpenCvIntrinsics<f32>, undistort: &UndistortMap, stereo_iso| {
+            let point_tuples = merge_point_tuples(create_point_tuples(points), merge_radius);
             let points_raw: Vec<_> = point_tuples.iter().map(|&(_, p)| p).collect();
-            let points_transformed = transform_points(&points_raw, camera_model);
+            let points_transformed = undistort.undistort_points(&points_raw);
             let intrinsics = ats_common::ros_opencv_intrinsics_type_convert(camera_model);
             let normalized_points: ArrayVec<_, 16> = points_transformed
                 .iter()
@@ -262,170 +595,294 @@ async fn markers_loop(runner: Arc<Mutex<MotRunner>>) {
             let markers = point_tuples
                 .iter()
                 .zip(&normalized_points)
-                .map(|(&(mot_id, _), &normalized)| Marker {
+                .map(|(&(mot_id, raw), &normalized)| Marker {
                     mot_id,
                     pattern_id: None,
+                    raw,
                     normalized,
                 })
                 .collect();
             (point_tuples, points_transformed, normalized_points, markers)
         };
 
-        // Process nf_points and wf_points
-        let (nf_point_tuples, nf_points_transformed, nf_normalized, nf_markers2) =
-            process_points(&nf_points, &runner.general_config.camera_model_nf, None);
-        let (wf_point_tuples, wf_points_transformed, wf_normalized, wf_markers2) = process_points(
-            &wf_points,
-            &runner.general_config.camera_model_wf,
-            Some(&runner.general_config.stereo_iso.cast()),
-        );
+    // Process nf_points and wf_points
+    let sensor_res = runner.state.sensor_resolution();
+    let MotRunner {
+        state,
+        general_config,
+        ..
+    } = &mut *runner;
+    let nf_undistort = state
+        .nf_undistort
+        .get(&general_config.camera_model_nf, sensor_res);
+    let wf_undistort = state
+        .wf_undistort
+        .get(&general_config.camera_model_wf, sensor_res);
+    let (nf_point_tuples, nf_points_transformed, nf_normalized, nf_markers2) = process_points(
+        &nf_points,
+        &general_config.camera_model_nf,
+        nf_undistort,
+        None,
+    );
+    let (wf_point_tuples, wf_points_transformed, wf_normalized, wf_markers2) = process_points(
+        &wf_points,
+        &general_config.camera_model_wf,
+        wf_undistort,
+        Some(&general_config.stereo_iso.cast()),
+    );
 
-        runner.state.nf_markers2 = nf_markers2;
-        runner.state.wf_markers2 = wf_markers2;
+    runner.state.nf_markers2 = nf_markers2;
+    runner.state.wf_markers2 = wf_markers2;
 
-        let gravity_vec = UnitVector3::new_unchecked(
-            runner
-                .state
-                .orientation
-                .inverse_transform_vector(&Vector3::z_axis())
-                .xzy(),
-        );
+    let gravity_vec = UnitVector3::new_unchecked(
+        runner
+            .state
+            .orientation
+            .inverse_transform_vector(&Vector3::z_axis())
+            .xzy(),
+    );
 
-        // Re-alignment logic
-        if runner.wfnf_realign {
-            if let Some((wf_match_ix, _, _)) = ats_cv::foveated::identify_markers(
-                &wf_normalized,
-                gravity_vec.cast(),
-                &runner.screen_calibrations,
-            ) {
-                let wf_match = wf_match_ix.map(|i| wf_normalized[i].coords);
-                let (nf_match_ix, _) = ats_cv::foveated::match3(&nf_normalized, &wf_match);
-                if nf_match_ix.iter().all(Option::is_some) {
-                    let nf_ordered =
-                        nf_match_ix.map(|i| nf_normalized[i.unwrap()].coords.push(1.0));
-                    let wf_ordered = wf_match_ix.map(|i| wf_normalized[i].coords.push(1.0));
-                    let q = calculate_rotational_offset(&wf_ordered, &nf_ordered);
-                    runner.general_config.stereo_iso.rotation *= q.cast();
-                    runner.wfnf_realign = false;
-                }
+    // Re-alignment logic
+    if runner.wfnf_realign {
+        if let Some((wf_match_ix, _, _)) = ats_cv::foveated::identify_markers(
+            &wf_normalized,
+            gravity_vec.cast(),
+            &runner.screen_calibrations,
+        ) {
+            let wf_match = wf_match_ix.map(|i| wf_normalized[i].coords);
+            let (nf_match_ix, _) = ats_cv::foveated::match3(&nf_normalized, &wf_match);
+            if nf_match_ix.iter().all(Option::is_some) {
+                let nf_ordered = nf_match_ix.map(|i| nf_normalized[i.unwrap()].coords.push(1.0));
+                let wf_ordered = wf_match_ix.map(|i| wf_normalized[i].coords.push(1.0));
+                let q = calculate_rotational_offset(&wf_ordered, &nf_ordered);
+                runner.general_config.stereo_iso.rotation *= q.cast();
+                runner.wfnf_realign = false;
             }
         }
+    }
 
-        // Observe markers
-        let nf_markers_cv = runner
-            .state
-            .nf_markers2
-            .iter()
-            .map(|m| m.ats_cv_marker())
-            .collect::<ArrayVec<_, 16>>();
-        let wf_markers_cv = runner
-            .state
-            .wf_markers2
-            .iter()
-            .map(|m| m.ats_cv_marker())
-            .collect::<ArrayVec<_, 16>>();
-        let screen_calibrations = runner.screen_calibrations.clone();
-        runner.state.fv_state.observe_markers(
-            &nf_markers_cv,
-            &wf_markers_cv,
-            gravity_vec.cast(),
-            &screen_calibrations,
-        );
+    // Drop stale filter state once tracking has been lost for a while
+    if nf_normalized.len().max(wf_normalized.len()) < MARKER_PATTERN_LEN {
+        runner.state.frames_without_markers += 1;
+        if runner.state.frames_without_markers == TRACKING_LOST_FRAMES {
+            tracing::info!("Tracking lost, resetting filters");
+            runner.state.reset_tracking();
+        }
+    } else {
+        runner.state.frames_without_markers = 0;
+    }
 
-        my_raycast_update(&mut runner);
+    // Observe markers
+    let nf_markers_cv = runner
+        .state
+        .nf_markers2
+        .iter()
+        .map(|m| m.ats_cv_marker())
+        .collect::<ArrayVec<_, 16>>();
+    let wf_markers_cv = runner
+        .state
+        .wf_markers2
+        .iter()
+        .map(|m| m.ats_cv_marker())
+        .collect::<ArrayVec<_, 16>>();
+    // Identify the screen from this report alone, then hold it so the filter isn't pulled
+    // towards another screen by a few ambiguous reports near a boundary
+    let observed = crate::fusion::fuse_foveated_normalized(
+        &nf_markers_cv,
+        &wf_markers_cv,
+        gravity_vec,
+        &runner.screen_calibrations,
+    )
+    .map(|result| result.screen_id);
+    let MotRunner {
+        state,
+        screen_calibrations,
+        ..
+    } = &mut *runner;
+    let screen_calibrations = hold_screen(state, observed, screen_calibrations);
+    state.fv_state.observe_markers(
+        &nf_markers_cv,
+        &wf_markers_cv,
+        gravity_vec.cast(),
+        &screen_calibrations,
+    );
 
-        let wf_markers: Option<(
-            [usize; MARKER_PATTERN_LEN],
-            [Vector2<f32>; MARKER_PATTERN_LEN],
-            Option<u8>,
-        )> = None;
+    let raycast = my_raycast_update(runner);
 
-        let (wf_marker_ix, wf_reproj) = wf_markers
-            .as_ref()
-            .map(|(markers, reproj, _)| (markers.as_slice(), reproj.as_slice()))
-            .unwrap_or_default();
+    if !runner.test_ready && runner.state.orientation_converged() {
+        runner.test_ready = true;
+        runner.emit(RunnerEvent::AimpointConverged);
+    }
 
-        // Match nf_markers with wf_markers
-        let mut nf_markers = ArrayVec::<Point2<f32>, 16>::new();
-        if wf_marker_ix.len() >= MARKER_PATTERN_LEN {
-            let chosen_wf_markers: [_; MARKER_PATTERN_LEN] = wf_marker_ix
-                .iter()
-                .map(|&i| wf_normalized[i].coords)
-                .collect::<ArrayVec<_, MARKER_PATTERN_LEN>>()
-                .into_inner()
-                .unwrap();
-
-            let (nf_match_ix, _) = ats_cv::foveated::match3(&nf_normalized, &chosen_wf_markers);
-
-            for (i, &wf_idx) in wf_marker_ix.iter().enumerate() {
-                runner.state.wf_markers2[wf_idx].pattern_id = Some(i as u8);
-                if let Some(nf_idx) = nf_match_ix[i] {
-                    nf_markers.push(nf_points_transformed[nf_idx]);
-                    runner.state.nf_markers2[nf_idx].pattern_id = Some(i as u8);
-                } else {
-                    nf_markers.push(Point2::new(-9999., -9999.));
-                }
-            }
+    // Assign model indices to the markers using the filtered pose
+    let object_points = runner
+        .screen_calibrations
+        .iter()
+        .find(|(id, _)| *id == runner.state.screen_id)
+        .map(|(_, calibration)| calibration.object_points);
+    let filter = &runner.state.fv_state.filter;
+    let filter_pose =
+        Isometry3::from_parts(filter.position.cast().into(), filter.orientation.cast());
+    let match_with_filter = |detected: &[Point2<f32>]| {
+        object_points.as_ref().map(|object_points| {
+            correspondence::match_with_pose(
+                detected,
+                object_points,
+                &filter_pose,
+                correspondence::DEFAULT_MAX_MATCH_DISTANCE,
+            )
+        })
+    };
+    let mut nf_match = match_with_filter(&nf_normalized);
+    let wf_match = match_with_filter(&wf_normalized);
+    // The filtered pose is no help before tracking has converged, search for the pattern
+    // instead. Only for frames with few enough markers, see `MAX_SEARCH_DETECTIONS`.
+    let pose_matched = nf_match.as_ref().map_or(0, |m| m.matched_count());
+    if pose_matched < crate::fusion::MIN_POSE_MARKERS {
+        let searched = object_points.as_ref().and_then(|object_points| {
+            correspondence::match_by_search(
+                &nf_normalized,
+                object_points,
+                runner.marker_pattern,
+                correspondence::DEFAULT_MAX_MATCH_DISTANCE,
+            )
+        });
+        if let Some(searched) = searched.filter(|m| m.matched_count() > pose_matched) {
+            nf_match = Some(searched);
         }
+    }
+    let nf_match = runner
+        .state
+        .nf_tracker
+        .track(&nf_normalized, nf_match.as_ref());
 
-        // Update runner state
-        runner.state.nf_points = nf_point_tuples
-            .into_iter()
-            .filter(|&(mot_id, p)| {
-                !runner
-                    .state
-                    .nf_markers2
-                    .iter()
-                    .any(|m| m.mot_id == mot_id && nf_markers.contains(&p))
-            })
-            .collect();
+    let matched = nf_match.matched_count();
+    let status = TrackingStatus::classify(
+        nf_normalized.len().max(wf_normalized.len()),
+        (matched > 0).then(|| nf_match.error / matched as f32),
+        runner.state.frames_without_markers,
+    );
+    if status != runner.state.tracking_status {
+        runner.state.tracking_status = status;
+        runner.emit(RunnerEvent::TrackingStatusChanged(status));
+    }
 
-        runner.state.wf_points = wf_point_tuples
-            .into_iter()
-            .enumerate()
-            .filter(|(i, _)| !wf_marker_ix.contains(i))
-            .map(|(_, p)| p)
-            .collect();
+    for (marker, pattern_id) in runner
+        .state
+        .nf_markers2
+        .iter_mut()
+        .zip(nf_match.pattern_ids)
+    {
+        marker.pattern_id = pattern_id;
+    }
+    if let Some(wf_match) = wf_match {
+        for (marker, pattern_id) in runner
+            .state
+            .wf_markers2
+            .iter_mut()
+            .zip(wf_match.pattern_ids)
+        {
+            marker.pattern_id = pattern_id;
+        }
+    }
+    blend_update(runner, raycast);
+
+    let wf_markers: Option<(
+        [usize; MARKER_PATTERN_LEN],
+        [Vector2<f32>; MARKER_PATTERN_LEN],
+        Option<u8>,
+    )> = None;
+
+    let (wf_marker_ix, wf_reproj) = wf_markers
+        .as_ref()
+        .map(|(markers, reproj, _)| (markers.as_slice(), reproj.as_slice()))
+        .unwrap_or_default();
 
-        runner.state.nf_markers = nf_markers;
-        runner.state.wf_markers = wf_marker_ix
+    // Match nf_markers with wf_markers
+    let mut nf_markers = ArrayVec::<Point2<f32>, 16>::new();
+    if wf_marker_ix.len() >= MARKER_PATTERN_LEN {
+        let chosen_wf_markers: [_; MARKER_PATTERN_LEN] = wf_marker_ix
             .iter()
-            .map(|&i| wf_points_transformed[i])
-            .collect();
-        runner.state.wf_reproj = wf_reproj.iter().copied().map(Into::into).collect();
-
-        // Update aimpoint history
-        let gravity_angle = -gravity_vec.z.atan2(-gravity_vec.x).to_degrees() + 90.0;
-        let index = runner.state.fv_aimpoint_history_index;
-        runner.state.fv_aimpoint_history[index] = (
-            runner.state.fv_aimpoint,
-            gravity_angle,
-            runner.state.translation_mat,
-        );
-        runner.state.fv_aimpoint_history_index =
-            (index + 1) % runner.state.fv_aimpoint_history.len();
-
-        // Record packets if enabled
-        if runner.record_packets {
-            let packet_data = if is_poc {
-                ats_usb::packets::vm::PacketData::PocMarkersReport(PocMarkersReport {
-                    points: nf_points,
-                })
+            .map(|&i| wf_normalized[i].coords)
+            .collect::<ArrayVec<_, MARKER_PATTERN_LEN>>()
+            .into_inner()
+            .unwrap();
+
+        let (nf_match_ix, _) = ats_cv::foveated::match3(&nf_normalized, &chosen_wf_markers);
+
+        for (i, &wf_idx) in wf_marker_ix.iter().enumerate() {
+            runner.state.wf_markers2[wf_idx].pattern_id = Some(i as u8);
+            if let Some(nf_idx) = nf_match_ix[i] {
+                nf_markers.push(nf_points_transformed[nf_idx]);
+                runner.state.nf_markers2[nf_idx].pattern_id = Some(i as u8);
             } else {
-                ats_usb::packets::vm::PacketData::CombinedMarkersReport(CombinedMarkersReport {
-                    nf_points,
-                    wf_points,
-                })
-            };
-            runner.packets.lock().push((
-                std::time::SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-                packet_data,
-            ));
+                nf_markers.push(Point2::new(-9999., -9999.));
+            }
         }
     }
+
+    // Update runner state
+    runner.state.nf_points = nf_point_tuples
+        .into_iter()
+        .filter(|&(mot_id, p)| {
+            !runner
+                .state
+                .nf_markers2
+                .iter()
+                .any(|m| m.mot_id == mot_id && nf_markers.contains(&p))
+        })
+        .collect();
+
+    runner.state.wf_points = wf_point_tuples
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !wf_marker_ix.contains(i))
+        .map(|(_, p)| p)
+        .collect();
+
+    runner.state.nf_markers = nf_markers;
+    runner.state.wf_markers = wf_marker_ix
+        .iter()
+        .map(|&i| wf_points_transformed[i])
+        .collect();
+    runner.state.wf_reproj = wf_reproj.iter().copied().map(Into::into).collect();
+
+    // Update aimpoint history
+    let gravity_angle = -gravity_vec.z.atan2(-gravity_vec.x).to_degrees() + 90.0;
+    let sample = AimpointSample {
+        timestamp: runner.state.last_imu_timestamp,
+        aimpoint: runner.state.fv_aimpoint,
+        raw_aimpoint: runner.state.fv_aimpoint_raw,
+        opposite_cant: gravity_angle,
+        translation: runner.state.translation_mat,
+        distance: runner.state.distance,
+    };
+    runner.state.fv_aimpoint_history.push(sample);
+
+    // Record packets if enabled
+    if runner.record_packets {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let packet_data = if is_poc {
+            ats_usb::packets::vm::PacketData::PocMarkersReport(PocMarkersReport {
+                points: nf_points,
+            })
+        } else {
+            ats_usb::packets::vm::PacketData::CombinedMarkersReport(CombinedMarkersReport {
+                nf_points,
+                wf_points,
+            })
+        };
+        let packet_index = {
+            let mut packets = runner.packets.lock();
+            packets.push((timestamp, packet_data));
+            packets.len() - 1
+        };
+        let snapshot = FoveatedSnapshot::capture(&runner.state.fv_state, timestamp, packet_index);
+        runner.fv_snapshots.lock().push(snapshot);
+    }
 }
 
 fn create_point_tuples(points: &[Point2<u16>]) -> Vec<(u8, Point2<f32>)> {
@@ -437,14 +894,25 @@ fn create_point_tuples(points: &[Point2<u16>]) -> Vec<(u8, Point2<f32>)> {
         .collect()
 }
 
-fn transform_points(
-    points: &[Point2<f32>],
-    camera_intrinsics: &RosOpenCvIntrinsics<f32>,
-) -> Vec<Point2<f32>> {
-    ats_cv::undistort_points(
-        &ats_common::ros_opencv_intrinsics_type_convert(camera_intrinsics),
-        points,
-    )
+/// Merge detections within `radius` of each other with [`correspondence::merge_close_points`],
+/// before they are matched to the model. A merged detection keeps the id of the nearest original
+/// one.
+fn merge_point_tuples(tuples: Vec<(u8, Point2<f32>)>, radius: f32) -> Vec<(u8, Point2<f32>)> {
+    let mut points: ArrayVec<Point2<f32>, 16> = tuples.iter().map(|&(_, p)| p).take(16).collect();
+    correspondence::merge_close_points(&mut points, radius);
+    if points.len() == tuples.len() {
+        return tuples;
+    }
+    points
+        .iter()
+        .filter_map(|&p| {
+            let distance = |(_, q): &&(u8, Point2<f32>)| (q - p).norm_squared();
+            let &(id, _) = tuples
+                .iter()
+                .min_by(|a, b| distance(a).total_cmp(&distance(b)))?;
+            Some((id, p))
+        })
+        .collect()
 }
 
 async fn accel_stream(runner: Arc<Mutex<MotRunner>>) {
@@ -461,80 +929,117 @@ async fn accel_stream(runner: Arc<Mutex<MotRunner>>) {
     };
     let mut prev_timestamp = None;
     while let Some(accel) = accel_stream.next().await {
-        let mut runner = runner.lock();
-        let accel_odr = runner.general_config.accel_config.accel_odr;
-
-        // correct accel and gyro bias and scale
-        let accel = ats_usb::packets::vm::AccelReport {
-            accel: accel.corrected_accel(&runner.general_config.accel_config),
-            gyro: accel.corrected_gyro(&runner.general_config.gyro_config),
-            timestamp: accel.timestamp,
-        };
+        accel_report(&mut runner.lock(), accel, &mut prev_timestamp);
+    }
+}
 
-        // println!("Timestamp: {}", accel.timestamp);
+/// Process one accel report. `prev_timestamp` is the timestamp of the previous report, for the
+/// time step.
+pub fn accel_report(runner: &mut MotRunner, accel: AccelReport, prev_timestamp: &mut Option<u32>) {
+    runner.state.packet_rate.tick(std::time::Instant::now());
+    let accel_odr = runner.general_config.accel_config.accel_odr;
 
-        // println!("{:7.3?} {:7.3?}", accel.accel.xzy(), accel.gyro.xzy());
-        // println!("{:7.3?}", accel.accel.norm());
+    // correct accel and gyro bias and scale
+    let accel = AccelReport {
+        accel: accel.corrected_accel(&runner.general_config.accel_config),
+        gyro: accel.corrected_gyro(&runner.general_config.gyro_config),
+        timestamp: accel.timestamp,
+    };
+
+    // println!("Timestamp: {}", accel.timestamp);
 
-        // print rotation in degrees
-        // println!("Rotation: {}", accel.gyro.xzy().map(|x| x.to_degrees()));
+    // println!("{:7.3?} {:7.3?}", accel.accel.xzy(), accel.gyro.xzy());
+    // println!("{:7.3?}", accel.accel.norm());
 
-        if let Some(_prev_timestamp) = prev_timestamp {
+    // print rotation in degrees
+    // println!("Rotation: {}", accel.gyro.xzy().map(|x| x.to_degrees()));
+
+    if let Some(_prev_timestamp) = *prev_timestamp {
+        if accel.timestamp < _prev_timestamp {
+            *prev_timestamp = None;
             if accel.timestamp < _prev_timestamp {
-                prev_timestamp = None;
-                if accel.timestamp < _prev_timestamp {
-                    prev_timestamp = None;
-                    continue;
-                }
-                continue;
+                *prev_timestamp = None;
+                return;
             }
+            return;
         }
+    }
 
-        if let Some(prev_timestamp) = prev_timestamp {
-            let elapsed = accel.timestamp as u64 - prev_timestamp as u64;
-            runner.state.fv_state.predict(
-                -accel.accel.xzy(),
-                -accel.gyro.xzy(),
-                Duration::from_micros(elapsed),
-            );
+    let dt = match *prev_timestamp {
+        Some(prev_timestamp) => {
+            Duration::from_micros(accel.timestamp as u64 - prev_timestamp as u64)
+        }
+        None => Duration::from_secs_f32(1. / accel_odr as f32),
+    };
+    *prev_timestamp = Some(accel.timestamp);
+    runner.state.last_imu_timestamp = Some(accel.timestamp);
 
-            let sample_period = runner.state.madgwick.sample_period_mut();
-            *sample_period = elapsed as f32 / 1_000_000.;
-        } else {
-            runner.state.fv_state.predict(
-                -accel.accel.xzy(),
-                -accel.gyro.xzy(),
-                Duration::from_secs_f32(1. / accel_odr as f32),
-            );
+    let windows = runner.state.impact_accel.push(crate::imu::AccelSample {
+        timestamp: accel.timestamp,
+        accel: accel.accel,
+        gyro: accel.gyro,
+    });
+    for window in windows {
+        tracing::info!(
+            "Impact at {} captured {} IMU samples, peak accel {:.1} m/s^2",
+            window.impact_timestamp,
+            window.samples.len(),
+            window.peak_accel().unwrap_or(0.0),
+        );
+        let impact_accel_windows = &mut runner.state.impact_accel_windows;
+        if impact_accel_windows.len() == crate::imu::IMPACT_ACCEL_WINDOWS_LEN {
+            impact_accel_windows.pop_front();
         }
-        prev_timestamp = Some(accel.timestamp);
+        impact_accel_windows.push_back(window);
+    }
 
-        let _ = runner
+    if runner.software_impact
+        && runner
             .state
-            .madgwick
-            .update_imu(&Vector3::from(accel.gyro), &Vector3::from(accel.accel));
-        runner.state.orientation = runner.state.madgwick.quat.to_rotation_matrix();
+            .impact_detector
+            .update(accel.accel, accel.timestamp)
+    {
+        handle_impact(runner, accel.timestamp);
+    }
 
-        ats_cv::series_add!(
-            imu_data,
-            (-accel.accel.xzy().cast(), -accel.gyro.xzy().cast())
-        );
+    // remove the gyro drift estimated during stationary periods
+    let gyro = runner
+        .state
+        .gyro_bias
+        .update(accel.accel, accel.gyro, dt.as_secs_f32());
 
-        my_raycast_update(&mut runner);
+    runner
+        .state
+        .fv_state
+        .predict(-accel.accel.xzy(), -gyro.xzy(), dt);
+    *runner.state.madgwick.sample_period_mut() = dt.as_secs_f32();
 
-        if runner.record_packets {
-            runner.packets.lock().push((
-                std::time::SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-                ats_usb::packets::vm::PacketData::AccelReport(accel),
-            ));
-        }
+    let _ = runner
+        .state
+        .madgwick
+        .update_imu(&Vector3::from(gyro), &Vector3::from(accel.accel));
+    runner.state.orientation = runner.state.madgwick.quat.to_rotation_matrix();
+    let quat = runner.state.madgwick.quat;
+    runner.state.orientation_quality.update(&quat, accel.accel);
+
+    ats_cv::series_add!(
+        imu_data,
+        (-accel.accel.xzy().cast(), -accel.gyro.xzy().cast())
+    );
+
+    my_raycast_update(runner);
+
+    if runner.record_packets {
+        runner.packets.lock().push((
+            std::time::SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            ats_usb::packets::vm::PacketData::AccelReport(accel),
+        ));
     }
 }
 
-// todo use an aimpoint history to choose the aimpoint closest to the timestamp
 async fn impact_loop(runner: Arc<Mutex<MotRunner>>) {
     let device = match runner.lock().device.as_ref() {
         Some(d) => d.c(),
@@ -547,51 +1052,102 @@ async fn impact_loop(runner: Arc<Mutex<MotRunner>>) {
             return;
         }
     };
-    while let Some(_impact) = impact_stream.next().await {
-        let runner = runner.lock();
-        if runner.record_impact {
-            let mut frame = TestFrame {
-                fv_aimpoint_x: None,
-                fv_aimpoint_y: None,
-                opposite_cant: None,
-                position_x: None,
-                position_y: None,
-                position_z: None,
-            };
+    while let Some(impact) = impact_stream.next().await {
+        impact_report(&mut runner.lock(), impact);
+    }
+}
 
-            {
-                let data = runner.state.fv_aimpoint_history[runner.state.fv_aimpoint_history_index];
-                let fv_aimpoint = data.0;
-                frame.fv_aimpoint_x = Some(fv_aimpoint.x);
-                frame.fv_aimpoint_y = Some(fv_aimpoint.y);
-                frame.opposite_cant = Some(data.1);
-                frame.position_x = Some(data.2.x);
-                frame.position_y = Some(data.2.y);
-                frame.position_z = Some(data.2.z);
-            }
+/// Process one hardware impact report.
+pub fn impact_report(runner: &mut MotRunner, impact: ImpactReport) {
+    runner.state.packet_rate.tick(std::time::Instant::now());
+    handle_impact(runner, impact.timestamp);
+    if runner.record_packets {
+        runner.packets.lock().push((
+            std::time::SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            ats_usb::packets::vm::PacketData::ImpactReport(impact),
+        ));
+    }
+}
 
-            if runner.datapoints.is_locked() {
-                continue;
-            }
+/// Common handling for hardware impact reports and the software impact detector.
+fn handle_impact(runner: &mut MotRunner, timestamp: u32) {
+    runner.emit(RunnerEvent::ImpactDetected { timestamp });
+    if runner.record_impact && !runner.test_ready {
+        tracing::warn!("Ignoring impact, the test is waiting for the orientation to converge");
+    }
+    let record = runner.record_impact && runner.test_ready;
+    let Some(frame) = impact_datapoint(
+        &mut runner.state,
+        timestamp,
+        record,
+        runner.log_raw_aimpoint,
+    ) else {
+        return;
+    };
 
-            runner.datapoints.lock().push(frame);
+    if runner.datapoints.is_locked() {
+        return;
+    }
 
-            let ui_update = runner.ui_update.c();
+    runner.datapoints.lock().push(frame);
 
-            runner.ui_ctx.queue_main(move || {
-                leptos_reactive::SignalSet::set(&ui_update, ());
-            });
-        }
-        if runner.record_packets {
-            runner.packets.lock().push((
-                std::time::SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-                ats_usb::packets::vm::PacketData::ImpactReport(_impact),
-            ));
-        }
+    let ui_update = runner.ui_update.c();
+
+    if let Some(ui_ctx) = runner.ui_ctx {
+        ui_ctx.queue_main(move || {
+            leptos_reactive::SignalSet::set(&ui_update, ());
+        });
+    }
+}
+
+/// Look up the aimpoint at an impact in the history and keep it in
+/// [`MotState::impact_aimpoints`](crate::MotState::impact_aimpoints).
+///
+/// Returns the datapoint to record if `record` is set and there was any history. The raw aimpoint
+/// columns are only filled in when `log_raw_aimpoint` is set.
+pub fn impact_datapoint(
+    state: &mut crate::MotState,
+    timestamp: u32,
+    record: bool,
+    log_raw_aimpoint: bool,
+) -> Option<TestFrame> {
+    state.impact_accel.impact(timestamp);
+    let Some(sample) = state
+        .fv_aimpoint_history
+        .nearest_in_time(timestamp)
+        .copied()
+    else {
+        tracing::warn!("Impact arrived before any aimpoint history was recorded");
+        return None;
+    };
+    tracing::info!(
+        "Impact at {timestamp} aimed at ({:.4}, {:.4}), distance {:.2}",
+        sample.aimpoint.x,
+        sample.aimpoint.y,
+        sample.distance,
+    );
+    let impact_aimpoints = &mut state.impact_aimpoints;
+    if impact_aimpoints.len() == IMPACT_AIMPOINTS_LEN {
+        impact_aimpoints.pop_front();
     }
+    impact_aimpoints.push_back(ImpactAimpoint {
+        impact_timestamp: timestamp,
+        aimpoint: sample.aimpoint,
+        distance: sample.distance,
+    });
+    record.then(|| TestFrame {
+        fv_aimpoint_x: Some(sample.aimpoint.x),
+        fv_aimpoint_y: Some(sample.aimpoint.y),
+        opposite_cant: Some(sample.opposite_cant),
+        position_x: Some(sample.translation.x),
+        position_y: Some(sample.translation.y),
+        position_z: Some(sample.translation.z),
+        raw_aimpoint_x: log_raw_aimpoint.then_some(sample.raw_aimpoint.x),
+        raw_aimpoint_y: log_raw_aimpoint.then_some(sample.raw_aimpoint.y),
+    })
 }
 
 pub fn rescale(val: f64) -> f64 {
@@ -629,3 +1185,256 @@ fn _dummy_nf_data() -> [MotData; 4] {
         },
     ]
 }
+
+/// A runner without a device or UI, for tests.
+#[cfg(test)]
+pub(crate) fn test_runner() -> MotRunner {
+    MotRunner {
+        state: crate::MotState::default(),
+        device: None,
+        general_config: GeneralSettings {
+            accel_config: ats_usb::packets::vm::AccelConfig {
+                accel_odr: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        record_impact: true,
+        test_ready: false,
+        software_impact: false,
+        log_raw_aimpoint: false,
+        record_packets: false,
+        datapoints: Default::default(),
+        packets: Default::default(),
+        fv_snapshots: Default::default(),
+        ui_update: leptos_reactive::create_rw_signal(()),
+        ui_ctx: None,
+        wfnf_realign: false,
+        show_screen_outline: false,
+        marker_pattern: Default::default(),
+        screen_calibrations: ArrayVec::new(),
+        event_tx: tokio::sync::broadcast::channel(64).0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MotState;
+    use ats_common::ScreenCalibration;
+
+    fn calibrations() -> ArrayVec<(u8, ScreenCalibration<f32>), 2> {
+        [0, 1]
+            .into_iter()
+            .map(|id| {
+                let info = crate::ScreenInfo {
+                    screen_dimensions_meters: [1.6, 0.9],
+                    marker_points: [nalgebra::Point3::origin(); MARKER_PATTERN_LEN],
+                };
+                (id, info.into())
+            })
+            .collect()
+    }
+
+    fn held_ids(cals: &ArrayVec<(u8, ScreenCalibration<f32>), 2>) -> Vec<u8> {
+        cals.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn held_screen_selects_calibration() {
+        let cals = calibrations();
+        let mut state = MotState::default();
+        // Nothing held yet, every screen is a candidate
+        assert_eq!(held_ids(&hold_screen(&mut state, None, &cals)), [0, 1]);
+        assert_eq!(held_ids(&hold_screen(&mut state, Some(0), &cals)), [0]);
+
+        // A single spurious report of the other screen doesn't switch
+        assert_eq!(held_ids(&hold_screen(&mut state, Some(1), &cals)), [0]);
+        assert_eq!(state.screen_id, 0);
+        assert_eq!(held_ids(&hold_screen(&mut state, Some(0), &cals)), [0]);
+        // Neither does a report that identified no screen
+        assert_eq!(held_ids(&hold_screen(&mut state, None, &cals)), [0]);
+
+        // A sustained one does, on the last of the required reports
+        for _ in 1..DEFAULT_SCREEN_SWITCH_FRAMES {
+            assert_eq!(held_ids(&hold_screen(&mut state, Some(1), &cals)), [0]);
+        }
+        assert_eq!(held_ids(&hold_screen(&mut state, Some(1), &cals)), [1]);
+        assert_eq!(state.screen_id, 1);
+    }
+
+    #[test]
+    fn impact_datapoint_raw_columns() {
+        let mut state = MotState::default();
+        state.set_aimpoint_filter(crate::aimpoint_filter::AimpointFilterKind::Exponential);
+        for (timestamp, raw) in [
+            (1_000, Point2::new(0.2, 0.2)),
+            (2_000, Point2::new(0.8, 0.6)),
+        ] {
+            let aimpoint = state.filter_aimpoint(raw);
+            state.fv_aimpoint_history.push(AimpointSample {
+                timestamp: Some(timestamp),
+                aimpoint,
+                raw_aimpoint: raw,
+                ..Default::default()
+            });
+        }
+
+        let frame = impact_datapoint(&mut state, 2_010, true, true).unwrap();
+        let sample = *state.fv_aimpoint_history.latest().unwrap();
+        assert_eq!(frame.raw_aimpoint_x, Some(sample.raw_aimpoint.x));
+        assert_eq!(frame.raw_aimpoint_y, Some(sample.raw_aimpoint.y));
+        assert_eq!(frame.fv_aimpoint_x, Some(sample.aimpoint.x));
+        // The filtered aimpoint lags behind the jump
+        assert!(frame.fv_aimpoint_x < frame.raw_aimpoint_x);
+        assert!(frame.fv_aimpoint_y < frame.raw_aimpoint_y);
+        assert_eq!(state.impact_aimpoints.len(), 1);
+
+        let frame = impact_datapoint(&mut state, 2_010, true, false).unwrap();
+        assert_eq!((frame.raw_aimpoint_x, frame.raw_aimpoint_y), (None, None));
+        assert!(impact_datapoint(&mut state, 2_010, false, true).is_none());
+        assert_eq!(state.impact_aimpoints.len(), 3);
+    }
+
+    /// Every order of the four points of `quad`
+    fn permutations(quad: [Point2<f64>; 4]) -> impl Iterator<Item = [Point2<f64>; 4]> {
+        (0..256).filter_map(move |n| {
+            let order = [n & 3, (n >> 2) & 3, (n >> 4) & 3, (n >> 6) & 3];
+            let distinct = (0..4).all(|i| !order[i + 1..].contains(&order[i]));
+            distinct.then(|| order.map(|i| quad[i]))
+        })
+    }
+
+    #[test]
+    fn sort_rectangle_any_input_order() {
+        // Top left, top right, bottom right, bottom left with y pointing down
+        let square =
+            [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(x, y)| Point2::new(x, y));
+        assert_eq!(permutations(square).count(), 24);
+        for mut quad in permutations(square) {
+            assert!(sort_rectangle(&mut quad));
+            assert_eq!(quad, square);
+        }
+    }
+
+    #[test]
+    fn sort_rectangle_degenerate() {
+        let collinear =
+            [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0005), (3.0, 0.0)].map(|(x, y)| Point2::new(x, y));
+        assert!(is_degenerate_quad(&collinear));
+        let mut expected = collinear;
+        assert!(!sort_rectangle(&mut expected));
+        // Still the same order whichever way the points come in
+        for mut quad in permutations(collinear) {
+            assert!(!sort_rectangle(&mut quad));
+            assert_eq!(quad, expected);
+        }
+
+        let coincident =
+            [(0.0, 0.0), (1.0, 0.0), (1.0, 0.0), (0.0, 1.0)].map(|(x, y)| Point2::new(x, y));
+        assert!(is_degenerate_quad(&coincident));
+        assert!(is_degenerate_quad(&[Point2::<f64>::origin(); 4]));
+        // A skewed quad is fine
+        let skewed =
+            [(0.0, 0.0), (1.0, 0.1), (1.2, 0.9), (0.1, 1.0)].map(|(x, y)| Point2::new(x, y));
+        assert!(!is_degenerate_quad(&skewed));
+    }
+
+    #[test]
+    fn tracking_status_states() {
+        let full = MARKER_PATTERN_LEN;
+        assert_eq!(
+            TrackingStatus::classify(full, Some(DEGRADED_MATCH_ERROR / 2.0), 0),
+            TrackingStatus::Tracking
+        );
+        // Too few markers, a poor match or nothing matched
+        assert_eq!(
+            TrackingStatus::classify(full - 1, Some(0.0), 1),
+            TrackingStatus::Degraded
+        );
+        assert_eq!(
+            TrackingStatus::classify(full, Some(DEGRADED_MATCH_ERROR * 2.0), 0),
+            TrackingStatus::Degraded
+        );
+        assert_eq!(
+            TrackingStatus::classify(full, None, 0),
+            TrackingStatus::Degraded
+        );
+        assert_eq!(
+            TrackingStatus::classify(0, None, TRACKING_LOST_FRAMES - 1),
+            TrackingStatus::Degraded
+        );
+        assert_eq!(
+            TrackingStatus::classify(0, None, TRACKING_LOST_FRAMES),
+            TrackingStatus::Lost
+        );
+    }
+
+    /// Markers report with `points` on both sensors. Built through serde so it doesn't depend on
+    /// how the report stores its 16 slots, unused ones are zero.
+    fn combined_report(points: &[(u16, u16)]) -> MarkersReport {
+        let slots: Vec<_> = (0..16)
+            .map(|i| points.get(i).map_or([0, 0], |&(x, y)| [x, y]))
+            .collect();
+        let report = serde_json::json!({ "nf_points": slots, "wf_points": slots });
+        MarkersReport::Combined(serde_json::from_value(report).unwrap())
+    }
+
+    #[test]
+    fn session_event_order() {
+        let mut runner = test_runner();
+        let mut events = runner.events();
+        let mut marker_seen = false;
+        let mut prev_timestamp = None;
+
+        markers_report(&mut runner, combined_report(&[]), &mut marker_seen);
+        markers_report(
+            &mut runner,
+            combined_report(&[(1800, 2000), (2300, 2000)]),
+            &mut marker_seen,
+        );
+        // Held still, slightly off level so the Madgwick gradient isn't exactly zero
+        for i in 0..crate::imu::DEFAULT_ORIENTATION_WINDOW as u32 {
+            let accel = AccelReport {
+                accel: Vector3::new(0.2, -0.1, crate::imu::GRAVITY),
+                gyro: Vector3::zeros(),
+                timestamp: 10_000 * (i + 1),
+            };
+            accel_report(&mut runner, accel, &mut prev_timestamp);
+        }
+        markers_report(
+            &mut runner,
+            combined_report(&[(1800, 2000), (2300, 2000)]),
+            &mut marker_seen,
+        );
+        impact_report(
+            &mut runner,
+            ImpactReport {
+                timestamp: 1_000_500,
+            },
+        );
+        for _ in 0..TRACKING_LOST_FRAMES {
+            markers_report(&mut runner, combined_report(&[]), &mut marker_seen);
+        }
+
+        let received: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            [
+                RunnerEvent::FirstMarkerSeen,
+                RunnerEvent::AimpointConverged,
+                RunnerEvent::ImpactDetected {
+                    timestamp: 1_000_500
+                },
+                RunnerEvent::TrackingStatusChanged(TrackingStatus::Lost),
+            ]
+        );
+        assert_eq!(runner.datapoints.lock().len(), 1);
+    }
+
+    #[test]
+    fn held_screen_without_calibration() {
+        let cals = calibrations();
+        assert_eq!(held_ids(&held_calibrations(&cals, Some(5))), [0, 1]);
+    }
+}
@@ -18,7 +18,8 @@ use plotters::{
 
 use crate::CloneButShorter;
 
-pub fn plots_window(ui: &UI) -> Window {
+/// `redraw_interval_ms` is the time between redraws while the window is shown.
+pub fn plots_window(ui: &UI, redraw_interval_ms: i32) -> Window {
     let mut window = Window::new(ui, "le plot", 640, 480, WindowType::NoMenubar);
     let paused = Rc::new(Cell::new(false));
     crate::layout! { ui,
@@ -37,7 +38,7 @@ pub fn plots_window(ui: &UI) -> Window {
         }
     });
 
-    ui.ui_timer(16, {
+    ui.ui_timer(redraw_interval_ms, {
         let window = window.c();
         let ui = ui.c();
         let area = area.c();
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use iui::controls::Window;
+use iui::UI;
+use serde::{Deserialize, Serialize};
+
+use crate::marker_pattern::MarkerPattern;
+use crate::theme::Theme;
+
+/// Saved sizes smaller than this are ignored, e.g. from a window that was minimized
+const MIN_SIZE: i32 = 50;
+
+/// Where a window was and whether it was open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    /// Content size, without decorations
+    pub width: i32,
+    pub height: i32,
+    pub open: bool,
+}
+
+impl WindowGeometry {
+    pub fn capture(ui: &UI, window: &Window) -> Self {
+        let (x, y) = window.position(ui);
+        let (width, height) = window.content_size(ui);
+        Self {
+            x,
+            y,
+            width,
+            height,
+            open: window.visible(ui),
+        }
+    }
+
+    /// Restore the size and position. Doesn't show or hide the window.
+    pub fn apply(&self, ui: &UI, window: &mut Window) {
+        if self.width >= MIN_SIZE && self.height >= MIN_SIZE {
+            window.set_content_size(ui, self.width, self.height);
+        }
+        window.set_position(ui, self.x, self.y);
+    }
+}
+
+/// Settings of the desktop app saved between runs.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prefs {
+    /// Keyed by window name
+    pub windows: BTreeMap<String, WindowGeometry>,
+    pub theme: Theme,
+    /// Milliseconds between redraws of the tracking canvases and plots, e.g. 33 for 30 Hz on slow
+    /// machines. `None` keeps each window's built-in interval. Device data is processed as it
+    /// arrives either way, only drawing is throttled.
+    pub redraw_interval_ms: Option<u32>,
+    /// Aimpoint samples kept for matching impacts to where the module was aimed. Widen it if
+    /// impacts at high update rates land after their aimpoint was evicted. `None` keeps
+    /// [`DEFAULT_HISTORY_LEN`](crate::history::DEFAULT_HISTORY_LEN).
+    pub aimpoint_history_len: Option<usize>,
+    pub marker_pattern: MarkerPattern,
+    /// Filtered accel magnitude in g that fires the software impact detector. Separate from the
+    /// device's impact threshold, which is in firmware units. `None` keeps
+    /// [`DEFAULT_SOFTWARE_IMPACT_THRESHOLD_G`](crate::imu::DEFAULT_SOFTWARE_IMPACT_THRESHOLD_G).
+    pub software_impact_threshold_g: Option<f32>,
+}
+
+impl Prefs {
+    /// Load the prefs at `path`. A missing or unreadable file gives empty prefs.
+    pub fn load(path: &Path) -> Self {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                tracing::warn!("Failed to read prefs {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        serde_json::from_slice(&data).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring invalid prefs {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// [`redraw_interval_ms`](Self::redraw_interval_ms), or `default` if unset, in the form
+    /// `UI::ui_timer` takes.
+    pub fn redraw_interval(&self, default: i32) -> i32 {
+        self.redraw_interval_ms
+            .map_or(default, |ms| ms.clamp(1, i32::MAX as u32) as i32)
+    }
+
+    /// [`aimpoint_history_len`](Self::aimpoint_history_len), or the default if unset. Clamped to
+    /// [`MAX_HISTORY_LEN`](crate::history::MAX_HISTORY_LEN) so a corrupt value can't exhaust
+    /// memory at startup.
+    pub fn aimpoint_history_len(&self) -> usize {
+        self.aimpoint_history_len
+            .map_or(crate::history::DEFAULT_HISTORY_LEN, |len| {
+                len.clamp(1, crate::history::MAX_HISTORY_LEN)
+            })
+    }
+
+    /// [`software_impact_threshold_g`](Self::software_impact_threshold_g) in m/s^2, or the default
+    /// if unset or not a positive number.
+    pub fn software_impact_threshold(&self) -> f32 {
+        let g = self
+            .software_impact_threshold_g
+            .filter(|g| g.is_finite() && *g > 0.0)
+            .unwrap_or(crate::imu::DEFAULT_SOFTWARE_IMPACT_THRESHOLD_G);
+        g * crate::imu::GRAVITY
+    }
+
+    pub fn window(&self, name: &str) -> Option<WindowGeometry> {
+        self.windows.get(name).copied()
+    }
+
+    pub fn set_window(&mut self, name: &str, geometry: WindowGeometry) {
+        self.windows.insert(name.to_owned(), geometry);
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Default length of the window a [`RateCounter`] averages over
+pub const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Events per second over a rolling time window, e.g. canvas redraws or received packets.
+///
+/// Each tick is O(1) amortized, so it is cheap enough to call from the draw handler.
+#[derive(Clone, Debug)]
+pub struct RateCounter {
+    window: Duration,
+    ticks: VecDeque<Instant>,
+}
+
+impl Default for RateCounter {
+    fn default() -> Self {
+        Self::new(DEFAULT_RATE_WINDOW)
+    }
+}
+
+impl RateCounter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.max(Duration::from_millis(1)),
+            ticks: VecDeque::new(),
+        }
+    }
+
+    /// Forget all events, e.g. after reconnecting.
+    pub fn reset(&mut self) {
+        self.ticks.clear();
+    }
+
+    /// Record one event at `now`. Events must be recorded in time order.
+    pub fn tick(&mut self, now: Instant) {
+        self.ticks.push_back(now);
+        self.evict(now);
+    }
+
+    /// Events per second in the window ending at `now`.
+    ///
+    /// Until the window has filled the rate is taken over the span of the recorded events, so it
+    /// settles quickly after a reset. Returns 0.0 with fewer than two events.
+    pub fn rate(&mut self, now: Instant) -> f32 {
+        self.evict(now);
+        let (Some(&first), Some(&last)) = (self.ticks.front(), self.ticks.back()) else {
+            return 0.0;
+        };
+        let span = last.duration_since(first).as_secs_f32();
+        if self.ticks.len() < 2 || span <= 0.0 {
+            return 0.0;
+        }
+        (self.ticks.len() - 1) as f32 / span
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some(&oldest) = self.ticks.front() {
+            if now.saturating_duration_since(oldest) > self.window {
+                self.ticks.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steady_rate() {
+        let start = Instant::now();
+        let mut counter = RateCounter::default();
+        for i in 0..200 {
+            counter.tick(start + Duration::from_millis(i * 10));
+        }
+        let now = start + Duration::from_millis(1990);
+        assert!((counter.rate(now) - 100.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn settles_before_window_fills() {
+        let start = Instant::now();
+        let mut counter = RateCounter::new(Duration::from_secs(10));
+        assert_eq!(counter.rate(start), 0.0);
+        counter.tick(start);
+        assert_eq!(counter.rate(start), 0.0);
+        for i in 1..=5 {
+            counter.tick(start + Duration::from_millis(i * 50));
+        }
+        assert!((counter.rate(start + Duration::from_millis(250)) - 20.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn old_events_are_evicted() {
+        let start = Instant::now();
+        let mut counter = RateCounter::new(Duration::from_secs(1));
+        // 100 Hz for a second, then 10 Hz for two
+        for i in 0..100 {
+            counter.tick(start + Duration::from_millis(i * 10));
+        }
+        for i in 0..20 {
+            counter.tick(start + Duration::from_millis(1000 + i * 100));
+        }
+        let rate = counter.rate(start + Duration::from_millis(2900));
+        assert!((rate - 10.0).abs() < 0.01, "{rate}");
+        // Nothing left once the events fall out of the window
+        assert_eq!(counter.rate(start + Duration::from_secs(10)), 0.0);
+    }
+
+    #[test]
+    fn reset_forgets_events() {
+        let start = Instant::now();
+        let mut counter = RateCounter::default();
+        counter.tick(start);
+        counter.tick(start + Duration::from_millis(10));
+        assert!(counter.rate(start + Duration::from_millis(10)) > 0.0);
+        counter.reset();
+        assert_eq!(counter.rate(start + Duration::from_millis(10)), 0.0);
+    }
+}
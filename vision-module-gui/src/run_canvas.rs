@@ -1,17 +1,59 @@
+use crate::hover::MarkerHover;
 use crate::mot_runner::MotRunner;
+use crate::shortcuts::{Shortcut, ShortcutHandler};
+use crate::theme::Theme;
 use crate::{tracking_canvas_helpers, CloneButShorter};
-use iui::controls::{Area, AreaDrawParams, AreaHandler};
+use iui::controls::{Area, AreaDrawParams, AreaHandler, AreaKeyEvent, AreaMouseEvent};
 use iui::UI;
+use leptos_reactive::{RwSignal, SignalGetUntracked};
+use nalgebra::Point2;
 use parking_lot::Mutex;
 use std::sync::Arc;
 
 pub struct RunCanvas {
     pub ctx: UI,
     pub runner: Arc<Mutex<MotRunner>>,
+    pub on_shortcut: ShortcutHandler,
+    pub theme: RwSignal<Theme>,
+    pub hover: MarkerHover,
 }
 
 impl AreaHandler for RunCanvas {
     fn draw(&mut self, _area: &Area, draw_params: &AreaDrawParams) {
-        tracking_canvas_helpers::draw(self.ctx.c(), self.runner.c(), _area, draw_params, false);
+        let palette = self.theme.get_untracked().palette();
+        let drawn = tracking_canvas_helpers::draw(
+            self.ctx.c(),
+            self.runner.c(),
+            _area,
+            draw_params,
+            false,
+            false,
+            palette,
+        );
+        self.hover.set_drawn(drawn);
+        tracking_canvas_helpers::draw_hover_readout(draw_params, &self.hover, palette);
+    }
+
+    fn mouse_event(&mut self, area: &Area, area_mouse_event: &AreaMouseEvent) {
+        let cursor = Point2::new(area_mouse_event.x, area_mouse_event.y);
+        if self.hover.set_cursor(Some(cursor)) {
+            area.queue_redraw_all(&self.ctx);
+        }
+    }
+
+    fn mouse_crossed(&mut self, area: &Area, left: bool) {
+        if left && self.hover.set_cursor(None) {
+            area.queue_redraw_all(&self.ctx);
+        }
+    }
+
+    fn key_event(&mut self, _area: &Area, area_key_event: &AreaKeyEvent) -> bool {
+        match Shortcut::from_key_event(area_key_event) {
+            Some(shortcut) => {
+                (self.on_shortcut)(shortcut);
+                true
+            }
+            None => false,
+        }
     }
 }
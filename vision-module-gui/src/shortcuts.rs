@@ -0,0 +1,35 @@
+use iui::controls::{AreaKeyEvent, Modifiers};
+
+/// Keyboard shortcuts for the main window's buttons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shortcut {
+    /// Ctrl+, (Cmd+, on macOS)
+    Config,
+    /// Ctrl+T
+    Tracking,
+    /// Ctrl+Shift+T
+    RawTracking,
+    /// Ctrl+R
+    RunTest,
+}
+
+/// Called with each shortcut pressed while a canvas that forwards shortcuts has focus.
+pub type ShortcutHandler = Box<dyn FnMut(Shortcut)>;
+
+impl Shortcut {
+    /// The shortcut pressed by `event`, or `None`. Only key presses count, not releases.
+    pub fn from_key_event(event: &AreaKeyEvent) -> Option<Shortcut> {
+        let command = Modifiers::MODIFIER_CTRL | Modifiers::MODIFIER_SUPER;
+        if event.up || !event.modifiers.intersects(command) {
+            return None;
+        }
+        let shift = event.modifiers.contains(Modifiers::MODIFIER_SHIFT);
+        match event.key.to_ascii_lowercase() {
+            b',' => Some(Shortcut::Config),
+            b't' if shift => Some(Shortcut::RawTracking),
+            b't' => Some(Shortcut::Tracking),
+            b'r' => Some(Shortcut::RunTest),
+            _ => None,
+        }
+    }
+}
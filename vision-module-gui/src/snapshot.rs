@@ -0,0 +1,164 @@
+use ats_cv::foveated::FoveatedAimpointState;
+use nalgebra::{Point2, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+/// Serializable copy of the parts of [`FoveatedAimpointState`] needed to reproduce a moment of a
+/// session.
+///
+/// `FoveatedAimpointState` lives in `ats_cv` and isn't serde-aware, so this mirrors its public
+/// filter state. Snapshots are written one JSON object per line so they can sit alongside an
+/// ndjson log.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FoveatedSnapshot {
+    /// Milliseconds since the Unix epoch, the same clock as the recorded packets
+    pub timestamp: u128,
+    /// Index in the recording of the markers report the state was captured after
+    pub packet_index: usize,
+    pub screen_id: u8,
+    pub position: Vector3<f32>,
+    pub orientation: UnitQuaternion<f32>,
+}
+
+impl FoveatedSnapshot {
+    pub fn capture(state: &FoveatedAimpointState, timestamp: u128, packet_index: usize) -> Self {
+        Self {
+            timestamp,
+            packet_index,
+            screen_id: state.screen_id,
+            position: state.filter.position.cast(),
+            orientation: state.filter.orientation.cast(),
+        }
+    }
+
+    /// Apply the snapshot on top of `state`.
+    pub fn restore(&self, state: &mut FoveatedAimpointState) {
+        state.screen_id = self.screen_id;
+        state.filter.position = self.position.cast();
+        state.filter.orientation = self.orientation.cast();
+    }
+
+    /// Build a fresh [`FoveatedAimpointState`] from the snapshot.
+    pub fn to_state(&self) -> FoveatedAimpointState {
+        let mut state = FoveatedAimpointState::new();
+        self.restore(&mut state);
+        state
+    }
+
+    /// Append the snapshot to `writer` as a single ndjson line.
+    pub fn write_line(&self, mut writer: impl Write) -> std::io::Result<()> {
+        serde_json::to_writer(&mut writer, self)?;
+        writer.write_all(b"\n")
+    }
+
+    /// Read every snapshot line from `reader`, skipping blank lines.
+    pub fn read_lines(reader: impl BufRead) -> anyhow::Result<Vec<Self>> {
+        let mut snapshots = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            snapshots.push(serde_json::from_str(&line)?);
+        }
+        Ok(snapshots)
+    }
+}
+
+/// Serializable copy of the observable parts of a [`MotState`](crate::MotState).
+///
+/// Meant for comparing replays against a reference run, so filter internals (Madgwick, the
+/// foveated filter, the aimpoint history) are left out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MotStateSnapshot {
+    pub screen_id: u8,
+    /// Coordinates between 0.0 and 1.0
+    pub aimpoint: Point2<f32>,
+    pub distance: f32,
+    pub nf_markers: Vec<Point2<f32>>,
+    pub wf_markers: Vec<Point2<f32>>,
+    pub orientation: UnitQuaternion<f32>,
+    pub translation: Vector3<f32>,
+}
+
+impl From<&crate::MotState> for MotStateSnapshot {
+    fn from(state: &crate::MotState) -> Self {
+        Self {
+            screen_id: state.screen_id,
+            aimpoint: state.fv_aimpoint,
+            distance: state.distance,
+            nf_markers: state.nf_markers.to_vec(),
+            wf_markers: state.wf_markers.to_vec(),
+            orientation: UnitQuaternion::from_rotation_matrix(&state.orientation),
+            translation: state.translation_mat,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A filter state moved away from its initial value by a few IMU updates
+    fn advanced_state() -> FoveatedAimpointState {
+        let mut state = FoveatedAimpointState::new();
+        state.screen_id = 2;
+        for _ in 0..5 {
+            state.predict(
+                Vector3::new(0.3, -9.7, 0.4),
+                Vector3::new(0.2, -0.1, 0.05),
+                Duration::from_millis(10),
+            );
+        }
+        state
+    }
+
+    #[test]
+    fn foveated_round_trip() {
+        let mut state = advanced_state();
+        let first = FoveatedSnapshot::capture(&state, 1_700_000_000_000, 7);
+        state.predict(Vector3::y(), Vector3::x(), Duration::from_millis(10));
+        let second = FoveatedSnapshot::capture(&state, 1_700_000_000_010, 8);
+
+        let mut ndjson = vec![];
+        first.write_line(&mut ndjson).unwrap();
+        ndjson.push(b'\n');
+        second.write_line(&mut ndjson).unwrap();
+        let read = FoveatedSnapshot::read_lines(&ndjson[..]).unwrap();
+        assert_eq!(read, [first, second]);
+
+        let restored = read[0].to_state();
+        assert_eq!(
+            FoveatedSnapshot::capture(&restored, first.timestamp, 7),
+            first
+        );
+        let mut state = FoveatedAimpointState::new();
+        read[1].restore(&mut state);
+        assert_eq!(
+            FoveatedSnapshot::capture(&state, second.timestamp, 8),
+            second
+        );
+    }
+
+    #[test]
+    fn mot_state_serde_round_trip() {
+        let mut state = crate::MotState::default();
+        state.screen_id = 3;
+        state.fv_aimpoint = Point2::new(0.4, 0.55);
+        state.distance = 2.5;
+        state.nf_markers.push(Point2::new(0.1, -0.2));
+        state
+            .wf_markers
+            .extend([Point2::new(0.3, 0.1), Point2::new(-0.05, 0.2)]);
+        state.orientation = nalgebra::Rotation3::from_euler_angles(0.1, -0.2, 0.3);
+        state.translation_mat = Vector3::new(0.8, 0.45, -2.5);
+
+        let snapshot = MotStateSnapshot::from(&state);
+        assert_eq!(snapshot.nf_markers, state.nf_markers.to_vec());
+        assert_eq!(snapshot.wf_markers, state.wf_markers.to_vec());
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let read: MotStateSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, snapshot);
+    }
+}
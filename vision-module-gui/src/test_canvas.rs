@@ -1,8 +1,10 @@
 use crate::custom_shapes::{draw_crosshair, draw_grid, draw_text};
-use crate::mot_runner::MotRunner;
+use crate::mot_runner::{MotRunner, TrackingStatus};
+use crate::theme::Theme;
 use iui::controls::{Area, AreaDrawParams, AreaHandler, AreaKeyEvent, Window};
-use iui::draw::{Brush, FillMode, Path, SolidBrush, StrokeParams};
+use iui::draw::{FillMode, Path, StrokeParams};
 use iui::UI;
+use leptos_reactive::{RwSignal, SignalGetUntracked};
 use nalgebra::{Isometry3, Point2, Translation3};
 use parking_lot::Mutex;
 use std::sync::Arc;
@@ -15,6 +17,7 @@ pub struct TestCanvas {
     pub runner: Arc<Mutex<MotRunner>>,
     pub last_draw_width: Option<f64>,
     pub last_draw_height: Option<f64>,
+    pub theme: RwSignal<Theme>,
 }
 
 impl AreaHandler for TestCanvas {
@@ -22,20 +25,13 @@ impl AreaHandler for TestCanvas {
         self.last_draw_width = Some(draw_params.area_width);
         self.last_draw_height = Some(draw_params.area_height);
         let ctx = &draw_params.context;
+        let palette = self.theme.get_untracked().palette();
 
         let background = Path::new(ctx, FillMode::Winding);
         background.add_rectangle(ctx, 0., 0., draw_params.area_width, draw_params.area_height);
         background.end(ctx);
 
-        ctx.fill(
-            &background,
-            &Brush::Solid(SolidBrush {
-                r: 0.5,
-                g: 0.5,
-                b: 0.5,
-                a: 1.,
-            }),
-        );
+        ctx.fill(&background, &palette.test_background.brush());
 
         let fv_ch_path = Path::new(ctx, FillMode::Winding);
         let runner = self.runner.lock();
@@ -56,13 +52,28 @@ impl AreaHandler for TestCanvas {
             20.0,
             20.0,
             &format!("distance = {:.4}", runner.state.distance),
+            palette.foreground,
         );
         draw_text(
             &ctx,
             20.0,
             40.0,
             &format!("screen_id = {}", runner.state.fv_state.screen_id),
+            palette.foreground,
         );
+        if !runner.test_ready {
+            let error = state.orientation_quality.mean_error().unwrap_or(f32::NAN);
+            draw_text(
+                &ctx,
+                20.0,
+                60.0,
+                &format!(
+                    "waiting for orientation to converge (error = {:.2} deg)",
+                    error.to_degrees()
+                ),
+                palette.foreground,
+            );
+        }
 
         let grid_path = Path::new(ctx, FillMode::Winding);
 
@@ -110,12 +121,15 @@ impl AreaHandler for TestCanvas {
             dash_phase: 0.,
         };
 
-        let brush = Brush::Solid(SolidBrush {
-            r: 0.,
-            g: 1.,
-            b: 0.,
-            a: 1.,
-        });
+        // Dim the aimpoint when it can't be trusted
+        let brush = palette
+            .aimpoint
+            .with_alpha(match runner.state.tracking_status {
+                TrackingStatus::Tracking => 1.,
+                TrackingStatus::Degraded => 0.6,
+                TrackingStatus::Lost => 0.25,
+            })
+            .brush();
 
         ctx.stroke(&fv_ch_path, &brush, &stroke);
 
@@ -129,12 +143,7 @@ impl AreaHandler for TestCanvas {
         };
 
         // Grid
-        let brush = Brush::Solid(SolidBrush {
-            r: 0.5,
-            g: 0.,
-            b: 0.,
-            a: 1.,
-        });
+        let brush = palette.test_grid.brush();
         let stroke = StrokeParams {
             cap: 0,  // Bevel
             join: 0, // Flat
@@ -146,12 +155,7 @@ impl AreaHandler for TestCanvas {
         ctx.stroke(&grid_path, &brush, &stroke);
 
         // Center target
-        let brush = Brush::Solid(SolidBrush {
-            r: 0.,
-            g: 0.,
-            b: 1.,
-            a: 1.,
-        });
+        let brush = palette.test_target.brush();
         let stroke = StrokeParams {
             cap: 0,  // Bevel
             join: 0, // Flat
@@ -0,0 +1,251 @@
+use iui::draw::{Brush, SolidBrush};
+use serde::{Deserialize, Serialize};
+
+/// An RGBA color with components between 0.0 and 1.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+impl Color {
+    pub const fn rgb(r: f64, g: f64, b: f64) -> Self {
+        Self { r, g, b, a: 1.0 }
+    }
+
+    pub const fn with_alpha(self, a: f64) -> Self {
+        Self { a, ..self }
+    }
+
+    pub fn brush(self) -> Brush {
+        let Self { r, g, b, a } = self;
+        Brush::Solid(SolidBrush { r, g, b, a })
+    }
+}
+
+/// Canvas colors by what they show.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    /// Behind the tracking canvases, `None` keeps the window's own background
+    pub background: Option<Color>,
+    /// Outlines, labels and crosshairs that aren't color coded
+    pub foreground: Color,
+    /// Line pointing in the vision module's up direction
+    pub up: Color,
+    /// Nearfield and widefield blob bounding boxes, drawn translucent
+    pub nf_blob: Color,
+    pub wf_blob: Color,
+    /// Markers by pattern id modulo 3
+    pub pattern: [Color; 3],
+    /// Markers with a pattern id past the known patterns
+    pub pattern_other: Color,
+    pub fv_reprojection: Color,
+    pub pnp_reprojection: Color,
+    pub wf_reprojection: Color,
+    /// Calibrated screen rectangle projected from the current pose
+    pub screen_outline: Color,
+    /// Aimpoint test window
+    pub test_background: Color,
+    pub aimpoint: Color,
+    pub test_grid: Color,
+    pub test_target: Color,
+}
+
+impl Palette {
+    /// Color for a marker with the given pattern id.
+    ///
+    /// Ids below `known` cycle through `pattern`, higher ids get `pattern_other` and markers
+    /// without an id get `unidentified`.
+    pub fn marker(&self, pattern_id: Option<u8>, known: u8, unidentified: Color) -> Color {
+        match pattern_id {
+            Some(id) if id < known => self.pattern[usize::from(id % 3)],
+            Some(_) => self.pattern_other,
+            None => unidentified,
+        }
+    }
+}
+
+const DARK: Palette = Palette {
+    background: None,
+    foreground: Color::rgb(0.0, 0.0, 0.0),
+    up: Color::rgb(0.0, 1.0, 0.0),
+    nf_blob: Color::rgb(1.0, 0.0, 0.0).with_alpha(0.5),
+    wf_blob: Color::rgb(0.0, 0.0, 1.0).with_alpha(0.5),
+    pattern: [
+        Color::rgb(1.0, 0.0, 0.0),
+        Color::rgb(0.0, 1.0, 0.0),
+        Color::rgb(0.0, 0.0, 1.0),
+    ],
+    pattern_other: Color::rgb(1.0, 0.0, 1.0),
+    fv_reprojection: Color::rgb(0.0, 0.69, 0.42),
+    pnp_reprojection: Color::rgb(0.3, 0.3, 0.3),
+    wf_reprojection: Color::rgb(0.627, 0.125, 0.941),
+    screen_outline: Color::rgb(1.0, 0.5, 0.0),
+    test_background: Color::rgb(0.5, 0.5, 0.5),
+    aimpoint: Color::rgb(0.0, 1.0, 0.0),
+    test_grid: Color::rgb(0.5, 0.0, 0.0),
+    test_target: Color::rgb(0.0, 0.0, 1.0),
+};
+
+const LIGHT: Palette = Palette {
+    background: Some(Color::rgb(1.0, 1.0, 1.0)),
+    test_background: Color::rgb(0.95, 0.95, 0.95),
+    aimpoint: Color::rgb(0.0, 0.6, 0.0),
+    up: Color::rgb(0.0, 0.6, 0.0),
+    pattern: [
+        Color::rgb(0.8, 0.0, 0.0),
+        Color::rgb(0.0, 0.6, 0.0),
+        Color::rgb(0.0, 0.0, 0.8),
+    ],
+    ..DARK
+};
+
+const HIGH_CONTRAST: Palette = Palette {
+    background: Some(Color::rgb(0.0, 0.0, 0.0)),
+    foreground: Color::rgb(1.0, 1.0, 1.0),
+    up: Color::rgb(1.0, 1.0, 0.0),
+    nf_blob: Color::rgb(1.0, 0.3, 0.3).with_alpha(0.6),
+    wf_blob: Color::rgb(0.3, 0.6, 1.0).with_alpha(0.6),
+    pattern: [
+        Color::rgb(1.0, 0.3, 0.3),
+        Color::rgb(0.3, 1.0, 0.3),
+        Color::rgb(0.3, 0.6, 1.0),
+    ],
+    pattern_other: Color::rgb(1.0, 0.3, 1.0),
+    fv_reprojection: Color::rgb(0.0, 1.0, 0.8),
+    pnp_reprojection: Color::rgb(0.8, 0.8, 0.8),
+    wf_reprojection: Color::rgb(0.8, 0.5, 1.0),
+    screen_outline: Color::rgb(1.0, 0.7, 0.2),
+    test_background: Color::rgb(0.0, 0.0, 0.0),
+    aimpoint: Color::rgb(1.0, 1.0, 0.0),
+    test_grid: Color::rgb(0.7, 0.7, 0.7),
+    test_target: Color::rgb(0.0, 1.0, 1.0),
+};
+
+/// Selects the canvas [`Palette`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Theme {
+    /// The original colors
+    #[default]
+    Dark,
+    /// White backgrounds, for bright rooms
+    Light,
+    HighContrast,
+}
+
+impl Theme {
+    /// In the order shown in the UI.
+    pub const ALL: [Theme; 3] = [Self::Dark, Self::Light, Self::HighContrast];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Dark => "Dark",
+            Self::Light => "Light",
+            Self::HighContrast => "High contrast",
+        }
+    }
+
+    pub fn palette(self) -> &'static Palette {
+        match self {
+            Self::Dark => &DARK,
+            Self::Light => &LIGHT,
+            Self::HighContrast => &HIGH_CONTRAST,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dark_keeps_original_colors() {
+        assert_eq!(Theme::default(), Theme::Dark);
+        let palette = Theme::Dark.palette();
+        assert_eq!(palette.background, None);
+        assert_eq!(palette.foreground, Color::rgb(0.0, 0.0, 0.0));
+        assert_eq!(palette.pattern[0], Color::rgb(1.0, 0.0, 0.0));
+        assert_eq!(palette.test_background, Color::rgb(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn marker_colors() {
+        let unidentified = Color::rgb(0.1, 0.2, 0.3);
+        for theme in Theme::ALL {
+            let palette = theme.palette();
+            for id in 0..9 {
+                assert_eq!(
+                    palette.marker(Some(id), 9, unidentified),
+                    palette.pattern[usize::from(id % 3)],
+                );
+            }
+            assert_eq!(
+                palette.marker(Some(9), 9, unidentified),
+                palette.pattern_other
+            );
+            assert_eq!(
+                palette.marker(Some(6), 6, unidentified),
+                palette.pattern_other
+            );
+            assert_eq!(palette.marker(None, 9, unidentified), unidentified);
+        }
+    }
+
+    #[test]
+    fn colors_stand_out() {
+        for theme in Theme::ALL {
+            let palette = theme.palette();
+            let name = theme.name();
+            let [a, b, c] = palette.pattern;
+            assert!(a != b && b != c && a != c, "{name}");
+            assert!(!palette.pattern.contains(&palette.pattern_other), "{name}");
+            if let Some(background) = palette.background {
+                assert_ne!(palette.foreground, background, "{name}");
+                assert!(!palette.pattern.contains(&background), "{name}");
+            }
+            for color in [palette.aimpoint, palette.test_grid, palette.test_target] {
+                assert_ne!(color, palette.test_background, "{name}");
+            }
+            let all = [
+                palette.foreground,
+                palette.up,
+                palette.nf_blob,
+                palette.wf_blob,
+                palette.pattern_other,
+                palette.fv_reprojection,
+                palette.pnp_reprojection,
+                palette.wf_reprojection,
+                palette.screen_outline,
+                palette.test_background,
+                palette.aimpoint,
+                palette.test_grid,
+                palette.test_target,
+            ];
+            for color in all.iter().chain(&palette.pattern) {
+                for c in [color.r, color.g, color.b, color.a] {
+                    assert!((0.0..=1.0).contains(&c), "{name}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn themes_differ() {
+        for (i, a) in Theme::ALL.iter().enumerate() {
+            for b in &Theme::ALL[i + 1..] {
+                assert_ne!(a.palette(), b.palette());
+                assert_ne!(a.name(), b.name());
+            }
+        }
+    }
+
+    #[test]
+    fn theme_round_trips() {
+        for theme in Theme::ALL {
+            let json = serde_json::to_string(&theme).unwrap();
+            assert_eq!(serde_json::from_str::<Theme>(&json).unwrap(), theme);
+        }
+    }
+}
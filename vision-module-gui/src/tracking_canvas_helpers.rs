@@ -1,16 +1,21 @@
 use crate::custom_shapes::{
-    self, draw_crosshair_rotated, draw_diamond, draw_rect, draw_square, draw_text, solid_brush,
+    self, draw_crosshair_rotated, draw_diamond, draw_rect, draw_square, draw_text,
 };
-use crate::mot_runner::MotRunner;
+use crate::hover::{MarkerHover, MarkerReadout};
+use crate::mot_runner::{MotRunner, TrackingStatus};
+use crate::theme::Palette;
 use crate::MotState;
 use arrayvec::ArrayVec;
+use ats_usb::packets::vm::{normalized_point, MotData};
 use iui::controls::{Area, AreaDrawParams};
-use iui::draw::{Brush, DrawContext, FillMode, Path, SolidBrush, StrokeParams};
+use iui::draw::{DrawContext, FillMode, Path, StrokeParams};
 use iui::UI;
 use nalgebra::{Isometry3, Point2, Rotation2, Scale2, Transform2, Translation2, Vector2, Vector3};
+use opencv_ros_camera::RosOpenCvIntrinsics;
 use parking_lot::Mutex;
 use std::f64::consts::PI;
 use std::sync::Arc;
+use std::time::Instant;
 
 pub fn draw(
     _ctx: UI,
@@ -18,10 +23,19 @@ pub fn draw(
     _area: &Area,
     draw_params: &AreaDrawParams,
     raw: bool,
-) {
+    undistort: bool,
+    palette: &Palette,
+) -> Vec<MarkerReadout> {
     let ctx = &draw_params.context;
     let awidth = draw_params.area_width;
     let aheight = draw_params.area_height;
+
+    if let Some(background) = palette.background {
+        let background_path = Path::new(ctx, FillMode::Winding);
+        background_path.add_rectangle(ctx, 0., 0., awidth, aheight);
+        background_path.end(ctx);
+        ctx.fill(&background_path, &background.brush());
+    }
     let draw_size = (awidth.min(aheight).powi(2) / 2.0).sqrt();
     let draw_size = if raw {
         draw_size
@@ -47,7 +61,11 @@ pub fn draw(
     let nf_path = Path::new(ctx, FillMode::Winding);
     let wf_path = Path::new(ctx, FillMode::Winding);
     let nf_grid_path = Path::new(ctx, FillMode::Winding);
-    let runner = runner.lock();
+    let mut runner = runner.lock();
+    let now = Instant::now();
+    runner.state.redraw_rate.tick(now);
+    let redraw_rate = runner.state.redraw_rate.rate(now);
+    let packet_rate = runner.state.packet_rate.rate(now);
     let state = &runner.state;
 
     let gravity_vec = state.orientation.inverse_transform_vector(&Vector3::z());
@@ -68,16 +86,7 @@ pub fn draw(
             draw_square(ctx, &border_path, border_transform);
         }
         border_path.end(ctx);
-        ctx.stroke(
-            &border_path,
-            &Brush::Solid(SolidBrush {
-                r: 0.,
-                g: 0.,
-                b: 0.,
-                a: 1.,
-            }),
-            &stroke1,
-        );
+        ctx.stroke(&border_path, &palette.foreground.brush(), &stroke1);
     }
 
     // Green line representing the up direction relative to the vision module.
@@ -95,16 +104,7 @@ pub fn draw(
             0.5 * draw_params.area_height + 50.0 * angle.sin(),
         );
         gravity_line_path.end(ctx);
-        ctx.stroke(
-            &gravity_line_path,
-            &Brush::Solid(SolidBrush {
-                r: 0.,
-                g: 1.,
-                b: 0.,
-                a: 1.,
-            }),
-            &stroke2,
-        );
+        ctx.stroke(&gravity_line_path, &palette.up.brush(), &stroke2);
     }
 
     let draw_tf = Transform2::from_matrix_unchecked(
@@ -116,7 +116,8 @@ pub fn draw(
         &ctx,
         20.0,
         20.0,
-        &format!("screen_id = {}", state.fv_state.screen_id),
+        &format!("screen_id = {}", state.screen_id),
+        palette.foreground,
     );
 
     let gravity_rot = Rotation2::new(-gravity_angle);
@@ -126,18 +127,64 @@ pub fn draw(
         20.0,
         40.0,
         &format!("gravity_angle = {:.3}", gravity_angle.to_degrees()),
+        palette.foreground,
+    );
+
+    let gyro_bias = state.gyro_bias.bias();
+    draw_text(
+        &ctx,
+        20.0,
+        60.0,
+        &format!(
+            "gyro_bias = ({:.4}, {:.4}, {:.4}){}",
+            gyro_bias.x,
+            gyro_bias.y,
+            gyro_bias.z,
+            if state.gyro_bias.is_stationary() {
+                " (stationary)"
+            } else {
+                ""
+            }
+        ),
+        palette.foreground,
+    );
+
+    draw_text(
+        &ctx,
+        20.0,
+        80.0,
+        &format!("fps = {redraw_rate:.1}, packets/s = {packet_rate:.0}"),
+        palette.foreground,
     );
 
+    if let Some(blend) = state.aimpoint_blend {
+        draw_text(
+            &ctx,
+            20.0,
+            100.0,
+            &format!(
+                "nf/wf weight = {:.2}/{:.2}",
+                blend.nf_weight, blend.wf_weight
+            ),
+            palette.foreground,
+        );
+    }
+
+    let mut drawn = Vec::new();
     if raw {
         draw_raw(
             ctx,
             state,
+            &runner.general_config,
+            undistort,
             draw_tf,
             gravity_rot,
             &nf_path,
             &wf_path,
             &nf_grid_path,
             &ch_path,
+            palette,
+            &mut drawn,
         );
     } else {
         let screen_calibration = runner
@@ -148,6 +195,7 @@ pub fn draw(
             ctx,
             state,
             &runner.general_config,
+            runner.show_screen_outline,
             draw_tf,
             gravity_rot,
             &nf_path,
@@ -155,35 +203,17 @@ pub fn draw(
             &nf_grid_path,
             &ch_path,
             screen_calibration,
+            palette,
+            &mut drawn,
         );
     }
 
     ch_path.end(ctx);
 
-    let brush = Brush::Solid(SolidBrush {
-        r: 1.,
-        g: 0.,
-        b: 0.,
-        a: 0.5,
-    });
-
-    ctx.fill(&nf_path, &brush);
-
-    let brush = Brush::Solid(SolidBrush {
-        r: 0.,
-        g: 0.,
-        b: 1.,
-        a: 0.5,
-    });
+    ctx.fill(&nf_path, &palette.nf_blob.brush());
+    ctx.fill(&wf_path, &palette.wf_blob.brush());
 
-    ctx.fill(&wf_path, &brush);
-
-    let brush = Brush::Solid(SolidBrush {
-        r: 0.,
-        g: 0.,
-        b: 0.,
-        a: 1.,
-    });
+    let brush = palette.foreground.brush();
 
     let stroke = StrokeParams {
         cap: 0,  // Bevel
@@ -197,12 +227,7 @@ pub fn draw(
     ctx.stroke(&ch_path, &brush, &stroke);
 
     // Grid
-    let _brush = Brush::Solid(SolidBrush {
-        r: 0.5,
-        g: 0.,
-        b: 0.,
-        a: 1.,
-    });
+    let _brush = palette.test_grid.brush();
     let _stroke = StrokeParams {
         cap: 0,  // Bevel
         join: 0, // Flat
@@ -214,12 +239,7 @@ pub fn draw(
     // ctx.stroke(&nf_grid_path, &brush, &stroke);
 
     // Center point
-    let brush = Brush::Solid(SolidBrush {
-        r: 0.0,
-        g: 0.,
-        b: 0.,
-        a: 1.,
-    });
+    let brush = palette.foreground.brush();
     let center_point_path = Path::new(ctx, FillMode::Winding);
     draw_diamond(
         ctx,
@@ -231,43 +251,107 @@ pub fn draw(
     );
     center_point_path.end(ctx);
     ctx.stroke(&center_point_path, &brush, &stroke2);
+
+    drawn
+}
+
+/// Details of the hovered marker along the bottom of the canvas, nothing if no marker is hovered.
+pub fn draw_hover_readout(draw_params: &AreaDrawParams, hover: &MarkerHover, palette: &Palette) {
+    if let Some(marker) = hover.hovered() {
+        draw_text(
+            &draw_params.context,
+            20.0,
+            draw_params.area_height - 30.0,
+            &marker.describe(),
+            palette.foreground,
+        );
+    }
+}
+
+/// Positions of `mot_data` between 0.0 and 1.0, undistorted with `intrinsics` if given.
+fn raw_positions(
+    mot_data: &[MotData],
+    state: &MotState,
+    intrinsics: Option<&RosOpenCvIntrinsics<f32>>,
+) -> Vec<Point2<f32>> {
+    let [width, height] = state.sensor_resolution();
+    let positions = mot_data.iter().map(|m| normalized_point(m, width, height));
+    let Some(intrinsics) = intrinsics else {
+        return positions.collect();
+    };
+    // The intrinsics are in the markers report's units, which span the sensor's max coordinate
+    let sensor_max = state.sensor_max();
+    let pixels: Vec<Point2<f32>> = positions
+        .map(|p| p.coords.component_mul(&sensor_max).into())
+        .collect();
+    ats_cv::undistort_points(
+        &ats_common::ros_opencv_intrinsics_type_convert(intrinsics),
+        &pixels,
+    )
+    .into_iter()
+    .map(|p: Point2<f32>| p.coords.component_div(&sensor_max).into())
+    .collect()
 }
 
+/// Draw the blobs at their reported positions, or undistorted with the device's intrinsics.
+///
+/// Blob bounding boxes can't be undistorted, so they are only drawn for reported positions.
 fn draw_raw(
     ctx: &DrawContext,
     state: &MotState,
+    config: &ats_usb::device::GeneralSettings,
+    undistort: bool,
     draw_tf: Transform2<f64>,
     gravity_rot: Rotation2<f64>,
     nf_path: &Path,
     wf_path: &Path,
     nf_grid_path: &Path,
     ch_path: &Path,
+    palette: &Palette,
+    drawn: &mut Vec<MarkerReadout>,
 ) {
+    let sensor_max = state.sensor_max().cast::<f64>();
     if let Some(nf_data) = state.nf_data.as_ref() {
+        let positions = raw_positions(nf_data, state, undistort.then_some(&config.camera_model_nf));
         let mut nf_points = ArrayVec::<Point2<f64>, 16>::new();
         for (i, mot_data) in nf_data.iter().enumerate() {
             if mot_data.area == 0 {
                 continue;
             }
-            // todo don't use hardcoded 4095x4095 res assumption
-            let p = Point2::new(mot_data.cx, mot_data.cy).cast::<f64>() / 4095.
-                - Vector2::new(0.5, 0.5);
+            let p = positions[i].cast::<f64>() - Vector2::new(0.5, 0.5);
             let p = gravity_rot * p;
-            nf_points.push((p + Vector2::new(0.5, 0.5)) * 4095.);
+            nf_points.push(
+                (p + Vector2::new(0.5, 0.5))
+                    .coords
+                    .component_mul(&sensor_max)
+                    .into(),
+            );
             let p = draw_tf * p;
+            drawn.push(MarkerReadout {
+                position: p,
+                camera: "nf",
+                id: i as u8,
+                pattern_id: None,
+                screen_id: state.screen_id,
+                raw: Point2::new(mot_data.cx as f32, mot_data.cy as f32),
+                normalized: positions[i],
+                area: Some(mot_data.area as u32),
+            });
 
             let left = mot_data.boundary_left as f64 / 98.;
             let down = mot_data.boundary_down as f64 / 98.;
             let right = mot_data.boundary_right as f64 / 98.;
             let up = mot_data.boundary_up as f64 / 98.;
 
-            custom_shapes::draw_rectangle(
-                ctx,
-                &nf_path,
-                &[left, down, right, up],
-                &gravity_rot,
-                &draw_tf,
-            );
+            if !undistort {
+                custom_shapes::draw_rectangle(
+                    ctx,
+                    &nf_path,
+                    &[left, down, right, up],
+                    &gravity_rot,
+                    &draw_tf,
+                );
+            }
             custom_shapes::draw_marker(
                 ctx,
                 &ch_path,
@@ -276,6 +360,7 @@ fn draw_raw(
                     "({:.3}, {:.3}) id={} area={}",
                     mot_data.cx, mot_data.cy, i, mot_data.area
                 ),
+                palette.foreground,
             );
         }
     }
@@ -283,28 +368,40 @@ fn draw_raw(
     nf_grid_path.end(ctx);
 
     if let Some(wf_data) = state.wf_data.as_ref() {
+        let positions = raw_positions(wf_data, state, undistort.then_some(&config.camera_model_wf));
         for (i, mot_data) in wf_data.iter().enumerate() {
             if mot_data.area == 0 {
                 continue;
             }
 
-            let p = Point2::new(mot_data.cx, mot_data.cy).cast::<f64>() / 4095.
-                - Vector2::new(0.5, 0.5);
+            let p = positions[i].cast::<f64>() - Vector2::new(0.5, 0.5);
             let p = gravity_rot * p;
             let p = draw_tf * p;
+            drawn.push(MarkerReadout {
+                position: p,
+                camera: "wf",
+                id: i as u8,
+                pattern_id: None,
+                screen_id: state.screen_id,
+                raw: Point2::new(mot_data.cx as f32, mot_data.cy as f32),
+                normalized: positions[i],
+                area: Some(mot_data.area as u32),
+            });
 
             let left = mot_data.boundary_left as f64 / 98.;
             let down = mot_data.boundary_down as f64 / 98.;
             let right = mot_data.boundary_right as f64 / 98.;
             let up = mot_data.boundary_up as f64 / 98.;
 
-            custom_shapes::draw_rectangle(
-                ctx,
-                &wf_path,
-                &[left, down, right, up],
-                &gravity_rot,
-                &draw_tf,
-            );
+            if !undistort {
+                custom_shapes::draw_rectangle(
+                    ctx,
+                    &wf_path,
+                    &[left, down, right, up],
+                    &gravity_rot,
+                    &draw_tf,
+                );
+            }
 
             custom_shapes::draw_marker(
                 ctx,
@@ -314,6 +411,7 @@ fn draw_raw(
                     "({:.3}, {:.3}) id={} area={}",
                     mot_data.cx, mot_data.cy, i, mot_data.area
                 ),
+                palette.foreground,
             );
             // draw_crosshair_rotated(&ctx, &ch_path, p.x, p.y, 50.);
         }
@@ -325,6 +423,7 @@ fn draw_not_raw(
     ctx: &DrawContext,
     state: &MotState,
     config: &ats_usb::device::GeneralSettings,
+    show_screen_outline: bool,
     draw_tf: Transform2<f64>,
     gravity_rot: Rotation2<f64>,
     nf_path: &Path,
@@ -332,6 +431,8 @@ fn draw_not_raw(
     _nf_grid_path: &Path,
     _ch_path: &Path,
     screen: Option<&(u8, ats_common::ScreenCalibration<f32>)>,
+    palette: &Palette,
+    drawn: &mut Vec<MarkerReadout>,
 ) {
     nf_path.end(ctx);
     wf_path.end(ctx);
@@ -339,24 +440,19 @@ fn draw_not_raw(
     let fy = config.camera_model_nf.p.m22;
 
     // After to_normalized_image_coordinates, coords are in normalized image space.
-    // Scale to fit display box. The divisor matches the sensor's max coordinate range.
-    // For POC: box is 0.5 x 0.375 (4:3 aspect), sensor is 20479 x 15359
-    // For regular: box is 0.5 x 0.5 (square), sensor is 4095 x 4095
-    let normalized_scale = if state.is_poc_markers {
-        // POC: scale so edge of sensor maps to edge of box
-        Scale2::new(fx / 20479.0, fy / 15359.0)
-    } else {
-        // Regular: 4095x4095 max coordinate range, square box
-        Scale2::new(fx / 4095.0, fy / 4095.0)
-    };
+    // Scale to fit display box. The divisor matches the sensor's max coordinate range, so the edge
+    // of the sensor maps to the edge of the box (0.5 x 0.5 for a square sensor, 0.5 x 0.375 for
+    // the 4:3 POC sensor).
+    let sensor_max = state.sensor_max();
+    let normalized_scale = Scale2::new(fx / sensor_max.x, fy / sensor_max.y);
 
     if state.is_poc_markers {
         tracing::debug!(
             "POC scale: fx={}, fy={}, scale_x={}, scale_y={}",
             fx,
             fy,
-            fx / 20479.0,
-            fy / 15359.0
+            normalized_scale.vector.x,
+            normalized_scale.vector.y
         );
     }
 
@@ -398,6 +494,16 @@ fn draw_not_raw(
         }
         let p = gravity_rot * p.cast();
         let p = draw_tf * p;
+        drawn.push(MarkerReadout {
+            position: p,
+            camera: "nf",
+            id: marker.mot_id,
+            pattern_id: marker.pattern_id,
+            screen_id: state.screen_id,
+            raw: marker.raw,
+            normalized: marker.normalized,
+            area: None,
+        });
 
         let marker_path = Path::new(ctx, FillMode::Winding);
         custom_shapes::draw_marker(
@@ -408,32 +514,33 @@ fn draw_not_raw(
                 "nf: x={:.2} y={:.2}",
                 marker.normalized.x, marker.normalized.y
             ),
+            palette.foreground,
         );
         marker_path.end(ctx);
-        match marker.pattern_id {
-            Some(0 | 3 | 6) => ctx.stroke(&marker_path, &solid_brush(1.0, 0.0, 0.0), &thin),
-            Some(1 | 4 | 7) => ctx.stroke(&marker_path, &solid_brush(0.0, 1.0, 0.0), &thin),
-            Some(2 | 5 | 8) => ctx.stroke(&marker_path, &solid_brush(0.0, 0.0, 1.0), &thin),
-            Some(_) => ctx.stroke(&marker_path, &solid_brush(1.0, 0.0, 1.0), &thin),
-            None => ctx.stroke(&marker_path, &solid_brush(0.0, 0.0, 0.0), &thin),
-        }
+        let color = palette.marker(marker.pattern_id, 9, palette.foreground);
+        ctx.stroke(&marker_path, &color.brush(), &thin);
     }
 
     for marker in state.wf_markers2.iter() {
         let p = normalized_scale * marker.normalized;
         let p = gravity_rot * p.cast();
         let p = draw_tf * p;
+        drawn.push(MarkerReadout {
+            position: p,
+            camera: "wf",
+            id: marker.mot_id,
+            pattern_id: marker.pattern_id,
+            screen_id: state.screen_id,
+            raw: marker.raw,
+            normalized: marker.normalized,
+            area: None,
+        });
 
         let marker_path = Path::new(ctx, FillMode::Winding);
-        custom_shapes::draw_marker_rotated(ctx, &marker_path, p, "wf");
+        custom_shapes::draw_marker_rotated(ctx, &marker_path, p, "wf", palette.foreground);
         marker_path.end(ctx);
-        match marker.pattern_id {
-            Some(0 | 3) => ctx.stroke(&marker_path, &solid_brush(1.0, 0.0, 0.0), &thin),
-            Some(1 | 4) => ctx.stroke(&marker_path, &solid_brush(0.0, 1.0, 0.0), &thin),
-            Some(2 | 5) => ctx.stroke(&marker_path, &solid_brush(0.0, 0.0, 1.0), &thin),
-            Some(_) => ctx.stroke(&marker_path, &solid_brush(1.0, 0.0, 1.0), &thin),
-            None => ctx.stroke(&marker_path, &solid_brush(1.0, 0.0, 0.0), &thin),
-        }
+        let color = palette.marker(marker.pattern_id, 6, palette.pattern[0]);
+        ctx.stroke(&marker_path, &color.brush(), &thin);
     }
 
     if let Some((_, screen_calibration)) = screen {
@@ -445,14 +552,12 @@ fn draw_not_raw(
             let fv_reproj_path = Path::new(ctx, FillMode::Winding);
             let p = reproj_tf.cast().inverse_transform_point(&p);
             let p = p / p.z;
-            // todo don't use hardcoded 4095x4095 res assumption
-            let p = Point2::new(p.x * fx, p.y * fy);
-            let p = p / 4095.;
+            let p = normalized_scale * p.xy();
             let p = gravity_rot * p.cast();
             let p = draw_tf * p;
             draw_crosshair_rotated(&ctx, &fv_reproj_path, p.x, p.y, 20.);
             fv_reproj_path.end(&ctx);
-            ctx.stroke(&fv_reproj_path, &solid_brush(0.0, 0.69, 0.42), &thick3);
+            ctx.stroke(&fv_reproj_path, &palette.fv_reprojection.brush(), &thick3);
         }
     }
     let pnp_iso = ats_cv::telemetry::pnp_solutions().get_last();
@@ -464,26 +569,73 @@ fn draw_not_raw(
                 let pnp_reproj_path = Path::new(ctx, FillMode::Winding);
                 let p = reproj_tf.cast().inverse_transform_point(&p);
                 let p = p / p.z;
-                // todo don't use hardcoded 4095x4095 res assumption
-                let p = Point2::new(p.x * fx, p.y * fy);
-                let p = p / 4095.;
+                let p = normalized_scale * p.xy();
                 let p = gravity_rot * p.cast();
                 let p = draw_tf * p;
                 draw_crosshair_rotated(&ctx, &pnp_reproj_path, p.x, p.y, 20.);
                 pnp_reproj_path.end(&ctx);
-                ctx.stroke(&pnp_reproj_path, &solid_brush(0.3, 0.3, 0.3), &thick3);
+                ctx.stroke(&pnp_reproj_path, &palette.pnp_reprojection.brush(), &thick3);
             }
         }
     }
+    if let Some((_, screen_calibration)) = screen.filter(|_| show_screen_outline) {
+        draw_screen_outline(
+            ctx,
+            state,
+            screen_calibration,
+            |p| draw_tf * (gravity_rot * (normalized_scale * p).cast()),
+            palette,
+            &thick2,
+        );
+    }
     for p in &state.wf_reproj {
         let wf_reproj_path = Path::new(ctx, FillMode::Winding);
-        // todo don't use hardcoded 4095x4095 res assumption
-        let p = Point2::new(p.x * fx, p.y * fy);
-        let p = p / 4095.;
+        let p = normalized_scale * *p;
         let p = gravity_rot * p.cast();
         let p = draw_tf * p;
         draw_crosshair_rotated(&ctx, &wf_reproj_path, p.x, p.y, 20.);
         wf_reproj_path.end(&ctx);
-        ctx.stroke(&wf_reproj_path, &solid_brush(0.627, 0.125, 0.941), &thick2);
+        ctx.stroke(&wf_reproj_path, &palette.wf_reprojection.brush(), &thick2);
+    }
+}
+
+/// Outline of the calibrated screen as seen from the filter's current pose.
+///
+/// Nothing is drawn while tracking is lost or when a corner is behind the camera, since the
+/// outline would be meaningless. `to_canvas` maps normalized image coordinates to the canvas.
+fn draw_screen_outline(
+    ctx: &DrawContext,
+    state: &MotState,
+    screen_calibration: &ats_common::ScreenCalibration<f32>,
+    to_canvas: impl Fn(Point2<f32>) -> Point2<f64>,
+    palette: &Palette,
+    stroke: &StrokeParams,
+) {
+    if state.tracking_status == TrackingStatus::Lost {
+        return;
+    }
+    let Some(corners) = crate::fusion::screen_corners(screen_calibration) else {
+        return;
+    };
+    let pose: Isometry3<f32> = Isometry3::from_parts(
+        state.fv_state.filter.position.into(),
+        state.fv_state.filter.orientation,
+    )
+    .cast();
+    let projected = crate::correspondence::project_object_points(&pose, &corners);
+    if !projected.iter().all(|p| p.x.is_finite() && p.y.is_finite()) {
+        return;
+    }
+    let outline_path = Path::new(ctx, FillMode::Winding);
+    for (i, &p) in projected.iter().enumerate() {
+        let p = to_canvas(p);
+        if i == 0 {
+            outline_path.new_figure(ctx, p.x, p.y);
+        } else {
+            outline_path.line_to(ctx, p.x, p.y);
+        }
     }
+    outline_path.close_figure(ctx);
+    outline_path.end(ctx);
+    ctx.stroke(&outline_path, &palette.screen_outline.brush(), stroke);
 }